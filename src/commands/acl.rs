@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+use clap::Subcommand;
+
+use crate::core::security::acl_backup::{AclBackup, AclBackupManager, Drift};
+
+#[derive(Subcommand)]
+pub enum AclCommand {
+    /// Save owner, group, DACL and SACL of a path to a JSON backup file
+    Save {
+        /// File or folder to back up
+        path: PathBuf,
+
+        /// Backup file to write
+        #[clap(long)]
+        out: PathBuf,
+
+        /// Include every file and folder below the path
+        #[clap(long)]
+        recursive: bool,
+    },
+    /// Restore the ACLs saved in a backup file
+    Restore {
+        /// Backup file written by `acl save`
+        file: PathBuf,
+
+        /// Only report entries that differ from the backup
+        #[clap(long)]
+        verify: bool,
+    },
+}
+
+pub fn run(command: AclCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let manager = AclBackupManager::new();
+
+    match command {
+        AclCommand::Save { path, out, recursive } => {
+            let backup = manager.save(&path, recursive)?;
+            backup.write(&out)?;
+            println!("Saved ACLs of {} item(s) to {}", backup.entries.len(), out.display());
+        }
+        AclCommand::Restore { file, verify: true } => {
+            let backup = AclBackup::read(&file)?;
+            let drift = manager.verify(&backup)?;
+            for (path, drift) in &drift {
+                match drift {
+                    Drift::Missing => println!("{}: missing", path.display()),
+                    Drift::Changed(parts) => println!("{}: changed {}", path.display(), parts.join(", ")),
+                }
+            }
+            println!("{} of {} item(s) differ from the backup", drift.len(), backup.entries.len());
+        }
+        AclCommand::Restore { file, verify: false } => {
+            let report = manager.restore(&AclBackup::read(&file)?)?;
+            println!(
+                "Restored {} item(s), {} unchanged, {} missing",
+                report.restored, report.unchanged, report.missing
+            );
+        }
+    }
+    Ok(())
+}
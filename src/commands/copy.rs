@@ -0,0 +1,77 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use clap::Args;
+
+use crate::core::files::copy::{backup_copy, format_manifest, CopyOptions, CopyProgress};
+use crate::utils::files::file_error;
+
+#[derive(Args)]
+pub struct CopyArgs {
+    /// File or folder to copy
+    source: PathBuf,
+
+    /// Destination path; a file copied onto a folder lands inside it
+    destination: PathBuf,
+
+    /// Enable SeBackupPrivilege/SeRestorePrivilege and ignore DACLs
+    #[clap(long)]
+    backup_semantics: bool,
+
+    /// Copy folders with everything below them
+    #[clap(long, short)]
+    recursive: bool,
+
+    /// Also copy alternate data streams
+    #[clap(long)]
+    streams: bool,
+
+    /// Also copy owner, DACL and SACL
+    #[clap(long)]
+    security: bool,
+
+    /// Write the SHA-256 manifest to this file instead of stdout
+    #[clap(long)]
+    manifest: Option<PathBuf>,
+}
+
+pub fn run(args: CopyArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let options = CopyOptions {
+        backup_semantics: args.backup_semantics,
+        recursive: args.recursive,
+        keep_streams: args.streams,
+        keep_security: args.security,
+    };
+
+    let mut stderr = std::io::stderr();
+    // consoles without VT processing print escape codes as text, so a shorter
+    // line is padded over the previous one instead of clearing it
+    let mut previous = 0;
+    let mut report = |progress: &CopyProgress| {
+        let line = format!(
+            "[{}/{}] {} ({} bytes)",
+            progress.index,
+            progress.total,
+            progress.path.display(),
+            progress.bytes_copied
+        );
+        let _ = write!(stderr, "\r{:<width$}", line, width = previous);
+        previous = line.chars().count();
+    };
+    let manifest = backup_copy(&args.source, &args.destination, options, &mut report);
+    eprintln!();
+    let manifest = manifest?;
+
+    let bytes: u64 = manifest.iter().map(|entry| entry.size).sum();
+    eprintln!("Copied {} file(s), {} bytes", manifest.len(), bytes);
+
+    let lines = format_manifest(&manifest);
+    match &args.manifest {
+        Some(path) => {
+            fs::write(path, lines).map_err(|e| file_error(e, &format!("Failed to write {}", path.display())))?;
+            eprintln!("Manifest written to {}", path.display());
+        }
+        None => print!("{}", lines),
+    }
+    Ok(())
+}
@@ -0,0 +1,24 @@
+use clap::Args;
+
+use crate::core::elevation::system_service::run_helper;
+
+/// Started by the temporary service of `--system`, not meant to be run by hand
+#[derive(Args)]
+pub struct HelperArgs {
+    /// Name of the temporary service this process runs as
+    #[clap(long)]
+    service: String,
+
+    /// Pipe the client listens on
+    #[clap(long)]
+    pipe: String,
+
+    /// Command and arguments to run
+    #[clap(last = true, required = true)]
+    command: Vec<String>,
+}
+
+pub fn run(args: HelperArgs) -> Result<(), Box<dyn std::error::Error>> {
+    run_helper(&args.service, &args.pipe, args.command)?;
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+use clap::Subcommand;
+
+use crate::core::registry::hive::HiveManager;
+use crate::utils::signal;
+
+#[derive(Subcommand)]
+pub enum HiveCommand {
+    /// Load a hive file, e.g. another installation's SOFTWARE, under HKLM
+    Load {
+        /// Hive file such as D:\Windows\System32\config\SOFTWARE
+        file: PathBuf,
+        /// Key name under HKLM to mount the hive as
+        name: String,
+        /// Leave the hive loaded after exiting instead of waiting for Ctrl+C
+        #[clap(long)]
+        keep: bool,
+    },
+    /// Unload a hive loaded with `hive load`
+    Unload {
+        name: String,
+    },
+    /// List loaded hives, including stale ones left by crashed runs
+    List,
+}
+
+pub fn run(command: HiveCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let manager = HiveManager::new()?;
+    manager.report_stale()?;
+
+    match command {
+        HiveCommand::Load { file, name, keep: true } => {
+            manager.load(&file, &name, true)?;
+            println!("Loaded {} as HKLM\\{}, run `hive unload {}` when done", file.display(), name, name);
+        }
+        HiveCommand::Load { file, name, keep: false } => {
+            signal::install_ctrl_handler()?;
+            let hive = manager.load(&file, &name, false)?;
+            println!("Loaded {} as HKLM\\{}, press Ctrl+C to unload", file.display(), hive.name());
+
+            signal::wait_for_stop();
+            drop(hive);
+            signal::cleanup_finished();
+        }
+        HiveCommand::Unload { name } => {
+            manager.unload(&name)?;
+            println!("Unloaded HKLM\\{}", name);
+        }
+        HiveCommand::List => {
+            for mount in manager.mounts()? {
+                let state = if mount.is_stale() {
+                    "stale"
+                } else if mount.kept {
+                    "kept"
+                } else {
+                    "active"
+                };
+                println!("HKLM\\{:<20} {:<7} pid {:<7} {}", mount.name, state, mount.pid, mount.file.display());
+            }
+        }
+    }
+    Ok(())
+}
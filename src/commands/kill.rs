@@ -0,0 +1,45 @@
+use std::time::Duration;
+use clap::Args;
+
+use crate::core::elevation::impersonation::TrustedInstallerImpersonation;
+use crate::core::terminate::{end, targets, Ending};
+
+#[derive(Args)]
+pub struct KillArgs {
+    /// Process id, or image name such as TiWorker.exe to end every process of that name
+    target: String,
+
+    /// Also end every process the target started, and the ones those started
+    #[clap(long)]
+    tree: bool,
+
+    /// Seconds a process gets to close after being asked, before it is terminated
+    #[clap(long, value_name = "SECONDS", default_value_t = 5)]
+    timeout: u64,
+}
+
+pub fn run(args: KillArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let _ti = TrustedInstallerImpersonation::begin(&["SeDebugPrivilege"])?;
+
+    let targets = targets(&args.target, args.tree)?;
+    let mut failed = 0;
+    for entry in &targets {
+        match end(entry.pid, Duration::from_secs(args.timeout)) {
+            Ok(ending) => {
+                if let Ending::Refused(_) = ending {
+                    failed += 1;
+                }
+                println!("{} ({}): {}", entry.name, entry.pid, ending);
+            }
+            Err(error) => {
+                failed += 1;
+                eprintln!("{} ({}): {}", entry.name, entry.pid, error);
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(format!("{} of {} process(es) could not be ended", failed, targets.len()).into());
+    }
+    Ok(())
+}
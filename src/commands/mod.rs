@@ -0,0 +1,90 @@
+use clap::Subcommand;
+
+use crate::utils::config::Config;
+
+pub mod acl;
+pub mod copy;
+pub mod helper;
+pub mod hive;
+pub mod kill;
+pub mod own;
+pub mod pending;
+pub mod ps;
+pub mod reg;
+pub mod replace;
+pub mod request;
+pub mod run;
+pub mod runs;
+pub mod sddl;
+pub mod service;
+pub mod session;
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Parse, explain and canonicalize SDDL security descriptors
+    #[clap(subcommand)]
+    Sddl(sddl::SddlCommand),
+    /// Take ownership of a protected file or folder, saving its security first
+    Own(own::OwnArgs),
+    /// Give ownership back to TrustedInstaller and restore the saved DACL
+    RestoreOwner(own::RestoreOwnerArgs),
+    /// Save ACLs to a JSON file and restore or verify them later
+    #[clap(subcommand)]
+    Acl(acl::AclCommand),
+    /// Read and change registry keys as TrustedInstaller, backing them up first
+    #[clap(subcommand)]
+    Reg(reg::RegCommand),
+    /// Mount offline registry hives under HKLM for repairs
+    #[clap(subcommand)]
+    Hive(hive::HiveCommand),
+    /// Copy files with BackupRead/BackupWrite, keeping streams and security
+    Copy(copy::CopyArgs),
+    /// Swap a protected file as TrustedInstaller, rolling back on failure
+    Replace(replace::ReplaceArgs),
+    /// Manage file deletes and renames scheduled for the next boot
+    #[clap(subcommand)]
+    Pending(pending::PendingCommand),
+    /// Query and control any service with TrustedInstaller rights, or install the broker service
+    Service(service::ServiceArgs),
+    /// Ask the broker service to run a pre-approved command as TrustedInstaller
+    Request(request::RequestArgs),
+    /// End a process, or every process of a name, with TrustedInstaller rights
+    Kill(kill::KillArgs),
+    /// List processes with their session, user, integrity level, elevation and protection
+    Ps(ps::PsArgs),
+    /// Run a command as TrustedInstaller, the same as leaving out the subcommand
+    Run(run::RunArgs),
+    /// List, wait for, check on and end commands started with `run --detach`
+    #[clap(subcommand)]
+    Runs(runs::RunsCommand),
+    /// Keep a TrustedInstaller worker running for fast repeated commands
+    #[clap(subcommand)]
+    Session(session::SessionCommand),
+    /// Runs inside the temporary service created by --system
+    #[clap(hide = true)]
+    ServiceHelper(helper::HelperArgs),
+}
+
+impl Command {
+    pub fn run(self, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            Command::Sddl(command) => sddl::run(command),
+            Command::Own(args) => own::own(args),
+            Command::RestoreOwner(args) => own::restore_owner(args),
+            Command::Acl(command) => acl::run(command),
+            Command::Reg(command) => reg::run(command),
+            Command::Hive(command) => hive::run(command),
+            Command::Copy(args) => copy::run(args),
+            Command::Replace(args) => replace::run(args),
+            Command::Pending(command) => pending::run(command),
+            Command::Service(args) => service::run(args, config),
+            Command::Request(args) => request::run(args, config),
+            Command::Kill(args) => kill::run(args),
+            Command::Ps(args) => ps::run(args),
+            Command::Run(args) => run::run(args, config),
+            Command::Runs(command) => runs::run(command),
+            Command::Session(command) => session::run(command, config),
+            Command::ServiceHelper(args) => helper::run(args),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use std::path::PathBuf;
+use clap::Args;
+
+use crate::core::security::descriptor::lookup_account;
+use crate::core::security::ownership::{OwnOptions, OwnershipManager};
+
+#[derive(Args)]
+pub struct OwnArgs {
+    /// File or folder to take ownership of
+    path: PathBuf,
+
+    /// Apply to every file and folder below the path
+    #[clap(long)]
+    recursive: bool,
+
+    /// New owner, as an account name, SID or SDDL alias
+    #[clap(long, default_value = "BA")]
+    owner: String,
+
+    /// Also grant this principal the full access TrustedInstaller has
+    #[clap(long)]
+    grant: Option<String>,
+}
+
+#[derive(Args)]
+pub struct RestoreOwnerArgs {
+    /// File or folder previously changed with `own`
+    path: PathBuf,
+
+    /// Reset the owner of children too when no backup exists
+    #[clap(long)]
+    recursive: bool,
+}
+
+pub fn own(args: OwnArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let options = OwnOptions {
+        owner: lookup_account(&args.owner)?,
+        grant: args.grant.as_deref().map(lookup_account).transpose()?,
+        recursive: args.recursive,
+    };
+
+    let changed = OwnershipManager::new()?.take(&args.path, &options)?;
+    println!("Took ownership of {} item(s) under {}", changed, args.path.display());
+    Ok(())
+}
+
+pub fn restore_owner(args: RestoreOwnerArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let restored = OwnershipManager::new()?.restore(&args.path, args.recursive)?;
+    println!("Restored TrustedInstaller ownership of {} item(s) under {}", restored, args.path.display());
+    Ok(())
+}
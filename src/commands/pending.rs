@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+use clap::Subcommand;
+
+use crate::core::files::pending::PendingManager;
+
+#[derive(Subcommand)]
+pub enum PendingCommand {
+    /// Show the deletes and renames scheduled for the next boot
+    List,
+    /// Schedule deleting a file, or moving it over another one, at the next boot
+    Add {
+        /// File to delete or move
+        source: PathBuf,
+        /// Where to move the file; without it the file is deleted
+        destination: Option<PathBuf>,
+    },
+    /// Drop one scheduled operation by its number in `pending list`
+    Remove { index: usize },
+    /// Drop every scheduled operation
+    Clear,
+}
+
+pub fn run(command: PendingCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let manager = PendingManager::new()?;
+
+    match command {
+        PendingCommand::List => {
+            let operations = manager.list()?;
+            for (index, operation) in operations.iter().enumerate() {
+                println!("{:>3}  {}", index + 1, operation);
+            }
+            println!("{} operation(s) pending until the next boot", operations.len());
+        }
+        PendingCommand::Add { source, destination } => {
+            manager.add(&source, destination.as_deref())?;
+            match destination {
+                Some(destination) => {
+                    println!("{} moves to {} at the next boot", source.display(), destination.display())
+                }
+                None => println!("{} is deleted at the next boot", source.display()),
+            }
+        }
+        PendingCommand::Remove { index } => {
+            let removed = manager.remove(index)?;
+            println!("Removed {}", removed);
+        }
+        PendingCommand::Clear => {
+            let count = manager.clear()?;
+            println!("Cleared {} pending operation(s)", count);
+        }
+    }
+    Ok(())
+}
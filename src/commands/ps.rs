@@ -0,0 +1,80 @@
+use clap::Args;
+
+use crate::core::inspect::{inspect, ProcessDetails};
+use crate::core::process::processes;
+use crate::core::token::Principal;
+use crate::utils::security::SecurityContext;
+
+#[derive(Args)]
+pub struct PsArgs {
+    /// only processes whose user, owner or an enabled group matches, such as TrustedInstaller or S-1-5-18
+    #[clap(long, value_name = "ACCOUNT")]
+    user: Option<String>,
+
+    /// only processes in this session, 0 being the services' session
+    #[clap(long, value_name = "ID")]
+    session: Option<u32>,
+
+    /// only processes whose image name contains this, ignoring case
+    #[clap(long)]
+    name: Option<String>,
+
+    /// print the processes as JSON
+    #[clap(long)]
+    json: bool,
+}
+
+pub fn run(args: PsArgs) -> Result<(), Box<dyn std::error::Error>> {
+    // SeDebugPrivilege opens nearly every process, without it more are partial
+    if let Err(error) = SecurityContext::new() {
+        eprintln!("Listing without SeDebugPrivilege ({}), more processes will be partial", error);
+    }
+
+    let name = args.name.as_deref().map(str::to_lowercase);
+    let listed: Vec<ProcessDetails> = processes()?
+        .into_iter()
+        .filter(|entry| name.as_deref().map_or(true, |name| entry.name.to_lowercase().contains(name)))
+        .map(inspect)
+        .filter(|details| args.session.map_or(true, |session| details.session == Some(session)))
+        .filter(|details| args.user.as_deref().map_or(true, |user| details.runs_as(user)))
+        .collect();
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&listed)?);
+        return Ok(());
+    }
+
+    println!(
+        "{:>6} {:>6} {:>4}  {:<9} {:<4} {:<17} {:<28} IMAGE",
+        "PID", "PPID", "SESS", "INTEGRITY", "ELEV", "PROTECTION", "USER"
+    );
+    for details in &listed {
+        println!(
+            "{:>6} {:>6} {:>4}  {:<9} {:<4} {:<17} {:<28} {}{}",
+            details.pid,
+            details.parent_pid,
+            or_unknown(details.session),
+            or_unknown(details.integrity.map(|level| format!("{:?}", level).to_lowercase())),
+            or_unknown(details.elevated.map(|elevated| if elevated { "yes" } else { "no" })),
+            or_unknown(details.protection),
+            or_unknown(details.user.as_ref().map(account)),
+            details.image.as_deref().unwrap_or(&details.name),
+            if details.partial { "  (partial)" } else { "" }
+        );
+    }
+    let partial = listed.iter().filter(|details| details.partial).count();
+    println!("{} process(es), {} partial for lack of access", listed.len(), partial);
+    Ok(())
+}
+
+fn account(principal: &Principal) -> String {
+    if principal.name.is_empty() {
+        principal.sid.clone()
+    } else {
+        principal.name.clone()
+    }
+}
+
+fn or_unknown<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "?".to_string(), |value| value.to_string())
+}
@@ -0,0 +1,192 @@
+use std::fs;
+use std::path::PathBuf;
+use clap::{Args, Subcommand};
+
+use crate::core::registry::manager::RegistryManager;
+use crate::core::registry::path::{KeyPath, RegistryView};
+use crate::core::registry::regfile::{RegFile, RegFileVersion};
+use crate::core::registry::value::RegValue;
+use crate::core::security::descriptor::lookup_account;
+use crate::utils::error::ElevateError;
+
+#[derive(Subcommand)]
+pub enum RegCommand {
+    /// Print a value of a key
+    Get {
+        /// Key such as HKLM\SOFTWARE\Microsoft
+        #[clap(value_parser = parse_key)]
+        key: KeyPath,
+        /// Value name, "@" or omitted for the default value
+        name: Option<String>,
+        #[clap(flatten)]
+        view: ViewArgs,
+    },
+    /// Write a value, creating the key if needed
+    Set {
+        #[clap(value_parser = parse_key)]
+        key: KeyPath,
+        /// Value name, "@" for the default value
+        name: String,
+        /// Data as reg.exe takes it: 0x hex numbers, hex bytes, "\0" between strings
+        data: String,
+        /// REG_SZ, REG_EXPAND_SZ, REG_MULTI_SZ, REG_DWORD, REG_QWORD, REG_BINARY or REG_NONE
+        #[clap(long = "type", default_value = "REG_SZ")]
+        kind: String,
+        #[clap(flatten)]
+        view: ViewArgs,
+    },
+    /// Delete a value, or the whole key when no value name is given
+    Delete {
+        #[clap(value_parser = parse_key)]
+        key: KeyPath,
+        name: Option<String>,
+        #[clap(flatten)]
+        view: ViewArgs,
+    },
+    /// List the subkeys and values of a key
+    List {
+        #[clap(value_parser = parse_key)]
+        key: KeyPath,
+        #[clap(flatten)]
+        view: ViewArgs,
+    },
+    /// Take ownership of a key, saving its security first
+    Own {
+        #[clap(value_parser = parse_key)]
+        key: KeyPath,
+        /// New owner, as an account name, SID or SDDL alias
+        #[clap(long, default_value = "BA")]
+        owner: String,
+        /// Also grant this principal full control of the key
+        #[clap(long)]
+        grant: Option<String>,
+        #[clap(flatten)]
+        view: ViewArgs,
+    },
+    /// Apply a .reg file (REGEDIT4 or version 5) as TrustedInstaller
+    Import {
+        file: PathBuf,
+        /// Show the changes against the live registry without applying them
+        #[clap(long)]
+        dry_run: bool,
+        #[clap(flatten)]
+        view: ViewArgs,
+    },
+    /// Write a key and its subkeys to a .reg file
+    Export {
+        #[clap(value_parser = parse_key)]
+        key: KeyPath,
+        file: PathBuf,
+        /// Write the ANSI REGEDIT4 format instead of version 5
+        #[clap(long)]
+        regedit4: bool,
+        #[clap(flatten)]
+        view: ViewArgs,
+    },
+    /// Undo the last change made to a key by these commands
+    Restore {
+        #[clap(value_parser = parse_key)]
+        key: KeyPath,
+        #[clap(flatten)]
+        view: ViewArgs,
+    },
+}
+
+#[derive(Args)]
+pub struct ViewArgs {
+    /// Registry view: 32, 64 or native
+    #[clap(long, default_value = "native", value_parser = parse_view)]
+    view: RegistryView,
+}
+
+pub fn run(command: RegCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let manager = RegistryManager::new()?;
+
+    match command {
+        RegCommand::Get { key, name, view } => {
+            let name = value_name(name.as_deref());
+            match manager.get(&key, view.view, name)? {
+                Some(value) => println!("{}    {}", value.type_name(), value),
+                None => return Err(format!("{} has no value {}", key, display_name(name)).into()),
+            }
+        }
+        RegCommand::Set { key, name, data, kind, view } => {
+            let value = RegValue::parse(&kind, &data)?;
+            manager.set(&key, view.view, value_name(Some(&name)), &value)?;
+            println!("Set {} in {}", name, key);
+        }
+        RegCommand::Delete { key, name: Some(name), view } => {
+            if manager.delete_value(&key, view.view, value_name(Some(&name)))? {
+                println!("Deleted {} from {}", name, key);
+            } else {
+                println!("{} has no value {}", key, name);
+            }
+        }
+        RegCommand::Delete { key, name: None, view } => {
+            manager.delete_key(&key, view.view)?;
+            println!("Deleted {}", key);
+        }
+        RegCommand::List { key, view } => {
+            let listing = manager.list(&key, view.view)?;
+            for subkey in &listing.subkeys {
+                println!("{}\\{}", key, subkey);
+            }
+            for (name, value) in &listing.values {
+                println!("    {:<30} {:<14} {}", display_name(name), value.type_name(), value);
+            }
+        }
+        RegCommand::Own { key, owner, grant, view } => {
+            let owner = lookup_account(&owner)?;
+            let grant = grant.as_deref().map(lookup_account).transpose()?;
+            manager.own(&key, view.view, &owner, grant.as_ref())?;
+            println!("Took ownership of {}", key);
+        }
+        RegCommand::Import { file, dry_run, view } => {
+            let bytes = fs::read(&file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+            let reg_file = RegFile::parse(&bytes)?;
+            let changes = if dry_run {
+                manager.preview_import(&reg_file, view.view)?
+            } else {
+                manager.import(&reg_file, view.view)?
+            };
+
+            for change in &changes {
+                println!("{}", change);
+            }
+            let verb = if dry_run { "would be made" } else { "made" };
+            println!("{} change(s) {} from {}", changes.len(), verb, file.display());
+        }
+        RegCommand::Export { key, file, regedit4, view } => {
+            let version = if regedit4 { RegFileVersion::Regedit4 } else { RegFileVersion::Version5 };
+            let reg_file = manager.export(&key, view.view, version)?;
+            fs::write(&file, reg_file.to_bytes()).map_err(|e| format!("Failed to write {}: {}", file.display(), e))?;
+            println!("Exported {} key(s) to {}", reg_file.keys.len(), file.display());
+        }
+        RegCommand::Restore { key, view } => {
+            let backup = manager.restore(&key, view.view)?;
+            println!("Restored {} as it was before {}", key, backup.operation);
+        }
+    }
+    Ok(())
+}
+
+// clap wants errors that are Send + Sync, ElevateError is neither
+fn parse_key(key: &str) -> Result<KeyPath, String> {
+    KeyPath::parse(key).map_err(|e| e.to_string())
+}
+
+fn parse_view(view: &str) -> Result<RegistryView, String> {
+    view.parse().map_err(|e: ElevateError| e.to_string())
+}
+
+// "@" names the default value, as in .reg files
+fn value_name(name: Option<&str>) -> &str {
+    match name {
+        None | Some("@") => "",
+        Some(name) => name,
+    }
+}
+
+fn display_name(name: &str) -> &str {
+    if name.is_empty() { "(Default)" } else { name }
+}
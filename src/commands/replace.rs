@@ -0,0 +1,29 @@
+use std::path::PathBuf;
+use clap::Args;
+
+use crate::core::files::replace::{FileReplacer, ReplaceOutcome};
+
+#[derive(Args)]
+pub struct ReplaceArgs {
+    /// Protected file to replace
+    target: PathBuf,
+
+    /// File with the new contents
+    new: PathBuf,
+}
+
+pub fn run(args: ReplaceArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let report = FileReplacer::new()?.replace(&args.target, &args.new)?;
+
+    println!("Original saved to {}", report.backup.backup.display());
+    match report.outcome {
+        ReplaceOutcome::Replaced => println!("Replaced {} (SHA-256 {})", args.target.display(), report.sha256),
+        ReplaceOutcome::Scheduled { staged } => println!(
+            "{} is in use, {} replaces it at the next boot (SHA-256 {})",
+            args.target.display(),
+            staged.display(),
+            report.sha256
+        ),
+    }
+    Ok(())
+}
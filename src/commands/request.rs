@@ -0,0 +1,21 @@
+use clap::Args;
+
+use crate::core::broker::protocol::{call, BrokerRequest};
+use crate::core::broker::server::connect_broker;
+use crate::core::service::relay::print_output;
+use crate::utils::config::Config;
+
+#[derive(Args)]
+pub struct RequestArgs {
+    /// Command exactly as the broker rules name it, and its arguments
+    #[clap(trailing_var_arg = true, required = true)]
+    command: Vec<String>,
+}
+
+pub fn run(args: RequestArgs, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let mut pipe = connect_broker(&config.broker.pipe)?;
+    let request = BrokerRequest { command: args.command[0].clone(), args: args.command[1..].to_vec() };
+
+    let code = call(&mut pipe, &request, &mut print_output)?;
+    std::process::exit(code);
+}
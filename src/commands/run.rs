@@ -0,0 +1,296 @@
+use std::time::Duration;
+use clap::Args;
+use tracing::info;
+
+use crate::core::elevation::command::ElevatedCommand;
+use crate::core::elevation::system_service::SystemServiceElevation;
+use crate::core::elevation::trusted_installer::TrustedInstallerElevation;
+use crate::core::hardening::preset;
+use crate::core::launch::{Affinity, ConsoleMode, ConsoleSize, LaunchSettings, Priority, WindowState};
+use crate::core::runs::{RunRecord, RunRegistry};
+use crate::core::service::machine::WaitOptions;
+use crate::core::session::registry::SessionRegistry;
+use crate::core::session::worker::run_in_session;
+use crate::core::restart::RestartPolicy;
+use crate::core::supervisor::supervise;
+use crate::utils::command_line::join_command_line;
+use crate::utils::config::{Config, HardeningConfig, JobConfig};
+use crate::utils::error::ElevateResult;
+use crate::utils::security::SecurityContext;
+use crate::utils::signal;
+use crate::utils::time::{short_id, unix_timestamp, SystemClock};
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// stop and re-disable the TrustedInstaller service afterwards if it was before
+    #[clap(long)]
+    restore_service: bool,
+
+    /// run as LocalSystem through a temporary service instead, relaying its output and exit code
+    #[clap(long)]
+    system: bool,
+
+    /// run in a worker started by `session start`, relaying its output and exit code
+    #[clap(long, value_name = "ID", conflicts_with_all = ["restore_service", "system"])]
+    session: Option<String>,
+
+    /// return right away and print a run id for `runs wait`, `runs status` and `runs kill`
+    #[clap(long, conflicts_with_all = ["system", "session", "kill_on_close"])]
+    detach: bool,
+
+    /// restart the command whenever it exits with a non-zero code or crashes, until Ctrl+C
+    #[clap(long, conflicts_with_all = ["system", "session", "detach", "restore_service"])]
+    supervise: bool,
+
+    /// restarts in a row before --supervise gives up, defaults to process.supervise.max_restarts in the config
+    #[clap(long, value_name = "COUNT", requires = "supervise")]
+    max_restarts: Option<u32>,
+
+    /// seconds the command has to run for --supervise to count restarts from zero again
+    #[clap(long, value_name = "SECONDS", requires = "supervise")]
+    cooldown: Option<u64>,
+
+    /// kill the command and everything it starts once ElevateKit exits
+    #[clap(long, conflicts_with_all = ["system", "session"])]
+    kill_on_close: bool,
+
+    /// limit the memory of the command and its children together, in megabytes
+    #[clap(long, value_name = "MB", conflicts_with_all = ["system", "session"])]
+    memory_limit: Option<u64>,
+
+    /// cap the CPU use of the command and its children, in percent of all processors
+    #[clap(long, value_name = "PERCENT", value_parser = clap::value_parser!(u32).range(1..=100), conflicts_with_all = ["system", "session"])]
+    cpu_rate: Option<u32>,
+
+    /// limit how many processes the command may have running at once, itself included
+    #[clap(long, value_name = "COUNT", conflicts_with_all = ["system", "session"])]
+    max_processes: Option<u32>,
+
+    /// keep the command away from the clipboard, desktop and system settings
+    #[clap(long, conflicts_with_all = ["system", "session"])]
+    restrict_ui: bool,
+
+    /// apply a named set of mitigations: standard, strict or one from the config
+    #[clap(long, value_name = "PRESET", conflicts_with_all = ["system", "session"])]
+    hardening: Option<String>,
+
+    /// let the command load only DLLs signed by Microsoft
+    #[clap(long, conflicts_with_all = ["system", "session"])]
+    microsoft_signed_only: bool,
+
+    /// keep the command from loading images off network shares
+    #[clap(long, conflicts_with_all = ["system", "session"])]
+    no_remote_images: bool,
+
+    /// keep the command from starting processes of its own
+    #[clap(long, conflicts_with_all = ["system", "session"])]
+    no_child_processes: bool,
+
+    /// end the command if it uses an invalid handle
+    #[clap(long, conflicts_with_all = ["system", "session"])]
+    strict_handle_checks: bool,
+
+    /// enforce control flow guard in images built with it
+    #[clap(long = "cfg", conflicts_with_all = ["system", "session"])]
+    control_flow_guard: bool,
+
+    /// forbid dynamic code (arbitrary code guard); breaks .NET and PowerShell
+    #[clap(long = "acg", conflicts_with_all = ["system", "session"])]
+    no_dynamic_code: bool,
+
+    /// priority class: idle, below-normal, normal, above-normal, high or realtime
+    #[clap(long, value_name = "LEVEL", conflicts_with_all = ["system", "session"])]
+    priority: Option<Priority>,
+
+    /// processors the command may run on, as a mask such as 0x0f or GROUP:MASK such as 1:0xff
+    #[clap(long, value_name = "MASK", conflicts_with_all = ["system", "session"])]
+    affinity: Option<Affinity>,
+
+    /// start the command's window hidden
+    #[clap(long, conflicts_with_all = ["system", "session", "minimized", "maximized"])]
+    hidden: bool,
+
+    /// start the command's window minimized, without taking the focus
+    #[clap(long, conflicts_with_all = ["system", "session", "maximized"])]
+    minimized: bool,
+
+    /// start the command's window maximized
+    #[clap(long, conflicts_with_all = ["system", "session"])]
+    maximized: bool,
+
+    /// title of the command's console window
+    #[clap(long, value_name = "TEXT", conflicts_with_all = ["system", "session"])]
+    title: Option<String>,
+
+    /// console buffer size of the command, such as 120x40
+    #[clap(long, value_name = "COLSxROWS", conflicts_with_all = ["system", "session"])]
+    console_size: Option<ConsoleSize>,
+
+    /// give the command a console without a window
+    #[clap(long, conflicts_with_all = ["system", "session", "detached"])]
+    no_window: bool,
+
+    /// start the command without any console
+    #[clap(long, conflicts_with_all = ["system", "session"])]
+    detached: bool,
+
+    /// start the command outside the job ElevateKit runs in, if that job allows it
+    #[clap(long, conflicts_with_all = ["system", "session"])]
+    breakaway_from_job: bool,
+
+    /// command and arguments to execute (defaults to "powershell.exe" if not provided)
+    #[clap(trailing_var_arg = true)]
+    args: Vec<String>,
+}
+
+pub fn run(args: RunArgs, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    //  default to powershell.exe if no command is provided
+    let (command, command_args) = if args.args.is_empty() {
+        ("powershell.exe".to_string(), Vec::new())
+    } else {
+        (args.args[0].clone(), args.args[1..].to_vec())
+    };
+
+    info!("Running command: {} {:?}", command, command_args);
+
+    if let Some(id) = &args.session {
+        let session = SessionRegistry::new()?.find(id)?;
+        let code = run_in_session(&session, &command, &command_args)?;
+        std::process::exit(code);
+    }
+
+    if args.system {
+        let wait = WaitOptions::from_config(&config.services, signal::stop_token());
+        let code = SystemServiceElevation::new(wait).execute(&command, &command_args)?;
+        std::process::exit(code);
+    }
+
+    if args.supervise {
+        let code = run_supervised(&args, config, &command, &command_args)?;
+        std::process::exit(code as i32);
+    }
+
+    if args.detach && config.process.job.kill_on_close {
+        eprintln!("Ignoring kill_on_close from the config: a detached run keeps running after ElevateKit exits");
+    }
+
+    let security_context = SecurityContext::new()?;
+
+    // execute with TrustedInstaller privileges
+    let ti = TrustedInstallerElevation::new(&security_context)
+        .wait_options(WaitOptions::from_config(&config.services, signal::stop_token()))
+        .restore_service(
+            (args.restore_service || config.services.restore_trusted_installer).then_some(config.services.restore_after),
+        )
+        .job(job_config(&args, &config.process.job))
+        .hardening(hardening_config(&args, config)?)
+        .launch(LaunchSettings::from_flags(&config.process.creation_flags)?.overridden_by(&launch_settings(&args)))
+        .detach(args.detach);
+    let execution = ti.execute(&command, &command_args)?;
+    if args.detach {
+        let id = short_id();
+        RunRegistry::new()?.add(RunRecord {
+            id: id.clone(),
+            pid: execution.pid,
+            command: join_command_line(&[&[command], &command_args[..]].concat()),
+            started_at: unix_timestamp(),
+            created: execution.created,
+            finished_at: None,
+            exit_code: None,
+        })?;
+        println!("{}", id);
+    }
+    if let Some(mitigations) = &execution.mitigations {
+        eprintln!("Mitigations in effect: {}", mitigations);
+        let missing = hardening_config(&args, config)?.missing_from(mitigations);
+        if !missing.is_empty() {
+            eprintln!("Not applied by this system: {}", missing.join(", "));
+        }
+    }
+    if let Some(accounting) = execution.accounting {
+        eprintln!("Job used {}", accounting);
+    }
+
+    info!("Operation completed successfully");
+    Ok(())
+}
+
+// output and errors of the child go to our console, restarts are reported on stderr
+fn run_supervised(args: &RunArgs, config: &Config, command: &str, command_args: &[String]) -> ElevateResult<u32> {
+    let mut policy_config = config.process.supervise.clone();
+    policy_config.max_restarts = args.max_restarts.unwrap_or(policy_config.max_restarts);
+    policy_config.cooldown = args.cooldown.unwrap_or(policy_config.cooldown);
+
+    let mut elevated = ElevatedCommand::new(command);
+    elevated
+        .args(command_args)
+        .wait_options(WaitOptions::from_config(&config.services, signal::stop_token()))
+        .job(job_config(args, &config.process.job))
+        .hardening(hardening_config(args, config)?)
+        .launch(LaunchSettings::from_flags(&config.process.creation_flags)?.overridden_by(&launch_settings(args)));
+
+    signal::install_ctrl_handler()?;
+    let code = supervise(
+        &mut elevated,
+        &mut RestartPolicy::new(&policy_config),
+        &SystemClock,
+        &signal::stop_token(),
+        Duration::from_secs(policy_config.stop_timeout),
+        &mut |event| eprintln!("{}", event),
+    );
+    signal::cleanup_finished();
+    code
+}
+
+// flags add to the preset they are given with
+fn hardening_config(args: &RunArgs, config: &Config) -> ElevateResult<HardeningConfig> {
+    let flags = HardeningConfig {
+        microsoft_signed_only: args.microsoft_signed_only,
+        no_remote_images: args.no_remote_images,
+        no_child_processes: args.no_child_processes,
+        strict_handle_checks: args.strict_handle_checks,
+        control_flow_guard: args.control_flow_guard,
+        no_dynamic_code: args.no_dynamic_code,
+    };
+    match &args.hardening {
+        Some(name) => Ok(preset(name, &config.process.hardening_presets)?.union(&flags)),
+        None => Ok(flags),
+    }
+}
+
+// flags win over the creation flags in the config
+fn launch_settings(args: &RunArgs) -> LaunchSettings {
+    let window = if args.hidden {
+        Some(WindowState::Hidden)
+    } else if args.minimized {
+        Some(WindowState::Minimized)
+    } else {
+        args.maximized.then_some(WindowState::Maximized)
+    };
+    let console = if args.no_window {
+        Some(ConsoleMode::NoWindow)
+    } else {
+        args.detached.then_some(ConsoleMode::Detached)
+    };
+    LaunchSettings {
+        priority: args.priority,
+        affinity: args.affinity,
+        window,
+        console,
+        title: args.title.clone(),
+        console_size: args.console_size,
+        breakaway_from_job: args.breakaway_from_job,
+    }
+}
+
+// flags add to the limits in the config and win where both set one,
+// a detached run outlives us so it never gets kill_on_close
+fn job_config(args: &RunArgs, config: &JobConfig) -> JobConfig {
+    JobConfig {
+        kill_on_close: !args.detach && (args.kill_on_close || config.kill_on_close),
+        memory_limit: args.memory_limit.or(config.memory_limit),
+        cpu_rate: args.cpu_rate.or(config.cpu_rate),
+        max_processes: args.max_processes.or(config.max_processes),
+        restrict_ui: args.restrict_ui || config.restrict_ui,
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::{Duration, Instant};
+use clap::Subcommand;
+use winapi::um::winnt::PROCESS_TERMINATE;
+
+use crate::core::runs::{RunRecord, RunRegistry};
+use crate::core::terminate::{end_opened, exit_code, Ending};
+use crate::utils::security::SecurityContext;
+use crate::utils::signal;
+use crate::utils::time::{format_age, unix_timestamp};
+
+const WAIT_POLL: Duration = Duration::from_millis(200);
+
+#[derive(Subcommand)]
+pub enum RunsCommand {
+    /// List detached runs, running or finished within the last day
+    List,
+    /// Show the command, process and state of a run
+    Status { id: String },
+    /// Wait for a run to finish and exit with its exit code
+    Wait {
+        id: String,
+        /// Seconds to wait before giving up, without it waits until the run finishes or Ctrl+C
+        #[clap(long, value_name = "SECONDS")]
+        timeout: Option<u64>,
+    },
+    /// End a run, asking it to close first
+    Kill {
+        id: String,
+        /// Seconds the run gets to close after being asked, before it is terminated
+        #[clap(long, value_name = "SECONDS", default_value_t = 5)]
+        timeout: u64,
+    },
+}
+
+pub fn run(command: RunsCommand) -> Result<(), Box<dyn std::error::Error>> {
+    // runs belong to TrustedInstaller, SeDebugPrivilege lets us open them
+    let _context = SecurityContext::new()?;
+    let registry = RunRegistry::new()?;
+
+    match command {
+        RunsCommand::List => {
+            let runs = registry.list()?;
+            if runs.is_empty() {
+                println!("No detached runs");
+            }
+            for run in runs {
+                println!(
+                    "{}  pid {:<6}  up {:<8}  {:<22}  {}",
+                    run.id,
+                    run.pid,
+                    format_age(unix_timestamp().saturating_sub(run.started_at)),
+                    state(&run),
+                    run.command
+                );
+            }
+        }
+        RunsCommand::Status { id } => {
+            let run = registry.find(&id)?;
+            println!("{:<9} {}", "Run", run.id);
+            println!("{:<9} {}", "Command", run.command);
+            println!("{:<9} {}", "Pid", run.pid);
+            println!("{:<9} {} ago", "Started", format_age(unix_timestamp().saturating_sub(run.started_at)));
+            println!("{:<9} {}", "State", state(&run));
+        }
+        RunsCommand::Wait { id, timeout } => {
+            let mut run = registry.find(&id)?;
+            signal::install_ctrl_handler()?;
+            if let Some(process) = run.open(0)? {
+                let deadline = timeout.map(|secs| Instant::now() + Duration::from_secs(secs));
+                while !process.wait(Some(WAIT_POLL))? {
+                    if signal::stop_requested() || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        return Err(format!("Run {} is still running", run.id).into());
+                    }
+                }
+                run.finish(Some(exit_code(&process)?));
+                registry.update(&run)?;
+            }
+            eprintln!("Run {} {}", run.id, state(&run));
+            if let Some(code) = run.exit_code {
+                std::process::exit(code as i32);
+            }
+        }
+        RunsCommand::Kill { id, timeout } => {
+            let mut run = registry.find(&id)?;
+            let Some(process) = run.open(PROCESS_TERMINATE)? else {
+                registry.update(&run)?;
+                println!("Run {} {}", run.id, state(&run));
+                return Ok(());
+            };
+            let ending = end_opened(&process, run.pid, Duration::from_secs(timeout))?;
+            match ending {
+                Ending::Closed(code) | Ending::Terminated(code) => run.finish(Some(code)),
+                Ending::AlreadyExited => run.finish(Some(exit_code(&process)?)),
+                Ending::Refused(_) => {}
+            }
+            registry.update(&run)?;
+            println!("Run {} ({}): {}", run.id, run.pid, ending);
+        }
+    }
+    Ok(())
+}
+
+fn state(run: &RunRecord) -> String {
+    match (run.is_running(), run.exit_code) {
+        (true, _) => "running".to_string(),
+        (false, Some(code)) => format!("exited with code {}", code),
+        (false, None) => "finished".to_string(),
+    }
+}
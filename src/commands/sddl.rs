@@ -0,0 +1,30 @@
+use clap::Subcommand;
+
+use crate::core::security::sddl::SecurityDescriptor;
+
+#[derive(Subcommand)]
+pub enum SddlCommand {
+    /// Show owner, group and every ACE of an SDDL string as a table
+    Explain {
+        /// SDDL string, e.g. "O:TIG:TID:PAI(A;;FA;;;TI)"
+        sddl: String,
+    },
+    /// Print the canonical form of an SDDL string
+    Canonical {
+        sddl: String,
+    },
+}
+
+pub fn run(command: SddlCommand) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        SddlCommand::Explain { sddl } => {
+            let sd = SecurityDescriptor::parse(&sddl)?;
+            print!("{}", sd.explain());
+            println!("\nCanonical: {}", sd);
+        }
+        SddlCommand::Canonical { sddl } => {
+            println!("{}", SecurityDescriptor::parse(&sddl)?);
+        }
+    }
+    Ok(())
+}
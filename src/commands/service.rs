@@ -0,0 +1,149 @@
+use std::time::Duration;
+use clap::{Args, Parser, Subcommand};
+
+use crate::core::broker::server::{install, run_broker, uninstall, BROKER_SERVICE};
+use crate::core::elevation::impersonation::{TrustedInstallerImpersonation, SECURITY_PRIVILEGES};
+use crate::core::service::controller::ServiceController;
+use crate::core::service::machine::WaitOptions;
+use crate::core::service::state::{ServiceStatus, StartType};
+use crate::utils::config::Config;
+use crate::utils::error::ElevateError;
+use crate::utils::signal;
+
+// what the installed broker service runs
+const BROKER_ARGS: &[&str] = &["service", "broker"];
+
+#[derive(Args)]
+#[clap(override_usage = "elevatekit service <NAME> <ACTION> [--timeout <SECONDS>]\n       elevatekit service <install|uninstall>")]
+pub struct ServiceArgs {
+    #[clap(subcommand)]
+    command: ServiceCommand,
+}
+
+#[derive(Subcommand)]
+pub enum ServiceCommand {
+    /// Install the broker service, which runs the commands allowed in the config for non-admins
+    Install,
+    /// Stop and remove the broker service
+    Uninstall,
+    /// Run by the service control manager as the broker service
+    #[clap(hide = true)]
+    Broker,
+    /// Any other service, followed by status, start, stop, pause, continue or config
+    #[clap(external_subcommand)]
+    Control(Vec<String>),
+}
+
+// parsed separately, as a service name cannot sit next to install and uninstall
+#[derive(Parser)]
+#[clap(name = "elevatekit service")]
+struct ControlArgs {
+    /// Service name as shown by `sc query`, not the display name
+    name: String,
+
+    #[clap(subcommand)]
+    action: ServiceAction,
+
+    /// Seconds to wait for the service to reach the new state, defaults to services.timeout in the config
+    #[clap(long, global = true)]
+    timeout: Option<u64>,
+}
+
+#[derive(Subcommand)]
+pub enum ServiceAction {
+    /// Show the state and process of the service
+    Status,
+    /// Start the service and the services it depends on
+    Start,
+    /// Stop the service
+    Stop {
+        /// Stop running services that depend on it first
+        #[clap(long)]
+        dependents: bool,
+    },
+    /// Pause the service
+    Pause,
+    /// Continue a paused service
+    Continue,
+    /// Show the configuration, or change the start type
+    Config {
+        /// boot, system, auto, demand (manual) or disabled
+        #[clap(long, value_parser = parse_start_type)]
+        start_type: Option<StartType>,
+    },
+}
+
+pub fn run(args: ServiceArgs, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let wait = WaitOptions::from_config(&config.services, signal::stop_token());
+    match args.command {
+        ServiceCommand::Install => {
+            install(BROKER_ARGS, &config.broker, &wait)?;
+            println!("{} installed and running, listening on pipe {}", BROKER_SERVICE, config.broker.pipe);
+            Ok(())
+        }
+        ServiceCommand::Uninstall => {
+            uninstall(&wait)?;
+            println!("{} removed", BROKER_SERVICE);
+            Ok(())
+        }
+        ServiceCommand::Broker => {
+            run_broker(config.broker.clone(), Duration::from_secs(config.services.timeout))?;
+            Ok(())
+        }
+        ServiceCommand::Control(control) => {
+            let args = ControlArgs::try_parse_from(std::iter::once("elevatekit service".to_string()).chain(control))
+                .unwrap_or_else(|e| e.exit());
+            control_service(args, wait)
+        }
+    }
+}
+
+fn control_service(args: ControlArgs, mut wait: WaitOptions) -> Result<(), Box<dyn std::error::Error>> {
+    signal::install_ctrl_handler()?;
+    if let Some(timeout) = args.timeout {
+        wait.timeout = Duration::from_secs(timeout);
+    }
+    let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+    let service = match args.action {
+        ServiceAction::Status | ServiceAction::Config { start_type: None } => ServiceController::open(&args.name)?,
+        _ => ServiceController::open_writable(&args.name)?,
+    };
+
+    match args.action {
+        ServiceAction::Status => print_status(&args.name, &service.status()?),
+        ServiceAction::Start => print_status(&args.name, &service.start(&wait)?),
+        ServiceAction::Stop { dependents } => print_status(&args.name, &service.stop(dependents, &wait)?),
+        ServiceAction::Pause => print_status(&args.name, &service.pause(&wait)?),
+        ServiceAction::Continue => print_status(&args.name, &service.resume(&wait)?),
+        ServiceAction::Config { start_type: Some(start_type) } => {
+            service.set_start_type(start_type)?;
+            println!("Start type of {} set to {}", args.name, start_type);
+        }
+        ServiceAction::Config { start_type: None } => {
+            let config = service.config()?;
+            println!("{:<14} {}", "Name", args.name);
+            println!("{:<14} {}", "Display name", config.display_name);
+            println!("{:<14} {}", "Binary", config.binary_path);
+            match config.start_type {
+                Some(start_type) => println!("{:<14} {}", "Start type", start_type),
+                None => println!("{:<14} unknown", "Start type"),
+            }
+            println!("{:<14} {}", "Account", config.account);
+            println!("{:<14} {}", "Dependencies", config.dependencies.join(", "));
+        }
+    }
+    Ok(())
+}
+
+fn print_status(name: &str, status: &ServiceStatus) {
+    if status.pid == 0 {
+        println!("{}: {}", name, status.state);
+    } else {
+        println!("{}: {} (pid {})", name, status.state, status.pid);
+    }
+}
+
+// clap wants errors that are Send + Sync, ElevateError is neither
+fn parse_start_type(start_type: &str) -> Result<StartType, String> {
+    start_type.parse().map_err(|e: ElevateError| e.to_string())
+}
@@ -0,0 +1,77 @@
+use clap::Subcommand;
+
+use crate::core::service::machine::WaitOptions;
+use crate::core::session::registry::SessionRegistry;
+use crate::core::session::worker::{run_worker, start_session, stop_session};
+use crate::utils::config::Config;
+use crate::utils::security::SecurityContext;
+use crate::utils::signal;
+use crate::utils::time::{format_age, unix_timestamp};
+
+// what the worker runs, before its id, owner and timeout
+const WORKER_ARGS: &[&str] = &["session", "worker"];
+
+#[derive(Subcommand)]
+pub enum SessionCommand {
+    /// Start a worker running as TrustedInstaller and print its id for `run --session`
+    Start {
+        /// Seconds without commands before the worker exits, 0 for never; defaults to sessions.idle_timeout in the config
+        #[clap(long)]
+        idle_timeout: Option<u64>,
+    },
+    /// List running sessions
+    List,
+    /// Stop a session and any command it is still running
+    Stop {
+        id: String,
+    },
+    /// Run as TrustedInstaller by `session start`
+    #[clap(hide = true)]
+    Worker {
+        #[clap(long)]
+        id: String,
+        /// SID of the only user who may connect
+        #[clap(long)]
+        owner: String,
+        #[clap(long)]
+        idle_timeout: u64,
+    },
+}
+
+pub fn run(command: SessionCommand, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        SessionCommand::Start { idle_timeout } => {
+            let context = SecurityContext::new()?;
+            let wait = WaitOptions::from_config(&config.services, signal::stop_token());
+            let idle_timeout = idle_timeout.unwrap_or(config.sessions.idle_timeout);
+            let session = start_session(&context, WORKER_ARGS, idle_timeout, &wait)?;
+            println!("{}", session.id);
+        }
+        SessionCommand::List => {
+            let sessions = SessionRegistry::new()?.list()?;
+            if sessions.is_empty() {
+                println!("No running sessions");
+            }
+            for session in sessions {
+                let idle = match session.idle_timeout {
+                    0 => "never idles out".to_string(),
+                    secs => format!("idle timeout {}s", secs),
+                };
+                println!(
+                    "{}  pid {:<6}  up {:<8}  {}",
+                    session.id,
+                    session.pid,
+                    format_age(unix_timestamp().saturating_sub(session.started_at)),
+                    idle
+                );
+            }
+        }
+        SessionCommand::Stop { id } => {
+            let session = SessionRegistry::new()?.find(&id)?;
+            stop_session(&session)?;
+            println!("Session {} stopped", session.id);
+        }
+        SessionCommand::Worker { id, owner, idle_timeout } => run_worker(&id, &owner, idle_timeout)?,
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+//! Who is on the other end of a pipe, read from the client's own token while
+//! impersonating it. Whatever the client sends about itself is never trusted.
+
+use std::fs::File;
+use std::os::windows::io::AsRawHandle;
+use std::ptr::null_mut;
+use winapi::um::{
+    namedpipeapi::ImpersonateNamedPipeClient,
+    processthreadsapi::{GetCurrentProcess, GetCurrentThread, OpenProcessToken, OpenThreadToken},
+    securitybaseapi::RevertToSelf,
+    winnt::TOKEN_QUERY,
+};
+
+use crate::core::broker::policy::{Caller, Principal};
+use crate::core::handle::{HandleKind, OwnedHandle};
+use crate::core::token::{token_groups, token_user};
+use crate::utils::error::{ElevateError, ElevateResult, WindowsError, WindowsErrorKind};
+
+// reverts the impersonation however the token reads end
+struct RevertGuard;
+
+impl Drop for RevertGuard {
+    fn drop(&mut self) {
+        unsafe { RevertToSelf() };
+    }
+}
+
+/// The user and enabled groups of the connected client
+pub fn pipe_client(pipe: &File) -> ElevateResult<Caller> {
+    let token = pipe_client_token(pipe)?;
+    Ok(Caller { user: token_user(&token)?, groups: token_groups(&token)? })
+}
+
+/// The connected client's token. Clients connect at identification level,
+/// which is enough to query it after the impersonation ends.
+pub fn pipe_client_token(pipe: &File) -> ElevateResult<OwnedHandle> {
+    if unsafe { ImpersonateNamedPipeClient(pipe.as_raw_handle() as _) } == 0 {
+        return Err(token_error("Failed to impersonate the pipe client"));
+    }
+    let _revert = RevertGuard;
+
+    let mut token = null_mut();
+    // opened as ourselves, an identification level token can still be queried
+    if unsafe { OpenThreadToken(GetCurrentThread(), TOKEN_QUERY, 1, &mut token) } == 0 {
+        return Err(token_error("Failed to open the pipe client's token"));
+    }
+    Ok(unsafe { OwnedHandle::from_raw(token, HandleKind::Token) })
+}
+
+/// The account this process runs as
+pub fn current_user() -> ElevateResult<Principal> {
+    let mut token = null_mut();
+    if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) } == 0 {
+        return Err(token_error("Failed to open the process token"));
+    }
+    token_user(&unsafe { OwnedHandle::from_raw(token, HandleKind::Token) })
+}
+
+fn token_error(context: &str) -> ElevateError {
+    WindowsError::last_error().to_elevate_error(WindowsErrorKind::SecurityOperation, context)
+}
@@ -0,0 +1,7 @@
+#[cfg(windows)]
+pub mod identity;
+pub mod policy;
+pub mod protocol;
+#[cfg(windows)]
+pub mod server;
+pub mod session;
@@ -0,0 +1,208 @@
+//! Decides which broker requests run. Nothing is allowed unless a rule names
+//! the caller or one of its groups, the command and every argument.
+
+use crate::core::broker::protocol::BrokerRequest;
+#[cfg(windows)]
+use crate::core::security::descriptor::lookup_account;
+use crate::utils::config::BrokerRule;
+use crate::utils::error::{ElevateError, ElevateResult};
+
+pub use crate::core::security::sddl::Principal;
+
+/// Who sent a request, taken from the pipe client's token
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub user: Principal,
+    /// enabled groups only, deny-only groups grant nothing
+    pub groups: Vec<Principal>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// index of the first matching rule
+    Allowed(usize),
+    Denied(String),
+}
+
+pub struct Policy {
+    rules: Vec<BrokerRule>,
+    accounts: Vec<RuleAccounts>,
+}
+
+// SIDs of the users and groups a rule names. Only SIDs are compared, so an
+// account of the same name in another domain is never taken for them.
+struct RuleAccounts {
+    users: Vec<String>,
+    groups: Vec<String>,
+}
+
+impl Policy {
+    /// Resolves the accounts the rules name to SIDs once
+    #[cfg(windows)]
+    pub fn new(rules: Vec<BrokerRule>) -> ElevateResult<Self> {
+        Self::resolve(rules, |account| Ok(lookup_account(account)?.as_str().to_string()))
+    }
+
+    /// Like `new`, with `lookup` giving the SID of an account name
+    pub fn resolve(rules: Vec<BrokerRule>, mut lookup: impl FnMut(&str) -> ElevateResult<String>) -> ElevateResult<Self> {
+        let mut accounts = Vec::with_capacity(rules.len());
+        for rule in &rules {
+            accounts.push(RuleAccounts {
+                users: rule.users.iter().map(|user| lookup(user)).collect::<ElevateResult<_>>()?,
+                groups: rule.groups.iter().map(|group| lookup(group)).collect::<ElevateResult<_>>()?,
+            });
+        }
+        Ok(Self { rules, accounts })
+    }
+
+    pub fn rule(&self, index: usize) -> &BrokerRule {
+        &self.rules[index]
+    }
+
+    pub fn check(&self, caller: &Caller, request: &BrokerRequest) -> Decision {
+        let mut command_known = false;
+        for (index, (rule, accounts)) in self.rules.iter().zip(&self.accounts).enumerate() {
+            if !rule.command.eq_ignore_ascii_case(&request.command) {
+                continue;
+            }
+            command_known = true;
+            if applies_to(accounts, caller) && args_match(rule.args.as_deref(), &request.args) {
+                return Decision::Allowed(index);
+            }
+        }
+
+        Decision::Denied(if command_known {
+            format!("{} may not run {} with these arguments", caller.user.name, request.command)
+        } else {
+            format!("{} is not an approved command", request.command)
+        })
+    }
+}
+
+/// Rejects rules whose command is not a full path: a bare name is looked up
+/// in the broker's own directory first, which may not be where the rule means
+pub fn validate_rules(rules: &[BrokerRule]) -> ElevateResult<()> {
+    match rules.iter().find(|rule| !is_full_path(&rule.command)) {
+        Some(rule) => Err(ElevateError::ConfigError(format!(
+            "Broker rule command {:?} must be a full path",
+            rule.command
+        ))),
+        None => Ok(()),
+    }
+}
+
+// a drive with a rooted path, or a UNC path, checked the same on any host
+fn is_full_path(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    let drive = bytes.len() > 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && matches!(bytes[2], b'\\' | b'/');
+    drive || path.starts_with(r"\\")
+}
+
+fn applies_to(accounts: &RuleAccounts, caller: &Caller) -> bool {
+    accounts.users.iter().any(|sid| sid.eq_ignore_ascii_case(&caller.user.sid))
+        || accounts.groups.iter().any(|sid| caller.groups.iter().any(|group| group.sid.eq_ignore_ascii_case(sid)))
+}
+
+fn args_match(patterns: Option<&[String]>, args: &[String]) -> bool {
+    let Some(patterns) = patterns else { return true };
+    patterns.len() == args.len()
+        && patterns
+            .iter()
+            .zip(args)
+            .all(|(pattern, arg)| pattern == "*" || pattern.eq_ignore_ascii_case(arg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(name: &str, sid: &str) -> Principal {
+        Principal { name: name.into(), sid: sid.into() }
+    }
+
+    fn caller() -> Caller {
+        Caller {
+            user: principal(r"CONTOSO\alice", "S-1-5-21-1-2-3-1104"),
+            groups: vec![principal(r"CONTOSO\Helpdesk", "S-1-5-21-1-2-3-1200"), principal(r"BUILTIN\Users", "S-1-5-32-545")],
+        }
+    }
+
+    fn rule(users: &[&str], groups: &[&str], command: &str, args: Option<&[&str]>) -> BrokerRule {
+        BrokerRule {
+            users: users.iter().map(|s| s.to_string()).collect(),
+            groups: groups.iter().map(|s| s.to_string()).collect(),
+            command: command.into(),
+            args: args.map(|args| args.iter().map(|s| s.to_string()).collect()),
+        }
+    }
+
+    // the SIDs a CONTOSO domain controller would give
+    fn policy(rules: Vec<BrokerRule>) -> Policy {
+        Policy::resolve(rules, |account| match account.to_ascii_lowercase().as_str() {
+            r"contoso\alice" => Ok("S-1-5-21-1-2-3-1104".into()),
+            r"contoso\helpdesk" => Ok("S-1-5-21-1-2-3-1200".into()),
+            r"fabrikam\alice" => Ok("S-1-5-21-4-5-6-1104".into()),
+            sid if sid.starts_with("s-1-") => Ok(account.into()),
+            _ => Err(ElevateError::ValidationError(format!("No account {}", account))),
+        })
+        .unwrap()
+    }
+
+    fn request(command: &str, args: &[&str]) -> BrokerRequest {
+        BrokerRequest { command: command.into(), args: args.iter().map(|s| s.to_string()).collect() }
+    }
+
+    #[test]
+    fn test_principal_matching() {
+        let alice = principal(r"CONTOSO\alice", "S-1-5-21-1-2-3-1104");
+        assert!(alice.matches(r"contoso\ALICE"));
+        assert!(alice.matches("s-1-5-21-1-2-3-1104"));
+        assert!(!alice.matches(r"FABRIKAM\alice"));
+        assert!(!principal("", "S-1-5-21-9").matches(""));
+    }
+
+    #[test]
+    fn test_rules_by_user_group_and_arguments() {
+        let policy = policy(vec![
+            rule(&[r"CONTOSO\alice"], &[], r"C:\Windows\System32\sc.exe", Some(&["stop", "wuauserv"])),
+            rule(&[], &[r"CONTOSO\Helpdesk"], r"C:\Windows\System32\sc.exe", Some(&["query", "*"])),
+            rule(&["S-1-5-21-1-2-3-9999"], &[], "cmd.exe", None),
+        ]);
+        let sc = r"c:\windows\system32\SC.EXE";
+
+        assert_eq!(policy.check(&caller(), &request(sc, &["stop", "wuauserv"])), Decision::Allowed(0));
+        assert_eq!(policy.check(&caller(), &request(sc, &["query", "bits"])), Decision::Allowed(1));
+        assert!(matches!(policy.check(&caller(), &request(sc, &["query"])), Decision::Denied(_)));
+        assert!(matches!(policy.check(&caller(), &request(sc, &["stop", "wuauserv", "x"])), Decision::Denied(_)));
+        assert!(matches!(policy.check(&caller(), &request("cmd.exe", &[])), Decision::Denied(_)));
+        // the same program under another path is a different command
+        match policy.check(&caller(), &request(r"C:\Temp\sc.exe", &["query", "bits"])) {
+            Decision::Denied(reason) => assert!(reason.contains("not an approved command"), "{}", reason),
+            decision => panic!("{:?}", decision),
+        }
+    }
+
+    #[test]
+    fn test_accounts_are_compared_by_sid() {
+        let sc = r"C:\Windows\System32\sc.exe";
+        let policy = policy(vec![
+            rule(&[r"FABRIKAM\alice"], &[], sc, None),
+            rule(&[], &["s-1-5-32-545"], sc, Some(&["query"])),
+        ]);
+
+        // an alice of another domain is another account
+        assert!(matches!(policy.check(&caller(), &request(sc, &[])), Decision::Denied(_)));
+        assert_eq!(policy.check(&caller(), &request(sc, &["query"])), Decision::Allowed(1));
+
+        let unknown = |account: &str| Err(ElevateError::ValidationError(format!("No account {}", account)));
+        assert!(Policy::resolve(vec![rule(&["nobody"], &[], sc, None)], unknown).is_err());
+    }
+
+    #[test]
+    fn test_rules_need_full_paths() {
+        assert!(validate_rules(&[rule(&["alice"], &[], r"C:\Windows\System32\sc.exe", None)]).is_ok());
+        assert!(validate_rules(&[rule(&["alice"], &[], r"\\server\tools\sc.exe", None)]).is_ok());
+        assert!(validate_rules(&[rule(&["alice"], &[], "sc.exe", None)]).is_err());
+        assert!(validate_rules(&[rule(&["alice"], &[], r"System32\sc.exe", None)]).is_err());
+    }
+}
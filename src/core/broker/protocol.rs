@@ -0,0 +1,67 @@
+//! What travels over the broker and session pipes: the client sends one
+//! request as a length-prefixed JSON document, the server answers with relay
+//! messages, ending with an exit code or an error.
+
+use std::io::{Read, Write};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::core::service::relay::{read_message, RelayMessage};
+use crate::utils::error::{ElevateError, ElevateResult};
+
+// requests are a command line, not a payload
+const MAX_REQUEST: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BrokerRequest {
+    pub command: String,
+    pub args: Vec<String>,
+}
+
+pub fn write_request<R: Serialize>(writer: &mut impl Write, request: &R) -> ElevateResult<()> {
+    let body = serde_json::to_vec(request).map_err(|e| protocol_error(&e.to_string()))?;
+    writer
+        .write_all(&(body.len() as u32).to_le_bytes())
+        .and_then(|_| writer.write_all(&body))
+        .and_then(|_| writer.flush())
+        .map_err(|e| protocol_error(&format!("failed to send request: {}", e)))
+}
+
+pub fn read_request<R: DeserializeOwned>(reader: &mut impl Read) -> ElevateResult<R> {
+    let mut len = [0u8; 4];
+    reader
+        .read_exact(&mut len)
+        .map_err(|e| protocol_error(&format!("failed to read request: {}", e)))?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_REQUEST {
+        return Err(protocol_error(&format!("request of {} bytes", len)));
+    }
+
+    let mut body = vec![0u8; len];
+    reader
+        .read_exact(&mut body)
+        .map_err(|e| protocol_error(&format!("failed to read request: {}", e)))?;
+    serde_json::from_slice(&body).map_err(|e| protocol_error(&e.to_string()))
+}
+
+/// Sends a request and hands every chunk of output to `output` until the
+/// server reports the exit code, which is returned
+pub fn call<S: Read + Write, R: Serialize>(
+    stream: &mut S,
+    request: &R,
+    output: &mut dyn FnMut(&RelayMessage),
+) -> ElevateResult<i32> {
+    write_request(stream, request)?;
+    loop {
+        match read_message(stream).map_err(|e| protocol_error(&format!("failed to read response: {}", e)))? {
+            Some(RelayMessage::Exit(code)) => return Ok(code),
+            Some(RelayMessage::Error(message)) => return Err(ElevateError::ProcessError(message)),
+            Some(message) => output(&message),
+            None => return Err(protocol_error("the connection closed without an exit code")),
+        }
+    }
+}
+
+fn protocol_error(what: &str) -> ElevateError {
+    ElevateError::ProcessError(format!("Pipe protocol error: {}", what))
+}
@@ -0,0 +1,287 @@
+//! The broker service: listens on a pipe only the users and groups named in
+//! the rules can open, identifies each client from its token, and runs what
+//! the policy allows as TrustedInstaller. It runs as LocalSystem from its own
+//! binary and config, so it refuses to when anyone but administrators could
+//! change either.
+
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use winapi::um::winnt::{
+    DELETE, FILE_APPEND_DATA, FILE_DELETE_CHILD, FILE_WRITE_DATA, GENERIC_ALL, GENERIC_WRITE, WRITE_DAC, WRITE_OWNER,
+};
+
+use crate::core::broker::identity::pipe_client;
+use crate::core::broker::policy::{validate_rules, Policy};
+use crate::core::broker::session::{handle_client, AuditEntry, AuditSink, Launcher};
+use crate::core::elevation::trusted_installer::TrustedInstallerElevation;
+use crate::core::pipe::{
+    connect_pipe, create_server_pipe, pipe_server_pid, read_request_within, wait_for_client, CLIENT_ACCESS,
+};
+use crate::core::security::descriptor::{lookup_account, read_security, ObjectType};
+use crate::core::security::sddl::{AceFlags, AceType, SecurityDescriptor, Sid, TRUSTED_INSTALLER_SID};
+use crate::core::service::controller::ServiceController;
+use crate::core::service::host::run_as_service;
+use crate::core::service::machine::WaitOptions;
+use crate::core::service::relay::RelayMessage;
+use crate::core::service::state::{ServiceState, StartType};
+use crate::utils::cancel::CancellationToken;
+use crate::utils::command_line::join_command_line;
+use crate::utils::config::{BrokerConfig, BrokerRule, Config};
+use crate::utils::error::{ElevateError, ElevateResult};
+use crate::utils::files::{file_error, path_name};
+use crate::utils::security::SecurityContext;
+
+pub const BROKER_SERVICE: &str = "ElevateKitBroker";
+const BROKER_DISPLAY_NAME: &str = "ElevateKit Broker";
+const STOP_POLL: Duration = Duration::from_millis(250);
+// a client that connects but never asks does not keep a thread forever
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// SYSTEM, administrators and TrustedInstaller, who may own and change what the broker runs from
+const TRUSTED_SIDS: &[&str] = &["S-1-5-18", "S-1-5-32-544", TRUSTED_INSTALLER_SID];
+// rights that change a file, or what a folder holds
+const WRITE_RIGHTS: u32 =
+    FILE_WRITE_DATA | FILE_APPEND_DATA | FILE_DELETE_CHILD | DELETE | WRITE_DAC | WRITE_OWNER | GENERIC_WRITE | GENERIC_ALL;
+
+/// Registers this binary as an automatically started service running
+/// `service_args`, and starts it
+pub fn install(service_args: &[&str], config: &BrokerConfig, wait: &WaitOptions) -> ElevateResult<()> {
+    validate_rules(&config.rules)?;
+    ensure_admin_only()?;
+    let exe = std::env::current_exe()
+        .map_err(|e| ElevateError::ProcessError(format!("Failed to locate ElevateKit: {}", e)))?;
+    let mut line = vec![exe.to_string_lossy().into_owned()];
+    line.extend(service_args.iter().map(|arg| arg.to_string()));
+
+    let service = ServiceController::create(BROKER_SERVICE, BROKER_DISPLAY_NAME, &join_command_line(&line))?;
+    service.set_start_type(StartType::Automatic)?;
+    service.start(wait)?;
+    Ok(())
+}
+
+pub fn uninstall(wait: &WaitOptions) -> ElevateResult<()> {
+    let service = ServiceController::open_to_delete(BROKER_SERVICE)?;
+    if service.status()?.state != ServiceState::Stopped {
+        service.stop(false, wait)?;
+    }
+    service.delete()
+}
+
+/// Opens the broker's pipe for a request. Anyone may create a pipe of the name
+/// while the broker is not running, so it has to be served by the broker
+/// service's own process.
+pub fn connect_broker(pipe: &str) -> ElevateResult<File> {
+    let client = connect_pipe(pipe)?;
+    let server = pipe_server_pid(&client)?;
+    let broker = ServiceController::open(BROKER_SERVICE)?.status()?.pid;
+    if broker == 0 || server != broker {
+        return Err(ElevateError::ProcessError(format!(
+            "Pipe {} is served by process {}, not the broker service",
+            pipe, server
+        )));
+    }
+    Ok(client)
+}
+
+/// Full control for SYSTEM and administrators, read and write for everyone a rule names
+pub fn pipe_sddl(rules: &[BrokerRule]) -> ElevateResult<String> {
+    let mut sids = BTreeSet::new();
+    for account in rules.iter().flat_map(|rule| rule.users.iter().chain(&rule.groups)) {
+        sids.insert(lookup_account(account)?.to_string());
+    }
+
+    let mut sddl = "D:P(A;;GA;;;SY)(A;;GA;;;BA)".to_string();
+    for sid in sids {
+        sddl.push_str(&format!("(A;;{};;;{})", CLIENT_ACCESS, sid));
+    }
+    Ok(sddl)
+}
+
+/// Entry point of the installed service
+pub fn run_broker(config: BrokerConfig, timeout: Duration) -> ElevateResult<()> {
+    run_as_service(BROKER_SERVICE, move |stop| match serve(&config, timeout, stop) {
+        Ok(()) => 0,
+        Err(e) => {
+            error!("Broker failed: {}", e);
+            1
+        }
+    })
+}
+
+fn serve(config: &BrokerConfig, timeout: Duration, stop: CancellationToken) -> ElevateResult<()> {
+    validate_rules(&config.rules)?;
+    ensure_admin_only()?;
+    let sddl = pipe_sddl(&config.rules)?;
+    let policy = Arc::new(Policy::new(config.rules.clone())?);
+    let audit = Arc::new(FileAudit::open(match &config.audit_log {
+        Some(path) => path.clone(),
+        None => Config::state_dir()?.join("broker-audit.log"),
+    })?);
+    // stopping the service also ends the commands it runs
+    let launcher = Arc::new(TiLauncher { wait: WaitOptions { timeout, cancel: stop.clone() } });
+
+    // connecting ourselves releases the wait for the next client once a stop arrives
+    {
+        let (stop, pipe) = (stop.clone(), config.pipe.clone());
+        thread::spawn(move || {
+            while !stop.is_cancelled() {
+                thread::sleep(STOP_POLL);
+            }
+            let _ = connect_pipe(&pipe);
+        });
+    }
+
+    info!("Broker listening on {}", config.pipe);
+    let mut first = true;
+    loop {
+        let pipe = create_server_pipe(&config.pipe, &sddl, first)?;
+        first = false;
+        wait_for_client(&pipe)?;
+        if stop.is_cancelled() {
+            info!("Broker stopping");
+            return Ok(());
+        }
+
+        let (policy, audit, launcher) = (policy.clone(), audit.clone(), launcher.clone());
+        thread::spawn(move || {
+            if let Err(e) = serve_client(pipe, &policy, &*launcher, &*audit) {
+                warn!("Broker client failed: {}", e);
+            }
+        });
+    }
+}
+
+fn serve_client(mut pipe: File, policy: &Policy, launcher: &dyn Launcher, audit: &dyn AuditSink) -> ElevateResult<()> {
+    let caller = pipe_client(&pipe)?;
+    info!("Broker request from {} ({})", caller.user.name, caller.user.sid);
+    let request = read_request_within(&mut pipe, REQUEST_TIMEOUT)?;
+    handle_client(&mut pipe, &request, &caller, policy, launcher, audit)
+}
+
+// the binary, its folder and the config decide what runs as LocalSystem
+fn ensure_admin_only() -> ElevateResult<()> {
+    let exe = std::env::current_exe()
+        .map_err(|e| ElevateError::ProcessError(format!("Failed to locate ElevateKit: {}", e)))?;
+    let dir = exe.parent().ok_or_else(|| ElevateError::ConfigError("Failed to get parent directory".into()))?;
+    let config = Config::get_config_path()?;
+
+    let mut paths = vec![exe.as_path(), dir];
+    if config.exists() {
+        paths.push(&config);
+    }
+    for path in paths {
+        let descriptor = read_security(&path_name(path), ObjectType::File, false)?;
+        if let Some(reason) = untrusted_access(&descriptor) {
+            return Err(ElevateError::ValidationError(format!(
+                "Refusing to run the broker: {} {}; only administrators and SYSTEM may own and change it",
+                path.display(),
+                reason
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// How someone besides SYSTEM, administrators and TrustedInstaller could
+/// change an object, if anyone can
+fn untrusted_access(descriptor: &SecurityDescriptor) -> Option<String> {
+    let trusted = |sid: &Sid| TRUSTED_SIDS.contains(&sid.as_str());
+    let name = |sid: &Sid| sid.display_name().map_or_else(|| sid.to_string(), str::to_string);
+
+    match &descriptor.owner {
+        Some(owner) if trusted(owner) => {}
+        Some(owner) => return Some(format!("is owned by {}", name(owner))),
+        None => return Some("has no owner".into()),
+    }
+    let Some(dacl) = &descriptor.dacl else {
+        return Some("has no DACL, which grants everyone full access".into());
+    };
+    dacl.aces
+        .iter()
+        .filter(|ace| {
+            matches!(
+                ace.ace_type,
+                AceType::AccessAllowed
+                    | AceType::ObjectAccessAllowed
+                    | AceType::CallbackAccessAllowed
+                    | AceType::CallbackObjectAccessAllowed
+            )
+        })
+        // inherit-only ACEs only apply to what is created below
+        .filter(|ace| !ace.flags.contains(AceFlags::INHERIT_ONLY))
+        .find(|ace| ace.rights.0 & WRITE_RIGHTS != 0 && !trusted(&ace.trustee))
+        .map(|ace| format!("can be changed by {}", name(&ace.trustee)))
+}
+
+struct TiLauncher {
+    wait: WaitOptions,
+}
+
+impl Launcher for TiLauncher {
+    fn launch(&self, command: &str, args: &[String], output: &mut dyn FnMut(RelayMessage)) -> ElevateResult<i32> {
+        let context = SecurityContext::new().map_err(|e| ElevateError::ProcessError(e.to_string()))?;
+        TrustedInstallerElevation::new(&context)
+            .wait_options(self.wait.clone())
+            .execute_captured(command, args, output)
+    }
+}
+
+// one JSON object per line, appended as decisions are made
+struct FileAudit {
+    file: Mutex<File>,
+}
+
+impl FileAudit {
+    fn open(path: PathBuf) -> ElevateResult<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| file_error(e, &format!("Failed to open audit log {}", path.display())))?;
+        Ok(Self { file: Mutex::new(file) })
+    }
+}
+
+impl AuditSink for FileAudit {
+    fn record(&self, entry: &AuditEntry) {
+        info!("Broker audit: {:?}", entry);
+        let line = match serde_json::to_string(entry) {
+            Ok(line) => line,
+            Err(e) => return warn!("Failed to serialize audit entry: {}", e),
+        };
+        if let Err(e) = writeln!(self.file.lock().unwrap(), "{}", line) {
+            warn!("Failed to write audit entry: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(sddl: &str) -> Option<String> {
+        untrusted_access(&SecurityDescriptor::parse(sddl).unwrap())
+    }
+
+    #[test]
+    fn test_program_files_style_folder_is_trusted() {
+        assert_eq!(access("O:TIG:TID:PAI(A;;FA;;;TI)(A;OICIIO;GA;;;TI)(A;;FA;;;SY)(A;;FA;;;BA)(A;;0x1200a9;;;BU)(A;OICIIO;GA;;;CO)"), None);
+        assert_eq!(access("O:BAG:SYD:(A;;FA;;;SY)(A;;FA;;;BA)(A;;FRFX;;;AU)"), None);
+    }
+
+    #[test]
+    fn test_user_writable_or_owned_objects_are_not() {
+        assert_eq!(
+            access("O:BAG:SYD:(A;;FA;;;BA)(A;OICIID;0x1301bf;;;AU)").as_deref(),
+            Some(r"can be changed by NT AUTHORITY\Authenticated Users")
+        );
+        assert!(access("O:S-1-5-21-1-2-3-1104G:SYD:(A;;FA;;;BA)").unwrap().starts_with("is owned by"));
+        assert!(access("O:BAG:SY").unwrap().contains("no DACL"));
+    }
+}
@@ -0,0 +1,249 @@
+//! One broker conversation: check the request, run it if allowed and stream
+//! the result back. Transport, launcher and audit log are passed in, so
+//! the whole exchange runs the same over a pipe or in memory.
+
+use std::io::Write;
+use serde::Serialize;
+
+use crate::core::broker::policy::{Caller, Decision, Policy};
+use crate::core::broker::protocol::BrokerRequest;
+use crate::core::service::relay::{write_message, RelayMessage};
+use crate::utils::error::{ElevateError, ElevateResult};
+use crate::utils::time::unix_timestamp;
+
+/// Runs approved commands
+pub trait Launcher {
+    /// Hands output to `output` as it arrives and returns the exit code
+    fn launch(&self, command: &str, args: &[String], output: &mut dyn FnMut(RelayMessage)) -> ElevateResult<i32>;
+}
+
+pub trait AuditSink {
+    fn record(&self, entry: &AuditEntry);
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub time: u64,
+    pub user: String,
+    pub sid: String,
+    pub command: String,
+    pub args: Vec<String>,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum AuditEvent {
+    Allowed { rule: usize },
+    Denied { reason: String },
+    Finished { exit_code: i32 },
+    Failed { error: String },
+}
+
+/// Answers a request read from `stream` with `protocol::read_request`
+pub fn handle_client<S: Write>(
+    stream: &mut S,
+    request: &BrokerRequest,
+    caller: &Caller,
+    policy: &Policy,
+    launcher: &dyn Launcher,
+    audit: &dyn AuditSink,
+) -> ElevateResult<()> {
+    let record = |event| {
+        audit.record(&AuditEntry {
+            time: unix_timestamp(),
+            user: caller.user.name.clone(),
+            sid: caller.user.sid.clone(),
+            command: request.command.clone(),
+            args: request.args.clone(),
+            event,
+        })
+    };
+
+    let index = match policy.check(caller, request) {
+        Decision::Allowed(index) => index,
+        Decision::Denied(reason) => {
+            record(AuditEvent::Denied { reason: reason.clone() });
+            return send(stream, &RelayMessage::Error(format!("Denied: {}", reason)));
+        }
+    };
+    // logged before the command runs, in case it never returns
+    record(AuditEvent::Allowed { rule: index });
+
+    // the command runs to the end even if the client goes away
+    let mut write_error = None;
+    let result = run(stream, request, &policy.rule(index).command, launcher, &mut write_error);
+    match result {
+        Ok(exit_code) => {
+            record(AuditEvent::Finished { exit_code });
+            match write_error {
+                Some(e) => Err(e),
+                None => send(stream, &RelayMessage::Exit(exit_code)),
+            }
+        }
+        Err(e) => {
+            record(AuditEvent::Failed { error: e.to_string() });
+            send(stream, &RelayMessage::Error(e.to_string()))
+        }
+    }
+}
+
+// runs the command the rule names, so the caller's spelling of it never reaches the launcher
+fn run<S: Write>(
+    stream: &mut S,
+    request: &BrokerRequest,
+    command: &str,
+    launcher: &dyn Launcher,
+    write_error: &mut Option<ElevateError>,
+) -> ElevateResult<i32> {
+    launcher.launch(command, &request.args, &mut |message| {
+        if write_error.is_none() {
+            if let Err(e) = send(stream, &message) {
+                *write_error = Some(e);
+            }
+        }
+    })
+}
+
+fn send(stream: &mut impl Write, message: &RelayMessage) -> ElevateResult<()> {
+    write_message(stream, message)
+        .map_err(|e| ElevateError::ProcessError(format!("Failed to answer the broker client: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::broker::policy::Principal;
+    use crate::core::broker::protocol::{call, read_request};
+    use crate::utils::config::BrokerRule;
+    use std::io::{self, Read};
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::sync::Mutex;
+    use std::thread;
+
+    // one end of an in-memory duplex pipe
+    struct MemoryPipe {
+        sender: Sender<Vec<u8>>,
+        receiver: Receiver<Vec<u8>>,
+        pending: Vec<u8>,
+    }
+
+    fn memory_pipe() -> (MemoryPipe, MemoryPipe) {
+        let (to_server, from_client) = channel();
+        let (to_client, from_server) = channel();
+        (
+            MemoryPipe { sender: to_server, receiver: from_server, pending: Vec::new() },
+            MemoryPipe { sender: to_client, receiver: from_client, pending: Vec::new() },
+        )
+    }
+
+    impl Read for MemoryPipe {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            if self.pending.is_empty() {
+                // a dropped other end reads as the end of the stream
+                match self.receiver.recv() {
+                    Ok(bytes) => self.pending = bytes,
+                    Err(_) => return Ok(0),
+                }
+            }
+            let read = buffer.len().min(self.pending.len());
+            buffer[..read].copy_from_slice(&self.pending[..read]);
+            self.pending.drain(..read);
+            Ok(read)
+        }
+    }
+
+    impl Write for MemoryPipe {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.sender.send(bytes.to_vec()).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct EchoLauncher;
+
+    impl Launcher for EchoLauncher {
+        fn launch(&self, command: &str, args: &[String], output: &mut dyn FnMut(RelayMessage)) -> ElevateResult<i32> {
+            if args.first().map(String::as_str) == Some("fail") {
+                return Err(ElevateError::ProcessError("Failed to create process".into()));
+            }
+            output(RelayMessage::Stdout(format!("{} {}", command, args.join(" ")).into_bytes()));
+            output(RelayMessage::Stderr(b"warning".to_vec()));
+            Ok(3)
+        }
+    }
+
+    #[derive(Default)]
+    struct MemoryAudit(Mutex<Vec<AuditEvent>>);
+
+    impl AuditSink for MemoryAudit {
+        fn record(&self, entry: &AuditEntry) {
+            self.0.lock().unwrap().push(entry.event.clone());
+        }
+    }
+
+    fn exchange(command: &str, args: &[&str]) -> (Result<i32, String>, Vec<RelayMessage>, Vec<AuditEvent>) {
+        let rules = vec![BrokerRule {
+            users: vec![],
+            groups: vec![r"CONTOSO\Helpdesk".into()],
+            command: r"C:\Windows\System32\sc.exe".into(),
+            args: None,
+        }];
+        let policy = Policy::resolve(rules, |_| Ok("S-1-5-21-1-2-3-1200".into())).unwrap();
+        let caller = Caller {
+            user: Principal { name: r"CONTOSO\alice".into(), sid: "S-1-5-21-1-2-3-1104".into() },
+            groups: vec![Principal { name: r"CONTOSO\Helpdesk".into(), sid: "S-1-5-21-1-2-3-1200".into() }],
+        };
+        let request = BrokerRequest { command: command.into(), args: args.iter().map(|s| s.to_string()).collect() };
+        let audit = MemoryAudit::default();
+        let (mut client, mut server) = memory_pipe();
+
+        let client = thread::spawn(move || {
+            let mut output = Vec::new();
+            let result = call(&mut client, &request, &mut |message| output.push(message.clone()));
+            // ElevateError cannot cross threads
+            (result.map_err(|e| e.to_string()), output)
+        });
+        let request = read_request(&mut server).unwrap();
+        handle_client(&mut server, &request, &caller, &policy, &EchoLauncher, &audit).unwrap();
+        drop(server);
+
+        let (result, output) = client.join().unwrap();
+        (result, output, audit.0.into_inner().unwrap())
+    }
+
+    #[test]
+    fn test_allowed_request_streams_output_and_exit_code() {
+        let (result, output, audit) = exchange(r"c:\windows\system32\sc.exe", &["query", "bits"]);
+        assert_eq!(result.unwrap(), 3);
+        // the rule's spelling of the command is what runs
+        assert_eq!(
+            output,
+            vec![
+                RelayMessage::Stdout(br"C:\Windows\System32\sc.exe query bits".to_vec()),
+                RelayMessage::Stderr(b"warning".to_vec())
+            ]
+        );
+        assert_eq!(audit, vec![AuditEvent::Allowed { rule: 0 }, AuditEvent::Finished { exit_code: 3 }]);
+    }
+
+    #[test]
+    fn test_denied_request_is_audited_and_reported() {
+        let (result, output, audit) = exchange("cmd.exe", &[]);
+        assert!(result.unwrap_err().contains("Denied"));
+        assert!(output.is_empty());
+        assert!(matches!(audit.as_slice(), [AuditEvent::Denied { .. }]));
+    }
+
+    #[test]
+    fn test_launch_failure_is_audited_and_reported() {
+        let (result, _, audit) = exchange(r"C:\Windows\System32\sc.exe", &["fail"]);
+        assert!(result.unwrap_err().contains("Failed to create process"));
+        assert!(matches!(audit.as_slice(), [AuditEvent::Allowed { .. }, AuditEvent::Failed { .. }]));
+    }
+}
@@ -0,0 +1,265 @@
+//! An elevated child for tokio. Its exit is signalled by a thread pool wait on
+//! the process handle and its output arrives on overlapped pipes, so awaiting
+//! it neither blocks the runtime nor polls.
+
+use std::fs::File;
+use std::io;
+use std::os::windows::io::IntoRawHandle;
+use std::os::windows::process::ExitStatusExt;
+use std::pin::Pin;
+use std::process::ExitStatus;
+use std::ptr::null_mut;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::net::windows::named_pipe::NamedPipeServer;
+use tokio::sync::Notify;
+use winapi::{
+    shared::ntdef::BOOLEAN,
+    um::{
+        handleapi::INVALID_HANDLE_VALUE,
+        processthreadsapi::{GetExitCodeProcess, TerminateProcess},
+        threadpoollegacyapiset::UnregisterWaitEx,
+        winbase::{RegisterWaitForSingleObject, INFINITE},
+        winnt::{HANDLE, PVOID, WT_EXECUTEONLYONCE},
+    },
+};
+
+use crate::core::handle::OwnedHandle;
+use crate::core::hardening;
+use crate::core::job::JobObject;
+use crate::core::process::ProcessInfo;
+use crate::utils::config::HardeningConfig;
+use crate::utils::error::{ElevateError, ElevateResult, WindowsError, WindowsErrorKind};
+
+/// A running elevated process, created by `TrustedInstallerElevation::spawn_async`.
+/// Dropping it does not kill the process unless its job kills on close.
+pub struct ElevatedChild {
+    pid: u32,
+    // unregistered before the handle it waits on is closed
+    exit: ExitSignal,
+    process: OwnedHandle,
+    job: Option<JobObject>,
+    pub stdout: Option<ChildOutput>,
+    pub stderr: Option<ChildOutput>,
+}
+
+impl ElevatedChild {
+    /// Takes over a child that is still suspended and resumes it once its exit
+    /// is watched; it is killed if this fails. Has to run inside a tokio
+    /// runtime for the pipes to register with it.
+    pub(crate) fn new(created: ProcessInfo, job: Option<JobObject>, stdout: File, stderr: File) -> ElevateResult<Self> {
+        let parts = ExitSignal::register(created.process().as_raw()).and_then(|exit| {
+            let (stdout, stderr) = (ChildOutput::new(stdout)?, ChildOutput::new(stderr)?);
+            created.resume()?;
+            Ok((exit, stdout, stderr))
+        });
+        match parts {
+            Ok((exit, stdout, stderr)) => Ok(Self {
+                pid: created.process_id(),
+                exit,
+                process: created.into_process(),
+                job,
+                stdout: Some(stdout),
+                stderr: Some(stderr),
+            }),
+            Err(e) => {
+                unsafe { TerminateProcess(created.process().as_raw(), 1) };
+                Err(e)
+            }
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    /// The exit status if the process has exited, without waiting
+    pub fn try_wait(&self) -> ElevateResult<Option<ExitStatus>> {
+        exit_status(&self.process)
+    }
+
+    /// Waits for the process to exit. Its output is not read meanwhile, so take
+    /// `stdout` and `stderr` first if it writes more than the pipes hold.
+    pub async fn wait(&mut self) -> ElevateResult<ExitStatus> {
+        loop {
+            if let Some(status) = self.try_wait()? {
+                return Ok(status);
+            }
+            self.exit.exited().await;
+        }
+    }
+
+    /// Like `wait`, but gives up after `timeout` and returns `None`
+    pub async fn wait_timeout(&mut self, timeout: Duration) -> ElevateResult<Option<ExitStatus>> {
+        match tokio::time::timeout(timeout, self.wait()).await {
+            Ok(status) => status.map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Asks the process to end without waiting for it. With a job, everything
+    /// it started ends too.
+    pub fn start_kill(&self) -> ElevateResult<()> {
+        terminate(&self.process, self.job.as_ref())
+    }
+
+    /// Kills the process and waits for it to exit
+    pub async fn kill(&mut self) -> ElevateResult<ExitStatus> {
+        self.start_kill()?;
+        self.wait().await
+    }
+
+    /// The job the process runs in, for its accounting
+    pub fn job(&self) -> Option<&JobObject> {
+        self.job.as_ref()
+    }
+
+    /// The mitigations in effect for the process
+    pub fn mitigations(&self) -> ElevateResult<HardeningConfig> {
+        hardening::query(&self.process)
+    }
+}
+
+/// Output of an elevated child. Ends once the child and everything that
+/// inherited the pipe have exited.
+pub struct ChildOutput(NamedPipeServer);
+
+impl ChildOutput {
+    fn new(read: File) -> ElevateResult<Self> {
+        unsafe { NamedPipeServer::from_raw_handle(read.into_raw_handle()) }
+            .map(Self)
+            .map_err(|e| ElevateError::ProcessError(format!("Failed to read child output: {}", e)))
+    }
+}
+
+impl AsyncRead for ChildOutput {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf).map(end_of_output)
+    }
+}
+
+// the writer went away, which is the end of the output
+fn end_of_output(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+// a one-shot thread pool wait that wakes `exited` once the process is signalled
+struct ExitSignal {
+    wait: HANDLE,
+    notify: *mut Notify,
+}
+
+// the wait handle and the notify it owns may be used from any thread
+unsafe impl Send for ExitSignal {}
+unsafe impl Sync for ExitSignal {}
+
+unsafe extern "system" fn on_exit(context: PVOID, _timed_out: BOOLEAN) {
+    (*(context as *const Notify)).notify_one();
+}
+
+impl ExitSignal {
+    fn register(process: HANDLE) -> ElevateResult<Self> {
+        let notify = Box::into_raw(Box::new(Notify::new()));
+        let mut wait = null_mut();
+        let ok = unsafe {
+            RegisterWaitForSingleObject(&mut wait, process, Some(on_exit), notify as PVOID, INFINITE, WT_EXECUTEONLYONCE)
+        };
+        if ok == 0 {
+            let error = process_error("Failed to wait for the process");
+            drop(unsafe { Box::from_raw(notify) });
+            return Err(error);
+        }
+        Ok(Self { wait, notify })
+    }
+
+    // the notification is stored if nobody waits yet, so an exit is never missed
+    async fn exited(&self) {
+        unsafe { &*self.notify }.notified().await
+    }
+}
+
+impl Drop for ExitSignal {
+    fn drop(&mut self) {
+        unsafe {
+            // waits for a running callback, so the notify can go afterwards
+            UnregisterWaitEx(self.wait, INVALID_HANDLE_VALUE);
+            drop(Box::from_raw(self.notify));
+        }
+    }
+}
+
+/// The exit status of a process if it has exited, without waiting
+pub(crate) fn exit_status(process: &OwnedHandle) -> ElevateResult<Option<ExitStatus>> {
+    if !process.wait(Some(Duration::ZERO))? {
+        return Ok(None);
+    }
+
+    let mut code = 0;
+    if unsafe { GetExitCodeProcess(process.as_raw(), &mut code) } == 0 {
+        return Err(process_error("Failed to get process exit code"));
+    }
+    Ok(Some(ExitStatus::from_raw(code)))
+}
+
+/// Ends a process, or its whole job if it has one; one that has already exited is fine
+pub(crate) fn terminate(process: &OwnedHandle, job: Option<&JobObject>) -> ElevateResult<()> {
+    let result = match job {
+        Some(job) => job.terminate(1),
+        None if unsafe { TerminateProcess(process.as_raw(), 1) } == 0 => Err(process_error("Failed to terminate process")),
+        None => Ok(()),
+    };
+    // terminating a process that has already exited fails
+    match result {
+        Err(_) if exit_status(process)?.is_some() => Ok(()),
+        result => result,
+    }
+}
+
+fn process_error(context: &str) -> ElevateError {
+    WindowsError::last_error().to_elevate_error(WindowsErrorKind::ProcessOperation, context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::windows::io::AsRawHandle;
+    use std::process::Command;
+    use winapi::um::processthreadsapi::GetCurrentProcess;
+    use crate::core::handle::HandleKind;
+
+    fn exited_child(code: i32) -> OwnedHandle {
+        let mut child = Command::new("cmd.exe").args(["/c", &format!("exit {}", code)]).spawn().unwrap();
+        child.wait().unwrap();
+        unsafe { OwnedHandle::duplicate_from(child.as_raw_handle() as _, HandleKind::Process) }.unwrap()
+    }
+
+    #[test]
+    fn test_broken_pipe_ends_output() {
+        assert!(end_of_output(Ok(())).is_ok());
+        assert!(end_of_output(Err(io::ErrorKind::BrokenPipe.into())).is_ok());
+        assert_eq!(
+            end_of_output(Err(io::ErrorKind::PermissionDenied.into())).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn test_exit_status_only_once_exited() {
+        let current = unsafe { OwnedHandle::duplicate_from(GetCurrentProcess(), HandleKind::Process) }.unwrap();
+        assert!(exit_status(&current).unwrap().is_none());
+
+        let status = exit_status(&exited_child(3)).unwrap().unwrap();
+        assert_eq!(status.code(), Some(3));
+    }
+
+    #[test]
+    fn test_terminating_an_exited_process_is_fine() {
+        let process = exited_child(0);
+        assert!(terminate(&process, None).is_ok());
+        assert_eq!(exit_status(&process).unwrap().unwrap().code(), Some(0));
+    }
+}
@@ -0,0 +1,477 @@
+//! A builder for elevated processes shaped like `std::process::Command`. The
+//! child is created suspended as a child of TrustedInstaller, or of another
+//! parent whose token it should run with, gets its privileges, integrity level
+//! and job, and only then starts running.
+
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::{AsRawHandle, RawHandle};
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Output};
+use std::ptr::null_mut;
+use std::thread;
+use winapi::{
+    um::{
+        handleapi::INVALID_HANDLE_VALUE,
+        processthreadsapi::TerminateProcess,
+        winnt::{HANDLE, TOKEN_ADJUST_DEFAULT, TOKEN_ADJUST_PRIVILEGES, TOKEN_QUERY},
+    },
+};
+
+use crate::core::elevation::child::{exit_status, terminate};
+use crate::core::elevation::trusted_installer::{create_pipe, ChildIo, LaunchOptions, TrustedInstallerElevation};
+use crate::core::handle::OwnedHandle;
+use crate::core::hardening;
+use crate::core::job::JobObject;
+use crate::core::launch::LaunchSettings;
+use crate::core::privilege::PrivilegeManager;
+use crate::core::service::machine::WaitOptions;
+use crate::core::token::{open_process_token, set_integrity_level};
+use crate::utils::config::{HardeningConfig, IntegrityLevel, JobConfig};
+use crate::utils::error::{ElevateError, ElevateResult};
+use crate::utils::security::SecurityContext;
+
+/// Where one of the child's standard streams goes
+#[derive(Debug)]
+pub struct Stdio(StdioKind);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StdioKind {
+    Inherit,
+    Piped,
+    Null,
+}
+
+impl Stdio {
+    /// The same stream as ours
+    pub fn inherit() -> Self {
+        Self(StdioKind::Inherit)
+    }
+
+    /// A pipe to us, in the child's `stdin`, `stdout` or `stderr` field
+    pub fn piped() -> Self {
+        Self(StdioKind::Piped)
+    }
+
+    pub fn null() -> Self {
+        Self(StdioKind::Null)
+    }
+}
+
+/// Whose child the process becomes, and so whose token it runs with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Parent {
+    /// the TrustedInstaller service, started if needed
+    #[default]
+    TrustedInstaller,
+    /// any process we may open with `PROCESS_CREATE_PROCESS`, such as a SYSTEM service
+    Process(u32),
+}
+
+#[derive(Debug)]
+pub struct ElevatedCommand {
+    program: String,
+    args: Vec<String>,
+    env: Vec<(OsString, Option<OsString>)>,
+    env_clear: bool,
+    current_dir: Option<PathBuf>,
+    stdin: Option<Stdio>,
+    stdout: Option<Stdio>,
+    stderr: Option<Stdio>,
+    privileges: Option<Vec<String>>,
+    integrity: Option<IntegrityLevel>,
+    parent: Parent,
+    job: Option<JobConfig>,
+    hardening: Option<HardeningConfig>,
+    launch: Option<LaunchSettings>,
+    wait: WaitOptions,
+}
+
+impl ElevatedCommand {
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        Self {
+            program: program.as_ref().to_string_lossy().into_owned(),
+            args: Vec::new(),
+            env: Vec::new(),
+            env_clear: false,
+            current_dir: None,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            privileges: None,
+            integrity: None,
+            parent: Parent::default(),
+            job: None,
+            hardening: None,
+            launch: None,
+            wait: WaitOptions::default(),
+        }
+    }
+
+    pub fn arg(&mut self, arg: impl AsRef<OsStr>) -> &mut Self {
+        self.args.push(arg.as_ref().to_string_lossy().into_owned());
+        self
+    }
+
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+
+    pub fn env(&mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> &mut Self {
+        self.env.push((key.as_ref().to_owned(), Some(value.as_ref().to_owned())));
+        self
+    }
+
+    pub fn envs<I, K, V>(&mut self, vars: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        for (key, value) in vars {
+            self.env(key, value);
+        }
+        self
+    }
+
+    pub fn env_remove(&mut self, key: impl AsRef<OsStr>) -> &mut Self {
+        self.env.push((key.as_ref().to_owned(), None));
+        self
+    }
+
+    /// Starts the child with only the variables set afterwards
+    pub fn env_clear(&mut self) -> &mut Self {
+        self.env.clear();
+        self.env_clear = true;
+        self
+    }
+
+    pub fn current_dir(&mut self, dir: impl AsRef<Path>) -> &mut Self {
+        self.current_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn stdin(&mut self, stdio: Stdio) -> &mut Self {
+        self.stdin = Some(stdio);
+        self
+    }
+
+    pub fn stdout(&mut self, stdio: Stdio) -> &mut Self {
+        self.stdout = Some(stdio);
+        self
+    }
+
+    pub fn stderr(&mut self, stdio: Stdio) -> &mut Self {
+        self.stderr = Some(stdio);
+        self
+    }
+
+    /// Enables exactly these privileges in the child's token, failing if one
+    /// cannot be. By default the usual set is enabled where the token has it.
+    pub fn privileges<I, S>(&mut self, privileges: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.privileges = Some(privileges.into_iter().map(|name| name.as_ref().to_string()).collect());
+        self
+    }
+
+    /// Runs the child at this integrity level; it can only be lowered
+    pub fn integrity(&mut self, level: IntegrityLevel) -> &mut Self {
+        self.integrity = Some(level);
+        self
+    }
+
+    pub fn parent(&mut self, parent: Parent) -> &mut Self {
+        self.parent = parent;
+        self
+    }
+
+    /// Runs the child in a job object with these limits
+    pub fn job(&mut self, config: JobConfig) -> &mut Self {
+        self.job = Some(config).filter(|config| !config.is_empty());
+        self
+    }
+
+    /// Creates the child with these mitigations in effect
+    pub fn hardening(&mut self, config: HardeningConfig) -> &mut Self {
+        self.hardening = Some(config).filter(|config| !config.is_empty());
+        self
+    }
+
+    /// Priority, affinity, window and console for the child, which has no
+    /// window unless these give it one
+    pub fn launch(&mut self, settings: LaunchSettings) -> &mut Self {
+        self.launch = Some(settings);
+        self
+    }
+
+    /// Timeout and cancellation for starting the TrustedInstaller service
+    pub fn wait_options(&mut self, wait: WaitOptions) -> &mut Self {
+        self.wait = wait;
+        self
+    }
+
+    /// Starts the child without a window; streams not set are inherited
+    pub fn spawn(&mut self) -> ElevateResult<ElevatedProcess> {
+        self.spawn_with([StdioKind::Inherit; 3])
+    }
+
+    /// Runs the child to completion and collects its output; stdin defaults
+    /// to null and stdout and stderr to pipes
+    pub fn output(&mut self) -> ElevateResult<Output> {
+        self.spawn_with([StdioKind::Null, StdioKind::Piped, StdioKind::Piped])?.wait_with_output()
+    }
+
+    /// Runs the child to completion with inherited streams
+    pub fn status(&mut self) -> ElevateResult<ExitStatus> {
+        self.spawn()?.wait()
+    }
+
+    fn spawn_with(&self, defaults: [StdioKind; 3]) -> ElevateResult<ElevatedProcess> {
+        let context = SecurityContext::new().map_err(|e| ElevateError::ProcessError(e.to_string()))?;
+        let parent_pid = match self.parent {
+            Parent::TrustedInstaller => TrustedInstallerElevation::get_trusted_installer_pid(&self.wait)?,
+            Parent::Process(pid) => pid,
+        };
+
+        let kind = |stdio: &Option<Stdio>, default| stdio.as_ref().map_or(default, |stdio| stdio.0);
+        let stdin = open_stdio(kind(&self.stdin, defaults[0]), io::stdin().as_raw_handle(), true)?;
+        let stdout = open_stdio(kind(&self.stdout, defaults[1]), io::stdout().as_raw_handle(), false)?;
+        let stderr = open_stdio(kind(&self.stderr, defaults[2]), io::stderr().as_raw_handle(), false)?;
+
+        let environment = (self.env_clear || !self.env.is_empty()).then(|| {
+            let base: Vec<_> = if self.env_clear { Vec::new() } else { std::env::vars_os().collect() };
+            environment_block(base, &self.env)
+        });
+        let created = TrustedInstallerElevation::create_process_with_ti_parent(
+            parent_pid,
+            &self.program,
+            &self.args,
+            ChildIo::Redirected([stdin.handle, stdout.handle, stderr.handle]),
+            LaunchOptions {
+                environment: environment.as_deref(),
+                current_dir: self.current_dir.as_deref(),
+                hardening: self.hardening.as_ref(),
+                launch: self.launch.as_ref(),
+            },
+        )?;
+
+        let job = match self.prepare(&context, created.process()).and_then(|job| created.resume().map(|_| job)) {
+            Ok(job) => job,
+            Err(e) => {
+                unsafe { TerminateProcess(created.process().as_raw(), 1) };
+                return Err(e);
+            }
+        };
+
+        Ok(ElevatedProcess {
+            pid: created.process_id(),
+            process: created.into_process(),
+            job,
+            stdin: stdin.ours,
+            stdout: stdout.ours,
+            stderr: stderr.ours,
+        })
+    }
+
+    // everything that has to happen before the suspended child runs
+    fn prepare(&self, context: &SecurityContext, process: &OwnedHandle) -> ElevateResult<Option<JobObject>> {
+        let job = match &self.job {
+            Some(config) => {
+                let job = JobObject::create(config)?;
+                job.assign(process)?;
+                Some(job)
+            }
+            None => None,
+        };
+
+        match &self.privileges {
+            None => context.set_all_privileges(process).map_err(|e| ElevateError::ProcessError(e.to_string()))?,
+            Some(privileges) => {
+                let token = open_process_token(process.as_raw(), TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY)?;
+                let manager = PrivilegeManager::new(token.as_raw());
+                for privilege in privileges {
+                    manager.enable(privilege)?;
+                }
+            }
+        }
+
+        if let Some(level) = self.integrity {
+            let token = open_process_token(process.as_raw(), TOKEN_ADJUST_DEFAULT | TOKEN_QUERY)?;
+            set_integrity_level(token.as_raw(), level)?;
+        }
+        Ok(job)
+    }
+}
+
+/// A running elevated process started by `ElevatedCommand::spawn`. Dropping it
+/// does not kill the process unless its job kills on close.
+pub struct ElevatedProcess {
+    pid: u32,
+    process: OwnedHandle,
+    job: Option<JobObject>,
+    pub stdin: Option<File>,
+    pub stdout: Option<File>,
+    pub stderr: Option<File>,
+}
+
+impl ElevatedProcess {
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn try_wait(&mut self) -> ElevateResult<Option<ExitStatus>> {
+        exit_status(&self.process)
+    }
+
+    /// Closes our end of a piped stdin, so the child sees its end, and waits for it to exit
+    pub fn wait(&mut self) -> ElevateResult<ExitStatus> {
+        drop(self.stdin.take());
+        self.process.wait(None)?;
+        exit_status(&self.process)?
+            .ok_or_else(|| ElevateError::ProcessError("Process still runs after the wait".into()))
+    }
+
+    /// Ends the process, and with a job everything it started; one that has already exited is fine
+    pub fn kill(&mut self) -> ElevateResult<()> {
+        terminate(&self.process, self.job.as_ref())
+    }
+
+    /// The mitigations in effect for the process, whether asked for or not
+    pub fn mitigations(&self) -> ElevateResult<HardeningConfig> {
+        hardening::query(&self.process)
+    }
+
+    /// Waits for the process while collecting whatever is piped from its stdout and stderr
+    pub fn wait_with_output(mut self) -> ElevateResult<Output> {
+        drop(self.stdin.take());
+        let stderr = self.stderr.take().map(|stderr| thread::spawn(move || read_all(stderr)));
+        let stdout = self.stdout.take().map(read_all).transpose();
+        let stderr = match stderr {
+            Some(reader) => reader.join().unwrap_or_else(|_| Err(io::ErrorKind::Other.into())).map(Some),
+            None => Ok(None),
+        };
+        let read_error = |e: io::Error| ElevateError::ProcessError(format!("Failed to read child output: {}", e));
+        Ok(Output {
+            status: self.wait()?,
+            stdout: stdout.map_err(read_error)?.unwrap_or_default(),
+            stderr: stderr.map_err(read_error)?.unwrap_or_default(),
+        })
+    }
+
+    /// The job the process runs in, for its accounting
+    pub fn job(&self) -> Option<&JobObject> {
+        self.job.as_ref()
+    }
+}
+
+// the child's side of one stream, kept open until it has been created, and ours if piped
+struct StdioEnds {
+    handle: HANDLE,
+    _child: Option<File>,
+    ours: Option<File>,
+}
+
+fn open_stdio(kind: StdioKind, inherited: RawHandle, child_reads: bool) -> ElevateResult<StdioEnds> {
+    match kind {
+        StdioKind::Inherit => {
+            // without a console we have nothing to pass on
+            let handle = inherited as HANDLE;
+            let handle = if handle == INVALID_HANDLE_VALUE { null_mut() } else { handle };
+            Ok(StdioEnds { handle, _child: None, ours: None })
+        }
+        StdioKind::Null => {
+            let null = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("NUL")
+                .map_err(|e| ElevateError::ProcessError(format!("Failed to open NUL: {}", e)))?;
+            Ok(StdioEnds { handle: null.as_raw_handle() as _, _child: Some(null), ours: None })
+        }
+        StdioKind::Piped => {
+            let (read, write) = create_pipe()?;
+            let (child, ours) = if child_reads { (read, write) } else { (write, read) };
+            Ok(StdioEnds { handle: child.as_raw_handle() as _, _child: Some(child), ours: Some(ours) })
+        }
+    }
+}
+
+fn read_all(mut file: File) -> io::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// A `KEY=value` block for CreateProcessW: `base` with `changes` applied in
+/// order, names compared without case and sorted as Windows expects
+fn environment_block(
+    base: impl IntoIterator<Item = (OsString, OsString)>,
+    changes: &[(OsString, Option<OsString>)],
+) -> Vec<u16> {
+    let key = |name: &OsStr| name.to_string_lossy().to_uppercase();
+    let mut vars: BTreeMap<String, (OsString, OsString)> =
+        base.into_iter().map(|(name, value)| (key(&name), (name, value))).collect();
+    for (name, value) in changes {
+        match value {
+            Some(value) => vars.insert(key(name), (name.clone(), value.clone())),
+            None => vars.remove(&key(name)),
+        };
+    }
+
+    let mut block = Vec::new();
+    for (name, value) in vars.values() {
+        block.extend(name.encode_wide());
+        block.push('=' as u16);
+        block.extend(value.encode_wide());
+        block.push(0);
+    }
+    // an empty block still needs both terminators
+    if block.is_empty() {
+        block.push(0);
+    }
+    block.push(0);
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(OsString, OsString)> {
+        pairs.iter().map(|(name, value)| (OsString::from(name), OsString::from(value))).collect()
+    }
+
+    fn decode(block: &[u16]) -> Vec<String> {
+        String::from_utf16(block).unwrap().split('\0').filter(|var| !var.is_empty()).map(String::from).collect()
+    }
+
+    #[test]
+    fn test_environment_block_applies_changes_in_order() {
+        let changes = vec![
+            (OsString::from("path"), Some(OsString::from(r"C:\Tools"))),
+            (OsString::from("TEMP"), None),
+            (OsString::from("Added"), Some(OsString::from("1"))),
+        ];
+        let block = environment_block(vars(&[("Path", r"C:\Windows"), ("TEMP", "x"), ("b", "2")]), &changes);
+
+        assert_eq!(decode(&block), vec!["Added=1", "b=2", r"path=C:\Tools"]);
+        assert_eq!(&block[block.len() - 2..], &[0, 0]);
+    }
+
+    #[test]
+    fn test_empty_environment_block_is_double_terminated() {
+        assert_eq!(environment_block(Vec::new(), &[]), vec![0, 0]);
+    }
+}
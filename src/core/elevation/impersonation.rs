@@ -0,0 +1,129 @@
+use std::ptr::null_mut;
+use tracing::{debug, info};
+use winapi::{
+    shared::winerror::ERROR_NO_TOKEN,
+    um::{
+        processthreadsapi::{GetCurrentThread, OpenProcess, OpenProcessToken, OpenThreadToken, SetThreadToken},
+        securitybaseapi::DuplicateTokenEx,
+        winnt::{
+            SecurityImpersonation, TokenImpersonation, PROCESS_QUERY_LIMITED_INFORMATION,
+            TOKEN_ALL_ACCESS, TOKEN_DUPLICATE, TOKEN_IMPERSONATE, TOKEN_QUERY,
+        },
+    },
+};
+
+use crate::core::elevation::trusted_installer::TrustedInstallerElevation;
+use crate::core::handle::{HandleKind, OwnedHandle};
+use crate::core::process::find_process_id;
+use crate::core::service::machine::WaitOptions;
+use crate::utils::error::{ElevateError, ElevateResult, WindowsError, WindowsErrorKind};
+use crate::utils::security::SecurityContext;
+
+/// Privileges needed to read and rewrite security descriptors of protected objects
+pub const SECURITY_PRIVILEGES: &[&str] = &[
+    "SeTakeOwnershipPrivilege",
+    "SeRestorePrivilege",
+    "SeBackupPrivilege",
+    "SeSecurityPrivilege",
+];
+
+/// Runs the current thread as TrustedInstaller until dropped.
+///
+/// The TrustedInstaller token only grants `TOKEN_DUPLICATE` to SYSTEM, so the
+/// thread first borrows the token of winlogon.exe and then switches to TI.
+/// Opening winlogon.exe takes SeDebugPrivilege, which `begin` enables first.
+pub struct TrustedInstallerImpersonation {
+    // none if the thread was not impersonating
+    previous: Option<OwnedHandle>,
+}
+
+impl TrustedInstallerImpersonation {
+    pub fn begin(privileges: &[&str]) -> ElevateResult<Self> {
+        let guard = Self { previous: current_thread_token()? };
+        SecurityContext::new().map_err(|e| ElevateError::ProcessError(e.to_string()))?;
+
+        let winlogon_pid = find_process_id("winlogon.exe")?
+            .ok_or_else(|| ElevateError::ProcessError("winlogon.exe is not running".into()))?;
+        impersonate_process(winlogon_pid)?;
+        debug!("Impersonating SYSTEM through winlogon.exe ({})", winlogon_pid);
+
+        let ti_pid = TrustedInstallerElevation::get_trusted_installer_pid(&WaitOptions::default())?;
+        let token = impersonate_process(ti_pid)?;
+        info!("Impersonating TrustedInstaller ({})", ti_pid);
+
+        for privilege in privileges {
+            if let Err(e) = SecurityContext::set_token_privilege(token.as_raw(), privilege) {
+                debug!("Failed to enable {}: {}", privilege, e);
+            }
+        }
+
+        Ok(guard)
+    }
+}
+
+impl Drop for TrustedInstallerImpersonation {
+    fn drop(&mut self) {
+        unsafe {
+            // a null token reverts the thread to the process token
+            SetThreadToken(null_mut(), self.previous.as_ref().map_or(null_mut(), OwnedHandle::as_raw));
+        }
+        debug!("Stopped impersonating TrustedInstaller");
+    }
+}
+
+fn current_thread_token() -> ElevateResult<Option<OwnedHandle>> {
+    let mut token = null_mut();
+    if unsafe { OpenThreadToken(GetCurrentThread(), TOKEN_IMPERSONATE, 1, &mut token) } == 0 {
+        let error = WindowsError::last_error();
+        if error.code() == ERROR_NO_TOKEN {
+            return Ok(None);
+        }
+        return Err(error.to_elevate_error(WindowsErrorKind::TokenOperation, "Failed to open thread token"));
+    }
+    Ok(Some(unsafe { OwnedHandle::from_raw(token, HandleKind::Token) }))
+}
+
+// duplicates the primary token of a process onto the current thread
+fn impersonate_process(pid: u32) -> ElevateResult<OwnedHandle> {
+    unsafe {
+        let process = OwnedHandle::try_from_raw(OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid), HandleKind::Process)
+            .ok_or_else(|| WindowsError::last_error().to_elevate_error(
+                WindowsErrorKind::ProcessOperation,
+                &format!("Failed to open process {}", pid),
+            ))?;
+
+        let mut token = null_mut();
+        if OpenProcessToken(process.as_raw(), TOKEN_DUPLICATE | TOKEN_QUERY, &mut token) == 0 {
+            return Err(WindowsError::last_error().to_elevate_error(
+                WindowsErrorKind::TokenOperation,
+                &format!("Failed to open token of process {}", pid),
+            ));
+        }
+        let token = OwnedHandle::from_raw(token, HandleKind::Token);
+
+        let mut duplicate = null_mut();
+        if DuplicateTokenEx(
+            token.as_raw(),
+            TOKEN_ALL_ACCESS,
+            null_mut(),
+            SecurityImpersonation,
+            TokenImpersonation,
+            &mut duplicate,
+        ) == 0 {
+            return Err(WindowsError::last_error().to_elevate_error(
+                WindowsErrorKind::TokenOperation,
+                "Failed to duplicate token",
+            ));
+        }
+        let duplicate = OwnedHandle::from_raw(duplicate, HandleKind::Token);
+
+        if SetThreadToken(null_mut(), duplicate.as_raw()) == 0 {
+            return Err(WindowsError::last_error().to_elevate_error(
+                WindowsErrorKind::TokenOperation,
+                "Failed to set thread token",
+            ));
+        }
+
+        Ok(duplicate)
+    }
+}
@@ -1 +1,5 @@
 pub mod trusted_installer;
+pub mod impersonation;
+pub mod system_service;
+pub mod child;
+pub mod command;
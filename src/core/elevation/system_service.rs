@@ -0,0 +1,210 @@
+//! Runs a command as LocalSystem without touching TrustedInstaller: a
+//! temporary service starts this binary in helper mode, which runs the command
+//! and relays its output and exit code back over a named pipe.
+
+use std::fs::File;
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use crate::core::pipe::{connect_pipe, create_pipe, wait_for_client};
+use crate::core::process::process_started_by;
+use crate::core::service::controller::ServiceController;
+use crate::core::service::host::run_as_service;
+use crate::core::service::machine::{ServiceApi, WaitOptions};
+use crate::core::service::relay::{print_output, read_message, run_captured, write_message, RelayMessage};
+use crate::core::service::state::ServiceState;
+use crate::utils::cancel::CancellationToken;
+use crate::utils::command_line::join_command_line;
+use crate::utils::error::{ElevateError, ElevateResult};
+use crate::utils::time::unix_timestamp;
+
+/// Temporary services are named `ElevateKitRun-<pid>-<timestamp>`
+pub const SERVICE_PREFIX: &str = "ElevateKitRun-";
+/// Hidden subcommand the temporary service runs
+pub const HELPER_COMMAND: &str = "service-helper";
+// only LocalSystem and administrators may open the pipe
+const PIPE_SDDL: &str = "D:P(A;;GA;;;SY)(A;;GA;;;BA)";
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub struct SystemServiceElevation {
+    wait: WaitOptions,
+}
+
+impl SystemServiceElevation {
+    pub fn new(wait: WaitOptions) -> Self {
+        Self { wait }
+    }
+
+    /// Runs the command as LocalSystem, copies its output to ours and returns its exit code
+    pub fn execute(&self, command: &str, args: &[String]) -> ElevateResult<i32> {
+        if let Err(e) = remove_orphans(&self.wait) {
+            warn!("Failed to remove services left by earlier runs: {}", e);
+        }
+
+        let name = format!("{}{}-{}", SERVICE_PREFIX, std::process::id(), unix_timestamp());
+        let pipe = create_pipe(&name, PIPE_SDDL)?;
+
+        let exe = std::env::current_exe()
+            .map_err(|e| ElevateError::ProcessError(format!("Failed to locate ElevateKit: {}", e)))?;
+        let mut line = vec![
+            exe.to_string_lossy().into_owned(),
+            HELPER_COMMAND.to_string(),
+            "--service".to_string(),
+            name.clone(),
+            "--pipe".to_string(),
+            name.clone(),
+            "--".to_string(),
+            command.to_string(),
+        ];
+        line.extend(args.iter().cloned());
+
+        let service = TemporaryService(ServiceController::create(
+            &name,
+            &format!("ElevateKit: {}", command),
+            &join_command_line(&line),
+        )?);
+        service.0.request_start()?;
+        // from here on the manager removes it by itself once it stops, even if we crash
+        service.0.delete()?;
+
+        let mut pipe = self.wait_for_helper(&service.0, pipe, &name)?;
+        info!("Helper service {} connected", name);
+        relay_output(&mut pipe)
+    }
+
+    // the helper may die before it connects, so the wait happens on another
+    // thread while this one watches the service
+    fn wait_for_helper(&self, service: &ServiceController, pipe: File, name: &str) -> ElevateResult<File> {
+        let (sender, receiver) = mpsc::channel();
+        // errors cross the thread as text, ElevateError is not Send
+        thread::spawn(move || {
+            let _ = sender.send(wait_for_client(&pipe).map(|_| pipe).map_err(|e| e.to_string()));
+        });
+
+        let deadline = Instant::now() + self.wait.timeout;
+        loop {
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(result) => return result.map_err(ElevateError::ProcessError),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(ElevateError::ProcessError("Stopped waiting for the helper service".into()))
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+
+            let status = service.status()?;
+            let reason = if status.state == ServiceState::Stopped {
+                format!("exited with code {} before connecting", status.exit_code)
+            } else if Instant::now() >= deadline {
+                format!("did not connect within {:?}", self.wait.timeout)
+            } else if self.wait.cancel.is_cancelled() {
+                "was cancelled before connecting".to_string()
+            } else {
+                continue;
+            };
+
+            // connecting ourselves releases the waiting thread
+            let _ = connect_pipe(name);
+            let _ = receiver.recv();
+            return Err(ElevateError::ProcessError(format!("Helper service {} {}", name, reason)));
+        }
+    }
+}
+
+// deletes the service if the run fails before it was started
+struct TemporaryService(ServiceController);
+
+impl Drop for TemporaryService {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.delete() {
+            warn!("Failed to delete helper service {}: {}", self.0.name(), e);
+        }
+    }
+}
+
+fn relay_output(pipe: &mut File) -> ElevateResult<i32> {
+    loop {
+        match read_message(pipe).map_err(|e| pipe_error(e, "Failed to read from the helper service"))? {
+            Some(RelayMessage::Exit(code)) => return Ok(code),
+            Some(RelayMessage::Error(message)) => return Err(ElevateError::ProcessError(message)),
+            Some(message) => print_output(&message),
+            None => {
+                return Err(ElevateError::ProcessError(
+                    "The helper service disconnected without an exit code".into(),
+                ))
+            }
+        }
+    }
+}
+
+/// Stops and deletes temporary services whose run no longer exists, such as
+/// one that crashed between creating its service and starting it
+pub fn remove_orphans(wait: &WaitOptions) -> ElevateResult<usize> {
+    let mut removed = 0;
+    for name in ServiceController::find(SERVICE_PREFIX)? {
+        // a pid reused since the service was made belongs to a process started after it
+        let owner = name
+            .get(SERVICE_PREFIX.len()..)
+            .and_then(|rest| rest.split_once('-'))
+            .and_then(|(pid, created)| Some((pid.parse().ok()?, created.parse().ok()?)));
+        if owner.is_some_and(|(pid, created)| process_started_by(pid, created)) {
+            debug!("Leaving {} to its running owner", name);
+            continue;
+        }
+
+        let service = ServiceController::open_to_delete(&name)?;
+        if service.status()?.state != ServiceState::Stopped {
+            service.stop(false, wait)?;
+        }
+        service.delete()?;
+        info!("Removed orphaned helper service {}", name);
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+/// The helper side, run by the temporary service as LocalSystem
+pub fn run_helper(service: &str, pipe: &str, command: Vec<String>) -> ElevateResult<()> {
+    let pipe = pipe.to_string();
+    run_as_service(service, move |stop| match relay_command(&pipe, &command, &stop) {
+        Ok(()) => 0,
+        Err(e) => {
+            warn!("Helper failed: {}", e);
+            1
+        }
+    })
+}
+
+fn relay_command(pipe: &str, command: &[String], stop: &CancellationToken) -> ElevateResult<()> {
+    let mut pipe = connect_pipe(pipe)?;
+    let Some((program, args)) = command.split_first() else {
+        return send(&mut pipe, &RelayMessage::Error("No command to run".into()));
+    };
+
+    let mut write_error = None;
+    let result = run_captured(program, args, stop, &mut |message| {
+        if write_error.is_none() {
+            if let Err(e) = send(&mut pipe, &message) {
+                // with nobody left to read the output, the command goes too
+                stop.cancel();
+                write_error = Some(e);
+            }
+        }
+    });
+
+    match (result, write_error) {
+        (_, Some(e)) => Err(e),
+        (Ok(code), None) => send(&mut pipe, &RelayMessage::Exit(code)),
+        (Err(e), None) => send(&mut pipe, &RelayMessage::Error(e.to_string())),
+    }
+}
+
+fn send(pipe: &mut File, message: &RelayMessage) -> ElevateResult<()> {
+    write_message(pipe, message).map_err(|e| pipe_error(e, "Failed to write to the client"))
+}
+
+fn pipe_error(error: io::Error, context: &str) -> ElevateError {
+    ElevateError::ProcessError(format!("{}: {}", context, error))
+}
@@ -1,151 +1,369 @@
 use std::ffi::OsStr;
+use std::fs::File;
+use std::io::Read;
+use std::mem::size_of;
 use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::{AsRawHandle, FromRawHandle};
+use std::path::Path;
 use std::ptr::null_mut;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 use tracing::info;
 use winapi::{
-    shared::{minwindef::DWORD, winerror::ERROR_SERVICE_ALREADY_RUNNING},
+    shared::minwindef::DWORD,
     um::{
-        errhandlingapi::GetLastError, heapapi::{GetProcessHeap, HeapAlloc, HeapFree}, processthreadsapi::*, winbase::*, winnt::PROCESS_CREATE_PROCESS, winsvc::*
+        handleapi::DuplicateHandle, heapapi::{GetProcessHeap, HeapAlloc, HeapFree}, namedpipeapi::CreatePipe,
+        processthreadsapi::*, winbase::*,
+        winnt::{DUPLICATE_CLOSE_SOURCE, HANDLE, PROCESS_CREATE_PROCESS, PROCESS_DUP_HANDLE}
     },
 };
 
-use crate::utils::{error::{ElevateError, ElevateResult}, security::SecurityContext};
+use crate::core::elevation::child::ElevatedChild;
+use crate::core::handle::{duplicate_into, HandleKind, OwnedHandle};
+use crate::core::hardening::{
+    self, child_process_policy, mitigation_policy, PROC_THREAD_ATTRIBUTE_CHILD_PROCESS_POLICY,
+    PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY,
+};
+use crate::core::job::{JobAccounting, JobObject};
+use crate::core::launch::LaunchSettings;
+use crate::core::pipe::create_output_pipe;
+use crate::core::process::{creation_time, ProcessInfo};
+use crate::core::service::controller::ServiceController;
+use crate::core::service::machine::WaitOptions;
+use crate::core::service::relay::RelayMessage;
+use crate::core::service::restore::ServiceRestore;
+use crate::core::service::state::ServiceState;
+use crate::utils::command_line::join_command_line;
+use crate::utils::config::{HardeningConfig, JobConfig, RestoreAfter};
+use crate::utils::{error::{ElevateError, ElevateResult, WindowsError, WindowsErrorKind}, security::SecurityContext, signal};
 
 const PROC_THREAD_ATTRIBUTE_PARENT_PROCESS: DWORD = 0x00020000;
+const TRUSTED_INSTALLER_SERVICE: &str = "TrustedInstaller";
+const READ_BUFFER: usize = 4096;
 
-// handle wrapper for windows handles
-struct HandleGuard<T>(pub *mut T);
+// where the child's console and output go
+pub(crate) enum ChildIo {
+    NewConsole,
+    Hidden,
+    /// stdin, stdout and stderr handles of ours, no window; a null handle leaves that stream unset
+    Redirected([HANDLE; 3]),
+}
 
-impl<T> Drop for HandleGuard<T> {
-    fn drop(&mut self) {
-        if !self.0.is_null() {
-            unsafe { winapi::um::handleapi::CloseHandle(self.0 as _) };
-        }
-    }
+/// What else a child starts with besides its command line
+#[derive(Default)]
+pub(crate) struct LaunchOptions<'a> {
+    /// a complete UTF-16 environment block instead of ours
+    pub environment: Option<&'a [u16]>,
+    pub current_dir: Option<&'a Path>,
+    pub hardening: Option<&'a HardeningConfig>,
+    pub launch: Option<&'a LaunchSettings>,
+}
+
+/// What `execute` started and, if it waited, what the child used
+#[derive(Debug)]
+pub struct Execution {
+    pub pid: u32,
+    /// creation time of the child, which tells it apart from a later process with the same pid
+    pub created: u64,
+    /// read back from the child when hardening was asked for
+    pub mitigations: Option<HardeningConfig>,
+    pub accounting: Option<JobAccounting>,
 }
 
 pub struct TrustedInstallerElevation<'a> {
     context: &'a SecurityContext,
+    wait: WaitOptions,
+    restore: Option<RestoreAfter>,
+    job: Option<JobConfig>,
+    hardening: Option<HardeningConfig>,
+    launch: Option<LaunchSettings>,
+    detach: bool,
 }
 
 impl<'a> TrustedInstallerElevation<'a> {
     pub fn new(context: &'a SecurityContext) -> Self {
-        Self { context }
+        Self {
+            context,
+            wait: WaitOptions::default(),
+            restore: None,
+            job: None,
+            hardening: None,
+            launch: None,
+            detach: false,
+        }
+    }
+
+    /// Put the TrustedInstaller service back as it was, after the child exits or right after it starts
+    pub fn restore_service(mut self, after: Option<RestoreAfter>) -> Self {
+        self.restore = after;
+        self
     }
 
-    pub fn execute(&self, command: &str, args: &[String]) -> ElevateResult<()> {
+    /// Timeout and cancellation for starting the TrustedInstaller service
+    pub fn wait_options(mut self, wait: WaitOptions) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    /// Run the child in a job object with these limits; `execute` then waits
+    /// for it to exit. An empty config runs it without a job.
+    pub fn job(mut self, config: JobConfig) -> Self {
+        self.job = Some(config).filter(|config| !config.is_empty());
+        self
+    }
+
+    /// Create the child with these mitigations in effect. An empty config
+    /// leaves it as it would be.
+    pub fn hardening(mut self, config: HardeningConfig) -> Self {
+        self.hardening = Some(config).filter(|config| !config.is_empty());
+        self
+    }
+
+    /// Priority, affinity, window and console for the child
+    pub fn launch(mut self, settings: LaunchSettings) -> Self {
+        self.launch = Some(settings).filter(|settings| *settings != LaunchSettings::default());
+        self
+    }
+
+    /// Return from `execute` as soon as the child runs, even with a job or a
+    /// service to restore; the service is then restored right away and the
+    /// job's limits stay on the child without anyone waiting for it
+    pub fn detach(mut self, detach: bool) -> Self {
+        self.detach = detach;
+        self
+    }
+
+    /// Starts the command in a console of its own. With a job, waits for it and
+    /// returns what the job used.
+    pub fn execute(&self, command: &str, args: &[String]) -> ElevateResult<Execution> {
         info!("Elevating to TrustedInstaller");
-        
+
+        // recorded before the service is touched, put back on every exit path
+        let restore = match self.restore {
+            Some(_) => Some(ServiceRestore::capture(TRUSTED_INSTALLER_SERVICE, &self.wait)?),
+            None => None,
+        };
+
         // get trustedinstaller pid
-        let ti_pid = Self::get_trusted_installer_pid()?;
+        let ti_pid = Self::get_trusted_installer_pid(&self.wait)?;
         info!("TrustedInstaller PID: {}", ti_pid);
 
         // create process with ti parent
-        let proc_info = Self::create_process_with_ti_parent(ti_pid, command, args)?;
-        
+        let child = Self::create_process_with_ti_parent(ti_pid, command, args, ChildIo::NewConsole, self.launch_options())?;
+        let job = self.assign_job(&child)?;
+
         // set privileges and resume
-        unsafe {
-            self.context.set_all_privileges(proc_info.hProcess)
-                .map_err(|e| ElevateError::ProcessError(e.to_string()))?;
-            ResumeThread(proc_info.hThread);
-            
-            // process handles cleaned up by handleguard
-            let _proc_guard = HandleGuard(proc_info.hProcess);
-            let _thread_guard = HandleGuard(proc_info.hThread);
+        self.start(&child)?;
+        let mitigations = self.hardening.as_ref().map(|_| hardening::query(child.process())).transpose()?;
+        let created = creation_time(child.process())?;
+
+        let mut restore = restore;
+        if self.restore == Some(RestoreAfter::Detach) || self.detach {
+            if let Some(restore) = restore.take() {
+                restore.restore()?;
+            }
+        }
+
+        if (restore.is_some() || job.is_some()) && !self.detach {
+            info!("Waiting for the elevated process to exit");
+            signal::install_ctrl_handler()?;
+            // Ctrl+C stops waiting but still restores the service; closing the job may kill the child
+            while !child.process().wait(Some(Duration::from_millis(200)))? && !signal::stop_requested() {}
         }
 
-        Ok(())
+        let accounting = job.as_ref().filter(|_| !self.detach).map(JobObject::accounting).transpose();
+        if let Some(restore) = restore {
+            restore.restore()?;
+        }
+        Ok(Execution { pid: child.process_id(), created, mitigations, accounting: accounting? })
     }
 
-    fn get_trusted_installer_pid() -> ElevateResult<u32> {
-        unsafe {
-            let scm = HandleGuard(OpenSCManagerW(null_mut(), null_mut(), SC_MANAGER_CONNECT));
-            if scm.0.is_null() {
-                return Err(ElevateError::ProcessError("Failed to open service control manager".into()));
-            }
-    
-            let service = HandleGuard(OpenServiceW(
-                scm.0,
-                to_wide_str("TrustedInstaller").as_ptr(),
-                SERVICE_START | SERVICE_QUERY_STATUS
-            ));
-    
-            if service.0.is_null() {
-                return Err(ElevateError::ProcessError(
-                    "Failed to open TrustedInstaller service (are you running as Administrator?)".into()
-                ));
-            }
-    
-            // try starting service
-            if StartServiceW(service.0, 0, null_mut()) == 0 {
-                let err = GetLastError();
-                if err != ERROR_SERVICE_ALREADY_RUNNING {
-                    return Err(ElevateError::ProcessError("Failed to start TrustedInstaller service".into()));
+    /// Runs the command without a console of its own and hands its output to
+    /// `output` as it arrives. Waits for the process and returns its exit code;
+    /// cancelling the wait options terminates it.
+    pub fn execute_captured(
+        &self,
+        command: &str,
+        args: &[String],
+        output: &mut dyn FnMut(RelayMessage),
+    ) -> ElevateResult<i32> {
+        let ti_pid = Self::get_trusted_installer_pid(&self.wait)?;
+        let (stdout_read, stdout_write) = create_pipe()?;
+        let (stderr_read, stderr_write) = create_pipe()?;
+
+        let child = Self::create_process_with_ti_parent(
+            ti_pid,
+            command,
+            args,
+            ChildIo::Redirected([null_mut(), stdout_write.as_raw_handle() as _, stderr_write.as_raw_handle() as _]),
+            self.launch_options(),
+        )?;
+        // only the child may hold the write ends, or the reads never end
+        drop((stdout_write, stderr_write));
+        let job = self.assign_job(&child)?;
+        self.start(&child)?;
+
+        let (sender, receiver) = mpsc::channel();
+        for (mut file, wrap) in [
+            (stdout_read, RelayMessage::Stdout as fn(Vec<u8>) -> RelayMessage),
+            (stderr_read, RelayMessage::Stderr),
+        ] {
+            let sender = sender.clone();
+            thread::spawn(move || {
+                let mut buffer = vec![0u8; READ_BUFFER];
+                while let Ok(read @ 1..) = file.read(&mut buffer) {
+                    if sender.send(wrap(buffer[..read].to_vec())).is_err() {
+                        break;
+                    }
                 }
+            });
+        }
+        drop(sender);
+
+        let mut terminated = false;
+        loop {
+            match receiver.recv_timeout(Duration::from_millis(200)) {
+                Ok(message) => output(message),
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
             }
-    
-            // wait up to 10 seconds for service to run
-            for _ in 0..20 {
-                let mut status: SERVICE_STATUS_PROCESS = std::mem::zeroed();
-                let mut bytes_needed = 0;
-    
-                if QueryServiceStatusEx(
-                    service.0,
-                    SC_STATUS_PROCESS_INFO,
-                    &mut status as *mut _ as *mut u8,
-                    std::mem::size_of::<SERVICE_STATUS_PROCESS>() as u32,
-                    &mut bytes_needed
-                ) == 0 {
-                    return Err(ElevateError::ProcessError("Failed to query service status".into()));
-                }
-    
-                match status.dwCurrentState {
-                    SERVICE_RUNNING => return Ok(status.dwProcessId),
-                    SERVICE_START_PENDING => {
-                        // wait 500ms before checking again
-                        std::thread::sleep(std::time::Duration::from_millis(500));
-                        continue;
-                    }
-                    _ => return Err(ElevateError::ProcessError(
-                        format!("Service in unexpected state: {}", status.dwCurrentState).into()
-                    ))
+            if self.wait.cancel.is_cancelled() && !terminated {
+                match &job {
+                    Some(job) => job.terminate(1)?,
+                    None => unsafe { TerminateProcess(child.process().as_raw(), 1); },
                 }
+                terminated = true;
             }
-    
-            Err(ElevateError::ProcessError("Timeout waiting for service to start".into()))
         }
+
+        let mut exit_code = 0;
+        child.process().wait(None)?;
+        unsafe { GetExitCodeProcess(child.process().as_raw(), &mut exit_code) };
+        Ok(exit_code as i32)
+    }
+
+    /// Starts the command without a window and returns it to be awaited, its
+    /// output on pipes that are read asynchronously. Has to be called from
+    /// within a tokio runtime.
+    pub fn spawn_async(&self, command: &str, args: &[String]) -> ElevateResult<ElevatedChild> {
+        let ti_pid = Self::get_trusted_installer_pid(&self.wait)?;
+        let (stdout_read, stdout_write) = create_output_pipe()?;
+        let (stderr_read, stderr_write) = create_output_pipe()?;
+
+        let created = Self::create_process_with_ti_parent(
+            ti_pid,
+            command,
+            args,
+            ChildIo::Redirected([null_mut(), stdout_write.as_raw_handle() as _, stderr_write.as_raw_handle() as _]),
+            self.launch_options(),
+        )?;
+        // only the child may hold the write ends, or the reads never end
+        drop((stdout_write, stderr_write));
+        let job = self.assign_job(&created)?;
+
+        kill_on_error(&created, self.set_privileges(&created))?;
+        ElevatedChild::new(created, job, stdout_read, stderr_read)
     }
 
-    fn create_process_with_ti_parent(
-        ti_pid: u32,
+    /// Starts the command without a window and returns its pid and creation
+    /// time without waiting for it
+    pub fn spawn_hidden(&self, command: &str, args: &[String]) -> ElevateResult<(u32, u64)> {
+        let ti_pid = Self::get_trusted_installer_pid(&self.wait)?;
+        let child = Self::create_process_with_ti_parent(ti_pid, command, args, ChildIo::Hidden, self.launch_options())?;
+
+        let created = kill_on_error(&child, creation_time(child.process()))?;
+        self.start(&child)?;
+        Ok((child.process_id(), created))
+    }
+
+    // sets the privileges of a suspended child and resumes it, killing it if either fails
+    fn start(&self, child: &ProcessInfo) -> ElevateResult<()> {
+        kill_on_error(child, self.set_privileges(child).and_then(|_| child.resume()))
+    }
+
+    fn set_privileges(&self, child: &ProcessInfo) -> ElevateResult<()> {
+        self.context.set_all_privileges(child.process())
+            .map_err(|e| ElevateError::ProcessError(e.to_string()))
+    }
+
+    fn launch_options(&self) -> LaunchOptions<'_> {
+        LaunchOptions { hardening: self.hardening.as_ref(), launch: self.launch.as_ref(), ..LaunchOptions::default() }
+    }
+
+    // the job has to hold the child before it runs, so a suspended child that
+    // cannot be assigned is killed rather than resumed
+    fn assign_job(&self, child: &ProcessInfo) -> ElevateResult<Option<JobObject>> {
+        let Some(config) = &self.job else {
+            return Ok(None);
+        };
+        let job = JobObject::create(config).and_then(|job| job.assign(child.process()).map(|_| job));
+        if job.is_err() {
+            unsafe { TerminateProcess(child.process().as_raw(), 1) };
+        }
+        job.map(Some)
+    }
+
+    /// Starts TrustedInstaller if needed, waiting out a pending stop first, and returns its pid
+    pub(crate) fn get_trusted_installer_pid(options: &WaitOptions) -> ElevateResult<u32> {
+        let service = ServiceController::open_to_start(TRUSTED_INSTALLER_SERVICE).map_err(|e| {
+            ElevateError::ProcessError(format!(
+                "Failed to open TrustedInstaller service (are you running as Administrator?): {}",
+                e
+            ))
+        })?;
+        Ok(service.reach(ServiceState::Running, options)?.pid)
+    }
+
+    /// Creates the command suspended as a child of `parent_pid`, TrustedInstaller
+    /// unless a caller picks another parent, so it runs with that process's token
+    pub(crate) fn create_process_with_ti_parent(
+        parent_pid: u32,
         command: &str,
-        args: &[String]
-    ) -> ElevateResult<PROCESS_INFORMATION> {
+        args: &[String],
+        io: ChildIo,
+        options: LaunchOptions,
+    ) -> ElevateResult<ProcessInfo> {
         unsafe {
             // open ti process
-            let ti_handle = HandleGuard(OpenProcess(PROCESS_CREATE_PROCESS, 0, ti_pid));
-            if ti_handle.0.is_null() {
-                return Err(ElevateError::ProcessError("Failed to open TI process".into()));
-            }
+            let ti_handle = OwnedHandle::try_from_raw(OpenProcess(PROCESS_CREATE_PROCESS | PROCESS_DUP_HANDLE, 0, parent_pid), HandleKind::Process)
+                .ok_or_else(|| ElevateError::ProcessError(format!("Failed to open parent process {}", parent_pid)))?;
+            let parent = ti_handle.as_raw();
+
+            // mitigations hold from the child's first instruction
+            let mitigations = options.hardening.map(mitigation_policy).filter(|&policy| policy != 0);
+            let child_policy = options.hardening.and_then(child_process_policy);
+            let count = 1 + mitigations.is_some() as DWORD + child_policy.is_some() as DWORD;
 
             // setup process attribute list
             let mut size = 0;
-            InitializeProcThreadAttributeList(null_mut(), 1, 0, &mut size);
+            InitializeProcThreadAttributeList(null_mut(), count, 0, &mut size);
             let attr_list = HeapAlloc(GetProcessHeap(), 0, size) as *mut PROC_THREAD_ATTRIBUTE_LIST;
             if attr_list.is_null() {
                 return Err(ElevateError::ProcessError("Failed to allocate attribute list".into()));
             }
 
-            InitializeProcThreadAttributeList(attr_list, 1, 0, &mut size);
+            InitializeProcThreadAttributeList(attr_list, count, 0, &mut size);
             UpdateProcThreadAttribute(
                 attr_list,
                 0,
                 PROC_THREAD_ATTRIBUTE_PARENT_PROCESS.try_into().unwrap(),
-                &ti_handle.0 as *const _ as *mut _,
+                &parent as *const _ as *mut _,
                 std::mem::size_of::<winapi::shared::ntdef::HANDLE>(),
                 null_mut(),
                 null_mut()
             );
+            let hardened = mitigations.as_ref().map_or(true, |policy| set_attribute(attr_list, PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY, policy))
+                && child_policy.as_ref().map_or(true, |policy| set_attribute(attr_list, PROC_THREAD_ATTRIBUTE_CHILD_PROCESS_POLICY, policy));
+            if !hardened {
+                let error = WindowsError::last_error().to_elevate_error(
+                    WindowsErrorKind::ProcessOperation,
+                    "Failed to set the child's mitigation policy",
+                );
+                DeleteProcThreadAttributeList(attr_list);
+                HeapFree(GetProcessHeap(), 0, attr_list as *mut _);
+                return Err(error);
+            }
 
             // setup startup info
             let mut startup_info = STARTUPINFOEXW {
@@ -155,10 +373,67 @@ impl<'a> TrustedInstallerElevation<'a> {
                 },
                 lpAttributeList: attr_list,
             };
+            let title = options.launch.and_then(|launch| launch.title.as_deref()).map(to_wide_str);
+            if let Some(title) = &title {
+                startup_info.StartupInfo.lpTitle = title.as_ptr() as *mut _;
+            }
+            if let Some(window) = options.launch.and_then(|launch| launch.window) {
+                startup_info.StartupInfo.dwFlags |= STARTF_USESHOWWINDOW;
+                startup_info.StartupInfo.wShowWindow = window.show_command();
+            }
+            if let Some(size) = options.launch.and_then(|launch| launch.console_size) {
+                startup_info.StartupInfo.dwFlags |= STARTF_USECOUNTCHARS;
+                startup_info.StartupInfo.dwXCountChars = size.columns;
+                startup_info.StartupInfo.dwYCountChars = size.rows;
+            }
+
+            // handles are inherited from the parent, so the child's stdio has to be
+            // copied into TrustedInstaller first
+            let mut inherited = Vec::new();
+            let console = match (options.launch.and_then(|launch| launch.console), &io) {
+                (Some(mode), _) => mode.flag(),
+                (None, ChildIo::NewConsole) => CREATE_NEW_CONSOLE,
+                (None, ChildIo::Hidden | ChildIo::Redirected(..)) => CREATE_NO_WINDOW,
+            };
+            let mut flags = CREATE_SUSPENDED
+                | EXTENDED_STARTUPINFO_PRESENT
+                | console
+                | options.launch.map_or(0, LaunchSettings::creation_flags);
+            if let ChildIo::Redirected(handles) = io {
+                let mut remote = [null_mut(); 3];
+                for (slot, handle) in remote.iter_mut().zip(handles) {
+                    if handle.is_null() {
+                        continue;
+                    }
+                    match duplicate_into(handle, &ti_handle, true) {
+                        Ok(copy) => {
+                            inherited.push(copy);
+                            *slot = copy;
+                        }
+                        Err(e) => {
+                            close_in(parent, &inherited);
+                            DeleteProcThreadAttributeList(attr_list);
+                            HeapFree(GetProcessHeap(), 0, attr_list as *mut _);
+                            return Err(e);
+                        }
+                    }
+                }
+                startup_info.StartupInfo.dwFlags |= STARTF_USESTDHANDLES;
+                startup_info.StartupInfo.hStdInput = remote[0];
+                startup_info.StartupInfo.hStdOutput = remote[1];
+                startup_info.StartupInfo.hStdError = remote[2];
+            }
+
+            let environment = options.environment.map_or(null_mut(), |block| block.as_ptr() as *mut _);
+            if options.environment.is_some() {
+                flags |= CREATE_UNICODE_ENVIRONMENT;
+            }
+            let current_dir = options.current_dir.map(|dir| to_wide_str(&dir.to_string_lossy()));
 
             // make command line
-            let cmd = format!("{} {}", command, args.join(" ")).trim().to_string();
-            let cmd_wide = to_wide_str(&cmd);
+            let mut line = vec![command.to_string()];
+            line.extend(args.iter().cloned());
+            let cmd_wide = to_wide_str(&join_command_line(&line));
 
             // create process
             let mut process_info: PROCESS_INFORMATION = std::mem::zeroed();
@@ -167,27 +442,68 @@ impl<'a> TrustedInstallerElevation<'a> {
                 cmd_wide.as_ptr() as *mut _,
                 null_mut(),
                 null_mut(),
-                0,
-                CREATE_SUSPENDED | EXTENDED_STARTUPINFO_PRESENT | CREATE_NEW_CONSOLE,
-                null_mut(),
-                null_mut(),
+                !inherited.is_empty() as i32,
+                flags,
+                environment,
+                current_dir.as_ref().map_or(null_mut(), |dir| dir.as_ptr() as *mut _),
                 &mut startup_info.StartupInfo,
                 &mut process_info
             );
 
             // cleanup
+            close_in(parent, &inherited);
             DeleteProcThreadAttributeList(attr_list);
             HeapFree(GetProcessHeap(), 0, attr_list as *mut _);
 
             if result == 0 {
-                return Err(ElevateError::ProcessError("Failed to create process".into()));
+                return Err(WindowsError::last_error().to_elevate_error(
+                    WindowsErrorKind::ProcessOperation,
+                    &format!("Failed to create process: {}", command),
+                ));
             }
 
-            Ok(process_info)
+            let child = ProcessInfo::from_raw(process_info);
+            if let Some(Err(e)) = options.launch.map(|launch| launch.apply_affinity(&child)) {
+                TerminateProcess(child.process().as_raw(), 1);
+                return Err(e);
+            }
+            Ok(child)
         }
     }
 }
 
+/// An anonymous pipe, as (read, write); either end can be handed to a child
+pub(crate) fn create_pipe() -> ElevateResult<(File, File)> {
+    let (mut read, mut write) = (null_mut(), null_mut());
+    if unsafe { CreatePipe(&mut read, &mut write, null_mut(), 0) } == 0 {
+        return Err(WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::ProcessOperation,
+            "Failed to create pipe",
+        ));
+    }
+    Ok(unsafe { (File::from_raw_handle(read as _), File::from_raw_handle(write as _)) })
+}
+
+// a suspended child that cannot be set up is killed rather than left behind
+fn kill_on_error<T>(child: &ProcessInfo, result: ElevateResult<T>) -> ElevateResult<T> {
+    if result.is_err() {
+        unsafe { TerminateProcess(child.process().as_raw(), 1) };
+    }
+    result
+}
+
+// adds a fixed-size value to an attribute list; it has to stay put until the process is created
+unsafe fn set_attribute<T>(list: *mut PROC_THREAD_ATTRIBUTE_LIST, attribute: usize, value: &T) -> bool {
+    UpdateProcThreadAttribute(list, 0, attribute, value as *const T as *mut _, size_of::<T>(), null_mut(), null_mut()) != 0
+}
+
+// closes handles that live in another process
+fn close_in(process: HANDLE, handles: &[HANDLE]) {
+    for &handle in handles {
+        unsafe { DuplicateHandle(process, handle, null_mut(), null_mut(), 0, 0, DUPLICATE_CLOSE_SOURCE) };
+    }
+}
+
 fn to_wide_str(s: &str) -> Vec<u16> {
     OsStr::new(s).encode_wide().chain(std::iter::once(0)).collect()
 }
\ No newline at end of file
@@ -0,0 +1,168 @@
+//! Splits the byte stream produced by `BackupRead` into its `WIN32_STREAM_ID`
+//! records, so single streams can be hashed or left out of a copy.
+
+use crate::utils::error::{ElevateError, ElevateResult};
+
+pub const BACKUP_DATA: u32 = 1;
+pub const BACKUP_SECURITY_DATA: u32 = 3;
+pub const BACKUP_ALTERNATE_DATA: u32 = 4;
+
+// dwStreamId, dwStreamAttributes, Size (u64) and dwStreamNameSize
+const HEADER_LEN: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamHeader {
+    pub id: u32,
+    pub attributes: u32,
+    pub size: u64,
+    /// `:name:$DATA` for alternate data streams, empty otherwise
+    pub name: String,
+}
+
+/// Part of a backup stream, in the order it appeared
+#[derive(Debug)]
+pub enum Piece<'a> {
+    /// a complete stream header with its raw bytes, including the name
+    Header { header: &'a StreamHeader, raw: &'a [u8] },
+    /// some of the data of the stream whose header came last
+    Data { id: u32, bytes: &'a [u8] },
+}
+
+#[derive(Debug, Default)]
+pub struct StreamSplitter {
+    pending: Vec<u8>,
+    current: Option<StreamHeader>,
+    remaining: u64,
+}
+
+impl StreamSplitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds the next chunk; headers may be split across chunks
+    pub fn feed(&mut self, mut chunk: &[u8], mut out: impl FnMut(Piece<'_>)) {
+        while !chunk.is_empty() {
+            if self.remaining > 0 {
+                let take = chunk.len().min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+                let id = self.current.as_ref().map_or(0, |h| h.id);
+                out(Piece::Data { id, bytes: &chunk[..take] });
+                self.remaining -= take as u64;
+                chunk = &chunk[take..];
+                continue;
+            }
+
+            let needed = self.header_len() - self.pending.len();
+            let take = needed.min(chunk.len());
+            self.pending.extend_from_slice(&chunk[..take]);
+            chunk = &chunk[take..];
+
+            // the name size is only known once the fixed part is complete
+            if self.pending.len() < self.header_len() {
+                continue;
+            }
+
+            let header = parse_header(&self.pending);
+            out(Piece::Header { header: &header, raw: &self.pending });
+            self.remaining = header.size;
+            self.current = Some(header);
+            self.pending.clear();
+        }
+    }
+
+    /// Fails if the stream ended in the middle of a record
+    pub fn finish(&self) -> ElevateResult<()> {
+        if !self.pending.is_empty() || self.remaining > 0 {
+            return Err(ElevateError::ValidationError("Backup stream ended in the middle of a record".into()));
+        }
+        Ok(())
+    }
+
+    fn header_len(&self) -> usize {
+        if self.pending.len() < HEADER_LEN {
+            HEADER_LEN
+        } else {
+            HEADER_LEN + name_len(&self.pending)
+        }
+    }
+}
+
+fn name_len(header: &[u8]) -> usize {
+    u32::from_le_bytes(header[16..20].try_into().unwrap()) as usize
+}
+
+fn parse_header(raw: &[u8]) -> StreamHeader {
+    let name: Vec<u16> = raw[HEADER_LEN..]
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+
+    StreamHeader {
+        id: u32::from_le_bytes(raw[0..4].try_into().unwrap()),
+        attributes: u32::from_le_bytes(raw[4..8].try_into().unwrap()),
+        size: u64::from_le_bytes(raw[8..16].try_into().unwrap()),
+        name: String::from_utf16_lossy(&name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: u32, name: &str, data: &[u8]) -> Vec<u8> {
+        let name: Vec<u8> = name.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let mut bytes = Vec::new();
+        bytes.extend(id.to_le_bytes());
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend((data.len() as u64).to_le_bytes());
+        bytes.extend((name.len() as u32).to_le_bytes());
+        bytes.extend(name);
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn test_split_in_any_chunk_size() {
+        let mut stream = record(BACKUP_SECURITY_DATA, "", &[1, 2, 3]);
+        stream.extend(record(BACKUP_DATA, "", b"hello world"));
+        stream.extend(record(BACKUP_ALTERNATE_DATA, ":Zone.Identifier:$DATA", b"[ZoneTransfer]"));
+        stream.extend(record(BACKUP_DATA, "", b""));
+
+        for chunk_size in [1, 3, 7, 20, 21, stream.len()] {
+            let mut splitter = StreamSplitter::new();
+            let mut headers = Vec::new();
+            let mut rebuilt: Vec<u8> = Vec::new();
+            let mut data: Vec<(u32, Vec<u8>)> = Vec::new();
+
+            for chunk in stream.chunks(chunk_size) {
+                splitter.feed(chunk, |piece| match piece {
+                    Piece::Header { header, raw } => {
+                        headers.push(header.clone());
+                        rebuilt.extend(raw);
+                        data.push((header.id, Vec::new()));
+                    }
+                    Piece::Data { id, bytes } => {
+                        assert_eq!(data.last().unwrap().0, id);
+                        data.last_mut().unwrap().1.extend(bytes);
+                        rebuilt.extend(bytes);
+                    }
+                });
+            }
+            splitter.finish().unwrap();
+
+            assert_eq!(rebuilt, stream, "chunk size {}", chunk_size);
+            assert_eq!(headers.len(), 4);
+            assert_eq!(headers[2].name, ":Zone.Identifier:$DATA");
+            assert_eq!(data[1], (BACKUP_DATA, b"hello world".to_vec()));
+            assert_eq!(data[2].1, b"[ZoneTransfer]");
+        }
+    }
+
+    #[test]
+    fn test_truncated_stream_is_an_error() {
+        let stream = record(BACKUP_DATA, "", b"hello");
+        let mut splitter = StreamSplitter::new();
+        splitter.feed(&stream[..stream.len() - 1], |_| {});
+        assert!(splitter.finish().is_err());
+    }
+}
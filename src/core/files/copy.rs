@@ -0,0 +1,343 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::ptr::null_mut;
+use sha2::{Digest, Sha256};
+use tracing::debug;
+use winapi::{
+    ctypes::c_void,
+    shared::winerror::ERROR_PRIVILEGE_NOT_HELD,
+    um::{
+        fileapi::{CreateFileW, CREATE_ALWAYS, OPEN_EXISTING},
+        winbase::{BackupRead, BackupWrite, FILE_FLAG_BACKUP_SEMANTICS, FILE_FLAG_OPEN_REPARSE_POINT},
+        winnt::{
+            ACCESS_SYSTEM_SECURITY, FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE, GENERIC_READ,
+            GENERIC_WRITE, HANDLE, WRITE_DAC, WRITE_OWNER,
+        },
+    },
+};
+
+use crate::core::handle::{HandleKind, OwnedHandle};
+use crate::core::files::backup_stream::{Piece, StreamSplitter, BACKUP_ALTERNATE_DATA, BACKUP_DATA, BACKUP_SECURITY_DATA};
+use crate::core::privilege::enable_for_current_thread;
+use crate::utils::error::{ElevateError, ElevateResult, WindowsError, WindowsErrorKind};
+use crate::utils::files::{collect_paths, file_error, path_name};
+use crate::utils::hash::to_hex;
+
+const BACKUP_PRIVILEGES: &[&str] = &["SeBackupPrivilege", "SeRestorePrivilege", "SeSecurityPrivilege"];
+// setting another owner needs SeRestorePrivilege, and the SACL SeSecurityPrivilege
+const SECURITY_PRIVILEGES: &[&str] = &["SeRestorePrivilege", "SeSecurityPrivilege"];
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CopyOptions {
+    /// open with `FILE_FLAG_BACKUP_SEMANTICS` and the backup privileges, bypassing DACLs
+    pub backup_semantics: bool,
+    pub recursive: bool,
+    /// copy alternate data streams such as `Zone.Identifier`
+    pub keep_streams: bool,
+    /// copy owner, DACL and SACL instead of inheriting them at the destination
+    pub keep_security: bool,
+}
+
+pub struct CopyProgress<'a> {
+    /// 1-based position of the current item
+    pub index: usize,
+    pub total: usize,
+    pub path: &'a Path,
+    pub bytes_copied: u64,
+}
+
+/// One copied file, `path` relative to the source root
+#[derive(Debug, Clone)]
+pub struct ManifestEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Copies files with `BackupRead`/`BackupWrite`, which ignore DACLs with
+/// backup semantics, or as plain data when neither backup semantics, streams
+/// nor security are asked for. Returns the SHA-256 of the main data stream of
+/// every file.
+pub fn backup_copy(
+    source: &Path,
+    destination: &Path,
+    options: CopyOptions,
+    progress: &mut dyn FnMut(&CopyProgress),
+) -> ElevateResult<Vec<ManifestEntry>> {
+    if options.backup_semantics {
+        enable_for_current_thread(BACKUP_PRIVILEGES)?;
+    } else if options.keep_security {
+        enable_for_current_thread(SECURITY_PRIVILEGES)?;
+    }
+
+    let source_is_dir = fs::symlink_metadata(source)
+        .map_err(|e| file_error(e, &format!("Failed to read {}", source.display())))?
+        .is_dir();
+    if source_is_dir && !options.recursive {
+        return Err(ElevateError::ValidationError(format!(
+            "{} is a directory, use --recursive",
+            source.display()
+        )));
+    }
+
+    // like copy, a file copied onto an existing directory lands inside it
+    let destination = match source.file_name() {
+        Some(name) if !source_is_dir && destination.is_dir() => destination.join(name),
+        _ => destination.to_path_buf(),
+    };
+
+    let paths = collect_paths(source, options.recursive)?;
+    let mut manifest = Vec::new();
+
+    for (index, path) in paths.iter().enumerate() {
+        let relative = path.strip_prefix(source).unwrap_or(path);
+        let target = if relative.as_os_str().is_empty() {
+            destination.clone()
+        } else {
+            destination.join(relative)
+        };
+
+        let metadata = fs::symlink_metadata(path)
+            .map_err(|e| file_error(e, &format!("Failed to read {}", path.display())))?;
+        let is_dir = metadata.is_dir();
+        let mut report = |bytes_copied| {
+            progress(&CopyProgress { index: index + 1, total: paths.len(), path, bytes_copied })
+        };
+
+        if is_dir {
+            fs::create_dir_all(&target)
+                .map_err(|e| file_error(e, &format!("Failed to create {}", target.display())))?;
+            // a directory only has streams worth copying when they are kept
+            if options.keep_security || options.keep_streams {
+                copy_streams(path, &target, true, options, &mut report)?;
+            }
+            report(0);
+            continue;
+        }
+
+        // a link is copied as a link, which takes the backup stream of its reparse data
+        let plain = !(options.backup_semantics || options.keep_streams || options.keep_security || metadata.is_symlink());
+        let (size, sha256) = if plain {
+            copy_data(path, &target, &mut report)?
+        } else {
+            copy_streams(path, &target, false, options, &mut report)?
+        };
+        let name = if relative.as_os_str().is_empty() {
+            PathBuf::from(path.file_name().unwrap_or_default())
+        } else {
+            relative.to_path_buf()
+        };
+        manifest.push(ManifestEntry { path: name, size, sha256 });
+    }
+
+    Ok(manifest)
+}
+
+/// `sha256sum` compatible manifest lines
+pub fn format_manifest(entries: &[ManifestEntry]) -> String {
+    entries
+        .iter()
+        .map(|e| format!("{}  {}\n", e.sha256, e.path.display()))
+        .collect()
+}
+
+// copies the main data stream only, as nothing else was asked for
+fn copy_data(source: &Path, target: &Path, report: &mut dyn FnMut(u64)) -> ElevateResult<(u64, String)> {
+    let mut reader = fs::File::open(source).map_err(|e| file_error(e, &format!("Failed to open {}", source.display())))?;
+    let mut writer =
+        fs::File::create(target).map_err(|e| file_error(e, &format!("Failed to create {}", target.display())))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut size = 0u64;
+
+    loop {
+        let read = reader.read(&mut buffer).map_err(|e| file_error(e, &format!("Failed to read {}", source.display())))?;
+        if read == 0 {
+            break;
+        }
+        writer
+            .write_all(&buffer[..read])
+            .map_err(|e| file_error(e, &format!("Failed to write {}", target.display())))?;
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+        report(size);
+    }
+
+    Ok((size, to_hex(&hasher.finalize())))
+}
+
+// streams one file or directory through BackupRead into BackupWrite
+fn copy_streams(
+    source: &Path,
+    target: &Path,
+    is_dir: bool,
+    options: CopyOptions,
+    report: &mut dyn FnMut(u64),
+) -> ElevateResult<(u64, String)> {
+    let flags = if options.backup_semantics {
+        FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT
+    } else {
+        FILE_FLAG_OPEN_REPARSE_POINT
+    };
+    let reader = open_source(source, options.keep_security, flags)?;
+    let writer = open_target(target, is_dir, options.keep_security, flags)?;
+    let process_security = options.keep_security as i32;
+
+    let mut read_context = BackupContext::new(reader.as_raw(), false, process_security);
+    let mut write_context = BackupContext::new(writer.as_raw(), true, process_security);
+    let mut splitter = StreamSplitter::new();
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let (mut size, mut skipping) = (0u64, false);
+
+    loop {
+        let mut read = 0;
+        let ok = unsafe {
+            BackupRead(
+                reader.as_raw(),
+                buffer.as_mut_ptr(),
+                buffer.len() as u32,
+                &mut read,
+                0,
+                process_security,
+                &mut read_context.context,
+            )
+        };
+        if ok == 0 {
+            return Err(WindowsError::last_error().to_elevate_error(
+                WindowsErrorKind::FileOperation,
+                &format!("Failed to read {}", source.display()),
+            ));
+        }
+        if read == 0 {
+            break;
+        }
+
+        let mut result = Ok(());
+        splitter.feed(&buffer[..read as usize], |piece| {
+            if result.is_err() {
+                return;
+            }
+            let bytes = match piece {
+                Piece::Header { header, raw } => {
+                    skipping = (header.id == BACKUP_ALTERNATE_DATA && !options.keep_streams)
+                        || (header.id == BACKUP_SECURITY_DATA && !options.keep_security);
+                    if skipping {
+                        debug!("Skipping stream {} {:?} of {}", header.id, header.name, source.display());
+                    }
+                    raw
+                }
+                Piece::Data { id, bytes } => {
+                    if id == BACKUP_DATA {
+                        hasher.update(bytes);
+                        size += bytes.len() as u64;
+                    }
+                    bytes
+                }
+            };
+            if !skipping {
+                result = write_context.write(bytes, target);
+            }
+        });
+        result?;
+        report(size);
+    }
+    splitter.finish()?;
+
+    Ok((size, to_hex(&hasher.finalize())))
+}
+
+fn open_source(path: &Path, with_sacl: bool, flags: u32) -> ElevateResult<OwnedHandle> {
+    let share = FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE;
+
+    if with_sacl {
+        match open(path, GENERIC_READ | ACCESS_SYSTEM_SECURITY, share, OPEN_EXISTING, flags) {
+            // the SACL needs SeSecurityPrivilege; the rest can still be copied without it
+            Err(ElevateError::Windows { code, .. }) if code as u32 == ERROR_PRIVILEGE_NOT_HELD => {
+                debug!("Copying {} without its SACL", path.display());
+            }
+            result => return result,
+        }
+    }
+    open(path, GENERIC_READ, share, OPEN_EXISTING, flags)
+}
+
+fn open_target(path: &Path, is_dir: bool, with_security: bool, flags: u32) -> ElevateResult<OwnedHandle> {
+    let mut access = GENERIC_WRITE;
+    if with_security {
+        access |= WRITE_OWNER | WRITE_DAC | ACCESS_SYSTEM_SECURITY;
+    }
+    // directories can only be opened with backup semantics
+    let (disposition, flags) = if is_dir {
+        (OPEN_EXISTING, flags | FILE_FLAG_BACKUP_SEMANTICS)
+    } else {
+        (CREATE_ALWAYS, flags)
+    };
+    open(path, access, 0, disposition, flags)
+}
+
+fn open(path: &Path, access: u32, share: u32, disposition: u32, flags: u32) -> ElevateResult<OwnedHandle> {
+    let name: Vec<u16> = path_name(path).encode_utf16().chain(std::iter::once(0)).collect();
+    let handle = unsafe { CreateFileW(name.as_ptr(), access, share, null_mut(), disposition, flags, null_mut()) };
+    unsafe { OwnedHandle::try_from_raw(handle, HandleKind::File) }.ok_or_else(|| {
+        WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::FileOperation,
+            &format!("Failed to open {}", path.display()),
+        )
+    })
+}
+
+// BackupRead and BackupWrite keep state in a context that must be released
+// with a final call that sets bAbort
+struct BackupContext {
+    handle: HANDLE,
+    writing: bool,
+    process_security: i32,
+    context: *mut c_void,
+}
+
+impl BackupContext {
+    fn new(handle: HANDLE, writing: bool, process_security: i32) -> Self {
+        Self { handle, writing, process_security, context: null_mut() }
+    }
+
+    fn write(&mut self, bytes: &[u8], target: &Path) -> ElevateResult<()> {
+        let mut written = 0;
+        let ok = unsafe {
+            BackupWrite(
+                self.handle,
+                bytes.as_ptr() as *mut u8,
+                bytes.len() as u32,
+                &mut written,
+                0,
+                self.process_security,
+                &mut self.context,
+            )
+        };
+        if ok == 0 || written as usize != bytes.len() {
+            return Err(WindowsError::last_error().to_elevate_error(
+                WindowsErrorKind::FileOperation,
+                &format!("Failed to write {}", target.display()),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for BackupContext {
+    fn drop(&mut self) {
+        if self.context.is_null() {
+            return;
+        }
+        let mut count = 0;
+        unsafe {
+            if self.writing {
+                BackupWrite(self.handle, null_mut(), 0, &mut count, 1, self.process_security, &mut self.context);
+            } else {
+                BackupRead(self.handle, null_mut(), 0, &mut count, 1, self.process_security, &mut self.context);
+            }
+        }
+    }
+}
@@ -0,0 +1,9 @@
+#[cfg(windows)]
+pub mod backup_stream;
+#[cfg(windows)]
+pub mod copy;
+#[cfg(windows)]
+pub mod pending;
+pub mod pending_format;
+#[cfg(windows)]
+pub mod replace;
@@ -0,0 +1,119 @@
+use std::path::Path;
+use std::ptr::null;
+use winapi::um::winbase::{MoveFileExW, MOVEFILE_DELAY_UNTIL_REBOOT, MOVEFILE_REPLACE_EXISTING};
+
+use crate::core::elevation::impersonation::{TrustedInstallerImpersonation, SECURITY_PRIVILEGES};
+use crate::core::files::pending_format::{parse_operations, serialize_operations, PendingOperation};
+use crate::core::registry::manager::RegistryManager;
+use crate::core::registry::path::{KeyPath, RegistryView};
+use crate::core::registry::value::RegValue;
+use crate::utils::error::{ElevateError, ElevateResult, WindowsError, WindowsErrorKind};
+use crate::utils::files::{file_error, path_name};
+
+const SESSION_MANAGER_KEY: &str = r"HKLM\SYSTEM\CurrentControlSet\Control\Session Manager";
+const PENDING_VALUE: &str = "PendingFileRenameOperations";
+
+/// Reads and edits the file operations the session manager runs at boot.
+/// Edits go through `RegistryManager`, so the value is backed up first.
+pub struct PendingManager {
+    registry: RegistryManager,
+    key: KeyPath,
+}
+
+impl PendingManager {
+    pub fn new() -> ElevateResult<Self> {
+        Ok(Self {
+            registry: RegistryManager::new()?,
+            key: KeyPath::parse(SESSION_MANAGER_KEY)?,
+        })
+    }
+
+    pub fn list(&self) -> ElevateResult<Vec<PendingOperation>> {
+        match self.registry.get(&self.key, RegistryView::Native, PENDING_VALUE)? {
+            None => Ok(Vec::new()),
+            Some(RegValue::MultiString(strings)) => Ok(parse_operations(&strings)),
+            Some(other) => Err(ElevateError::ValidationError(format!(
+                "{} is {}, expected REG_MULTI_SZ",
+                PENDING_VALUE,
+                other.type_name()
+            ))),
+        }
+    }
+
+    /// Schedules deleting `source`, or moving it over `destination`
+    pub fn add(&self, source: &Path, destination: Option<&Path>) -> ElevateResult<()> {
+        let source = absolute(source)?;
+        let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+        match destination {
+            Some(destination) => schedule_move(&source, &absolute(destination)?),
+            None => schedule_delete(&source),
+        }
+    }
+
+    /// Removes the operation at a 1-based position of `list`
+    pub fn remove(&self, index: usize) -> ElevateResult<PendingOperation> {
+        let mut operations = self.list()?;
+        if index == 0 || index > operations.len() {
+            return Err(ElevateError::ValidationError(format!(
+                "No pending operation {}, there are {}",
+                index,
+                operations.len()
+            )));
+        }
+
+        let removed = operations.remove(index - 1);
+        if operations.is_empty() {
+            self.registry.delete_value(&self.key, RegistryView::Native, PENDING_VALUE)?;
+        } else {
+            let value = RegValue::MultiString(serialize_operations(&operations));
+            self.registry.set(&self.key, RegistryView::Native, PENDING_VALUE, &value)?;
+        }
+        Ok(removed)
+    }
+
+    /// Drops every pending operation, returning how many there were
+    pub fn clear(&self) -> ElevateResult<usize> {
+        let count = self.list()?.len();
+        self.registry.delete_value(&self.key, RegistryView::Native, PENDING_VALUE)?;
+        Ok(count)
+    }
+}
+
+/// Moves `from` over `to` during the next boot, before anything can lock `to`
+pub fn schedule_move(from: &Path, to: &Path) -> ElevateResult<()> {
+    let from_wide = to_wide(from);
+    let to_wide = to_wide(to);
+    let flags = MOVEFILE_DELAY_UNTIL_REBOOT | MOVEFILE_REPLACE_EXISTING;
+    if unsafe { MoveFileExW(from_wide.as_ptr(), to_wide.as_ptr(), flags) } == 0 {
+        return Err(WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::FileOperation,
+            &format!("Failed to schedule moving {} to {}", from.display(), to.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Deletes `path` during the next boot
+pub fn schedule_delete(path: &Path) -> ElevateResult<()> {
+    let path_wide = to_wide(path);
+    if unsafe { MoveFileExW(path_wide.as_ptr(), null(), MOVEFILE_DELAY_UNTIL_REBOOT) } == 0 {
+        return Err(WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::FileOperation,
+            &format!("Failed to schedule deleting {}", path.display()),
+        ));
+    }
+    Ok(())
+}
+
+// the session manager runs before any current directory exists
+fn absolute(path: &Path) -> ElevateResult<std::path::PathBuf> {
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+    let current = std::env::current_dir().map_err(|e| file_error(e, &format!("Failed to resolve {}", path.display())))?;
+    Ok(current.join(path))
+}
+
+fn to_wide(path: &Path) -> Vec<u16> {
+    path_name(path).encode_utf16().chain(std::iter::once(0)).collect()
+}
@@ -0,0 +1,160 @@
+//! The `PendingFileRenameOperations` value of the session manager. It is a
+//! `REG_MULTI_SZ` of source/destination pairs in NT form (`\??\C:\...`). An
+//! empty destination deletes the source, a `!` prefix replaces an existing file.
+
+use std::fmt;
+
+const NT_PREFIX: &str = r"\??\";
+const NT_UNC_PREFIX: &str = r"\??\UNC\";
+const REPLACE_MARKER: char = '!';
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PendingOperation {
+    Delete { path: String },
+    Rename { source: String, destination: String, replace: bool },
+}
+
+impl fmt::Display for PendingOperation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Delete { path } => write!(f, "delete  {}", path),
+            Self::Rename { source, destination, replace: false } => write!(f, "rename  {} -> {}", source, destination),
+            Self::Rename { source, destination, replace: true } => {
+                write!(f, "replace {} -> {}", source, destination)
+            }
+        }
+    }
+}
+
+/// Parses the strings of the value into operations with Win32 paths.
+/// A trailing source without destination is a delete, as the registry drops
+/// the empty string at the end.
+pub fn parse_operations(strings: &[String]) -> Vec<PendingOperation> {
+    strings
+        .chunks(2)
+        .map(|pair| {
+            let source = from_nt_path(&pair[0]);
+            match pair.get(1).map(String::as_str).unwrap_or("") {
+                "" => PendingOperation::Delete { path: source },
+                destination => match destination.strip_prefix(REPLACE_MARKER) {
+                    Some(destination) => PendingOperation::Rename {
+                        source,
+                        destination: from_nt_path(destination),
+                        replace: true,
+                    },
+                    None => PendingOperation::Rename {
+                        source,
+                        destination: from_nt_path(destination),
+                        replace: false,
+                    },
+                },
+            }
+        })
+        .collect()
+}
+
+/// Strings for the value, in the form the session manager expects
+pub fn serialize_operations(operations: &[PendingOperation]) -> Vec<String> {
+    operations
+        .iter()
+        .flat_map(|operation| match operation {
+            PendingOperation::Delete { path } => [to_nt_path(path), String::new()],
+            PendingOperation::Rename { source, destination, replace } => {
+                let marker = if *replace { "!" } else { "" };
+                [to_nt_path(source), format!("{}{}", marker, to_nt_path(destination))]
+            }
+        })
+        .collect()
+}
+
+fn from_nt_path(path: &str) -> String {
+    if let Some(rest) = path.strip_prefix(NT_UNC_PREFIX) {
+        format!(r"\\{}", rest)
+    } else {
+        path.strip_prefix(NT_PREFIX).unwrap_or(path).to_string()
+    }
+}
+
+fn to_nt_path(path: &str) -> String {
+    if path.starts_with(NT_PREFIX) {
+        path.to_string()
+    } else if let Some(rest) = path.strip_prefix(r"\\") {
+        format!("{}{}", NT_UNC_PREFIX, rest)
+    } else {
+        format!("{}{}", NT_PREFIX, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::registry::value::{RegValue, REG_MULTI_SZ};
+
+    fn raw(strings: &[&str]) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        for s in strings {
+            data.extend(s.encode_utf16().chain(std::iter::once(0)).flat_map(u16::to_le_bytes));
+        }
+        data.extend([0, 0]);
+        data
+    }
+
+    #[test]
+    fn test_parse_deletes_and_renames() {
+        // as written by MoveFileExW, ending in a delete whose empty destination
+        // looks like the list terminator
+        let data = raw(&[
+            r"\??\C:\Windows\Temp\a.tmp",
+            "",
+            r"\??\C:\Windows\System32\new.dll",
+            r"!\??\C:\Windows\System32\old.dll",
+            r"\??\UNC\server\share\x.txt",
+            r"\??\C:\y.txt",
+            r"\??\C:\last.tmp",
+            "",
+        ]);
+        let RegValue::MultiString(strings) = RegValue::from_raw(REG_MULTI_SZ, &data) else {
+            panic!("not a multi-string");
+        };
+
+        let operations = parse_operations(&strings);
+        assert_eq!(
+            operations,
+            vec![
+                PendingOperation::Delete { path: r"C:\Windows\Temp\a.tmp".into() },
+                PendingOperation::Rename {
+                    source: r"C:\Windows\System32\new.dll".into(),
+                    destination: r"C:\Windows\System32\old.dll".into(),
+                    replace: true,
+                },
+                PendingOperation::Rename {
+                    source: r"\\server\share\x.txt".into(),
+                    destination: r"C:\y.txt".into(),
+                    replace: false,
+                },
+                PendingOperation::Delete { path: r"C:\last.tmp".into() },
+            ]
+        );
+        assert_eq!(operations[1].to_string(), r"replace C:\Windows\System32\new.dll -> C:\Windows\System32\old.dll");
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let operations = vec![
+            PendingOperation::Rename {
+                source: r"\\server\share\x.txt".into(),
+                destination: r"C:\y.txt".into(),
+                replace: false,
+            },
+            PendingOperation::Delete { path: r"C:\last.tmp".into() },
+        ];
+        let strings = serialize_operations(&operations);
+        assert_eq!(strings, vec![r"\??\UNC\server\share\x.txt", r"\??\C:\y.txt", r"\??\C:\last.tmp", ""]);
+
+        let value = RegValue::MultiString(strings);
+        let RegValue::MultiString(read_back) = RegValue::from_raw(REG_MULTI_SZ, &value.to_raw()) else {
+            panic!("not a multi-string");
+        };
+        assert_eq!(parse_operations(&read_back), operations);
+    }
+}
@@ -0,0 +1,282 @@
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::os::windows::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+use winapi::shared::winerror::{ERROR_ACCESS_DENIED, ERROR_LOCK_VIOLATION, ERROR_SHARING_VIOLATION};
+use winapi::um::winnt::{FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE};
+
+use crate::core::elevation::impersonation::{TrustedInstallerImpersonation, SECURITY_PRIVILEGES};
+use crate::core::files::pending::{schedule_delete, schedule_move};
+use crate::core::security::descriptor::{apply_security, read_security, ObjectType};
+use crate::core::security::sddl::{Acl, SecurityDescriptor};
+use crate::utils::config::Config;
+use crate::utils::error::{ElevateError, ElevateResult};
+use crate::utils::files::{canonical_path, file_error, path_name};
+use crate::utils::hash::sha256_hex;
+use crate::utils::time::unix_timestamp;
+
+const BACKUP_FILE: &str = "replacements.json";
+const BACKUP_DIR: &str = "backups";
+const STAGED_SUFFIX: &str = ".elevatekit-new";
+const RETIRED_SUFFIX: &str = ".elevatekit-old";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BackupStore {
+    backups: Vec<ReplaceBackup>,
+}
+
+/// The original file and its security descriptor before `replace`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplaceBackup {
+    pub target: PathBuf,
+    /// copy of the original file in the state directory
+    pub backup: PathBuf,
+    pub sddl: String,
+    pub sha256: String,
+    /// seconds since the unix epoch
+    pub saved_at: u64,
+}
+
+#[derive(Debug)]
+pub enum ReplaceOutcome {
+    Replaced,
+    /// the target was in use; the staged file moves over it at the next boot
+    Scheduled { staged: PathBuf },
+}
+
+#[derive(Debug)]
+pub struct ReplaceReport {
+    pub backup: ReplaceBackup,
+    /// hash of the new file
+    pub sha256: String,
+    pub outcome: ReplaceOutcome,
+}
+
+/// Swaps protected files as TrustedInstaller, undoing every step on failure
+pub struct FileReplacer {
+    store_path: PathBuf,
+}
+
+impl FileReplacer {
+    pub fn new() -> ElevateResult<Self> {
+        Ok(Self {
+            store_path: Config::state_dir()?.join(BACKUP_FILE),
+        })
+    }
+
+    pub fn replace(&self, target: &Path, replacement: &Path) -> ElevateResult<ReplaceReport> {
+        let target = canonical_path(target)?;
+        let data = fs::read(replacement)
+            .map_err(|e| file_error(e, &format!("Failed to read {}", replacement.display())))?;
+        let sha256 = sha256_hex(&data);
+
+        let (original, original_data) = {
+            let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+            let original = read_security(&path_name(&target), ObjectType::File, false)?;
+            let original_data = fs::read(&target)
+                .map_err(|e| file_error(e, &format!("Failed to read {}", target.display())))?;
+            (original, original_data)
+        };
+        let backup = self.save_backup(&target, &original, &original_data)?;
+        info!("Saved {} to {}", target.display(), backup.backup.display());
+
+        let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+        let staged = sibling(&target, STAGED_SUFFIX);
+        let retired = sibling(&target, RETIRED_SUFFIX);
+        if let Err(e) = fs::write(&staged, &data) {
+            // a partly written file would be left next to the target
+            let _ = fs::remove_file(&staged);
+            return Err(file_error(e, &format!("Failed to write {}", staged.display())));
+        }
+
+        match fs::rename(&target, &retired) {
+            Ok(()) => {}
+            Err(e) if is_in_use(&e, || is_mapped_image(&target)) => {
+                warn!("{} is in use, replacing it at the next boot", target.display());
+                if let Err(e) = stage_for_reboot(&target, &staged, &original) {
+                    let _ = fs::remove_file(&staged);
+                    return Err(e);
+                }
+                return Ok(ReplaceReport { backup, sha256, outcome: ReplaceOutcome::Scheduled { staged } });
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&staged);
+                return Err(file_error(e, &format!("Failed to move {} aside", target.display())));
+            }
+        }
+
+        if let Err(e) = swap_in(&target, &staged, &original, &sha256) {
+            warn!("Replacing {} failed, rolling back: {}", target.display(), e);
+            roll_back(&target, &staged, &retired, &original, &backup);
+            return Err(e);
+        }
+
+        // a running process may still map the old file
+        if fs::remove_file(&retired).is_err() {
+            schedule_delete(&retired)?;
+            info!("{} is deleted at the next boot", retired.display());
+        }
+
+        Ok(ReplaceReport { backup, sha256, outcome: ReplaceOutcome::Replaced })
+    }
+
+    fn save_backup(&self, target: &Path, original: &SecurityDescriptor, data: &[u8]) -> ElevateResult<ReplaceBackup> {
+        let dir = Config::state_dir()?.join(BACKUP_DIR);
+        fs::create_dir_all(&dir).map_err(|e| file_error(e, &format!("Failed to create {}", dir.display())))?;
+
+        let saved_at = unix_timestamp();
+        let name = target.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let path = dir.join(format!("{}-{}", saved_at, name));
+        fs::write(&path, data).map_err(|e| file_error(e, &format!("Failed to write {}", path.display())))?;
+
+        let backup = ReplaceBackup {
+            target: target.to_path_buf(),
+            backup: path,
+            sddl: original.to_string(),
+            sha256: sha256_hex(data),
+            saved_at,
+        };
+        let mut store = self.load()?;
+        store.backups.push(backup.clone());
+        self.save(&store)?;
+        Ok(backup)
+    }
+
+    fn load(&self) -> ElevateResult<BackupStore> {
+        if !self.store_path.exists() {
+            return Ok(BackupStore::default());
+        }
+
+        let contents = fs::read_to_string(&self.store_path)
+            .map_err(|e| file_error(e, "Failed to read replacement backups"))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| ElevateError::ConfigError(format!("Failed to parse replacement backups: {}", e)))
+    }
+
+    fn save(&self, store: &BackupStore) -> ElevateResult<()> {
+        let contents = serde_json::to_string_pretty(store)
+            .map_err(|e| ElevateError::ConfigError(format!("Failed to serialize replacement backups: {}", e)))?;
+        fs::write(&self.store_path, contents).map_err(|e| file_error(e, "Failed to write replacement backups"))
+    }
+}
+
+fn swap_in(target: &Path, staged: &Path, original: &SecurityDescriptor, sha256: &str) -> ElevateResult<()> {
+    fs::rename(staged, target).map_err(|e| file_error(e, &format!("Failed to move {} into place", staged.display())))?;
+    apply_security(&path_name(target), ObjectType::File, &restorable(original))?;
+
+    let written = fs::read(target).map_err(|e| file_error(e, &format!("Failed to read {}", target.display())))?;
+    let actual = sha256_hex(&written);
+    if actual != sha256 {
+        return Err(ElevateError::ValidationError(format!(
+            "{} has SHA-256 {} after the swap, expected {}",
+            target.display(),
+            actual,
+            sha256
+        )));
+    }
+    Ok(())
+}
+
+fn stage_for_reboot(target: &Path, staged: &Path, original: &SecurityDescriptor) -> ElevateResult<()> {
+    // the move keeps the staged file's descriptor, so it gets the original one now
+    apply_security(&path_name(staged), ObjectType::File, &restorable(original))?;
+    schedule_move(staged, target)
+}
+
+// best effort: every step runs even if an earlier one failed
+fn roll_back(target: &Path, staged: &Path, retired: &Path, original: &SecurityDescriptor, backup: &ReplaceBackup) {
+    for new_file in [target, staged] {
+        if let Err(e) = fs::remove_file(new_file) {
+            if e.kind() != io::ErrorKind::NotFound {
+                error!("Failed to remove {}: {}", new_file.display(), e);
+            }
+        }
+    }
+
+    if let Err(e) = fs::rename(retired, target) {
+        error!(
+            "Failed to move {} back, the original is saved at {}: {}",
+            retired.display(),
+            backup.backup.display(),
+            e
+        );
+        return;
+    }
+    if let Err(e) = apply_security(&path_name(target), ObjectType::File, &restorable(original)) {
+        error!("Failed to restore the security of {}: {}", target.display(), e);
+    }
+}
+
+// inherited ACEs are recomputed by Windows from the parent
+fn restorable(original: &SecurityDescriptor) -> SecurityDescriptor {
+    SecurityDescriptor {
+        owner: original.owner.clone(),
+        group: original.group.clone(),
+        dacl: original.dacl.as_ref().map(Acl::without_inherited),
+        sacl: None,
+    }
+}
+
+// renaming a running image fails with access denied, which otherwise means
+// the rename is really not allowed and is reported
+fn is_in_use(error: &io::Error, mapped_image: impl FnOnce() -> bool) -> bool {
+    match error.raw_os_error().map(|code| code as u32) {
+        Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION) => true,
+        Some(ERROR_ACCESS_DENIED) => mapped_image(),
+        _ => false,
+    }
+}
+
+// an image section refuses writers even when every open handle shares writing
+fn is_mapped_image(path: &Path) -> bool {
+    let opened = OpenOptions::new()
+        .write(true)
+        .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE)
+        .open(path);
+    matches!(opened, Err(e) if e.raw_os_error() == Some(ERROR_SHARING_VIOLATION as i32))
+}
+
+// next to the target, so renames stay on one volume
+fn sibling(target: &Path, suffix: &str) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    target.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sibling_keeps_directory() {
+        let target = Path::new(r"C:\Windows\System32\drivers\etc\hosts");
+        assert_eq!(
+            sibling(target, STAGED_SUFFIX),
+            PathBuf::from(r"C:\Windows\System32\drivers\etc\hosts.elevatekit-new")
+        );
+        assert_eq!(sibling(Path::new("a.dll"), RETIRED_SUFFIX), PathBuf::from("a.dll.elevatekit-old"));
+    }
+
+    #[test]
+    fn test_running_images_count_as_in_use() {
+        let denied = io::Error::from_raw_os_error(ERROR_ACCESS_DENIED as i32);
+        assert!(is_in_use(&denied, || true));
+        assert!(!is_in_use(&denied, || false));
+        assert!(is_in_use(&io::Error::from_raw_os_error(ERROR_SHARING_VIOLATION as i32), || false));
+        assert!(is_in_use(&io::Error::from_raw_os_error(ERROR_LOCK_VIOLATION as i32), || false));
+        assert!(!is_in_use(&io::Error::from(io::ErrorKind::NotFound), || true));
+    }
+
+    #[test]
+    fn test_a_running_image_is_mapped() {
+        let exe = std::env::current_exe().unwrap();
+        assert!(is_mapped_image(&exe));
+
+        let data = std::env::temp_dir().join(format!("elevatekit-replace-{}.txt", std::process::id()));
+        fs::write(&data, b"data").unwrap();
+        assert!(!is_mapped_image(&data));
+        fs::remove_file(&data).unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+//! The one owner of kernel handles. Every handle we open is wrapped in an
+//! `OwnedHandle` right away and closed when it drops. Debug builds count the
+//! live handles of each kind, so tests and the end of `main` can report leaks.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ptr::null_mut;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use winapi::{
+    shared::winerror::WAIT_TIMEOUT,
+    um::{
+        handleapi::{CloseHandle, DuplicateHandle, SetHandleInformation, INVALID_HANDLE_VALUE},
+        processthreadsapi::GetCurrentProcess,
+        synchapi::WaitForSingleObject,
+        winbase::{HANDLE_FLAG_INHERIT, INFINITE, WAIT_OBJECT_0},
+        winnt::{DUPLICATE_SAME_ACCESS, HANDLE},
+    },
+};
+
+use crate::utils::error::{ElevateError, ElevateResult, WindowsError, WindowsErrorKind};
+
+/// What a handle refers to, for the leak report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HandleKind {
+    Process,
+    Thread,
+    Token,
+    Job,
+    File,
+    Snapshot,
+    /// service manager and service handles, closed with `CloseServiceHandle`
+    Service,
+    /// registry keys, closed with `RegCloseKey`
+    RegistryKey,
+}
+
+pub struct OwnedHandle {
+    raw: HANDLE,
+    kind: HandleKind,
+}
+
+// kernel handles can be used and closed from any thread
+unsafe impl Send for OwnedHandle {}
+unsafe impl Sync for OwnedHandle {}
+
+impl OwnedHandle {
+    /// Takes ownership of a handle that is known to be open
+    ///
+    /// # Safety
+    /// `raw` must be an open handle that nothing else closes.
+    pub unsafe fn from_raw(raw: HANDLE, kind: HandleKind) -> Self {
+        opened(kind);
+        Self { raw, kind }
+    }
+
+    /// Takes ownership of what an API returned, `None` if it returned null or
+    /// `INVALID_HANDLE_VALUE` to signal failure; last error is left untouched
+    ///
+    /// # Safety
+    /// As for `from_raw`, if the handle is valid.
+    pub unsafe fn try_from_raw(raw: HANDLE, kind: HandleKind) -> Option<Self> {
+        if raw.is_null() || raw == INVALID_HANDLE_VALUE {
+            None
+        } else {
+            Some(Self::from_raw(raw, kind))
+        }
+    }
+
+    /// Copies a handle we do not own, such as a pseudo handle or one a `File`
+    /// holds, into one we do
+    ///
+    /// # Safety
+    /// `raw` must be a valid handle, or a pseudo handle, while this runs.
+    pub unsafe fn duplicate_from(raw: HANDLE, kind: HandleKind) -> ElevateResult<Self> {
+        let current = unsafe { GetCurrentProcess() };
+        let mut copy = null_mut();
+        if unsafe { DuplicateHandle(current, raw, current, &mut copy, 0, 0, DUPLICATE_SAME_ACCESS) } == 0 {
+            return Err(handle_error("Failed to duplicate handle"));
+        }
+        Ok(unsafe { Self::from_raw(copy, kind) })
+    }
+
+    pub fn duplicate(&self) -> ElevateResult<Self> {
+        unsafe { Self::duplicate_from(self.raw, self.kind) }
+    }
+
+    /// Copies this handle into `process`, which needs `PROCESS_DUP_HANDLE`,
+    /// and returns its value there. That copy belongs to the other process.
+    pub fn duplicate_into(&self, process: &OwnedHandle, inheritable: bool) -> ElevateResult<HANDLE> {
+        duplicate_into(self.raw, process, inheritable)
+    }
+
+    /// Whether child processes created with handle inheritance get a copy
+    pub fn set_inheritable(&self, inheritable: bool) -> ElevateResult<()> {
+        let flags = if inheritable { HANDLE_FLAG_INHERIT } else { 0 };
+        if unsafe { SetHandleInformation(self.raw, HANDLE_FLAG_INHERIT, flags) } == 0 {
+            return Err(handle_error("Failed to change handle inheritance"));
+        }
+        Ok(())
+    }
+
+    /// Waits for a process, thread or other waitable object to be signalled,
+    /// forever without a timeout. False if the timeout passed first.
+    pub fn wait(&self, timeout: Option<Duration>) -> ElevateResult<bool> {
+        let millis = timeout.map_or(INFINITE, |timeout| timeout.as_millis().min(INFINITE as u128 - 1) as u32);
+        match unsafe { WaitForSingleObject(self.raw, millis) } {
+            WAIT_OBJECT_0 => Ok(true),
+            WAIT_TIMEOUT => Ok(false),
+            _ => Err(handle_error("Failed to wait for handle")),
+        }
+    }
+
+    /// The handle for API calls; it stays ours
+    pub fn as_raw(&self) -> HANDLE {
+        self.raw
+    }
+
+    /// Gives up ownership without closing the handle
+    pub fn into_raw(self) -> HANDLE {
+        let raw = self.raw;
+        closed(self.kind);
+        std::mem::forget(self);
+        raw
+    }
+
+    pub fn kind(&self) -> HandleKind {
+        self.kind
+    }
+}
+
+impl Drop for OwnedHandle {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.raw) };
+        closed(self.kind);
+    }
+}
+
+impl fmt::Debug for OwnedHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "OwnedHandle({:?} {:p})", self.kind, self.raw)
+    }
+}
+
+/// Copies a handle into `process` and returns its value there
+pub(crate) fn duplicate_into(raw: HANDLE, process: &OwnedHandle, inheritable: bool) -> ElevateResult<HANDLE> {
+    let mut remote = null_mut();
+    let ok = unsafe {
+        DuplicateHandle(GetCurrentProcess(), raw, process.raw, &mut remote, 0, inheritable as i32, DUPLICATE_SAME_ACCESS)
+    };
+    if ok == 0 {
+        return Err(handle_error("Failed to pass a handle to another process"));
+    }
+    Ok(remote)
+}
+
+fn handle_error(context: &str) -> ElevateError {
+    WindowsError::last_error().to_elevate_error(WindowsErrorKind::ProcessOperation, context)
+}
+
+/// Live handles per kind
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HandleCounts(BTreeMap<HandleKind, i64>);
+
+impl HandleCounts {
+    pub fn opened(&mut self, kind: HandleKind) {
+        *self.0.entry(kind).or_default() += 1;
+    }
+
+    pub fn closed(&mut self, kind: HandleKind) {
+        *self.0.entry(kind).or_default() -= 1;
+    }
+
+    pub fn live(&self, kind: HandleKind) -> i64 {
+        self.0.get(&kind).copied().unwrap_or(0)
+    }
+
+    /// Kinds with handles still open
+    pub fn leaks(&self) -> Vec<(HandleKind, i64)> {
+        self.0.iter().filter(|(_, &count)| count != 0).map(|(&kind, &count)| (kind, count)).collect()
+    }
+}
+
+// only updated in debug builds
+static LIVE: Mutex<HandleCounts> = Mutex::new(HandleCounts(BTreeMap::new()));
+
+/// Counts a handle of a kind `OwnedHandle` cannot close, such as a service handle
+pub fn opened(kind: HandleKind) {
+    if cfg!(debug_assertions) {
+        live().opened(kind);
+    }
+}
+
+pub fn closed(kind: HandleKind) {
+    if cfg!(debug_assertions) {
+        live().closed(kind);
+    }
+}
+
+/// Live handles of every kind; always empty in release builds
+pub fn live_handles() -> HandleCounts {
+    live().clone()
+}
+
+fn live() -> MutexGuard<'static, HandleCounts> {
+    LIVE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Logs every kind with handles still open, for the end of a run
+pub fn report_leaks() {
+    for (kind, count) in live_handles().leaks() {
+        tracing::warn!("{} {:?} handle(s) still open", count, kind);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::winbase::FILE_FLAG_DELETE_ON_CLOSE;
+
+    #[test]
+    fn test_counts_track_each_kind() {
+        let mut counts = HandleCounts::default();
+        counts.opened(HandleKind::Process);
+        counts.opened(HandleKind::Process);
+        counts.opened(HandleKind::Token);
+        counts.closed(HandleKind::Token);
+        counts.closed(HandleKind::Process);
+
+        assert_eq!(counts.live(HandleKind::Process), 1);
+        assert_eq!(counts.live(HandleKind::Token), 0);
+        assert_eq!(counts.live(HandleKind::Job), 0);
+        assert_eq!(counts.leaks(), vec![(HandleKind::Process, 1)]);
+    }
+
+    #[test]
+    fn test_owned_handle_closes_on_drop() {
+        // the file goes away with its last handle, so only our handles are
+        // watched, whatever other tests open meanwhile
+        let path = std::env::temp_dir().join(format!("elevatekit-handle-{}.tmp", std::process::id()));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .custom_flags(FILE_FLAG_DELETE_ON_CLOSE)
+            .open(&path)
+            .unwrap();
+        let handle = unsafe { OwnedHandle::duplicate_from(file.as_raw_handle() as _, HandleKind::File) }.unwrap();
+        drop(file);
+        let copy = handle.duplicate().unwrap();
+
+        drop(handle);
+        assert!(path.exists());
+        drop(copy);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,218 @@
+//! Exploit mitigations for elevated children. They are set through the
+//! attribute list the child is created with, so they hold from its first
+//! instruction, and read back afterwards since older systems ignore some.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem::size_of;
+use winapi::{
+    shared::{minwindef::DWORD, winerror::ERROR_INVALID_PARAMETER},
+    um::{
+        processthreadsapi::GetProcessMitigationPolicy,
+        winnt::{
+            ProcessControlFlowGuardPolicy, ProcessDynamicCodePolicy, ProcessImageLoadPolicy, ProcessSignaturePolicy,
+            ProcessStrictHandleCheckPolicy, PROCESS_MITIGATION_POLICY,
+        },
+    },
+};
+
+use crate::core::handle::OwnedHandle;
+use crate::utils::config::HardeningConfig;
+use crate::utils::error::{ElevateError, ElevateResult, WindowsError, WindowsErrorKind};
+
+/// Attribute taking the `u64` of `mitigation_policy`
+pub const PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY: usize = 0x0002_0007;
+/// Attribute taking the `DWORD` of `child_process_policy`
+pub const PROC_THREAD_ATTRIBUTE_CHILD_PROCESS_POLICY: usize = 0x0002_000E;
+
+const STRICT_HANDLE_CHECKS_ALWAYS_ON: u64 = 1 << 24;
+const PROHIBIT_DYNAMIC_CODE_ALWAYS_ON: u64 = 1 << 36;
+const CONTROL_FLOW_GUARD_ALWAYS_ON: u64 = 1 << 40;
+const BLOCK_NON_MICROSOFT_BINARIES_ALWAYS_ON: u64 = 1 << 44;
+const IMAGE_LOAD_NO_REMOTE_ALWAYS_ON: u64 = 1 << 52;
+const PROCESS_CREATION_CHILD_PROCESS_RESTRICTED: DWORD = 0x01;
+// newer than the policies winapi knows
+#[allow(non_upper_case_globals)]
+const ProcessChildProcessPolicy: PROCESS_MITIGATION_POLICY = 13;
+
+/// Presets available without any in the config
+pub const BUILT_IN_PRESETS: &[&str] = &["standard", "strict"];
+
+impl HardeningConfig {
+    /// Whether a run needs any attribute at all
+    pub fn is_empty(&self) -> bool {
+        *self == HardeningConfig::default()
+    }
+
+    /// Everything that is on in either
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            microsoft_signed_only: self.microsoft_signed_only || other.microsoft_signed_only,
+            no_remote_images: self.no_remote_images || other.no_remote_images,
+            no_child_processes: self.no_child_processes || other.no_child_processes,
+            strict_handle_checks: self.strict_handle_checks || other.strict_handle_checks,
+            control_flow_guard: self.control_flow_guard || other.control_flow_guard,
+            no_dynamic_code: self.no_dynamic_code || other.no_dynamic_code,
+        }
+    }
+
+    /// Names of what is on here but not in `actual`, such as mitigations a
+    /// child was asked for that the system did not apply
+    pub fn missing_from(&self, actual: &Self) -> Vec<&'static str> {
+        self.settings()
+            .into_iter()
+            .zip(actual.settings())
+            .filter(|((_, wanted), (_, applied))| *wanted && !applied)
+            .map(|((name, _), _)| name)
+            .collect()
+    }
+
+    fn settings(&self) -> [(&'static str, bool); 6] {
+        [
+            ("microsoft-signed-only", self.microsoft_signed_only),
+            ("no-remote-images", self.no_remote_images),
+            ("no-child-processes", self.no_child_processes),
+            ("strict-handle-checks", self.strict_handle_checks),
+            ("cfg", self.control_flow_guard),
+            ("acg", self.no_dynamic_code),
+        ]
+    }
+}
+
+impl fmt::Display for HardeningConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = self.settings().into_iter().filter(|(_, on)| *on).map(|(name, _)| name).collect();
+        if names.is_empty() {
+            return f.write_str("none");
+        }
+        f.write_str(&names.join(", "))
+    }
+}
+
+/// A preset by name, from the config first and the built-in ones otherwise
+pub fn preset(name: &str, presets: &BTreeMap<String, HardeningConfig>) -> ElevateResult<HardeningConfig> {
+    if let Some(config) = presets.get(name) {
+        return Ok(config.clone());
+    }
+
+    let standard = HardeningConfig {
+        no_remote_images: true,
+        strict_handle_checks: true,
+        control_flow_guard: true,
+        ..HardeningConfig::default()
+    };
+    match name {
+        "standard" => Ok(standard),
+        "strict" => Ok(HardeningConfig {
+            microsoft_signed_only: true,
+            no_child_processes: true,
+            no_dynamic_code: true,
+            ..standard
+        }),
+        _ => {
+            let mut known: Vec<_> = BUILT_IN_PRESETS.iter().map(|name| name.to_string()).collect();
+            known.extend(presets.keys().filter(|name| !BUILT_IN_PRESETS.contains(&name.as_str())).cloned());
+            Err(ElevateError::ConfigError(format!(
+                "Unknown hardening preset {}, expected one of: {}",
+                name,
+                known.join(", ")
+            )))
+        }
+    }
+}
+
+/// The value of `PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY`, zero if nothing is set
+pub fn mitigation_policy(config: &HardeningConfig) -> u64 {
+    let mut policy = 0;
+    if config.microsoft_signed_only {
+        policy |= BLOCK_NON_MICROSOFT_BINARIES_ALWAYS_ON;
+    }
+    if config.no_remote_images {
+        policy |= IMAGE_LOAD_NO_REMOTE_ALWAYS_ON;
+    }
+    if config.strict_handle_checks {
+        policy |= STRICT_HANDLE_CHECKS_ALWAYS_ON;
+    }
+    if config.control_flow_guard {
+        policy |= CONTROL_FLOW_GUARD_ALWAYS_ON;
+    }
+    if config.no_dynamic_code {
+        policy |= PROHIBIT_DYNAMIC_CODE_ALWAYS_ON;
+    }
+    policy
+}
+
+/// The value of `PROC_THREAD_ATTRIBUTE_CHILD_PROCESS_POLICY`, if it is needed
+pub fn child_process_policy(config: &HardeningConfig) -> Option<DWORD> {
+    config.no_child_processes.then_some(PROCESS_CREATION_CHILD_PROCESS_RESTRICTED)
+}
+
+/// The mitigations in effect for a process
+pub fn query(process: &OwnedHandle) -> ElevateResult<HardeningConfig> {
+    Ok(HardeningConfig {
+        microsoft_signed_only: policy_enabled(process, ProcessSignaturePolicy)?,
+        no_remote_images: policy_enabled(process, ProcessImageLoadPolicy)?,
+        no_child_processes: policy_enabled(process, ProcessChildProcessPolicy)?,
+        strict_handle_checks: policy_enabled(process, ProcessStrictHandleCheckPolicy)?,
+        control_flow_guard: policy_enabled(process, ProcessControlFlowGuardPolicy)?,
+        no_dynamic_code: policy_enabled(process, ProcessDynamicCodePolicy)?,
+    })
+}
+
+// each of these policies is a DWORD of flags whose lowest bit turns it on
+fn policy_enabled(process: &OwnedHandle, policy: PROCESS_MITIGATION_POLICY) -> ElevateResult<bool> {
+    let mut flags: DWORD = 0;
+    let ok = unsafe {
+        GetProcessMitigationPolicy(process.as_raw(), policy, &mut flags as *mut DWORD as *mut _, size_of::<DWORD>())
+    };
+    if ok == 0 {
+        let error = WindowsError::last_error();
+        // a policy this system does not know cannot be in effect
+        if error.code() == ERROR_INVALID_PARAMETER {
+            return Ok(false);
+        }
+        return Err(error.to_elevate_error(WindowsErrorKind::ProcessOperation, "Failed to query mitigation policy"));
+    }
+    Ok(flags & 1 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mitigation_policy_follows_the_config() {
+        assert_eq!(mitigation_policy(&HardeningConfig::default()), 0);
+        assert_eq!(child_process_policy(&HardeningConfig::default()), None);
+
+        let config = HardeningConfig {
+            microsoft_signed_only: true,
+            control_flow_guard: true,
+            no_child_processes: true,
+            ..HardeningConfig::default()
+        };
+        assert_eq!(mitigation_policy(&config), (1 << 44) | (1 << 40));
+        assert_eq!(child_process_policy(&config), Some(1));
+    }
+
+    #[test]
+    fn test_presets_come_from_the_config_first() {
+        let mut presets = BTreeMap::new();
+        presets.insert("standard".to_string(), HardeningConfig { no_dynamic_code: true, ..HardeningConfig::default() });
+
+        assert_eq!(preset("standard", &presets).unwrap().to_string(), "acg");
+        assert_eq!(preset("standard", &BTreeMap::new()).unwrap().to_string(), "no-remote-images, strict-handle-checks, cfg");
+        assert!(preset("strict", &presets).unwrap().no_child_processes);
+        assert!(preset("paranoid", &presets).is_err());
+    }
+
+    #[test]
+    fn test_missing_lists_what_did_not_apply() {
+        let wanted = HardeningConfig { no_remote_images: true, no_dynamic_code: true, ..HardeningConfig::default() };
+        let actual = HardeningConfig { no_remote_images: true, strict_handle_checks: true, ..HardeningConfig::default() };
+
+        assert_eq!(wanted.missing_from(&actual), vec!["acg"]);
+        assert!(wanted.missing_from(&wanted.union(&actual)).is_empty());
+        assert_eq!(HardeningConfig::default().to_string(), "none");
+    }
+}
@@ -0,0 +1,239 @@
+//! What a running process is: its image, session, token and protection.
+//! Protected and some system processes refuse even limited queries, so
+//! whatever cannot be read is left out and the process marked partial.
+
+use std::fmt;
+use serde::Serialize;
+use winapi::{
+    shared::{minwindef::DWORD, winerror::ERROR_INVALID_PARAMETER},
+    um::{
+        processthreadsapi::{GetProcessInformation, OpenProcess, ProcessIdToSessionId, PROCESS_INFORMATION_CLASS},
+        winbase::QueryFullProcessImageNameW,
+        winnt::{PROCESS_QUERY_LIMITED_INFORMATION, TOKEN_QUERY},
+    },
+};
+
+use crate::core::handle::{HandleKind, OwnedHandle};
+use crate::core::process::ProcessEntry;
+use crate::core::token::{open_process_token, token_elevated, token_groups, token_integrity, token_owner, token_user, Principal};
+use crate::utils::config::IntegrityLevel;
+use crate::utils::error::{ElevateError, ElevateResult, WindowsError, WindowsErrorKind};
+
+// newer than the classes winapi knows
+#[allow(non_upper_case_globals)]
+const ProcessProtectionLevelInfo: PROCESS_INFORMATION_CLASS = 7;
+const PROTECTION_LEVEL_NONE: DWORD = 0xFFFF_FFFE;
+
+/// The protection level of a process; anything but `None` cannot be opened
+/// for more than limited queries, not even with SeDebugPrivilege
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Protection {
+    None,
+    WintcbLight,
+    Windows,
+    WindowsLight,
+    AntimalwareLight,
+    LsaLight,
+    Wintcb,
+    CodegenLight,
+    Authenticode,
+    App,
+    Unknown,
+}
+
+impl Protection {
+    fn from_level(level: DWORD) -> Self {
+        match level {
+            PROTECTION_LEVEL_NONE => Protection::None,
+            0 => Protection::WintcbLight,
+            1 => Protection::Windows,
+            2 => Protection::WindowsLight,
+            3 => Protection::AntimalwareLight,
+            4 => Protection::LsaLight,
+            5 => Protection::Wintcb,
+            6 => Protection::CodegenLight,
+            7 => Protection::Authenticode,
+            8 => Protection::App,
+            _ => Protection::Unknown,
+        }
+    }
+
+    pub fn is_protected(self) -> bool {
+        self != Protection::None
+    }
+}
+
+impl fmt::Display for Protection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Protection::None => "none",
+            Protection::WintcbLight => "wintcb-light",
+            Protection::Windows => "windows",
+            Protection::WindowsLight => "windows-light",
+            Protection::AntimalwareLight => "antimalware-light",
+            Protection::LsaLight => "lsa-light",
+            Protection::Wintcb => "wintcb",
+            Protection::CodegenLight => "codegen-light",
+            Protection::Authenticode => "authenticode",
+            Protection::App => "app",
+            Protection::Unknown => "unknown",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A process with everything that could be read about it
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessDetails {
+    pub pid: u32,
+    pub parent_pid: u32,
+    pub name: String,
+    pub image: Option<String>,
+    pub session: Option<u32>,
+    pub user: Option<Principal>,
+    pub owner: Option<Principal>,
+    /// enabled groups, which is where service SIDs such as TrustedInstaller's are
+    #[serde(skip)]
+    pub groups: Vec<Principal>,
+    pub integrity: Option<IntegrityLevel>,
+    pub elevated: Option<bool>,
+    /// `None` on systems before Windows 8, which cannot tell
+    pub protection: Option<Protection>,
+    /// whether anything could not be read, mostly for lack of access
+    pub partial: bool,
+}
+
+impl ProcessDetails {
+    /// Whether the user, owner or an enabled group matches, as `Principal::matches`
+    pub fn runs_as(&self, pattern: &str) -> bool {
+        self.user.iter().chain(&self.owner).chain(&self.groups).any(|principal| principal.matches(pattern))
+    }
+}
+
+/// Reads what it can about a process from the snapshot
+pub fn inspect(entry: ProcessEntry) -> ProcessDetails {
+    let mut partial = false;
+    let mut session = 0;
+    let session = read(&mut partial, if unsafe { ProcessIdToSessionId(entry.pid, &mut session) } != 0 {
+        Ok(session)
+    } else {
+        Err(process_error("Failed to read the session of the process"))
+    });
+
+    let process = read(&mut partial, open_limited(entry.pid));
+    let (image, protection) = match &process {
+        Some(process) => (read(&mut partial, image_path(process)), read(&mut partial, protection(process)).flatten()),
+        None => (None, None),
+    };
+    let token = process.as_ref().and_then(|process| read(&mut partial, open_process_token(process.as_raw(), TOKEN_QUERY)));
+    let (user, owner, groups, integrity, elevated) = match &token {
+        Some(token) => (
+            read(&mut partial, token_user(token)),
+            read(&mut partial, token_owner(token)),
+            read(&mut partial, token_groups(token)).unwrap_or_default(),
+            read(&mut partial, token_integrity(token)),
+            read(&mut partial, token_elevated(token)),
+        ),
+        None => (None, None, Vec::new(), None, None),
+    };
+
+    ProcessDetails {
+        pid: entry.pid,
+        parent_pid: entry.parent_pid,
+        name: entry.name,
+        image,
+        session,
+        user,
+        owner,
+        groups,
+        integrity,
+        elevated,
+        protection,
+        partial,
+    }
+}
+
+/// The protection level of a process, `None` where the system cannot tell
+pub fn protection(process: &OwnedHandle) -> ElevateResult<Option<Protection>> {
+    let mut level: DWORD = 0;
+    let ok = unsafe {
+        GetProcessInformation(
+            process.as_raw(),
+            ProcessProtectionLevelInfo,
+            &mut level as *mut DWORD as *mut _,
+            std::mem::size_of::<DWORD>() as DWORD,
+        )
+    };
+    if ok == 0 {
+        let error = WindowsError::last_error();
+        if error.code() == ERROR_INVALID_PARAMETER {
+            return Ok(None);
+        }
+        return Err(error.to_elevate_error(WindowsErrorKind::ProcessOperation, "Failed to read process protection"));
+    }
+    Ok(Some(Protection::from_level(level)))
+}
+
+/// Opens a process for the queries even protected processes allow
+pub fn open_limited(pid: u32) -> ElevateResult<OwnedHandle> {
+    unsafe { OwnedHandle::try_from_raw(OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid), HandleKind::Process) }
+        .ok_or_else(|| process_error(&format!("Failed to open process {}", pid)))
+}
+
+fn image_path(process: &OwnedHandle) -> ElevateResult<String> {
+    let mut buffer = vec![0u16; 32768];
+    let mut len = buffer.len() as DWORD;
+    if unsafe { QueryFullProcessImageNameW(process.as_raw(), 0, buffer.as_mut_ptr(), &mut len) } == 0 {
+        return Err(process_error("Failed to read the image path of the process"));
+    }
+    Ok(String::from_utf16_lossy(&buffer[..len as usize]))
+}
+
+// notes what could not be read instead of failing the whole process
+fn read<T>(partial: &mut bool, result: ElevateResult<T>) -> Option<T> {
+    *partial |= result.is_err();
+    result.ok()
+}
+
+fn process_error(context: &str) -> ElevateError {
+    WindowsError::last_error().to_elevate_error(WindowsErrorKind::ProcessOperation, context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protection_levels_decode() {
+        assert_eq!(Protection::from_level(PROTECTION_LEVEL_NONE), Protection::None);
+        assert_eq!(Protection::from_level(3).to_string(), "antimalware-light");
+        assert!(Protection::from_level(5).is_protected());
+        assert!(!Protection::None.is_protected());
+    }
+
+    #[test]
+    fn test_runs_as_looks_at_groups_too() {
+        let details = ProcessDetails {
+            pid: 4242,
+            parent_pid: 700,
+            name: "cmd.exe".into(),
+            image: None,
+            session: Some(0),
+            user: Some(Principal { name: r"NT AUTHORITY\SYSTEM".into(), sid: "S-1-5-18".into() }),
+            owner: None,
+            groups: vec![Principal {
+                name: r"NT SERVICE\TrustedInstaller".into(),
+                sid: "S-1-5-80-956008885-3418522649-1831038044-1853292631-2271478464".into(),
+            }],
+            integrity: Some(IntegrityLevel::System),
+            elevated: Some(true),
+            protection: Some(Protection::None),
+            partial: true,
+        };
+
+        assert!(details.runs_as("TrustedInstaller"));
+        assert!(details.runs_as("S-1-5-18"));
+        assert!(!details.runs_as("Administrators"));
+    }
+}
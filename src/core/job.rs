@@ -0,0 +1,266 @@
+//! Job objects for elevated children. The child is assigned while it is still
+//! suspended, so it cannot start anything outside the job, and the limits and
+//! accounting cover every process it starts.
+
+use std::fmt;
+use std::mem::{size_of, zeroed};
+use std::ptr::null_mut;
+use std::time::Duration;
+use winapi::{
+    shared::minwindef::DWORD,
+    um::{
+        jobapi2::{AssignProcessToJobObject, CreateJobObjectW, QueryInformationJobObject, SetInformationJobObject, TerminateJobObject},
+        winnt::*,
+    },
+};
+
+use crate::core::handle::{HandleKind, OwnedHandle};
+use crate::utils::config::JobConfig;
+use crate::utils::error::{ElevateError, ElevateResult, WindowsError, WindowsErrorKind};
+
+const MEGABYTE: u64 = 1024 * 1024;
+// FILETIME-style counters are in 100 ns ticks
+const TICKS_PER_SECOND: u64 = 10_000_000;
+
+impl JobConfig {
+    /// Whether a run needs a job at all
+    pub fn is_empty(&self) -> bool {
+        *self == JobConfig::default()
+    }
+}
+
+/// `LimitFlags` of the extended limit information for these settings
+pub fn limit_flags(config: &JobConfig) -> DWORD {
+    let mut flags = 0;
+    if config.kill_on_close {
+        flags |= JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+    }
+    if config.memory_limit.is_some() {
+        flags |= JOB_OBJECT_LIMIT_JOB_MEMORY;
+    }
+    if config.max_processes.is_some() {
+        flags |= JOB_OBJECT_LIMIT_ACTIVE_PROCESS;
+    }
+    flags
+}
+
+/// A CPU cap in percent as the job API wants it, in hundredths of a percent
+pub fn cpu_rate(percent: u32) -> ElevateResult<u32> {
+    match percent {
+        1..=100 => Ok(percent * 100),
+        _ => Err(ElevateError::ValidationError(format!(
+            "CPU rate must be between 1 and 100 percent, got {}",
+            percent
+        ))),
+    }
+}
+
+/// A memory limit in megabytes as the byte count a job takes
+pub fn memory_limit(megabytes: u64) -> ElevateResult<usize> {
+    megabytes
+        .checked_mul(MEGABYTE)
+        .and_then(|bytes| usize::try_from(bytes).ok())
+        .ok_or_else(|| ElevateError::ValidationError(format!("Memory limit of {} MB is too large", megabytes)))
+}
+
+/// What the processes of a job used, across all of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobAccounting {
+    pub peak_memory: u64,
+    pub user_time: Duration,
+    pub kernel_time: Duration,
+    /// every process that ran in the job, including ones that have exited
+    pub total_processes: u32,
+}
+
+impl JobAccounting {
+    pub fn cpu_time(&self) -> Duration {
+        self.user_time + self.kernel_time
+    }
+}
+
+impl fmt::Display for JobAccounting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "peak memory {:.1} MB, CPU {:.2}s (user {:.2}s, kernel {:.2}s), {} process(es)",
+            self.peak_memory as f64 / MEGABYTE as f64,
+            self.cpu_time().as_secs_f64(),
+            self.user_time.as_secs_f64(),
+            self.kernel_time.as_secs_f64(),
+            self.total_processes
+        )
+    }
+}
+
+/// An anonymous job; closing it kills its processes when `kill_on_close` is set
+pub struct JobObject {
+    handle: OwnedHandle,
+}
+
+impl JobObject {
+    pub fn create(config: &JobConfig) -> ElevateResult<Self> {
+        let handle = unsafe { OwnedHandle::try_from_raw(CreateJobObjectW(null_mut(), null_mut()), HandleKind::Job) }
+            .ok_or_else(|| job_error("Failed to create job object"))?;
+        let job = Self { handle };
+
+        let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { zeroed() };
+        limits.BasicLimitInformation.LimitFlags = limit_flags(config);
+        if let Some(megabytes) = config.memory_limit {
+            limits.JobMemoryLimit = memory_limit(megabytes)?;
+        }
+        if let Some(count) = config.max_processes {
+            limits.BasicLimitInformation.ActiveProcessLimit = count;
+        }
+        job.set(JobObjectExtendedLimitInformation, &mut limits, "Failed to set job limits")?;
+
+        if let Some(percent) = config.cpu_rate {
+            let mut rate: JOBOBJECT_CPU_RATE_CONTROL_INFORMATION = unsafe { zeroed() };
+            rate.ControlFlags = JOB_OBJECT_CPU_RATE_CONTROL_ENABLE | JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP;
+            unsafe { *rate.u.CpuRate_mut() = cpu_rate(percent)? };
+            job.set(JobObjectCpuRateControlInformation, &mut rate, "Failed to set job CPU rate")?;
+        }
+
+        if config.restrict_ui {
+            let mut ui = JOBOBJECT_BASIC_UI_RESTRICTIONS { UIRestrictionsClass: JOB_OBJECT_UILIMIT_ALL };
+            job.set(JobObjectBasicUIRestrictions, &mut ui, "Failed to set job UI restrictions")?;
+        }
+
+        Ok(job)
+    }
+
+    /// Puts a process into the job; do it before resuming a suspended child
+    pub fn assign(&self, process: &OwnedHandle) -> ElevateResult<()> {
+        if unsafe { AssignProcessToJobObject(self.handle.as_raw(), process.as_raw()) } == 0 {
+            return Err(job_error("Failed to assign the process to its job"));
+        }
+        Ok(())
+    }
+
+    /// Ends every process in the job
+    pub fn terminate(&self, exit_code: u32) -> ElevateResult<()> {
+        if unsafe { TerminateJobObject(self.handle.as_raw(), exit_code) } == 0 {
+            return Err(job_error("Failed to terminate job"));
+        }
+        Ok(())
+    }
+
+    pub fn accounting(&self) -> ElevateResult<JobAccounting> {
+        let mut basic: JOBOBJECT_BASIC_AND_IO_ACCOUNTING_INFORMATION = unsafe { zeroed() };
+        self.query(JobObjectBasicAndIoAccountingInformation, &mut basic, "Failed to query job accounting")?;
+        let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { zeroed() };
+        self.query(JobObjectExtendedLimitInformation, &mut limits, "Failed to query job memory use")?;
+
+        let (user, kernel) = unsafe {
+            (*basic.BasicInfo.TotalUserTime.QuadPart(), *basic.BasicInfo.TotalKernelTime.QuadPart())
+        };
+        Ok(JobAccounting {
+            peak_memory: limits.PeakJobMemoryUsed as u64,
+            user_time: ticks(user),
+            kernel_time: ticks(kernel),
+            total_processes: basic.BasicInfo.TotalProcesses,
+        })
+    }
+
+    fn set<T>(&self, class: JOBOBJECTINFOCLASS, info: &mut T, context: &str) -> ElevateResult<()> {
+        let ok = unsafe {
+            SetInformationJobObject(self.handle.as_raw(), class, info as *mut T as *mut _, size_of::<T>() as DWORD)
+        };
+        if ok == 0 {
+            return Err(job_error(context));
+        }
+        Ok(())
+    }
+
+    fn query<T>(&self, class: JOBOBJECTINFOCLASS, info: &mut T, context: &str) -> ElevateResult<()> {
+        let ok = unsafe {
+            QueryInformationJobObject(self.handle.as_raw(), class, info as *mut T as *mut _, size_of::<T>() as DWORD, null_mut())
+        };
+        if ok == 0 {
+            return Err(job_error(context));
+        }
+        Ok(())
+    }
+}
+
+fn ticks(count: i64) -> Duration {
+    let count = count.max(0) as u64;
+    Duration::from_secs(count / TICKS_PER_SECOND) + Duration::from_nanos(count % TICKS_PER_SECOND * 100)
+}
+
+fn job_error(context: &str) -> ElevateError {
+    WindowsError::last_error().to_elevate_error(WindowsErrorKind::ProcessOperation, context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::handle::live_handles;
+
+    #[test]
+    fn test_limit_flags_follow_the_config() {
+        assert_eq!(limit_flags(&JobConfig::default()), 0);
+
+        let config = JobConfig {
+            kill_on_close: true,
+            memory_limit: Some(512),
+            max_processes: Some(4),
+            ..JobConfig::default()
+        };
+        assert_eq!(
+            limit_flags(&config),
+            JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE | JOB_OBJECT_LIMIT_JOB_MEMORY | JOB_OBJECT_LIMIT_ACTIVE_PROCESS
+        );
+    }
+
+    #[test]
+    fn test_cpu_rate_is_in_hundredths_of_a_percent() {
+        assert_eq!(cpu_rate(1).unwrap(), 100);
+        assert_eq!(cpu_rate(100).unwrap(), 10000);
+        assert!(cpu_rate(0).is_err());
+        assert!(cpu_rate(101).is_err());
+    }
+
+    #[test]
+    fn test_memory_limit_rejects_overflow() {
+        assert_eq!(memory_limit(512).unwrap(), 512 * 1024 * 1024);
+        assert!(memory_limit(u64::MAX).is_err());
+        assert!(memory_limit(u64::MAX / MEGABYTE + 1).is_err());
+    }
+
+    #[test]
+    fn test_empty_config_needs_no_job() {
+        assert!(JobConfig::default().is_empty());
+        assert!(!JobConfig { restrict_ui: true, ..JobConfig::default() }.is_empty());
+    }
+
+    #[test]
+    fn test_accounting_converts_ticks() {
+        let accounting = JobAccounting {
+            peak_memory: 3 * MEGABYTE / 2,
+            user_time: ticks(12_500_000),
+            kernel_time: ticks(2_500_000),
+            total_processes: 2,
+        };
+        assert_eq!(accounting.user_time, Duration::from_millis(1250));
+        assert_eq!(accounting.cpu_time(), Duration::from_millis(1500));
+        assert_eq!(
+            accounting.to_string(),
+            "peak memory 1.5 MB, CPU 1.50s (user 1.25s, kernel 0.25s), 2 process(es)"
+        );
+    }
+
+    #[test]
+    fn test_job_handle_is_counted_until_dropped() {
+        // no other test opens jobs, so the count is ours alone
+        let before = live_handles().live(HandleKind::Job);
+        let job = JobObject::create(&JobConfig { max_processes: Some(1), ..JobConfig::default() }).unwrap();
+        assert_eq!(job.accounting().unwrap().total_processes, 0);
+        if cfg!(debug_assertions) {
+            assert_eq!(live_handles().live(HandleKind::Job), before + 1);
+        }
+
+        drop(job);
+        assert_eq!(live_handles().live(HandleKind::Job), before);
+    }
+}
@@ -0,0 +1,319 @@
+//! How an elevated child starts: priority class, processor affinity, window
+//! state and console. Everything but the affinity goes into the creation
+//! flags and `STARTUPINFO`; the affinity is set while the child is suspended.
+
+use std::str::FromStr;
+use winapi::{
+    ctypes::c_int,
+    shared::minwindef::{BOOL, DWORD},
+    um::{
+        processtopologyapi::SetThreadGroupAffinity,
+        winbase::{
+            ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS, CREATE_BREAKAWAY_FROM_JOB,
+            CREATE_NEW_CONSOLE, CREATE_NO_WINDOW, DETACHED_PROCESS, HIGH_PRIORITY_CLASS, IDLE_PRIORITY_CLASS,
+            NORMAL_PRIORITY_CLASS, REALTIME_PRIORITY_CLASS,
+        },
+        winnt::{GROUP_AFFINITY, HANDLE},
+        winuser::{SW_HIDE, SW_SHOWMAXIMIZED, SW_SHOWMINNOACTIVE},
+    },
+};
+
+use crate::core::process::ProcessInfo;
+use crate::utils::config::LaunchFlag;
+use crate::utils::error::{ElevateError, ElevateResult, WindowsError, WindowsErrorKind};
+
+// winapi declares the mask as a DWORD, which would cut it to 32 processors
+extern "system" {
+    #[link_name = "SetProcessAffinityMask"]
+    fn set_process_affinity_mask(process: HANDLE, mask: usize) -> BOOL;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Idle,
+    BelowNormal,
+    Normal,
+    AboveNormal,
+    High,
+    /// needs SeIncreaseBasePriorityPrivilege, which TrustedInstaller has; High otherwise
+    Realtime,
+}
+
+impl Priority {
+    pub fn class(self) -> DWORD {
+        match self {
+            Priority::Idle => IDLE_PRIORITY_CLASS,
+            Priority::BelowNormal => BELOW_NORMAL_PRIORITY_CLASS,
+            Priority::Normal => NORMAL_PRIORITY_CLASS,
+            Priority::AboveNormal => ABOVE_NORMAL_PRIORITY_CLASS,
+            Priority::High => HIGH_PRIORITY_CLASS,
+            Priority::Realtime => REALTIME_PRIORITY_CLASS,
+        }
+    }
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('_', "-").as_str() {
+            "idle" => Ok(Priority::Idle),
+            "below-normal" => Ok(Priority::BelowNormal),
+            "normal" => Ok(Priority::Normal),
+            "above-normal" => Ok(Priority::AboveNormal),
+            "high" => Ok(Priority::High),
+            "realtime" => Ok(Priority::Realtime),
+            _ => Err(format!(
+                "unknown priority \"{}\", expected idle, below-normal, normal, above-normal, high or realtime",
+                s
+            )),
+        }
+    }
+}
+
+/// How the child's first window is shown, if it makes one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowState {
+    Hidden,
+    Minimized,
+    Maximized,
+}
+
+impl WindowState {
+    /// For `STARTUPINFO.wShowWindow`
+    pub fn show_command(self) -> u16 {
+        let command: c_int = match self {
+            WindowState::Hidden => SW_HIDE,
+            // minimized without taking the focus from the caller
+            WindowState::Minimized => SW_SHOWMINNOACTIVE,
+            WindowState::Maximized => SW_SHOWMAXIMIZED,
+        };
+        command as u16
+    }
+}
+
+/// Which console the child gets, when not the default for how it is run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleMode {
+    /// a console without a window
+    NoWindow,
+    /// no console at all
+    Detached,
+    NewConsole,
+}
+
+impl ConsoleMode {
+    pub fn flag(self) -> DWORD {
+        match self {
+            ConsoleMode::NoWindow => CREATE_NO_WINDOW,
+            ConsoleMode::Detached => DETACHED_PROCESS,
+            ConsoleMode::NewConsole => CREATE_NEW_CONSOLE,
+        }
+    }
+}
+
+/// Processors the child may run on, optionally in one processor group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Affinity {
+    pub group: Option<u16>,
+    pub mask: u64,
+}
+
+impl FromStr for Affinity {
+    type Err = String;
+
+    /// A mask such as `0x0f` or `15`, or `GROUP:MASK` such as `1:0xff`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (group, mask) = match s.split_once(':') {
+            Some((group, mask)) => {
+                let group = group.trim().parse().map_err(|_| format!("invalid processor group \"{}\"", group))?;
+                (Some(group), mask)
+            }
+            None => (None, s),
+        };
+        let mask = mask.trim();
+        let parsed = match mask.strip_prefix("0x").or_else(|| mask.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => mask.parse(),
+        };
+        match parsed {
+            Ok(0) => Err("affinity mask must include at least one processor".to_string()),
+            Ok(mask) => Ok(Self { group, mask }),
+            Err(_) => Err(format!("invalid affinity mask \"{}\", expected a number such as 0x0f", mask)),
+        }
+    }
+}
+
+/// Console buffer size in character cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsoleSize {
+    pub columns: u32,
+    pub rows: u32,
+}
+
+impl FromStr for ConsoleSize {
+    type Err = String;
+
+    /// `COLUMNSxROWS`, such as `120x40`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid console size \"{}\", expected COLUMNSxROWS such as 120x40", s);
+        let (columns, rows) = s.split_once(['x', 'X']).ok_or_else(invalid)?;
+        let (columns, rows) = (columns.trim().parse().map_err(|_| invalid())?, rows.trim().parse().map_err(|_| invalid())?);
+        if columns == 0 || rows == 0 || columns > i16::MAX as u32 || rows > i16::MAX as u32 {
+            return Err(invalid());
+        }
+        Ok(Self { columns, rows })
+    }
+}
+
+/// Launch options for an elevated child; anything unset keeps the default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LaunchSettings {
+    pub priority: Option<Priority>,
+    pub affinity: Option<Affinity>,
+    pub window: Option<WindowState>,
+    pub console: Option<ConsoleMode>,
+    pub title: Option<String>,
+    pub console_size: Option<ConsoleSize>,
+    /// start outside the job the parent is in, if that job allows it
+    pub breakaway_from_job: bool,
+}
+
+impl LaunchSettings {
+    /// Settings from the flags of a config; at most one of each kind
+    pub fn from_flags(flags: &[LaunchFlag]) -> ElevateResult<Self> {
+        let mut settings = Self::default();
+        for &flag in flags {
+            let (slot, previous) = match flag {
+                LaunchFlag::IdlePriority => ("priority", settings.priority.replace(Priority::Idle).is_some()),
+                LaunchFlag::BelowNormalPriority => ("priority", settings.priority.replace(Priority::BelowNormal).is_some()),
+                LaunchFlag::NormalPriority => ("priority", settings.priority.replace(Priority::Normal).is_some()),
+                LaunchFlag::AboveNormalPriority => ("priority", settings.priority.replace(Priority::AboveNormal).is_some()),
+                LaunchFlag::HighPriority => ("priority", settings.priority.replace(Priority::High).is_some()),
+                LaunchFlag::RealtimePriority => ("priority", settings.priority.replace(Priority::Realtime).is_some()),
+                LaunchFlag::Hidden => ("window", settings.window.replace(WindowState::Hidden).is_some()),
+                LaunchFlag::Minimized => ("window", settings.window.replace(WindowState::Minimized).is_some()),
+                LaunchFlag::Maximized => ("window", settings.window.replace(WindowState::Maximized).is_some()),
+                LaunchFlag::NoWindow => ("console", settings.console.replace(ConsoleMode::NoWindow).is_some()),
+                LaunchFlag::Detached => ("console", settings.console.replace(ConsoleMode::Detached).is_some()),
+                LaunchFlag::NewConsole => ("console", settings.console.replace(ConsoleMode::NewConsole).is_some()),
+                LaunchFlag::BreakawayFromJob => {
+                    settings.breakaway_from_job = true;
+                    continue;
+                }
+            };
+            if previous {
+                return Err(ElevateError::ValidationError(format!(
+                    "creation_flags may only hold one {} flag, got {}",
+                    slot,
+                    join(flags)
+                )));
+            }
+        }
+        Ok(settings)
+    }
+
+    /// These settings with every one that `other` sets taken from it instead
+    pub fn overridden_by(&self, other: &Self) -> Self {
+        Self {
+            priority: other.priority.or(self.priority),
+            affinity: other.affinity.or(self.affinity),
+            window: other.window.or(self.window),
+            console: other.console.or(self.console),
+            title: other.title.clone().or_else(|| self.title.clone()),
+            console_size: other.console_size.or(self.console_size),
+            breakaway_from_job: self.breakaway_from_job || other.breakaway_from_job,
+        }
+    }
+
+    /// Creation flags besides the console, which the caller picks when unset
+    pub fn creation_flags(&self) -> DWORD {
+        let mut flags = self.priority.map_or(0, Priority::class);
+        if self.breakaway_from_job {
+            flags |= CREATE_BREAKAWAY_FROM_JOB;
+        }
+        flags
+    }
+
+    /// Sets the affinity of a child that is still suspended. Without a group
+    /// the mask is within the group the child started in.
+    pub fn apply_affinity(&self, child: &ProcessInfo) -> ElevateResult<()> {
+        let Some(affinity) = self.affinity else {
+            return Ok(());
+        };
+        let ok = match affinity.group {
+            None => unsafe { set_process_affinity_mask(child.process().as_raw(), affinity.mask as usize) },
+            // the process mask cannot span groups, so the main thread moves instead
+            Some(group) => {
+                let target = GROUP_AFFINITY { Mask: affinity.mask as usize, Group: group, Reserved: [0; 3] };
+                unsafe { SetThreadGroupAffinity(child.thread().as_raw(), &target, std::ptr::null_mut()) }
+            }
+        };
+        if ok == 0 {
+            return Err(WindowsError::last_error().to_elevate_error(
+                WindowsErrorKind::ProcessOperation,
+                &format!("Failed to set processor affinity {:#x}", affinity.mask),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn join(flags: &[LaunchFlag]) -> String {
+    flags.iter().map(|flag| flag.name()).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::config::ProcessConfig;
+
+    #[test]
+    fn test_flags_are_read_by_name() {
+        let config: ProcessConfig = serde_json::from_str(
+            r#"{ "default_shell": "cmd.exe", "timeout": null, "creation_flags": ["no_window", "below_normal_priority"] }"#,
+        )
+        .unwrap();
+        assert_eq!(config.creation_flags, vec![LaunchFlag::NoWindow, LaunchFlag::BelowNormalPriority]);
+
+        let settings = LaunchSettings::from_flags(&config.creation_flags).unwrap();
+        assert_eq!(settings.console, Some(ConsoleMode::NoWindow));
+        assert_eq!(settings.creation_flags(), BELOW_NORMAL_PRIORITY_CLASS);
+    }
+
+    #[test]
+    fn test_bad_flags_are_named_in_the_error() {
+        let parse = |flags: &str| {
+            let json = format!(r#"{{ "default_shell": "cmd.exe", "timeout": null, "creation_flags": {} }}"#, flags);
+            serde_json::from_str::<ProcessConfig>(&json).map(|config| config.creation_flags)
+        };
+        assert!(parse("0").unwrap().is_empty());
+        assert!(parse("16").unwrap_err().to_string().contains("write flags by name"));
+        assert!(parse(r#"["no_windows"]"#).unwrap_err().to_string().contains("unknown launch flag \"no_windows\""));
+
+        let error = LaunchSettings::from_flags(&[LaunchFlag::Hidden, LaunchFlag::Maximized]).unwrap_err();
+        assert!(error.to_string().contains("one window flag"));
+    }
+
+    #[test]
+    fn test_affinity_and_console_size_parse() {
+        assert_eq!("0x0f".parse::<Affinity>().unwrap(), Affinity { group: None, mask: 15 });
+        assert_eq!("1:255".parse::<Affinity>().unwrap(), Affinity { group: Some(1), mask: 255 });
+        assert!("0".parse::<Affinity>().is_err());
+        assert!("cores".parse::<Affinity>().is_err());
+
+        assert_eq!("120x40".parse::<ConsoleSize>().unwrap(), ConsoleSize { columns: 120, rows: 40 });
+        assert!("120".parse::<ConsoleSize>().is_err());
+        assert!("0x40".parse::<ConsoleSize>().is_err());
+    }
+
+    #[test]
+    fn test_run_settings_override_the_config() {
+        let config = LaunchSettings::from_flags(&[LaunchFlag::IdlePriority, LaunchFlag::BreakawayFromJob]).unwrap();
+        let run = LaunchSettings { priority: Some(Priority::High), ..LaunchSettings::default() };
+
+        let merged = config.overridden_by(&run);
+        assert_eq!(merged.priority, Some(Priority::High));
+        assert_eq!(merged.creation_flags(), HIGH_PRIORITY_CLASS | CREATE_BREAKAWAY_FROM_JOB);
+    }
+}
@@ -1,4 +1,33 @@
+#[cfg(windows)]
 pub mod process;
+#[cfg(windows)]
 pub mod token;
+#[cfg(windows)]
 pub mod privilege;
+#[cfg(windows)]
 pub mod elevation;
+pub mod security;
+pub mod registry;
+pub mod files;
+pub mod service;
+#[cfg(windows)]
+pub mod pipe;
+pub mod broker;
+pub mod session;
+#[cfg(windows)]
+pub mod job;
+#[cfg(windows)]
+pub mod handle;
+#[cfg(windows)]
+pub mod hardening;
+#[cfg(windows)]
+pub mod launch;
+#[cfg(windows)]
+pub mod inspect;
+#[cfg(windows)]
+pub mod terminate;
+#[cfg(windows)]
+pub mod runs;
+pub mod restart;
+#[cfg(windows)]
+pub mod supervisor;
@@ -0,0 +1,184 @@
+//! Local named pipes for talking to helper processes running as another user
+
+use std::fs::{File, OpenOptions};
+use std::os::windows::fs::OpenOptionsExt;
+use std::os::windows::io::{AsRawHandle, FromRawHandle};
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use serde::de::DeserializeOwned;
+use winapi::{
+    shared::{minwindef::FALSE, winerror::ERROR_PIPE_CONNECTED},
+    um::{
+        handleapi::INVALID_HANDLE_VALUE,
+        ioapiset::CancelSynchronousIo,
+        minwinbase::SECURITY_ATTRIBUTES,
+        namedpipeapi::{ConnectNamedPipe, CreateNamedPipeW},
+        processthreadsapi::{GetCurrentThreadId, OpenThread},
+        winbase::{
+            GetNamedPipeServerProcessId, FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED, PIPE_ACCESS_DUPLEX,
+            PIPE_ACCESS_INBOUND, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES,
+            PIPE_WAIT, SECURITY_IDENTIFICATION, SECURITY_SQOS_PRESENT,
+        },
+        winnt::{HANDLE, THREAD_TERMINATE},
+    },
+};
+
+use crate::core::broker::protocol::read_request;
+use crate::core::handle::{HandleKind, OwnedHandle};
+use crate::core::security::descriptor::BinaryDescriptor;
+use crate::utils::error::{ElevateError, ElevateResult, WindowsError, WindowsErrorKind};
+use crate::utils::files::file_error;
+
+const BUFFER_SIZE: u32 = 64 * 1024;
+/// Read and write without FILE_CREATE_PIPE_INSTANCE, for SDDL ACEs of clients
+/// who must not be able to serve the pipe themselves
+pub const CLIENT_ACCESS: &str = "0x12019b";
+const OUTPUT_PREFIX: &str = "ElevateKitOutput-";
+
+static OUTPUT_PIPES: AtomicU32 = AtomicU32::new(0);
+
+/// `\\.\pipe\<name>`
+pub fn pipe_path(name: &str) -> String {
+    format!(r"\\.\pipe\{}", name)
+}
+
+/// Creates a pipe that only one client can connect to. Fails if the name is
+/// already taken, so nobody can have created it first to listen in.
+/// `sddl` decides who may connect.
+pub fn create_pipe(name: &str, sddl: &str) -> ElevateResult<File> {
+    create_instance(name, sddl, 1, true)
+}
+
+/// Creates one more instance of a pipe many clients connect to, one per
+/// instance. Only the first may be created while the name is free.
+pub fn create_server_pipe(name: &str, sddl: &str, first: bool) -> ElevateResult<File> {
+    create_instance(name, sddl, PIPE_UNLIMITED_INSTANCES, first)
+}
+
+fn create_instance(name: &str, sddl: &str, max_instances: u32, first: bool) -> ElevateResult<File> {
+    let descriptor = BinaryDescriptor::from_sddl(sddl)?;
+    let mut attributes = SECURITY_ATTRIBUTES {
+        nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+        lpSecurityDescriptor: descriptor.as_ptr(),
+        bInheritHandle: FALSE,
+    };
+    let open_mode = if first { PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE } else { PIPE_ACCESS_DUPLEX };
+
+    let path: Vec<u16> = pipe_path(name).encode_utf16().chain(std::iter::once(0)).collect();
+    let handle = unsafe {
+        CreateNamedPipeW(
+            path.as_ptr(),
+            open_mode,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            max_instances,
+            BUFFER_SIZE,
+            BUFFER_SIZE,
+            0,
+            &mut attributes,
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::FileOperation,
+            &format!("Failed to create pipe {}", name),
+        ));
+    }
+    Ok(unsafe { File::from_raw_handle(handle as _) })
+}
+
+/// A one-way pipe for a child's output, as (read, write). Unlike an anonymous
+/// pipe the read end is overlapped, so it can be read asynchronously; the
+/// write end is an ordinary handle for the child.
+pub fn create_output_pipe() -> ElevateResult<(File, File)> {
+    let name = format!("{}{}-{}", OUTPUT_PREFIX, std::process::id(), OUTPUT_PIPES.fetch_add(1, Ordering::Relaxed));
+    let path: Vec<u16> = pipe_path(&name).encode_utf16().chain(std::iter::once(0)).collect();
+    let handle = unsafe {
+        CreateNamedPipeW(
+            path.as_ptr(),
+            PIPE_ACCESS_INBOUND | FILE_FLAG_FIRST_PIPE_INSTANCE | FILE_FLAG_OVERLAPPED,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            0,
+            BUFFER_SIZE,
+            0,
+            null_mut(),
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::FileOperation,
+            "Failed to create output pipe",
+        ));
+    }
+    let read = unsafe { File::from_raw_handle(handle as _) };
+
+    // the only instance is taken as soon as we connect, so nobody else can write to it
+    let write = OpenOptions::new()
+        .write(true)
+        .open(pipe_path(&name))
+        .map_err(|e| file_error(e, "Failed to open output pipe"))?;
+    Ok((read, write))
+}
+
+/// Blocks until a client opens the pipe
+pub fn wait_for_client(pipe: &File) -> ElevateResult<()> {
+    if unsafe { ConnectNamedPipe(pipe.as_raw_handle() as _, null_mut()) } == 0 {
+        let error = WindowsError::last_error();
+        // the client was quicker than us
+        if error.code() != ERROR_PIPE_CONNECTED {
+            return Err(error.to_elevate_error(WindowsErrorKind::FileOperation, "Failed to wait for a pipe client"));
+        }
+    }
+    Ok(())
+}
+
+/// Opens the client end of a pipe. The server may only identify us, so
+/// whoever holds the name cannot act with our token.
+pub fn connect_pipe(name: &str) -> ElevateResult<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .security_qos_flags(SECURITY_SQOS_PRESENT | SECURITY_IDENTIFICATION)
+        .open(pipe_path(name))
+        .map_err(|e| file_error(e, &format!("Failed to connect to pipe {}", name)))
+}
+
+/// The process serving the other end of a client pipe
+pub fn pipe_server_pid(pipe: &File) -> ElevateResult<u32> {
+    let mut pid = 0;
+    if unsafe { GetNamedPipeServerProcessId(pipe.as_raw_handle() as _, &mut pid) } == 0 {
+        return Err(WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::FileOperation,
+            "Failed to read the pipe server's process id",
+        ));
+    }
+    Ok(pid)
+}
+
+/// Reads a client's request, giving up after `timeout`, so a client that
+/// connects but never asks does not hold its thread forever. The pipe is
+/// synchronous, so a read past the deadline is cancelled from another thread.
+pub fn read_request_within<R: DeserializeOwned>(pipe: &mut File, timeout: Duration) -> ElevateResult<R> {
+    let thread = unsafe { OwnedHandle::try_from_raw(OpenThread(THREAD_TERMINATE, 0, GetCurrentThreadId()), HandleKind::Thread) }
+        .ok_or_else(|| ElevateError::ProcessError("Failed to open the pipe client thread".into()))?;
+    let raw = thread.as_raw() as usize;
+    let (done, finished) = channel::<()>();
+    let watchdog = thread::spawn(move || {
+        let expired = finished.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout);
+        if expired {
+            unsafe { CancelSynchronousIo(raw as HANDLE) };
+        }
+        expired
+    });
+
+    let request = read_request(pipe);
+    let _ = done.send(());
+    // nothing else is read or written until the watchdog is gone
+    if watchdog.join().unwrap_or(false) && request.is_err() {
+        return Err(ElevateError::ProcessError(format!("No request within {} seconds", timeout.as_secs())));
+    }
+    request
+}
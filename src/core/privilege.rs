@@ -1,8 +1,11 @@
 use std::ptr;
-use winapi::um::winnt::{TOKEN_PRIVILEGES, SE_PRIVILEGE_ENABLED, LUID};
+use winapi::shared::winerror::ERROR_NO_TOKEN;
+use winapi::um::processthreadsapi::{GetCurrentProcess, GetCurrentThread, OpenProcessToken, OpenThreadToken};
+use winapi::um::winnt::{TOKEN_PRIVILEGES, SE_PRIVILEGE_ENABLED, LUID, TOKEN_ADJUST_PRIVILEGES, TOKEN_QUERY};
 use winapi::um::winbase::LookupPrivilegeValueW;
 use winapi::um::securitybaseapi::AdjustTokenPrivileges;
-use crate::utils::error::{ElevateError, ElevateResult, PrivilegeErrorKind, TokenErrorKind};
+use crate::core::handle::{HandleKind, OwnedHandle};
+use crate::utils::error::{ElevateError, ElevateResult, PrivilegeErrorKind, TokenErrorKind, WindowsError, WindowsErrorKind};
 use log::info;
 
 pub struct PrivilegeManager {
@@ -72,3 +75,32 @@ impl PrivilegeManager {
         Ok(())
     }
 }
+
+/// Enables privileges on the token the current thread runs with: its
+/// impersonation token if it has one, the process token otherwise.
+pub fn enable_for_current_thread(privileges: &[&str]) -> ElevateResult<()> {
+    let mut token = ptr::null_mut();
+    let access = TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY;
+
+    unsafe {
+        if OpenThreadToken(GetCurrentThread(), access, 1, &mut token) == 0 {
+            let error = WindowsError::last_error();
+            if error.code() != ERROR_NO_TOKEN {
+                return Err(error.to_elevate_error(WindowsErrorKind::TokenOperation, "Failed to open thread token"));
+            }
+            if OpenProcessToken(GetCurrentProcess(), access, &mut token) == 0 {
+                return Err(WindowsError::last_error().to_elevate_error(
+                    WindowsErrorKind::TokenOperation,
+                    "Failed to open process token",
+                ));
+            }
+        }
+    }
+    let token = unsafe { OwnedHandle::from_raw(token, HandleKind::Token) };
+
+    let manager = PrivilegeManager::new(token.as_raw());
+    for privilege in privileges {
+        manager.enable(privilege)?;
+    }
+    Ok(())
+}
@@ -6,24 +6,42 @@ use winapi::um::processthreadsapi::{
     CreateProcessW,
     ResumeThread,
     GetExitCodeProcess,
+    GetProcessTimes,
+    OpenProcess,
+    GetCurrentProcess,
 };
-use winapi::um::handleapi::CloseHandle;
-use winapi::um::winnt::HANDLE;
+use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+use winapi::shared::winerror::ERROR_ACCESS_DENIED;
 use winapi::shared::minwindef::DWORD;
+use winapi::shared::minwindef::FILETIME;
 use winapi::um::minwinbase::STILL_ACTIVE;
+use winapi::um::tlhelp32::{CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS};
+use crate::core::handle::{HandleKind, OwnedHandle};
 use crate::utils::error::{ElevateResult, WindowsError, WindowsErrorKind};
+use crate::utils::time::filetime_to_unix;
 
-/// Represents a Windows process with safe handle management
+/// A created process and its main thread, both closed on drop
 pub struct ProcessInfo {
-    handle: HANDLE,
-    thread_handle: HANDLE,
+    process: OwnedHandle,
+    thread: OwnedHandle,
     process_id: u32,
     thread_id: u32,
 }
 
-
-
 impl ProcessInfo {
+    /// Takes ownership of what `CreateProcess` returned
+    ///
+    /// # Safety
+    /// Both handles must be open and closed by nothing else.
+    pub unsafe fn from_raw(info: PROCESS_INFORMATION) -> Self {
+        Self {
+            process: OwnedHandle::from_raw(info.hProcess, HandleKind::Process),
+            thread: OwnedHandle::from_raw(info.hThread, HandleKind::Thread),
+            process_id: info.dwProcessId,
+            thread_id: info.dwThreadId,
+        }
+    }
+
     pub fn process_id(&self) -> u32 {
         self.process_id
     }
@@ -31,30 +49,29 @@ impl ProcessInfo {
     pub fn thread_id(&self) -> u32 {
         self.thread_id
     }
-}
 
-// Implement Drop for automatic handle cleanup
-impl Drop for ProcessInfo {
-    fn drop(&mut self) {
-        unsafe {
-            if !self.handle.is_null() {
-                CloseHandle(self.handle);
-            }
-            if !self.thread_handle.is_null() {
-                CloseHandle(self.thread_handle);
-            }
-        }
+    pub fn process(&self) -> &OwnedHandle {
+        &self.process
     }
-}
 
-impl From<PROCESS_INFORMATION> for ProcessInfo {
-    fn from(info: PROCESS_INFORMATION) -> Self {
-        Self {
-            handle: info.hProcess,
-            thread_handle: info.hThread,
-            process_id: info.dwProcessId,
-            thread_id: info.dwThreadId,
+    pub fn thread(&self) -> &OwnedHandle {
+        &self.thread
+    }
+
+    /// Keeps the process handle and closes the thread's
+    pub fn into_process(self) -> OwnedHandle {
+        self.process
+    }
+
+    /// Starts the main thread of a process created suspended
+    pub fn resume(&self) -> ElevateResult<()> {
+        if unsafe { ResumeThread(self.thread.as_raw()) } == u32::MAX {
+            return Err(WindowsError::last_error().to_elevate_error(
+                WindowsErrorKind::ProcessOperation,
+                "Failed to resume process thread"
+            ));
         }
+        Ok(())
     }
 }
 
@@ -111,24 +128,17 @@ impl ProcessService for WindowsProcessService {
             &format!("Failed to create process: {}", command)
         )?;
 
-        Ok(process_info.into())
+        Ok(unsafe { ProcessInfo::from_raw(process_info) })
     }
 
     fn resume_process(&self, process: &ProcessInfo) -> ElevateResult<()> {
-        let result = unsafe { ResumeThread(process.thread_handle) };
-        if result == u32::MAX {
-            return Err(WindowsError::last_error().to_elevate_error(
-                WindowsErrorKind::ProcessOperation,
-                "Failed to resume process thread"
-            ));
-        }
-        Ok(())
+        process.resume()
     }
 
     fn wait_for_exit(&self, process: &ProcessInfo) -> ElevateResult<DWORD> {
         let mut exit_code = 0;
         let result = unsafe {
-            GetExitCodeProcess(process.handle, &mut exit_code)
+            GetExitCodeProcess(process.process.as_raw(), &mut exit_code)
         };
         
         Self::check_win32(
@@ -173,7 +183,7 @@ impl ProcessExt for ProcessInfo {
     fn wait_for_exit(&self) -> ElevateResult<DWORD> {
         let mut exit_code = 0;
         let result = unsafe {
-            GetExitCodeProcess(self.handle, &mut exit_code)
+            GetExitCodeProcess(self.process.as_raw(), &mut exit_code)
         };
 
         WindowsProcessService::check_win32(
@@ -187,7 +197,7 @@ impl ProcessExt for ProcessInfo {
     fn is_running(&self) -> ElevateResult<bool> {
         let mut exit_code = 0;
         let result = unsafe {
-            GetExitCodeProcess(self.handle, &mut exit_code)
+            GetExitCodeProcess(self.process.as_raw(), &mut exit_code)
         };
 
         if result == 0 {
@@ -199,4 +209,97 @@ impl ProcessExt for ProcessInfo {
 
         Ok(exit_code == STILL_ACTIVE)
     }
-}
\ No newline at end of file
+}
+
+/// A process as seen in a snapshot
+#[derive(Debug, Clone)]
+pub struct ProcessEntry {
+    pub pid: u32,
+    pub parent_pid: u32,
+    /// image file name without the path, such as `svchost.exe`
+    pub name: String,
+}
+
+/// Every running process. The parent ids are as they were at creation, so
+/// the parent may have exited and its id been reused since.
+pub fn processes() -> ElevateResult<Vec<ProcessEntry>> {
+    let snapshot = unsafe { OwnedHandle::try_from_raw(CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0), HandleKind::Snapshot) }
+        .ok_or_else(|| WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::ProcessOperation,
+            "Failed to snapshot running processes"
+        ))?;
+
+    let mut entry: PROCESSENTRY32W = unsafe { std::mem::zeroed() };
+    entry.dwSize = std::mem::size_of::<PROCESSENTRY32W>() as u32;
+
+    let mut entries = Vec::new();
+    let mut found = unsafe { Process32FirstW(snapshot.as_raw(), &mut entry) };
+    while found != 0 {
+        let len = entry.szExeFile.iter().position(|&c| c == 0).unwrap_or(entry.szExeFile.len());
+        entries.push(ProcessEntry {
+            pid: entry.th32ProcessID,
+            parent_pid: entry.th32ParentProcessID,
+            name: String::from_utf16_lossy(&entry.szExeFile[..len]),
+        });
+        found = unsafe { Process32NextW(snapshot.as_raw(), &mut entry) };
+    }
+
+    Ok(entries)
+}
+
+/// Finds the first process whose image name matches, ignoring case
+pub fn find_process_id(image_name: &str) -> ElevateResult<Option<u32>> {
+    Ok(processes()?.into_iter().find(|entry| entry.name.eq_ignore_ascii_case(image_name)).map(|entry| entry.pid))
+}
+
+/// When a process was created, in 100ns intervals since 1601. Together with
+/// the id it tells a process apart from a later one that reuses the id.
+pub fn creation_time(process: &OwnedHandle) -> ElevateResult<u64> {
+    let mut times: [FILETIME; 4] = unsafe { std::mem::zeroed() };
+    let [created, exited, kernel, user] = &mut times;
+    if unsafe { GetProcessTimes(process.as_raw(), created, exited, kernel, user) } == 0 {
+        return Err(WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::ProcessOperation,
+            "Failed to read process times"
+        ));
+    }
+    Ok((times[0].dwHighDateTime as u64) << 32 | times[0].dwLowDateTime as u64)
+}
+
+/// Whether a process with this id still runs. Ids are reused, so this is only a hint.
+pub fn process_is_running(pid: u32) -> bool {
+    let Some(process) = (unsafe { OwnedHandle::try_from_raw(OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid), HandleKind::Process) }) else {
+        // access denied still means something runs under that id
+        return WindowsError::last_error().code() == ERROR_ACCESS_DENIED;
+    };
+
+    let mut exit_code = 0;
+    unsafe { GetExitCodeProcess(process.as_raw(), &mut exit_code) != 0 && exit_code == STILL_ACTIVE }
+}
+
+/// Whether the process with this id and creation time still runs, telling a
+/// later process that reused the id apart
+pub fn process_instance_is_running(pid: u32, created: u64) -> bool {
+    process_is_running_since(pid, |time| time == created)
+}
+
+/// Whether a process with this id runs that was started by `unix_time`, so a
+/// process that took over the id of an older one later is not mistaken for it
+pub fn process_started_by(pid: u32, unix_time: u64) -> bool {
+    process_is_running_since(pid, |time| filetime_to_unix(time) <= unix_time)
+}
+
+fn process_is_running_since(pid: u32, created: impl FnOnce(u64) -> bool) -> bool {
+    let Some(process) = (unsafe { OwnedHandle::try_from_raw(OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid), HandleKind::Process) }) else {
+        return WindowsError::last_error().code() == ERROR_ACCESS_DENIED;
+    };
+
+    let mut exit_code = 0;
+    creation_time(&process).map_or(true, created)
+        && unsafe { GetExitCodeProcess(process.as_raw(), &mut exit_code) != 0 && exit_code == STILL_ACTIVE }
+}
+
+/// Creation time of this process, to record next to its id
+pub fn current_creation_time() -> ElevateResult<u64> {
+    creation_time(&unsafe { OwnedHandle::duplicate_from(GetCurrentProcess(), HandleKind::Process) }?)
+}
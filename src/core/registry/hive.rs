@@ -0,0 +1,220 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use winapi::um::winreg::{RegLoadKeyW, RegUnLoadKeyW, HKEY_LOCAL_MACHINE};
+
+use crate::core::privilege::enable_for_current_thread;
+use crate::core::process::{current_creation_time, process_instance_is_running, process_is_running};
+use crate::core::registry::key::{check, to_wide, RegistryKey};
+use crate::core::registry::path::{KeyPath, RegistryView, RootKey};
+use crate::utils::config::Config;
+use crate::utils::error::{ElevateError, ElevateResult};
+use crate::utils::files::{canonical_path, file_error};
+use crate::utils::time::unix_timestamp;
+
+const MOUNTS_FILE: &str = "hives.json";
+const HIVE_PRIVILEGES: &[&str] = &["SeBackupPrivilege", "SeRestorePrivilege"];
+
+/// A hive file loaded under `HKLM\<name>` by ElevateKit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HiveMount {
+    pub name: String,
+    pub file: PathBuf,
+    /// process that loaded the hive and is expected to unload it
+    pub pid: u32,
+    /// creation time of that process, so a later one reusing the id does not
+    /// keep the mount from looking stale; missing in older records
+    #[serde(default)]
+    pub created: Option<u64>,
+    /// seconds since the unix epoch
+    pub loaded_at: u64,
+    /// left loaded on purpose with `--keep`
+    pub kept: bool,
+}
+
+impl HiveMount {
+    /// Still loaded although the process that loaded it is gone
+    pub fn is_stale(&self) -> bool {
+        let running = match self.created {
+            Some(created) => process_instance_is_running(self.pid, created),
+            None => process_is_running(self.pid),
+        };
+        !self.kept && self.pid != std::process::id() && !running
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MountStore {
+    mounts: Vec<HiveMount>,
+}
+
+/// Loads offline hives with `RegLoadKeyW` and keeps track of them across runs
+pub struct HiveManager {
+    store_path: PathBuf,
+}
+
+impl HiveManager {
+    pub fn new() -> ElevateResult<Self> {
+        Ok(Self { store_path: Config::state_dir()?.join(MOUNTS_FILE) })
+    }
+
+    /// Loads `file` as `HKLM\<name>`. The hive is unloaded when the returned
+    /// guard is dropped, unless `keep` is set.
+    pub fn load(&self, file: &Path, name: &str, keep: bool) -> ElevateResult<MountedHive> {
+        validate_mount_name(name)?;
+        let file = canonical_path(file)?;
+
+        let mut mounts = self.mounts()?;
+        if is_loaded(name)? {
+            return Err(ElevateError::ValidationError(format!("HKLM\\{} is already in use", name)));
+        }
+
+        enable_for_current_thread(HIVE_PRIVILEGES)?;
+        let name_wide = to_wide(name);
+        let file_wide = to_wide(&file.to_string_lossy());
+        let status = unsafe { RegLoadKeyW(HKEY_LOCAL_MACHINE, name_wide.as_ptr(), file_wide.as_ptr()) };
+        check(status, &format!("Failed to load {} as HKLM\\{}", file.display(), name))?;
+        info!("Loaded {} as HKLM\\{}", file.display(), name);
+
+        mounts.push(HiveMount {
+            name: name.to_string(),
+            file,
+            pid: std::process::id(),
+            created: current_creation_time().ok(),
+            loaded_at: unix_timestamp(),
+            kept: keep,
+        });
+        // an untracked mount would never be reported, so it does not stay
+        if let Err(error) = self.save_store(&MountStore { mounts }) {
+            if let Err(e) = unload_hive(name) {
+                warn!("Failed to unload HKLM\\{}: {}", name, e);
+            }
+            return Err(error);
+        }
+
+        Ok(MountedHive { name: name.to_string(), store_path: self.store_path.clone(), kept: keep })
+    }
+
+    pub fn unload(&self, name: &str) -> ElevateResult<()> {
+        unload_hive(name)?;
+
+        let mut store = self.load_store()?;
+        store.mounts.retain(|m| !m.name.eq_ignore_ascii_case(name));
+        self.save_store(&store)
+    }
+
+    /// Tracked mounts that are still loaded; entries for hives unloaded elsewhere are dropped
+    pub fn mounts(&self) -> ElevateResult<Vec<HiveMount>> {
+        let mut store = self.load_store()?;
+        let before = store.mounts.len();
+
+        let mut loaded = Vec::new();
+        for mount in store.mounts {
+            if is_loaded(&mount.name)? {
+                loaded.push(mount);
+            }
+        }
+        store.mounts = loaded;
+
+        if store.mounts.len() != before {
+            self.save_store(&store)?;
+        }
+        Ok(store.mounts)
+    }
+
+    /// Logs a warning for every hive a crashed run left loaded
+    pub fn report_stale(&self) -> ElevateResult<()> {
+        for mount in self.mounts()?.iter().filter(|m| m.is_stale()) {
+            warn!(
+                "HKLM\\{} ({}) was left loaded by process {}, run `hive unload {}`",
+                mount.name,
+                mount.file.display(),
+                mount.pid,
+                mount.name
+            );
+        }
+        Ok(())
+    }
+
+    fn load_store(&self) -> ElevateResult<MountStore> {
+        read_store(&self.store_path)
+    }
+
+    fn save_store(&self, store: &MountStore) -> ElevateResult<()> {
+        write_store(&self.store_path, store)
+    }
+}
+
+/// Unloads its hive on drop, including when a Ctrl+C handler lets the run end
+pub struct MountedHive {
+    name: String,
+    store_path: PathBuf,
+    kept: bool,
+}
+
+impl MountedHive {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Drop for MountedHive {
+    fn drop(&mut self) {
+        if self.kept {
+            return;
+        }
+        if let Err(e) = unload_hive(&self.name) {
+            warn!("Failed to unload HKLM\\{}: {}", self.name, e);
+            return;
+        }
+
+        let result = read_store(&self.store_path).and_then(|mut store| {
+            store.mounts.retain(|m| !m.name.eq_ignore_ascii_case(&self.name));
+            write_store(&self.store_path, &store)
+        });
+        if let Err(e) = result {
+            warn!("Failed to update {}: {}", self.store_path.display(), e);
+        }
+    }
+}
+
+fn unload_hive(name: &str) -> ElevateResult<()> {
+    enable_for_current_thread(HIVE_PRIVILEGES)?;
+    let name_wide = to_wide(name);
+    let status = unsafe { RegUnLoadKeyW(HKEY_LOCAL_MACHINE, name_wide.as_ptr()) };
+    check(status, &format!("Failed to unload HKLM\\{}, is a key still open?", name))?;
+    info!("Unloaded HKLM\\{}", name);
+    Ok(())
+}
+
+fn is_loaded(name: &str) -> ElevateResult<bool> {
+    let key = KeyPath { root: RootKey::LocalMachine, subkey: name.to_string() };
+    Ok(RegistryKey::try_open(&key, RegistryView::Native)?.is_some())
+}
+
+fn validate_mount_name(name: &str) -> ElevateResult<()> {
+    if name.is_empty() || name.contains('\\') {
+        return Err(ElevateError::ValidationError(format!(
+            "Invalid mount name {:?}, expected a single key name",
+            name
+        )));
+    }
+    Ok(())
+}
+
+fn read_store(path: &Path) -> ElevateResult<MountStore> {
+    if !path.exists() {
+        return Ok(MountStore::default());
+    }
+
+    let contents = fs::read_to_string(path).map_err(|e| file_error(e, "Failed to read hive mounts"))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| ElevateError::ConfigError(format!("Failed to parse hive mounts: {}", e)))
+}
+
+fn write_store(path: &Path, store: &MountStore) -> ElevateResult<()> {
+    let contents = serde_json::to_string_pretty(store)
+        .map_err(|e| ElevateError::ConfigError(format!("Failed to serialize hive mounts: {}", e)))?;
+    fs::write(path, contents).map_err(|e| file_error(e, "Failed to write hive mounts"))
+}
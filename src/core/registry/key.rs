@@ -0,0 +1,286 @@
+use std::ptr::null_mut;
+use winapi::{
+    shared::{
+        minwindef::HKEY,
+        winerror::{ERROR_FILE_NOT_FOUND, ERROR_MORE_DATA, ERROR_NO_MORE_ITEMS, ERROR_SUCCESS},
+    },
+    um::{
+        winnt::{DELETE, KEY_ALL_ACCESS, KEY_READ, REG_OPTION_NON_VOLATILE},
+        winreg::{
+            RegCloseKey, RegCreateKeyExW, RegDeleteKeyExW, RegDeleteTreeW, RegDeleteValueW, RegEnumKeyExW,
+            RegEnumValueW, RegOpenKeyExW, RegQueryInfoKeyW, RegQueryValueExW, RegSetValueExW,
+            HKEY_CLASSES_ROOT, HKEY_CURRENT_CONFIG, HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, HKEY_USERS,
+        },
+    },
+};
+
+use crate::core::handle::{self, HandleKind};
+use crate::core::registry::path::{KeyPath, RegistryView, RootKey};
+use crate::core::registry::value::RegValue;
+use crate::utils::error::{ElevateError, ElevateResult, WindowsError, WindowsErrorKind};
+
+/// An open registry key, closed on drop
+pub struct RegistryKey {
+    handle: HKEY,
+    path: KeyPath,
+}
+
+impl RegistryKey {
+    pub fn open(path: &KeyPath, view: RegistryView) -> ElevateResult<Self> {
+        Self::open_with(path, view, KEY_READ)
+    }
+
+    pub fn open_writable(path: &KeyPath, view: RegistryView) -> ElevateResult<Self> {
+        Self::open_with(path, view, KEY_ALL_ACCESS)
+    }
+
+    /// Opens the key, `None` if it does not exist
+    pub fn try_open(path: &KeyPath, view: RegistryView) -> ElevateResult<Option<Self>> {
+        match Self::open(path, view) {
+            Ok(key) => Ok(Some(key)),
+            Err(ElevateError::Windows { code, .. }) if code as u32 == ERROR_FILE_NOT_FOUND => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Opens the key for writing, creating it and any missing parents
+    pub fn create(path: &KeyPath, view: RegistryView) -> ElevateResult<Self> {
+        let subkey = to_wide(&path.subkey);
+        let mut handle = null_mut();
+        let status = unsafe {
+            RegCreateKeyExW(
+                root_handle(path.root),
+                subkey.as_ptr(),
+                0,
+                null_mut(),
+                REG_OPTION_NON_VOLATILE,
+                KEY_ALL_ACCESS | view.access_flags(),
+                null_mut(),
+                &mut handle,
+                null_mut(),
+            )
+        };
+        check(status, &format!("Failed to create {}", path))?;
+        handle::opened(HandleKind::RegistryKey);
+        Ok(Self { handle, path: path.clone() })
+    }
+
+    fn open_with(path: &KeyPath, view: RegistryView, access: u32) -> ElevateResult<Self> {
+        let subkey = to_wide(&path.subkey);
+        let mut handle = null_mut();
+        let status = unsafe {
+            RegOpenKeyExW(root_handle(path.root), subkey.as_ptr(), 0, access | view.access_flags(), &mut handle)
+        };
+        check(status, &format!("Failed to open {}", path))?;
+        handle::opened(HandleKind::RegistryKey);
+        Ok(Self { handle, path: path.clone() })
+    }
+
+    pub fn path(&self) -> &KeyPath {
+        &self.path
+    }
+
+    /// Reads a value, `None` if it does not exist. An empty name is the default value.
+    pub fn value(&self, name: &str) -> ElevateResult<Option<RegValue>> {
+        let name_wide = to_wide(name);
+        let mut data = vec![0u8; 256];
+
+        loop {
+            let mut kind = 0;
+            let mut len = data.len() as u32;
+            let status = unsafe {
+                RegQueryValueExW(self.handle, name_wide.as_ptr(), null_mut(), &mut kind, data.as_mut_ptr(), &mut len)
+            } as u32;
+
+            match status {
+                ERROR_SUCCESS => return Ok(Some(RegValue::from_raw(kind, &data[..len as usize]))),
+                ERROR_FILE_NOT_FOUND => return Ok(None),
+                // the value may grow between calls, so keep asking
+                ERROR_MORE_DATA => data.resize(len as usize, 0),
+                _ => check(status as i32, &format!("Failed to read {} in {}", display_name(name), self.path))?,
+            }
+        }
+    }
+
+    /// All values of the key in enumeration order
+    pub fn values(&self) -> ElevateResult<Vec<(String, RegValue)>> {
+        let info = self.info()?;
+        let mut name = vec![0u16; info.max_value_name + 1];
+        let mut data = vec![0u8; info.max_value_data];
+        let mut values = Vec::new();
+
+        let mut index = 0;
+        loop {
+            let mut name_len = name.len() as u32;
+            let mut data_len = data.len() as u32;
+            let mut kind = 0;
+            let status = unsafe {
+                RegEnumValueW(
+                    self.handle,
+                    index,
+                    name.as_mut_ptr(),
+                    &mut name_len,
+                    null_mut(),
+                    &mut kind,
+                    data.as_mut_ptr(),
+                    &mut data_len,
+                )
+            } as u32;
+
+            match status {
+                ERROR_SUCCESS => {
+                    values.push((
+                        String::from_utf16_lossy(&name[..name_len as usize]),
+                        RegValue::from_raw(kind, &data[..data_len as usize]),
+                    ));
+                    index += 1;
+                }
+                ERROR_NO_MORE_ITEMS => return Ok(values),
+                ERROR_MORE_DATA => {
+                    name.resize(name.len() * 2, 0);
+                    data.resize((data_len as usize).max(data.len() * 2), 0);
+                }
+                _ => check(status as i32, &format!("Failed to list values of {}", self.path))?,
+            }
+        }
+    }
+
+    /// Names of the direct subkeys
+    pub fn subkeys(&self) -> ElevateResult<Vec<String>> {
+        let info = self.info()?;
+        let mut name = vec![0u16; info.max_subkey_name + 1];
+        let mut subkeys = Vec::new();
+
+        let mut index = 0;
+        loop {
+            let mut name_len = name.len() as u32;
+            let status = unsafe {
+                RegEnumKeyExW(
+                    self.handle,
+                    index,
+                    name.as_mut_ptr(),
+                    &mut name_len,
+                    null_mut(),
+                    null_mut(),
+                    null_mut(),
+                    null_mut(),
+                )
+            } as u32;
+
+            match status {
+                ERROR_SUCCESS => {
+                    subkeys.push(String::from_utf16_lossy(&name[..name_len as usize]));
+                    index += 1;
+                }
+                ERROR_NO_MORE_ITEMS => return Ok(subkeys),
+                ERROR_MORE_DATA => name.resize(name.len() * 2, 0),
+                _ => check(status as i32, &format!("Failed to list subkeys of {}", self.path))?,
+            }
+        }
+    }
+
+    pub fn set_value(&self, name: &str, value: &RegValue) -> ElevateResult<()> {
+        let name_wide = to_wide(name);
+        let data = value.to_raw();
+        let status = unsafe {
+            RegSetValueExW(self.handle, name_wide.as_ptr(), 0, value.kind(), data.as_ptr(), data.len() as u32)
+        };
+        check(status, &format!("Failed to write {} in {}", display_name(name), self.path))
+    }
+
+    /// Deletes a value, returning false if it did not exist
+    pub fn delete_value(&self, name: &str) -> ElevateResult<bool> {
+        let name_wide = to_wide(name);
+        let status = unsafe { RegDeleteValueW(self.handle, name_wide.as_ptr()) };
+        if status as u32 == ERROR_FILE_NOT_FOUND {
+            return Ok(false);
+        }
+        check(status, &format!("Failed to delete {} in {}", display_name(name), self.path))?;
+        Ok(true)
+    }
+
+    fn info(&self) -> ElevateResult<KeyInfo> {
+        let (mut max_subkey_name, mut max_value_name, mut max_value_data) = (0, 0, 0);
+        let status = unsafe {
+            RegQueryInfoKeyW(
+                self.handle,
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                &mut max_subkey_name,
+                null_mut(),
+                null_mut(),
+                &mut max_value_name,
+                &mut max_value_data,
+                null_mut(),
+                null_mut(),
+            )
+        };
+        check(status, &format!("Failed to query {}", self.path))?;
+        Ok(KeyInfo {
+            max_subkey_name: max_subkey_name as usize,
+            max_value_name: max_value_name as usize,
+            max_value_data: max_value_data as usize,
+        })
+    }
+}
+
+impl Drop for RegistryKey {
+    fn drop(&mut self) {
+        unsafe { RegCloseKey(self.handle) };
+        handle::closed(HandleKind::RegistryKey);
+    }
+}
+
+struct KeyInfo {
+    max_subkey_name: usize,
+    max_value_name: usize,
+    max_value_data: usize,
+}
+
+/// Deletes a key with all of its values and subkeys
+pub fn delete_key(path: &KeyPath, view: RegistryView) -> ElevateResult<()> {
+    let parent = path
+        .parent()
+        .ok_or_else(|| ElevateError::ValidationError(format!("Refusing to delete root key {}", path)))?;
+
+    {
+        let key = RegistryKey::open_with(path, view, KEY_ALL_ACCESS | DELETE)?;
+        let status = unsafe { RegDeleteTreeW(key.handle, null_mut()) };
+        check(status, &format!("Failed to delete the contents of {}", path))?;
+    }
+
+    // RegDeleteTreeW has no view flag, the key itself goes through RegDeleteKeyExW
+    let parent = RegistryKey::open_with(&parent, view, KEY_ALL_ACCESS)?;
+    let name = to_wide(path.name());
+    let status = unsafe { RegDeleteKeyExW(parent.handle, name.as_ptr(), view.access_flags(), 0) };
+    check(status, &format!("Failed to delete {}", path))
+}
+
+pub(crate) fn root_handle(root: RootKey) -> HKEY {
+    match root {
+        RootKey::ClassesRoot => HKEY_CLASSES_ROOT,
+        // SYSTEM's hive while impersonating, RegistryManager opens the caller's under HKU
+        RootKey::CurrentUser => HKEY_CURRENT_USER,
+        RootKey::LocalMachine => HKEY_LOCAL_MACHINE,
+        RootKey::Users => HKEY_USERS,
+        RootKey::CurrentConfig => HKEY_CURRENT_CONFIG,
+    }
+}
+
+// the registry APIs return their error code instead of setting the last error
+pub(crate) fn check(status: i32, context: &str) -> ElevateResult<()> {
+    if status as u32 == ERROR_SUCCESS {
+        return Ok(());
+    }
+    Err(WindowsError::from_code(status as u32).to_elevate_error(WindowsErrorKind::RegistryOperation, context))
+}
+
+pub(crate) fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+fn display_name(name: &str) -> &str {
+    if name.is_empty() { "(Default)" } else { name }
+}
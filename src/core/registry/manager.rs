@@ -0,0 +1,343 @@
+use std::fs;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use winapi::um::processthreadsapi::GetCurrentProcess;
+use winapi::um::winnt::{KEY_ALL_ACCESS, TOKEN_QUERY};
+
+use crate::core::elevation::impersonation::{TrustedInstallerImpersonation, SECURITY_PRIVILEGES};
+use crate::core::registry::key::{delete_key, RegistryKey};
+use crate::core::registry::path::{KeyPath, RegistryView, RootKey};
+use crate::core::registry::regfile::{diff_key, RegChange, RegFile, RegFileKey, RegFileVersion, ValueChange};
+use crate::core::registry::value::RegValue;
+use crate::core::security::descriptor::{apply_security, read_security, ObjectType};
+use crate::core::security::sddl::{AccessMask, Ace, AceFlags, Acl, SecurityDescriptor, Sid};
+use crate::core::token::{open_process_token, token_user};
+use crate::utils::config::Config;
+use crate::utils::error::{ElevateError, ElevateResult};
+use crate::utils::files::file_error;
+use crate::utils::time::unix_timestamp;
+
+const BACKUP_FILE: &str = "registry.json";
+
+/// Security descriptor and values of a key, with subkeys when the whole tree was saved
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeySnapshot {
+    pub path: KeyPath,
+    pub sddl: String,
+    pub values: Vec<NamedValue>,
+    pub subkeys: Vec<KeySnapshot>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NamedValue {
+    pub name: String,
+    pub value: RegValue,
+}
+
+/// State of a key before a change, `snapshot` is `None` if the key did not exist
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistryBackup {
+    pub key: KeyPath,
+    pub view: RegistryView,
+    /// seconds since the unix epoch
+    pub saved_at: u64,
+    pub operation: String,
+    pub snapshot: Option<KeySnapshot>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BackupStore {
+    // oldest first, restore takes the newest backup of a key
+    backups: Vec<RegistryBackup>,
+}
+
+pub struct KeyListing {
+    pub subkeys: Vec<String>,
+    pub values: Vec<(String, RegValue)>,
+}
+
+/// Reads and changes registry keys while impersonating TrustedInstaller.
+/// Every change is preceded by a backup of the key it touches.
+pub struct RegistryManager {
+    store_path: PathBuf,
+    // HKCU keys are opened under HKU by this SID, see `KeyPath::for_user`
+    user_sid: String,
+}
+
+impl RegistryManager {
+    pub fn new() -> ElevateResult<Self> {
+        let token = open_process_token(unsafe { GetCurrentProcess() }, TOKEN_QUERY)?;
+        Ok(Self {
+            store_path: Config::state_dir()?.join(BACKUP_FILE),
+            user_sid: token_user(&token)?.sid,
+        })
+    }
+
+    pub fn get(&self, path: &KeyPath, view: RegistryView, name: &str) -> ElevateResult<Option<RegValue>> {
+        let path = &path.for_user(&self.user_sid);
+        let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+        RegistryKey::open(path, view)?.value(name)
+    }
+
+    pub fn list(&self, path: &KeyPath, view: RegistryView) -> ElevateResult<KeyListing> {
+        let path = &path.for_user(&self.user_sid);
+        let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+        let key = RegistryKey::open(path, view)?;
+        Ok(KeyListing { subkeys: key.subkeys()?, values: key.values()? })
+    }
+
+    /// Writes a value, creating the key if needed
+    pub fn set(&self, path: &KeyPath, view: RegistryView, name: &str, value: &RegValue) -> ElevateResult<()> {
+        let path = &path.for_user(&self.user_sid);
+        let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+        self.backup(path, view, "set", false)?;
+        RegistryKey::create(path, view)?.set_value(name, value)
+    }
+
+    /// Deletes a value, returning false if it did not exist
+    pub fn delete_value(&self, path: &KeyPath, view: RegistryView, name: &str) -> ElevateResult<bool> {
+        let path = &path.for_user(&self.user_sid);
+        let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+        let key = RegistryKey::open_writable(path, view)?;
+        if key.value(name)?.is_none() {
+            return Ok(false);
+        }
+        self.backup(path, view, "delete value", false)?;
+        key.delete_value(name)
+    }
+
+    /// Deletes a key and everything below it, after saving the whole tree
+    pub fn delete_key(&self, path: &KeyPath, view: RegistryView) -> ElevateResult<()> {
+        let path = &path.for_user(&self.user_sid);
+        let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+        // fails for a missing key before an empty backup is saved
+        RegistryKey::open(path, view)?;
+        self.backup(path, view, "delete key", true)?;
+        delete_key(path, view)
+    }
+
+    /// Sets the owner of a key and optionally grants a principal full control
+    pub fn own(&self, path: &KeyPath, view: RegistryView, owner: &Sid, grant: Option<&Sid>) -> ElevateResult<()> {
+        let path = &path.for_user(&self.user_sid);
+        let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+        let original = read_security(&path.object_name(), ObjectType::RegistryKey(view), false)?;
+        self.backup(path, view, "own", false)?;
+
+        let mut updated = SecurityDescriptor {
+            owner: Some(owner.clone()),
+            ..Default::default()
+        };
+        if let (Some(grantee), Some(dacl)) = (grant, &original.dacl) {
+            let mut dacl = dacl.without_inherited();
+            dacl.add_allow(Ace::allow(grantee.clone(), AccessMask(KEY_ALL_ACCESS), AceFlags::default()));
+            updated.dacl = Some(dacl);
+        }
+
+        apply_security(&path.object_name(), ObjectType::RegistryKey(view), &updated)
+    }
+
+    /// Changes a `.reg` file would make to the live registry
+    pub fn preview_import(&self, file: &RegFile, view: RegistryView) -> ElevateResult<Vec<RegChange>> {
+        let file = &self.for_user(file);
+        let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+        let mut changes = Vec::new();
+        for entry in &file.keys {
+            changes.extend(diff_key(entry, live_values(&entry.path, view)?.as_deref()));
+        }
+        Ok(changes)
+    }
+
+    /// Applies a `.reg` file, backing up each key before it changes.
+    /// Returns the changes made; keys that already match are left alone.
+    pub fn import(&self, file: &RegFile, view: RegistryView) -> ElevateResult<Vec<RegChange>> {
+        let file = &self.for_user(file);
+        let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+        let mut applied = Vec::new();
+
+        for entry in &file.keys {
+            let changes = diff_key(entry, live_values(&entry.path, view)?.as_deref());
+            if changes.is_empty() {
+                continue;
+            }
+
+            self.backup(&entry.path, view, "import", entry.delete)?;
+            if entry.delete {
+                delete_key(&entry.path, view)?;
+            } else {
+                let key = RegistryKey::create(&entry.path, view)?;
+                for change in &changes {
+                    match change {
+                        RegChange::SetValue { name, new, .. } => key.set_value(name, new)?,
+                        RegChange::DeleteValue { name, .. } => {
+                            key.delete_value(name)?;
+                        }
+                        RegChange::CreateKey(_) | RegChange::DeleteKey(_) => {}
+                    }
+                }
+            }
+            applied.extend(changes);
+        }
+
+        Ok(applied)
+    }
+
+    /// A key and everything below it as a `.reg` file
+    pub fn export(&self, path: &KeyPath, view: RegistryView, version: RegFileVersion) -> ElevateResult<RegFile> {
+        let mut keys = Vec::new();
+        {
+            let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+            export_key(&RegistryKey::open(&path.for_user(&self.user_sid), view)?, view, &mut keys)?;
+        }
+        if path.root == RootKey::CurrentUser {
+            for key in &mut keys {
+                key.path = key.path.as_current_user(&self.user_sid);
+            }
+        }
+        Ok(RegFile { version, keys })
+    }
+
+    /// Puts back the newest backup of a key and removes it from the store
+    pub fn restore(&self, path: &KeyPath, view: RegistryView) -> ElevateResult<RegistryBackup> {
+        let path = &path.for_user(&self.user_sid);
+        let mut store = self.load()?;
+        let index = store
+            .backups
+            .iter()
+            .rposition(|b| same_key(&b.key, path) && b.view == view)
+            .ok_or_else(|| ElevateError::ValidationError(format!("No registry backup of {}", path)))?;
+        let backup = store.backups.remove(index);
+
+        {
+            let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+            match &backup.snapshot {
+                Some(snapshot) => restore_snapshot(snapshot, view)?,
+                None => {
+                    info!("{} did not exist before {}, deleting it", path, backup.operation);
+                    delete_key(path, view)?;
+                }
+            }
+        }
+
+        self.save(&store)?;
+        Ok(backup)
+    }
+
+    fn for_user(&self, file: &RegFile) -> RegFile {
+        let mut file = file.clone();
+        for key in &mut file.keys {
+            key.path = key.path.for_user(&self.user_sid);
+        }
+        file
+    }
+
+    // must be called while impersonating
+    fn backup(&self, path: &KeyPath, view: RegistryView, operation: &str, recursive: bool) -> ElevateResult<()> {
+        let snapshot = RegistryKey::try_open(path, view)?
+            .map(|key| snapshot(&key, view, recursive))
+            .transpose()?;
+
+        let mut store = self.load()?;
+        store.backups.push(RegistryBackup {
+            key: path.clone(),
+            view,
+            saved_at: unix_timestamp(),
+            operation: operation.to_string(),
+            snapshot,
+        });
+        self.save(&store)?;
+        info!("Saved {} to {}", path, self.store_path.display());
+        Ok(())
+    }
+
+    fn load(&self) -> ElevateResult<BackupStore> {
+        if !self.store_path.exists() {
+            return Ok(BackupStore::default());
+        }
+
+        let contents = fs::read_to_string(&self.store_path)
+            .map_err(|e| file_error(e, "Failed to read registry backups"))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| ElevateError::ConfigError(format!("Failed to parse registry backups: {}", e)))
+    }
+
+    fn save(&self, store: &BackupStore) -> ElevateResult<()> {
+        let contents = serde_json::to_string_pretty(store)
+            .map_err(|e| ElevateError::ConfigError(format!("Failed to serialize registry backups: {}", e)))?;
+        fs::write(&self.store_path, contents).map_err(|e| file_error(e, "Failed to write registry backups"))
+    }
+}
+
+fn snapshot(key: &RegistryKey, view: RegistryView, recursive: bool) -> ElevateResult<KeySnapshot> {
+    let path = key.path().clone();
+    let sddl = read_security(&path.object_name(), ObjectType::RegistryKey(view), false)?.to_string();
+    let values = key
+        .values()?
+        .into_iter()
+        .map(|(name, value)| NamedValue { name, value })
+        .collect();
+
+    let mut subkeys = Vec::new();
+    if recursive {
+        for name in key.subkeys()? {
+            let child = RegistryKey::open(&path.join(&name), view)?;
+            subkeys.push(snapshot(&child, view, true)?);
+        }
+    }
+
+    Ok(KeySnapshot { path, sddl, values, subkeys })
+}
+
+fn live_values(path: &KeyPath, view: RegistryView) -> ElevateResult<Option<Vec<(String, RegValue)>>> {
+    RegistryKey::try_open(path, view)?.map(|key| key.values()).transpose()
+}
+
+fn export_key(key: &RegistryKey, view: RegistryView, keys: &mut Vec<RegFileKey>) -> ElevateResult<()> {
+    keys.push(RegFileKey {
+        path: key.path().clone(),
+        delete: false,
+        values: key
+            .values()?
+            .into_iter()
+            .map(|(name, value)| (name, ValueChange::Set(value)))
+            .collect(),
+    });
+    for name in key.subkeys()? {
+        export_key(&RegistryKey::open(&key.path().join(&name), view)?, view, keys)?;
+    }
+    Ok(())
+}
+
+// parents first, so the key exists before its security and children are restored
+fn restore_snapshot(snapshot: &KeySnapshot, view: RegistryView) -> ElevateResult<()> {
+    let key = RegistryKey::create(&snapshot.path, view)?;
+
+    for (name, _) in key.values()? {
+        if !snapshot.values.iter().any(|v| v.name.eq_ignore_ascii_case(&name)) {
+            key.delete_value(&name)?;
+        }
+    }
+    for value in &snapshot.values {
+        key.set_value(&value.name, &value.value)?;
+    }
+
+    let original = SecurityDescriptor::parse(&snapshot.sddl)?;
+    let restored = SecurityDescriptor {
+        owner: original.owner,
+        group: original.group,
+        dacl: original.dacl.as_ref().map(Acl::without_inherited),
+        sacl: None,
+    };
+    if let Err(e) = apply_security(&snapshot.path.object_name(), ObjectType::RegistryKey(view), &restored) {
+        warn!("Failed to restore the security of {}: {}", snapshot.path, e);
+    }
+
+    for child in &snapshot.subkeys {
+        restore_snapshot(child, view)?;
+    }
+    Ok(())
+}
+
+fn same_key(a: &KeyPath, b: &KeyPath) -> bool {
+    a.root == b.root && a.subkey.eq_ignore_ascii_case(&b.subkey)
+}
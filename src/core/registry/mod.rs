@@ -0,0 +1,9 @@
+pub mod path;
+pub mod value;
+#[cfg(windows)]
+pub mod key;
+#[cfg(windows)]
+pub mod manager;
+pub mod regfile;
+#[cfg(windows)]
+pub mod hive;
@@ -0,0 +1,218 @@
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::error::{ElevateError, ElevateResult};
+
+const KEY_WOW64_64KEY: u32 = 0x0100;
+const KEY_WOW64_32KEY: u32 = 0x0200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RootKey {
+    ClassesRoot,
+    CurrentUser,
+    LocalMachine,
+    Users,
+    CurrentConfig,
+}
+
+// (root, full name, short name, name used by the named security APIs)
+const ROOT_NAMES: &[(RootKey, &str, &str, &str)] = &[
+    (RootKey::ClassesRoot, "HKEY_CLASSES_ROOT", "HKCR", "CLASSES_ROOT"),
+    (RootKey::CurrentUser, "HKEY_CURRENT_USER", "HKCU", "CURRENT_USER"),
+    (RootKey::LocalMachine, "HKEY_LOCAL_MACHINE", "HKLM", "MACHINE"),
+    (RootKey::Users, "HKEY_USERS", "HKU", "USERS"),
+    (RootKey::CurrentConfig, "HKEY_CURRENT_CONFIG", "HKCC", "CURRENT_CONFIG"),
+];
+
+impl RootKey {
+    pub fn parse(name: &str) -> Option<Self> {
+        ROOT_NAMES
+            .iter()
+            .find(|(_, full, short, _)| name.eq_ignore_ascii_case(full) || name.eq_ignore_ascii_case(short))
+            .map(|(root, ..)| *root)
+    }
+
+    pub fn name(self) -> &'static str {
+        self.names().1
+    }
+
+    fn names(self) -> &'static (RootKey, &'static str, &'static str, &'static str) {
+        ROOT_NAMES.iter().find(|(root, ..)| *root == self).unwrap()
+    }
+}
+
+/// A key such as `HKLM\SOFTWARE\Microsoft`, displayed with the full root name
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyPath {
+    pub root: RootKey,
+    /// path below the root without leading or trailing backslashes, empty for the root itself
+    pub subkey: String,
+}
+
+impl KeyPath {
+    pub fn parse(path: &str) -> ElevateResult<Self> {
+        let path = path.trim().trim_matches('\\');
+        let (root, subkey) = path.split_once('\\').unwrap_or((path, ""));
+
+        let root = RootKey::parse(root)
+            .ok_or_else(|| ElevateError::ValidationError(format!("Unknown registry root in {}", path)))?;
+        if !subkey.is_empty() && subkey.split('\\').any(str::is_empty) {
+            return Err(ElevateError::ValidationError(format!("Empty key name in {}", path)));
+        }
+
+        Ok(Self { root, subkey: subkey.to_string() })
+    }
+
+    pub fn join(&self, child: &str) -> Self {
+        let subkey = if self.subkey.is_empty() {
+            child.to_string()
+        } else if child.is_empty() {
+            self.subkey.clone()
+        } else {
+            format!("{}\\{}", self.subkey, child)
+        };
+        Self { root: self.root, subkey }
+    }
+
+    pub fn parent(&self) -> Option<Self> {
+        if self.subkey.is_empty() {
+            return None;
+        }
+        let subkey = self.subkey.rsplit_once('\\').map_or("", |(parent, _)| parent);
+        Some(Self { root: self.root, subkey: subkey.to_string() })
+    }
+
+    /// Last path component, the root name for a root key
+    pub fn name(&self) -> &str {
+        match self.subkey.rsplit_once('\\') {
+            Some((_, name)) => name,
+            None if self.subkey.is_empty() => self.root.name(),
+            None => &self.subkey,
+        }
+    }
+
+    /// The same key with `HKCU` spelled as the user's hive under `HKU`.
+    /// Impersonation swaps `HKEY_CURRENT_USER` for the profile of SYSTEM.
+    pub fn for_user(&self, sid: &str) -> Self {
+        if self.root != RootKey::CurrentUser {
+            return self.clone();
+        }
+        Self { root: RootKey::Users, subkey: sid.to_string() }.join(&self.subkey)
+    }
+
+    /// Reverses `for_user`, so output names keys the way they were asked for
+    pub fn as_current_user(&self, sid: &str) -> Self {
+        if self.root != RootKey::Users {
+            return self.clone();
+        }
+        let below = match self.subkey.split_once('\\') {
+            Some((hive, below)) if hive.eq_ignore_ascii_case(sid) => below,
+            None if self.subkey.eq_ignore_ascii_case(sid) => "",
+            _ => return self.clone(),
+        };
+        Self { root: RootKey::CurrentUser, subkey: below.to_string() }
+    }
+
+    /// Name understood by `GetNamedSecurityInfoW`, e.g. `MACHINE\SOFTWARE`
+    pub fn object_name(&self) -> String {
+        let prefix = self.root.names().3;
+        if self.subkey.is_empty() {
+            prefix.to_string()
+        } else {
+            format!("{}\\{}", prefix, self.subkey)
+        }
+    }
+}
+
+impl fmt::Display for KeyPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.subkey.is_empty() {
+            write!(f, "{}", self.root.name())
+        } else {
+            write!(f, "{}\\{}", self.root.name(), self.subkey)
+        }
+    }
+}
+
+/// Which registry a 64-bit process sees; redirected keys differ between views
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RegistryView {
+    #[default]
+    Native,
+    Registry32,
+    Registry64,
+}
+
+impl RegistryView {
+    /// `KEY_WOW64_*` flag to add to the requested access
+    pub fn access_flags(self) -> u32 {
+        match self {
+            Self::Native => 0,
+            Self::Registry32 => KEY_WOW64_32KEY,
+            Self::Registry64 => KEY_WOW64_64KEY,
+        }
+    }
+}
+
+impl FromStr for RegistryView {
+    type Err = ElevateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "native" => Ok(Self::Native),
+            "32" => Ok(Self::Registry32),
+            "64" => Ok(Self::Registry64),
+            _ => Err(ElevateError::ValidationError(format!(
+                "Unknown registry view {}, expected 32, 64 or native",
+                s
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key_path() {
+        let key = KeyPath::parse("hklm\\SOFTWARE\\Microsoft\\").unwrap();
+        assert_eq!(key.root, RootKey::LocalMachine);
+        assert_eq!(key.subkey, "SOFTWARE\\Microsoft");
+        assert_eq!(key.to_string(), "HKEY_LOCAL_MACHINE\\SOFTWARE\\Microsoft");
+        assert_eq!(key.object_name(), "MACHINE\\SOFTWARE\\Microsoft");
+        assert_eq!(key.name(), "Microsoft");
+
+        assert_eq!(KeyPath::parse("HKEY_USERS").unwrap().subkey, "");
+        assert!(KeyPath::parse("HKXX\\Software").is_err());
+        assert!(KeyPath::parse("HKCU\\a\\\\b").is_err());
+    }
+
+    #[test]
+    fn test_parent_and_join() {
+        let key = KeyPath::parse("HKCU\\Software\\Vendor").unwrap();
+        let parent = key.parent().unwrap();
+        assert_eq!(parent.to_string(), "HKEY_CURRENT_USER\\Software");
+        assert_eq!(parent.join("Vendor"), key);
+        assert_eq!(parent.parent().unwrap().parent(), None);
+    }
+
+    #[test]
+    fn test_current_user_maps_to_the_users_hive() {
+        let sid = "S-1-5-21-1-2-3-1001";
+        let key = KeyPath::parse("HKCU\\Software\\Vendor").unwrap();
+        let mapped = key.for_user(sid);
+        assert_eq!(mapped.to_string(), "HKEY_USERS\\S-1-5-21-1-2-3-1001\\Software\\Vendor");
+        assert_eq!(mapped.as_current_user(sid), key);
+
+        let root = KeyPath::parse("HKCU").unwrap();
+        assert_eq!(root.for_user(sid).to_string(), "HKEY_USERS\\S-1-5-21-1-2-3-1001");
+        assert_eq!(root.for_user(sid).as_current_user(sid), root);
+
+        let machine = KeyPath::parse("HKLM\\SOFTWARE").unwrap();
+        assert_eq!(machine.for_user(sid), machine);
+        let other = KeyPath::parse("HKU\\S-1-5-18\\Software").unwrap();
+        assert_eq!(other.as_current_user(sid), other);
+    }
+}
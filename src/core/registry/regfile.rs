@@ -0,0 +1,486 @@
+use std::fmt;
+
+use crate::core::registry::path::KeyPath;
+use crate::core::registry::value::{RegValue, REG_EXPAND_SZ, REG_MULTI_SZ, REG_SZ};
+use crate::utils::error::{ElevateError, ElevateResult};
+
+const HEADER_V5: &str = "Windows Registry Editor Version 5.00";
+const HEADER_V4: &str = "REGEDIT4";
+// regedit wraps hex data before this column
+const LINE_WIDTH: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegFileVersion {
+    /// ANSI text, strings in `hex(n):` data are single byte
+    Regedit4,
+    /// UTF-16LE text, strings in `hex(n):` data are UTF-16LE
+    Version5,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueChange {
+    Set(RegValue),
+    /// `"name"=-`
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegFileKey {
+    pub path: KeyPath,
+    /// `[-HKEY_...]` removes the key and everything below it
+    pub delete: bool,
+    pub values: Vec<(String, ValueChange)>,
+}
+
+/// Contents of a `.reg` file as written by regedit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegFile {
+    pub version: RegFileVersion,
+    pub keys: Vec<RegFileKey>,
+}
+
+impl RegFile {
+    /// Decodes UTF-16LE (with BOM), UTF-8 or ANSI bytes and parses them
+    pub fn parse(bytes: &[u8]) -> ElevateResult<Self> {
+        Self::parse_str(&decode_text(bytes))
+    }
+
+    pub fn parse_str(text: &str) -> ElevateResult<Self> {
+        let lines = logical_lines(text);
+        let mut lines = lines.iter().filter(|(_, line)| !line.is_empty() && !line.starts_with(';'));
+
+        let version = match lines.next() {
+            Some((_, header)) if header == HEADER_V5 => RegFileVersion::Version5,
+            Some((_, header)) if header == HEADER_V4 => RegFileVersion::Regedit4,
+            _ => return Err(ElevateError::ValidationError("Missing .reg file header".into())),
+        };
+
+        let mut keys: Vec<RegFileKey> = Vec::new();
+        for (number, line) in lines {
+            let error = |message: &str| ElevateError::ValidationError(format!("Line {}: {}", number, message));
+
+            if let Some(header) = line.strip_prefix('[') {
+                let header = header.strip_suffix(']').ok_or_else(|| error("unterminated key"))?;
+                let (delete, path) = match header.strip_prefix('-') {
+                    Some(path) => (true, path),
+                    None => (false, header),
+                };
+                let path = KeyPath::parse(path).map_err(|e| error(&e.to_string()))?;
+                keys.push(RegFileKey { path, delete, values: Vec::new() });
+                continue;
+            }
+
+            let key = keys.last_mut().ok_or_else(|| error("value outside of a key"))?;
+            if key.delete {
+                return Err(error("values are not allowed under a deleted key"));
+            }
+            let value = parse_value_line(line, version).map_err(|message| error(&message))?;
+            key.values.push(value);
+        }
+
+        Ok(Self { version, keys })
+    }
+
+    /// Encoded the way regedit writes the file, UTF-16LE with a BOM for version 5
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let text = self.to_string();
+        match self.version {
+            RegFileVersion::Version5 => [0xff, 0xfe]
+                .into_iter()
+                .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+                .collect(),
+            RegFileVersion::Regedit4 => text.chars().map(ansi_byte).collect(),
+        }
+    }
+}
+
+impl fmt::Display for RegFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = match self.version {
+            RegFileVersion::Version5 => HEADER_V5,
+            RegFileVersion::Regedit4 => HEADER_V4,
+        };
+        write!(f, "{}\r\n\r\n", header)?;
+
+        for key in &self.keys {
+            if key.delete {
+                write!(f, "[-{}]\r\n", key.path)?;
+            } else {
+                write!(f, "[{}]\r\n", key.path)?;
+            }
+            for (name, change) in &key.values {
+                write!(f, "{}\r\n", format_value_line(name, change, self.version))?;
+            }
+            write!(f, "\r\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// One difference between a `.reg` file and the live registry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegChange {
+    CreateKey(KeyPath),
+    DeleteKey(KeyPath),
+    SetValue { key: KeyPath, name: String, old: Option<RegValue>, new: RegValue },
+    DeleteValue { key: KeyPath, name: String, old: RegValue },
+}
+
+impl fmt::Display for RegChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CreateKey(key) => write!(f, "+ [{}]", key),
+            Self::DeleteKey(key) => write!(f, "- [{}]", key),
+            Self::SetValue { key, name, old: None, new } => {
+                write!(f, "+ {}\\{} = {} {}", key, display_name(name), new.type_name(), new)
+            }
+            Self::SetValue { key, name, old: Some(old), new } => write!(
+                f,
+                "~ {}\\{} = {} {} (was {} {})",
+                key,
+                display_name(name),
+                new.type_name(),
+                new,
+                old.type_name(),
+                old
+            ),
+            Self::DeleteValue { key, name, old } => {
+                write!(f, "- {}\\{} (was {} {})", key, display_name(name), old.type_name(), old)
+            }
+        }
+    }
+}
+
+/// Changes needed to bring a live key, `None` if missing, in line with a file entry
+pub fn diff_key(entry: &RegFileKey, live: Option<&[(String, RegValue)]>) -> Vec<RegChange> {
+    let mut changes = Vec::new();
+
+    if entry.delete {
+        if live.is_some() {
+            changes.push(RegChange::DeleteKey(entry.path.clone()));
+        }
+        return changes;
+    }
+
+    if live.is_none() {
+        changes.push(RegChange::CreateKey(entry.path.clone()));
+    }
+    for (name, change) in &entry.values {
+        let old = live
+            .unwrap_or_default()
+            .iter()
+            .find(|(live_name, _)| live_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone());
+
+        match (change, old) {
+            (ValueChange::Set(new), old) if old.as_ref() != Some(new) => changes.push(RegChange::SetValue {
+                key: entry.path.clone(),
+                name: name.clone(),
+                old,
+                new: new.clone(),
+            }),
+            (ValueChange::Delete, Some(old)) => changes.push(RegChange::DeleteValue {
+                key: entry.path.clone(),
+                name: name.clone(),
+                old,
+            }),
+            _ => {}
+        }
+    }
+    changes
+}
+
+fn decode_text(bytes: &[u8]) -> String {
+    if let Some(utf16) = bytes.strip_prefix(&[0xff, 0xfe]) {
+        let units: Vec<u16> = utf16.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+        return String::from_utf16_lossy(&units);
+    }
+
+    let bytes = bytes.strip_prefix(&[0xef, 0xbb, 0xbf]).unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        // REGEDIT4 files are in the ANSI code page, read them as Latin-1
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+// joins value lines ending in a backslash with the next one, keeping the
+// first line number; keys and comments never continue
+fn logical_lines(text: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let (number, mut line) = match pending.take() {
+            Some((number, mut joined)) => {
+                joined.push_str(line.trim_start());
+                (number, joined)
+            }
+            None => (index + 1, line.trim().to_string()),
+        };
+
+        let trimmed = line.trim_end().len();
+        line.truncate(trimmed);
+        if !line.starts_with(['[', ';']) && line.ends_with('\\') {
+            line.pop();
+            pending = Some((number, line));
+        } else {
+            lines.push((number, line));
+        }
+    }
+    lines.extend(pending);
+    lines
+}
+
+fn parse_value_line(line: &str, version: RegFileVersion) -> Result<(String, ValueChange), String> {
+    let (name, rest) = match line.strip_prefix('@') {
+        Some(rest) => (String::new(), rest),
+        None => parse_quoted(line)?,
+    };
+    let data = rest
+        .trim_start()
+        .strip_prefix('=')
+        .ok_or("expected = after the value name")?
+        .trim();
+
+    if data == "-" {
+        return Ok((name, ValueChange::Delete));
+    }
+    if data.starts_with('"') {
+        let (text, rest) = parse_quoted(data)?;
+        if !rest.trim().is_empty() {
+            return Err(format!("unexpected text after string: {}", rest));
+        }
+        return Ok((name, ValueChange::Set(RegValue::String(text))));
+    }
+    if let Some(hex) = data.strip_prefix("dword:") {
+        let value = u32::from_str_radix(hex.trim(), 16).map_err(|_| format!("invalid dword: {}", hex))?;
+        return Ok((name, ValueChange::Set(RegValue::Dword(value))));
+    }
+
+    let (kind, hex) = if let Some(hex) = data.strip_prefix("hex:") {
+        (3, hex)
+    } else if let Some(rest) = data.strip_prefix("hex(") {
+        let (kind, hex) = rest.split_once("):").ok_or("unterminated hex type")?;
+        let kind = u32::from_str_radix(kind, 16).map_err(|_| format!("invalid hex type: {}", kind))?;
+        (kind, hex)
+    } else {
+        return Err(format!("unknown value data: {}", data));
+    };
+
+    let bytes = parse_hex_list(hex)?;
+    let value = match (version, kind) {
+        (RegFileVersion::Regedit4, REG_SZ | REG_EXPAND_SZ | REG_MULTI_SZ) => {
+            let wide: Vec<u8> = bytes.iter().flat_map(|&b| (b as u16).to_le_bytes()).collect();
+            RegValue::from_raw(kind, &wide)
+        }
+        _ => RegValue::from_raw(kind, &bytes),
+    };
+    Ok((name, ValueChange::Set(value)))
+}
+
+// parses a "quoted" string with \\ and \" escapes, returning it and the rest of the line
+fn parse_quoted(text: &str) -> Result<(String, &str), String> {
+    let body = text.strip_prefix('"').ok_or("expected a quoted string")?;
+    let mut value = String::new();
+    let mut chars = body.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &body[index + 1..])),
+            '\\' => match chars.next() {
+                Some((_, escaped @ ('\\' | '"'))) => value.push(escaped),
+                Some((_, other)) => {
+                    value.push('\\');
+                    value.push(other);
+                }
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    Err("unterminated string".into())
+}
+
+fn parse_hex_list(text: &str) -> Result<Vec<u8>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| u8::from_str_radix(item, 16).map_err(|_| format!("invalid hex byte: {}", item)))
+        .collect()
+}
+
+fn format_value_line(name: &str, change: &ValueChange, version: RegFileVersion) -> String {
+    let name = if name.is_empty() { "@".to_string() } else { quote(name) };
+    let value = match change {
+        ValueChange::Delete => return format!("{}=-", name),
+        ValueChange::Set(value) => value,
+    };
+
+    match value {
+        // regedit only writes single line strings in quotes
+        RegValue::String(s) if !s.contains(['\0', '\r', '\n']) => format!("{}={}", name, quote(s)),
+        RegValue::Dword(v) => format!("{}=dword:{:08x}", name, v),
+        RegValue::Binary(data) => format_hex(format!("{}=hex:", name), data),
+        value => {
+            let data = match value.kind() {
+                // REGEDIT4 keeps string data in single byte characters
+                REG_SZ | REG_EXPAND_SZ | REG_MULTI_SZ if version == RegFileVersion::Regedit4 => value
+                    .to_raw()
+                    .chunks_exact(2)
+                    .map(|c| char::from_u32(u16::from_le_bytes([c[0], c[1]]) as u32).map_or(b'?', ansi_byte))
+                    .collect(),
+                _ => value.to_raw(),
+            };
+            format_hex(format!("{}=hex({:x}):", name, value.kind()), &data)
+        }
+    }
+}
+
+// comma separated bytes, wrapped with a trailing backslash like regedit does
+fn format_hex(prefix: String, data: &[u8]) -> String {
+    let mut out = String::new();
+    let mut line = prefix;
+
+    for (index, byte) in data.iter().enumerate() {
+        let mut item = format!("{:02x}", byte);
+        if index + 1 < data.len() {
+            item.push(',');
+        }
+        if line.len() + item.len() > LINE_WIDTH - 2 {
+            out.push_str(&line);
+            out.push_str("\\\r\n");
+            line = "  ".to_string();
+        }
+        line.push_str(&item);
+    }
+    out.push_str(&line);
+    out
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn ansi_byte(c: char) -> u8 {
+    u8::try_from(c as u32).unwrap_or(b'?')
+}
+
+fn display_name(name: &str) -> &str {
+    if name.is_empty() { "(Default)" } else { name }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V5_FIXTURE: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/reg/version5.reg"));
+    const V4_FIXTURE: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/reg/regedit4.reg"));
+
+    fn value<'a>(file: &'a RegFile, key: usize, name: &str) -> &'a ValueChange {
+        &file.keys[key].values.iter().find(|(n, _)| n == name).unwrap().1
+    }
+
+    #[test]
+    fn test_parse_version5_fixture() {
+        let file = RegFile::parse(V5_FIXTURE).unwrap();
+        assert_eq!(file.version, RegFileVersion::Version5);
+        assert_eq!(file.keys.len(), 3);
+        assert_eq!(file.keys[0].path.to_string(), "HKEY_LOCAL_MACHINE\\SOFTWARE\\ElevateKit Test");
+
+        assert_eq!(value(&file, 0, ""), &ValueChange::Set(RegValue::String("default".into())));
+        assert_eq!(value(&file, 0, "Path"), &ValueChange::Set(RegValue::String("C:\\Program Files\\\"Quoted\"".into())));
+        assert_eq!(value(&file, 0, "Level"), &ValueChange::Set(RegValue::Dword(0x10)));
+        assert_eq!(value(&file, 0, "Big"), &ValueChange::Set(RegValue::Qword(0x0102_0304_0506_0708)));
+        assert_eq!(
+            value(&file, 0, "Expand"),
+            &ValueChange::Set(RegValue::ExpandString("%SystemRoot%\\system32".into()))
+        );
+        assert_eq!(
+            value(&file, 0, "Multi"),
+            &ValueChange::Set(RegValue::MultiString(vec!["first".into(), "second".into()]))
+        );
+        assert_eq!(value(&file, 0, "Blob"), &ValueChange::Set(RegValue::Binary((0..40).collect())));
+        assert_eq!(value(&file, 0, "Ünïcödé"), &ValueChange::Set(RegValue::String("日本語".into())));
+        assert_eq!(value(&file, 0, "Old"), &ValueChange::Delete);
+
+        assert!(file.keys[2].delete);
+        assert!(file.keys[2].values.is_empty());
+    }
+
+    #[test]
+    fn test_parse_regedit4_fixture() {
+        let file = RegFile::parse(V4_FIXTURE).unwrap();
+        assert_eq!(file.version, RegFileVersion::Regedit4);
+        assert_eq!(value(&file, 0, "Name"), &ValueChange::Set(RegValue::String("café".into())));
+        assert_eq!(
+            value(&file, 0, "Expand"),
+            &ValueChange::Set(RegValue::ExpandString("%windir%\\café".into()))
+        );
+        assert_eq!(value(&file, 0, "Blob"), &ValueChange::Set(RegValue::Binary(vec![0xaa, 0xbb, 0xcc, 0xdd])));
+        assert_eq!(value(&file, 0, "Flags"), &ValueChange::Set(RegValue::Dword(0xffff_ffff)));
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        for (fixture, version) in [(V5_FIXTURE, RegFileVersion::Version5), (V4_FIXTURE, RegFileVersion::Regedit4)] {
+            let file = RegFile::parse(fixture).unwrap();
+            let written = file.to_bytes();
+            assert_eq!(written.starts_with(&[0xff, 0xfe]), version == RegFileVersion::Version5);
+            assert_eq!(RegFile::parse(&written).unwrap(), file);
+        }
+    }
+
+    #[test]
+    fn test_hex_lines_are_wrapped() {
+        let line = format_value_line("Blob", &ValueChange::Set(RegValue::Binary(vec![0; 64])), RegFileVersion::Version5);
+        let lines: Vec<&str> = line.split("\r\n").collect();
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|l| l.len() <= LINE_WIDTH));
+        assert!(lines[1].starts_with("  00,"));
+    }
+
+    #[test]
+    fn test_parse_errors_have_line_numbers() {
+        let error = RegFile::parse_str("REGEDIT4\n\n[HKEY_CURRENT_USER\\Software]\n\"x\"=dword:zz\n").unwrap_err();
+        assert!(error.to_string().contains("Line 4"));
+        assert!(RegFile::parse_str("\"x\"=\"y\"\n").is_err());
+        assert!(RegFile::parse_str("REGEDIT4\n\"x\"=\"y\"\n").is_err());
+    }
+
+    #[test]
+    fn test_comment_ending_in_backslash_does_not_continue() {
+        let file = RegFile::parse_str("REGEDIT4\n\n[HKEY_CURRENT_USER\\Software]\n; C:\\Tools\\\n\"x\"=\"y\"\n").unwrap();
+        assert_eq!(file.keys[0].values, [("x".to_string(), ValueChange::Set(RegValue::String("y".into())))]);
+    }
+
+    #[test]
+    fn test_diff_key() {
+        let file = RegFile::parse_str(
+            "REGEDIT4\n\n[HKEY_CURRENT_USER\\Software\\Test]\n\"Same\"=dword:00000001\n\"New\"=\"a\"\n\"Changed\"=\"b\"\n\"Gone\"=-\n\"Absent\"=-\n\n[-HKEY_CURRENT_USER\\Software\\Old]\n",
+        )
+        .unwrap();
+        let live = vec![
+            ("same".to_string(), RegValue::Dword(1)),
+            ("Changed".to_string(), RegValue::String("old".into())),
+            ("Gone".to_string(), RegValue::Binary(vec![1])),
+        ];
+
+        let changes: Vec<String> = diff_key(&file.keys[0], Some(&live)).iter().map(ToString::to_string).collect();
+        assert_eq!(
+            changes,
+            vec![
+                "+ HKEY_CURRENT_USER\\Software\\Test\\New = REG_SZ a",
+                "~ HKEY_CURRENT_USER\\Software\\Test\\Changed = REG_SZ b (was REG_SZ old)",
+                "- HKEY_CURRENT_USER\\Software\\Test\\Gone (was REG_BINARY 01)",
+            ]
+        );
+
+        let created = diff_key(&file.keys[0], None);
+        assert_eq!(created[0], RegChange::CreateKey(file.keys[0].path.clone()));
+        assert_eq!(created.len(), 4);
+
+        assert!(diff_key(&file.keys[1], None).is_empty());
+        assert_eq!(diff_key(&file.keys[1], Some(&[])), vec![RegChange::DeleteKey(file.keys[1].path.clone())]);
+    }
+}
@@ -0,0 +1,206 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::error::{ElevateError, ElevateResult};
+
+pub const REG_NONE: u32 = 0;
+pub const REG_SZ: u32 = 1;
+pub const REG_EXPAND_SZ: u32 = 2;
+pub const REG_BINARY: u32 = 3;
+pub const REG_DWORD: u32 = 4;
+pub const REG_MULTI_SZ: u32 = 7;
+pub const REG_QWORD: u32 = 11;
+
+/// A registry value decoded from its raw type and bytes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum RegValue {
+    None,
+    String(String),
+    ExpandString(String),
+    Binary(Vec<u8>),
+    Dword(u32),
+    MultiString(Vec<String>),
+    Qword(u64),
+    /// any other type, or data too short for its declared type
+    Other { kind: u32, data: Vec<u8> },
+}
+
+impl RegValue {
+    pub fn from_raw(kind: u32, data: &[u8]) -> Self {
+        match kind {
+            REG_NONE if data.is_empty() => Self::None,
+            REG_SZ => Self::String(decode_string(data)),
+            REG_EXPAND_SZ => Self::ExpandString(decode_string(data)),
+            REG_BINARY => Self::Binary(data.to_vec()),
+            REG_DWORD if data.len() == 4 => Self::Dword(u32::from_le_bytes(data.try_into().unwrap())),
+            REG_QWORD if data.len() == 8 => Self::Qword(u64::from_le_bytes(data.try_into().unwrap())),
+            REG_MULTI_SZ => {
+                let text = decode_string(data);
+                let mut strings: Vec<String> = text.split('\0').map(str::to_string).collect();
+                // the list ends with an empty string
+                while strings.last().is_some_and(String::is_empty) {
+                    strings.pop();
+                }
+                Self::MultiString(strings)
+            }
+            _ => Self::Other { kind, data: data.to_vec() },
+        }
+    }
+
+    pub fn kind(&self) -> u32 {
+        match self {
+            Self::None => REG_NONE,
+            Self::String(_) => REG_SZ,
+            Self::ExpandString(_) => REG_EXPAND_SZ,
+            Self::Binary(_) => REG_BINARY,
+            Self::Dword(_) => REG_DWORD,
+            Self::MultiString(_) => REG_MULTI_SZ,
+            Self::Qword(_) => REG_QWORD,
+            Self::Other { kind, .. } => *kind,
+        }
+    }
+
+    /// Bytes as stored by `RegSetValueExW`, strings NUL terminated
+    pub fn to_raw(&self) -> Vec<u8> {
+        match self {
+            Self::None => Vec::new(),
+            Self::String(s) | Self::ExpandString(s) => encode_string(s),
+            Self::Binary(data) | Self::Other { data, .. } => data.clone(),
+            Self::Dword(v) => v.to_le_bytes().to_vec(),
+            Self::Qword(v) => v.to_le_bytes().to_vec(),
+            Self::MultiString(strings) => {
+                let mut data = Vec::new();
+                for s in strings {
+                    data.extend(encode_string(s));
+                }
+                data.extend([0, 0]);
+                data
+            }
+        }
+    }
+
+    pub fn type_name(&self) -> String {
+        match self.kind() {
+            REG_NONE => "REG_NONE".into(),
+            REG_SZ => "REG_SZ".into(),
+            REG_EXPAND_SZ => "REG_EXPAND_SZ".into(),
+            REG_BINARY => "REG_BINARY".into(),
+            REG_DWORD => "REG_DWORD".into(),
+            REG_MULTI_SZ => "REG_MULTI_SZ".into(),
+            REG_QWORD => "REG_QWORD".into(),
+            kind => format!("REG_0x{:x}", kind),
+        }
+    }
+
+    /// Parses command line data the way `reg.exe add` does: numbers in decimal
+    /// or `0x` hex, binary as hex digits and `\0` between MULTI_SZ strings.
+    pub fn parse(type_name: &str, data: &str) -> ElevateResult<Self> {
+        let invalid = |what: &str| ElevateError::ValidationError(format!("Invalid {} data: {}", what, data));
+
+        match type_name.to_ascii_uppercase().as_str() {
+            "REG_SZ" => Ok(Self::String(data.to_string())),
+            "REG_EXPAND_SZ" => Ok(Self::ExpandString(data.to_string())),
+            "REG_MULTI_SZ" if data.is_empty() => Ok(Self::MultiString(Vec::new())),
+            "REG_MULTI_SZ" => Ok(Self::MultiString(data.split("\\0").map(str::to_string).collect())),
+            "REG_DWORD" => parse_number(data)
+                .and_then(|v| u32::try_from(v).ok())
+                .map(Self::Dword)
+                .ok_or_else(|| invalid("REG_DWORD")),
+            "REG_QWORD" => parse_number(data).map(Self::Qword).ok_or_else(|| invalid("REG_QWORD")),
+            "REG_BINARY" => parse_hex_bytes(data).map(Self::Binary).ok_or_else(|| invalid("REG_BINARY")),
+            "REG_NONE" => Ok(Self::None),
+            other => Err(ElevateError::ValidationError(format!("Unknown registry type: {}", other))),
+        }
+    }
+}
+
+impl fmt::Display for RegValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => Ok(()),
+            Self::String(s) | Self::ExpandString(s) => write!(f, "{}", s),
+            Self::Dword(v) => write!(f, "0x{:08x} ({})", v, v),
+            Self::Qword(v) => write!(f, "0x{:016x} ({})", v, v),
+            Self::MultiString(strings) => write!(f, "{}", strings.join("\\0")),
+            Self::Binary(data) | Self::Other { data, .. } => {
+                let bytes: Vec<String> = data.iter().map(|b| format!("{:02x}", b)).collect();
+                write!(f, "{}", bytes.join(" "))
+            }
+        }
+    }
+}
+
+fn decode_string(data: &[u8]) -> String {
+    let mut units: Vec<u16> = data.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+    if units.last() == Some(&0) {
+        units.pop();
+    }
+    String::from_utf16_lossy(&units)
+}
+
+fn encode_string(s: &str) -> Vec<u8> {
+    s.encode_utf16().chain(std::iter::once(0)).flat_map(u16::to_le_bytes).collect()
+}
+
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    let digits: String = text.chars().filter(|c| !matches!(c, ',' | ' ')).collect();
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_round_trip() {
+        let values = [
+            RegValue::None,
+            RegValue::String("C:\\Windows".into()),
+            RegValue::ExpandString("%SystemRoot%\\system32".into()),
+            RegValue::Binary(vec![0xde, 0xad, 0xbe, 0xef]),
+            RegValue::Dword(0x8000_0001),
+            RegValue::Qword(u64::MAX),
+            RegValue::MultiString(vec!["one".into(), "two".into()]),
+            RegValue::MultiString(Vec::new()),
+            RegValue::Other { kind: 10, data: vec![1, 2] },
+        ];
+        for value in values {
+            assert_eq!(RegValue::from_raw(value.kind(), &value.to_raw()), value);
+        }
+    }
+
+    #[test]
+    fn test_from_raw_tolerates_missing_terminators() {
+        let data: Vec<u8> = "abc".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        assert_eq!(RegValue::from_raw(REG_SZ, &data), RegValue::String("abc".into()));
+        assert_eq!(RegValue::from_raw(REG_DWORD, &[1, 0]), RegValue::Other { kind: REG_DWORD, data: vec![1, 0] });
+    }
+
+    #[test]
+    fn test_parse_command_line_data() {
+        assert_eq!(RegValue::parse("reg_dword", "0x10").unwrap(), RegValue::Dword(16));
+        assert_eq!(RegValue::parse("REG_QWORD", "42").unwrap(), RegValue::Qword(42));
+        assert_eq!(RegValue::parse("REG_BINARY", "01,ff 0a").unwrap(), RegValue::Binary(vec![1, 0xff, 10]));
+        assert_eq!(
+            RegValue::parse("REG_MULTI_SZ", "a\\0b").unwrap(),
+            RegValue::MultiString(vec!["a".into(), "b".into()])
+        );
+        assert!(RegValue::parse("REG_DWORD", "0x100000000").is_err());
+        assert!(RegValue::parse("REG_BINARY", "abc").is_err());
+        assert!(RegValue::parse("REG_LINK", "x").is_err());
+    }
+}
@@ -0,0 +1,140 @@
+//! When `run --supervise` restarts its child: after a non-zero exit or a
+//! crash, waiting twice as long after each restart in a row. A child that ran
+//! for the cooldown counts as healthy again, and too many restarts in a row
+//! give up. The policy only sees times, so it is tested on a fake clock.
+
+use std::time::{Duration, Instant};
+
+use crate::utils::config::SupervisorConfig;
+
+/// What to do once the child has exited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// it exited cleanly, nothing more to do
+    Done,
+    Restart(Duration),
+    GiveUp,
+}
+
+/// When to restart a child, from how it exited and how long it ran
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    max_restarts: u32,
+    cooldown: Duration,
+    restarts: u32,
+}
+
+impl RestartPolicy {
+    pub fn new(config: &SupervisorConfig) -> Self {
+        Self {
+            initial_delay: Duration::from_secs(config.initial_delay),
+            max_delay: Duration::from_secs(config.max_delay),
+            max_restarts: config.max_restarts,
+            cooldown: Duration::from_secs(config.cooldown),
+            restarts: 0,
+        }
+    }
+
+    /// Restarts in a row so far
+    pub fn restarts(&self) -> u32 {
+        self.restarts
+    }
+
+    pub fn max_restarts(&self) -> u32 {
+        self.max_restarts
+    }
+
+    /// Decides about a child that started at `started` and exited with `code` at `now`
+    pub fn on_exit(&mut self, code: u32, started: Instant, now: Instant) -> Decision {
+        if code == 0 {
+            return Decision::Done;
+        }
+        if now.saturating_duration_since(started) >= self.cooldown {
+            self.restarts = 0;
+        }
+        if self.restarts >= self.max_restarts {
+            return Decision::GiveUp;
+        }
+
+        let delay = self.initial_delay.saturating_mul(1u32 << self.restarts.min(31)).min(self.max_delay);
+        self.restarts += 1;
+        Decision::Restart(delay)
+    }
+}
+
+/// An exit code in words, naming the statuses a crash leaves
+pub fn describe_exit(code: u32) -> String {
+    let crash = match code {
+        0x8000_0003 => "breakpoint",
+        0xC000_0005 => "access violation",
+        0xC000_0017 => "out of memory",
+        0xC000_001D => "illegal instruction",
+        0xC000_0094 => "integer division by zero",
+        0xC000_00FD => "stack overflow",
+        0xC000_0135 => "a DLL was not found",
+        0xC000_0142 => "a DLL failed to initialize",
+        0xC000_013A => "ended by Ctrl+C",
+        0xC000_0374 => "heap corruption",
+        0xC000_0409 => "fail fast or stack buffer overrun",
+        // error severity, as NTSTATUS codes of a crash have
+        code if code >= 0xC000_0000 => return format!("crashed with status {:#010X}", code),
+        code => return format!("exit code {}", code),
+    };
+    format!("crashed with status {:#010X} ({})", code, crash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::time::{Clock, FakeClock};
+
+    fn policy() -> RestartPolicy {
+        RestartPolicy::new(&SupervisorConfig { initial_delay: 1, max_delay: 5, max_restarts: 4, cooldown: 60, stop_timeout: 10 })
+    }
+
+    // a child that runs for `secs` and exits with `code`
+    fn run(policy: &mut RestartPolicy, clock: &FakeClock, secs: u64, code: u32) -> Decision {
+        let started = clock.now();
+        clock.sleep(Duration::from_secs(secs));
+        policy.on_exit(code, started, clock.now())
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_maximum() {
+        let (mut policy, clock) = (policy(), FakeClock::new());
+        let delays: Vec<_> = (0..4).map(|_| run(&mut policy, &clock, 2, 1)).collect();
+
+        let secs = |secs| Decision::Restart(Duration::from_secs(secs));
+        assert_eq!(delays, [secs(1), secs(2), secs(4), secs(5)]);
+        assert_eq!(run(&mut policy, &clock, 2, 0xC000_0005), Decision::GiveUp);
+    }
+
+    #[test]
+    fn test_cooldown_starts_the_count_over() {
+        let (mut policy, clock) = (policy(), FakeClock::new());
+        for _ in 0..4 {
+            run(&mut policy, &clock, 1, 1);
+        }
+        assert_eq!(policy.restarts(), 4);
+
+        assert_eq!(run(&mut policy, &clock, 60, 1), Decision::Restart(Duration::from_secs(1)));
+        assert_eq!(policy.restarts(), 1);
+    }
+
+    #[test]
+    fn test_clean_exit_is_not_restarted() {
+        let (mut policy, clock) = (policy(), FakeClock::new());
+        run(&mut policy, &clock, 1, 1);
+
+        assert_eq!(run(&mut policy, &clock, 1, 0), Decision::Done);
+    }
+
+    #[test]
+    fn test_exit_codes_are_decoded() {
+        assert_eq!(describe_exit(2), "exit code 2");
+        assert_eq!(describe_exit(0xC000_0005), "crashed with status 0xC0000005 (access violation)");
+        assert_eq!(describe_exit(0xC0DE_0001), "crashed with status 0xC0DE0001");
+    }
+}
@@ -0,0 +1,192 @@
+//! Runs started with `run --detach`, remembered across invocations in
+//! `runs.json`. Besides the pid a record holds the creation time of the
+//! process, so a later process that reuses the pid is never taken for the run.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use winapi::shared::{minwindef::DWORD, winerror::ERROR_INVALID_PARAMETER};
+use winapi::um::winnt::{PROCESS_QUERY_LIMITED_INFORMATION, SYNCHRONIZE};
+
+use crate::core::handle::OwnedHandle;
+use crate::core::process::creation_time;
+use crate::core::terminate::{exit_code, open};
+use crate::utils::config::Config;
+use crate::utils::error::{ElevateError, ElevateResult};
+use crate::utils::files::file_error;
+use crate::utils::time::unix_timestamp;
+
+const RUNS_FILE: &str = "runs.json";
+// finished runs stay listed for a day after they are first seen finished
+const FINISHED_RETENTION: u64 = 24 * 60 * 60;
+
+/// A command started by `run --detach`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub id: String,
+    pub pid: u32,
+    /// the command line it was started with
+    pub command: String,
+    /// seconds since the unix epoch
+    pub started_at: u64,
+    /// creation time of the process, as `process::creation_time` reads it
+    pub created: u64,
+    /// seconds since the unix epoch when the run was first seen finished
+    #[serde(default)]
+    pub finished_at: Option<u64>,
+    /// known only if the process could still be read after it exited
+    #[serde(default)]
+    pub exit_code: Option<u32>,
+}
+
+impl RunRecord {
+    pub fn is_running(&self) -> bool {
+        self.finished_at.is_none()
+    }
+
+    /// The run's process while it runs, opened with `access` besides
+    /// `SYNCHRONIZE` and limited queries. Notes when it has finished, with its
+    /// exit code if that can still be read.
+    pub fn open(&mut self, access: DWORD) -> ElevateResult<Option<OwnedHandle>> {
+        if !self.is_running() {
+            return Ok(None);
+        }
+        let process = match open(self.pid, access | SYNCHRONIZE | PROCESS_QUERY_LIMITED_INFORMATION) {
+            Ok(process) => process,
+            // no process has the id any more
+            Err(ElevateError::Windows { code, .. }) if code == ERROR_INVALID_PARAMETER as i32 => {
+                self.finish(None);
+                return Ok(None);
+            }
+            Err(error) => return Err(error),
+        };
+        if creation_time(&process)? != self.created {
+            self.finish(None);
+            return Ok(None);
+        }
+        if process.wait(Some(Duration::ZERO))? {
+            self.finish(Some(exit_code(&process)?));
+            return Ok(None);
+        }
+        Ok(Some(process))
+    }
+
+    /// Notes that the run has exited, with its exit code if known
+    pub fn finish(&mut self, exit_code: Option<u32>) {
+        self.finished_at.get_or_insert_with(unix_timestamp);
+        self.exit_code = self.exit_code.or(exit_code);
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct RunStore {
+    runs: Vec<RunRecord>,
+}
+
+/// Remembers detached runs across invocations in `runs.json`
+pub struct RunRegistry {
+    store_path: PathBuf,
+}
+
+impl RunRegistry {
+    pub fn new() -> ElevateResult<Self> {
+        Ok(Self { store_path: Config::state_dir()?.join(RUNS_FILE) })
+    }
+
+    /// Running runs and those finished within the last day, oldest first.
+    /// Records of runs finished before that are pruned.
+    pub fn list(&self) -> ElevateResult<Vec<RunRecord>> {
+        let mut store = read_store(&self.store_path)?;
+        let before = store.runs.clone();
+        for run in &mut store.runs {
+            // a run that cannot be read is left as it was
+            let _ = run.open(0);
+        }
+        prune(&mut store.runs, unix_timestamp());
+        if store.runs != before {
+            write_store(&self.store_path, &store)?;
+        }
+        Ok(store.runs)
+    }
+
+    pub fn find(&self, id: &str) -> ElevateResult<RunRecord> {
+        self.list()?
+            .into_iter()
+            .find(|run| run.id.eq_ignore_ascii_case(id))
+            .ok_or_else(|| ElevateError::ValidationError(format!("No run {}, see `runs list`", id)))
+    }
+
+    pub fn add(&self, run: RunRecord) -> ElevateResult<()> {
+        let mut store = read_store(&self.store_path)?;
+        store.runs.push(run);
+        write_store(&self.store_path, &store)
+    }
+
+    /// Saves what was learned about a run, such as its exit code
+    pub fn update(&self, run: &RunRecord) -> ElevateResult<()> {
+        let mut store = read_store(&self.store_path)?;
+        if let Some(stored) = store.runs.iter_mut().find(|stored| stored.id == run.id) {
+            *stored = run.clone();
+        }
+        write_store(&self.store_path, &store)
+    }
+}
+
+fn prune(runs: &mut Vec<RunRecord>, now: u64) {
+    runs.retain(|run| run.finished_at.map_or(true, |finished| now.saturating_sub(finished) < FINISHED_RETENTION));
+}
+
+fn read_store(path: &Path) -> ElevateResult<RunStore> {
+    if !path.exists() {
+        return Ok(RunStore::default());
+    }
+
+    let contents = fs::read_to_string(path).map_err(|e| file_error(e, "Failed to read runs"))?;
+    serde_json::from_str(&contents).map_err(|e| ElevateError::ConfigError(format!("Failed to parse runs: {}", e)))
+}
+
+fn write_store(path: &Path, store: &RunStore) -> ElevateResult<()> {
+    let contents = serde_json::to_string_pretty(store)
+        .map_err(|e| ElevateError::ConfigError(format!("Failed to serialize runs: {}", e)))?;
+    fs::write(path, contents).map_err(|e| file_error(e, "Failed to write runs"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(id: &str, finished_at: Option<u64>) -> RunRecord {
+        RunRecord {
+            id: id.into(),
+            pid: 4242,
+            command: "cmd.exe /c build.cmd".into(),
+            started_at: 1_000,
+            created: 133_000_000_000_000_000,
+            finished_at,
+            exit_code: None,
+        }
+    }
+
+    #[test]
+    fn test_finished_runs_are_pruned_after_a_day() {
+        let now = 1_000_000;
+        let mut runs = vec![run("running", None), run("recent", Some(now - 60)), run("stale", Some(now - FINISHED_RETENTION))];
+        prune(&mut runs, now);
+
+        let ids: Vec<_> = runs.iter().map(|run| run.id.as_str()).collect();
+        assert_eq!(ids, ["running", "recent"]);
+    }
+
+    #[test]
+    fn test_finish_keeps_what_was_seen_first() {
+        let mut record = run("a", None);
+        record.finish(Some(3));
+        let finished_at = record.finished_at;
+        record.finish(None);
+
+        assert!(!record.is_running());
+        assert_eq!(record.exit_code, Some(3));
+        assert_eq!(record.finished_at, finished_at);
+    }
+}
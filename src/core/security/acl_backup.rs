@@ -0,0 +1,264 @@
+//! ACL backup files, an `icacls /save` replacement with a documented JSON format:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "created_at": 1760000000,
+//!   "root": "\\\\?\\C:\\Windows\\Web",
+//!   "entries": [
+//!     {
+//!       "path": "\\\\?\\C:\\Windows\\Web",
+//!       "owner": "S-1-5-80-956008885-3418522649-1831038044-1853292631-2271478464",
+//!       "group": "S-1-5-80-956008885-3418522649-1831038044-1853292631-2271478464",
+//!       "dacl": "PAI(A;;FA;;;S-1-5-80-...)(A;OICIIO;GA;;;CO)(A;;0x1200a9;;;BA)",
+//!       "sacl": null,
+//!       "hash": "<sha256 of the canonical SDDL>"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Owner and group are SIDs or SDDL aliases, `dacl` and `sacl` are the ACL part
+//! of SDDL after `D:` and `S:`. `hash` is the SHA-256 of the canonical SDDL built
+//! from the other fields, so edited entries are detected and unchanged objects
+//! can be skipped on restore.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::core::elevation::impersonation::{TrustedInstallerImpersonation, SECURITY_PRIVILEGES};
+use crate::core::security::descriptor::{apply_security, read_security, ObjectType};
+use crate::core::security::sddl::{Acl, SecurityDescriptor};
+use crate::utils::error::{ElevateError, ElevateResult};
+use crate::utils::files::{canonical_path, collect_paths, file_error, path_name};
+use crate::utils::hash::sha256_hex;
+use crate::utils::time::unix_timestamp;
+
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AclBackup {
+    pub version: u32,
+    /// seconds since the unix epoch
+    pub created_at: u64,
+    pub root: PathBuf,
+    pub entries: Vec<AclEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AclEntry {
+    pub path: PathBuf,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    pub dacl: Option<String>,
+    pub sacl: Option<String>,
+    pub hash: String,
+}
+
+impl AclEntry {
+    pub fn new(path: PathBuf, descriptor: &SecurityDescriptor) -> Self {
+        Self {
+            path,
+            owner: descriptor.owner.as_ref().map(ToString::to_string),
+            group: descriptor.group.as_ref().map(ToString::to_string),
+            dacl: descriptor.dacl.as_ref().map(ToString::to_string),
+            sacl: descriptor.sacl.as_ref().map(ToString::to_string),
+            hash: descriptor_hash(descriptor),
+        }
+    }
+
+    /// Rebuilds the descriptor, refusing entries whose hash no longer matches
+    pub fn descriptor(&self) -> ElevateResult<SecurityDescriptor> {
+        let mut sddl = String::new();
+        for (tag, part) in [("O", &self.owner), ("G", &self.group), ("D", &self.dacl), ("S", &self.sacl)] {
+            if let Some(part) = part {
+                sddl.push_str(&format!("{}:{}", tag, part));
+            }
+        }
+
+        let descriptor = SecurityDescriptor::parse(&sddl)?;
+        if descriptor_hash(&descriptor) != self.hash {
+            return Err(ElevateError::ValidationError(format!(
+                "Backup entry for {} does not match its hash",
+                self.path.display()
+            )));
+        }
+        Ok(descriptor)
+    }
+}
+
+impl AclBackup {
+    pub fn read(file: &Path) -> ElevateResult<Self> {
+        let contents = fs::read_to_string(file)
+            .map_err(|e| file_error(e, &format!("Failed to read {}", file.display())))?;
+        let backup: Self = serde_json::from_str(&contents)
+            .map_err(|e| ElevateError::ValidationError(format!("Invalid ACL backup {}: {}", file.display(), e)))?;
+
+        if backup.version != FORMAT_VERSION {
+            return Err(ElevateError::ValidationError(format!(
+                "Unsupported ACL backup version {}",
+                backup.version
+            )));
+        }
+        Ok(backup)
+    }
+
+    pub fn write(&self, file: &Path) -> ElevateResult<()> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| ElevateError::ValidationError(format!("Failed to serialize ACL backup: {}", e)))?;
+        fs::write(file, contents).map_err(|e| file_error(e, &format!("Failed to write {}", file.display())))
+    }
+}
+
+/// Difference between a saved entry and the live object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Drift {
+    Missing,
+    Changed(Vec<&'static str>),
+}
+
+#[derive(Debug, Default)]
+pub struct RestoreReport {
+    pub restored: usize,
+    pub unchanged: usize,
+    pub missing: usize,
+}
+
+/// Saves and restores ACLs while impersonating TrustedInstaller
+#[derive(Default)]
+pub struct AclBackupManager;
+
+impl AclBackupManager {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn save(&self, path: &Path, recursive: bool) -> ElevateResult<AclBackup> {
+        let root = canonical_path(path)?;
+        let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+
+        let entries = collect_paths(&root, recursive)?
+            .into_iter()
+            .map(|p| Ok(AclEntry::new(p.clone(), &read_security(&path_name(&p), ObjectType::File, true)?)))
+            .collect::<ElevateResult<Vec<_>>>()?;
+
+        info!("Saved ACLs of {} item(s) under {}", entries.len(), root.display());
+        Ok(AclBackup { version: FORMAT_VERSION, created_at: unix_timestamp(), root, entries })
+    }
+
+    /// Writes back every entry that drifted. Running it twice changes nothing.
+    pub fn restore(&self, backup: &AclBackup) -> ElevateResult<RestoreReport> {
+        // validate the whole file before touching anything
+        let descriptors = backup
+            .entries
+            .iter()
+            .map(AclEntry::descriptor)
+            .collect::<ElevateResult<Vec<_>>>()?;
+
+        let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+        let mut report = RestoreReport::default();
+
+        // entries are saved parents first, so inheritance settles top down
+        for (entry, saved) in backup.entries.iter().zip(descriptors) {
+            if !entry.path.exists() {
+                warn!("{} no longer exists", entry.path.display());
+                report.missing += 1;
+                continue;
+            }
+
+            let name = path_name(&entry.path);
+            if read_security(&name, ObjectType::File, entry.sacl.is_some())? == saved {
+                debug!("{} is unchanged", entry.path.display());
+                report.unchanged += 1;
+                continue;
+            }
+
+            let explicit = SecurityDescriptor {
+                owner: saved.owner,
+                group: saved.group,
+                dacl: saved.dacl.as_ref().map(Acl::without_inherited),
+                sacl: saved.sacl.as_ref().map(Acl::without_inherited),
+            };
+            apply_security(&name, ObjectType::File, &explicit)?;
+            report.restored += 1;
+        }
+
+        Ok(report)
+    }
+
+    /// Compares every entry with the live object without changing anything
+    pub fn verify(&self, backup: &AclBackup) -> ElevateResult<Vec<(PathBuf, Drift)>> {
+        let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+        let mut drift = Vec::new();
+
+        for entry in &backup.entries {
+            if !entry.path.exists() {
+                drift.push((entry.path.clone(), Drift::Missing));
+                continue;
+            }
+
+            let saved = entry.descriptor()?;
+            let live = read_security(&path_name(&entry.path), ObjectType::File, entry.sacl.is_some())?;
+            let changed = changed_parts(&saved, &live);
+            if !changed.is_empty() {
+                drift.push((entry.path.clone(), Drift::Changed(changed)));
+            }
+        }
+
+        Ok(drift)
+    }
+}
+
+pub fn changed_parts(saved: &SecurityDescriptor, live: &SecurityDescriptor) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    if saved.owner != live.owner {
+        changed.push("owner");
+    }
+    if saved.group != live.group {
+        changed.push("group");
+    }
+    if saved.dacl != live.dacl {
+        changed.push("dacl");
+    }
+    if saved.sacl.is_some() && saved.sacl != live.sacl {
+        changed.push("sacl");
+    }
+    changed
+}
+
+fn descriptor_hash(descriptor: &SecurityDescriptor) -> String {
+    sha256_hex(descriptor.to_string().as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_round_trip() {
+        let sd = SecurityDescriptor::parse("O:TIG:SYD:PAI(A;;FA;;;TI)(A;;0x1200a9;;;BA)S:(ML;;NW;;;HI)").unwrap();
+        let entry = AclEntry::new(PathBuf::from(r"C:\Windows\Web"), &sd);
+
+        assert_eq!(entry.group.as_deref(), Some("SY"));
+        assert_eq!(entry.sacl.as_deref(), Some("(ML;;NW;;;HI)"));
+        assert_eq!(entry.descriptor().unwrap(), sd);
+    }
+
+    #[test]
+    fn test_edited_entry_is_rejected() {
+        let sd = SecurityDescriptor::parse("O:BAD:(A;;FA;;;BA)").unwrap();
+        let mut entry = AclEntry::new(PathBuf::from(r"C:\data"), &sd);
+        entry.dacl = Some("(A;;FA;;;WD)".into());
+        assert!(entry.descriptor().is_err());
+    }
+
+    #[test]
+    fn test_changed_parts() {
+        let saved = SecurityDescriptor::parse("O:TIG:SYD:(A;;FA;;;TI)").unwrap();
+        let live = SecurityDescriptor::parse("O:BAG:SYD:(A;;FA;;;TI)(A;;FA;;;BA)").unwrap();
+        assert_eq!(changed_parts(&saved, &live), vec!["owner", "dacl"]);
+        assert!(changed_parts(&saved, &saved).is_empty());
+    }
+}
@@ -0,0 +1,258 @@
+use std::ptr::null_mut;
+use winapi::{
+    ctypes::c_void,
+    shared::{
+        sddl::{
+            ConvertSecurityDescriptorToStringSecurityDescriptorW, ConvertSidToStringSidW,
+            ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+        },
+        winerror::ERROR_SUCCESS,
+    },
+    um::{
+        accctrl::{
+            SE_FILE_OBJECT, SE_OBJECT_TYPE, SE_REGISTRY_KEY, SE_REGISTRY_WOW64_32KEY, SE_REGISTRY_WOW64_64KEY,
+        },
+        aclapi::{GetNamedSecurityInfoW, SetNamedSecurityInfoW},
+        securitybaseapi::{
+            GetSecurityDescriptorDacl, GetSecurityDescriptorGroup, GetSecurityDescriptorOwner,
+            GetSecurityDescriptorSacl,
+        },
+        winbase::{LocalFree, LookupAccountNameW},
+        winnt::{
+            DACL_SECURITY_INFORMATION, GROUP_SECURITY_INFORMATION, LABEL_SECURITY_INFORMATION,
+            OWNER_SECURITY_INFORMATION, PROTECTED_DACL_SECURITY_INFORMATION,
+            PROTECTED_SACL_SECURITY_INFORMATION, SACL_SECURITY_INFORMATION,
+            UNPROTECTED_DACL_SECURITY_INFORMATION, UNPROTECTED_SACL_SECURITY_INFORMATION,
+        },
+    },
+};
+
+use crate::core::registry::path::RegistryView;
+use crate::core::security::sddl::{SecurityDescriptor, Sid};
+use crate::utils::error::{ElevateError, ElevateResult, WindowsError, WindowsErrorKind};
+
+/// Securable object types understood by the named security APIs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectType {
+    File,
+    /// named as `MACHINE\SOFTWARE\...`, see `KeyPath::object_name`
+    RegistryKey(RegistryView),
+}
+
+impl ObjectType {
+    fn se_object_type(self) -> SE_OBJECT_TYPE {
+        match self {
+            Self::File => SE_FILE_OBJECT,
+            Self::RegistryKey(RegistryView::Native) => SE_REGISTRY_KEY,
+            Self::RegistryKey(RegistryView::Registry32) => SE_REGISTRY_WOW64_32KEY,
+            Self::RegistryKey(RegistryView::Registry64) => SE_REGISTRY_WOW64_64KEY,
+        }
+    }
+}
+
+// memory returned by the security APIs must go back through LocalFree
+pub(crate) struct LocalGuard(pub *mut c_void);
+
+impl Drop for LocalGuard {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { LocalFree(self.0) };
+        }
+    }
+}
+
+/// A self-relative security descriptor built by Windows from SDDL, for APIs
+/// that take a `SECURITY_ATTRIBUTES` or raw descriptor
+pub struct BinaryDescriptor(LocalGuard);
+
+impl BinaryDescriptor {
+    pub fn from_sddl(sddl: &str) -> ElevateResult<Self> {
+        let sddl_wide = to_wide(sddl);
+        let mut binary = null_mut();
+        let converted = unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl_wide.as_ptr(),
+                SDDL_REVISION_1 as u32,
+                &mut binary,
+                null_mut(),
+            )
+        };
+        if converted == 0 {
+            return Err(WindowsError::last_error().to_elevate_error(
+                WindowsErrorKind::SecurityOperation,
+                &format!("Windows rejected security descriptor {}", sddl),
+            ));
+        }
+        Ok(Self(LocalGuard(binary)))
+    }
+
+    pub fn as_ptr(&self) -> *mut c_void {
+        self.0 .0
+    }
+}
+
+/// Reads owner, group, DACL and optionally the SACL of a named object
+pub fn read_security(name: &str, object: ObjectType, include_sacl: bool) -> ElevateResult<SecurityDescriptor> {
+    let mut info = OWNER_SECURITY_INFORMATION | GROUP_SECURITY_INFORMATION | DACL_SECURITY_INFORMATION;
+    if include_sacl {
+        info |= SACL_SECURITY_INFORMATION | LABEL_SECURITY_INFORMATION;
+    }
+
+    let name_wide = to_wide(name);
+    let mut descriptor = null_mut();
+    let status = unsafe {
+        GetNamedSecurityInfoW(
+            name_wide.as_ptr(),
+            object.se_object_type(),
+            info,
+            null_mut(),
+            null_mut(),
+            null_mut(),
+            null_mut(),
+            &mut descriptor,
+        )
+    };
+    if status != ERROR_SUCCESS {
+        return Err(WindowsError::from_code(status).to_elevate_error(
+            WindowsErrorKind::SecurityOperation,
+            &format!("Failed to read security of {}", name),
+        ));
+    }
+    let descriptor = LocalGuard(descriptor);
+
+    let mut text = null_mut();
+    let mut len = 0;
+    let converted = unsafe {
+        ConvertSecurityDescriptorToStringSecurityDescriptorW(
+            descriptor.0,
+            SDDL_REVISION_1 as u32,
+            info,
+            &mut text,
+            &mut len,
+        )
+    };
+    if converted == 0 {
+        return Err(WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::SecurityOperation,
+            "Failed to convert security descriptor to SDDL",
+        ));
+    }
+    let text = LocalGuard(text as *mut c_void);
+
+    SecurityDescriptor::parse(&from_wide_ptr(text.0 as *const u16))
+}
+
+/// Writes every component present in `descriptor` to a named object.
+///
+/// Windows recomputes inherited ACEs itself, so only explicit ACEs need to be
+/// present unless the ACL is protected.
+pub fn apply_security(name: &str, object: ObjectType, descriptor: &SecurityDescriptor) -> ElevateResult<()> {
+    let binary = BinaryDescriptor::from_sddl(&descriptor.to_string())?.0;
+
+    let mut info = 0;
+    let (mut owner, mut group, mut dacl, mut sacl) = (null_mut(), null_mut(), null_mut(), null_mut());
+    let (mut present, mut defaulted) = (0, 0);
+
+    unsafe {
+        if descriptor.owner.is_some() {
+            GetSecurityDescriptorOwner(binary.0, &mut owner, &mut defaulted);
+            info |= OWNER_SECURITY_INFORMATION;
+        }
+        if descriptor.group.is_some() {
+            GetSecurityDescriptorGroup(binary.0, &mut group, &mut defaulted);
+            info |= GROUP_SECURITY_INFORMATION;
+        }
+        if let Some(acl) = &descriptor.dacl {
+            GetSecurityDescriptorDacl(binary.0, &mut present, &mut dacl, &mut defaulted);
+            info |= DACL_SECURITY_INFORMATION;
+            info |= if acl.flags.protected {
+                PROTECTED_DACL_SECURITY_INFORMATION
+            } else {
+                UNPROTECTED_DACL_SECURITY_INFORMATION
+            };
+        }
+        if let Some(acl) = &descriptor.sacl {
+            GetSecurityDescriptorSacl(binary.0, &mut present, &mut sacl, &mut defaulted);
+            info |= SACL_SECURITY_INFORMATION | LABEL_SECURITY_INFORMATION;
+            info |= if acl.flags.protected {
+                PROTECTED_SACL_SECURITY_INFORMATION
+            } else {
+                UNPROTECTED_SACL_SECURITY_INFORMATION
+            };
+        }
+    }
+
+    let mut name_wide = to_wide(name);
+    let status = unsafe {
+        SetNamedSecurityInfoW(name_wide.as_mut_ptr(), object.se_object_type(), info, owner, group, dacl, sacl)
+    };
+    if status != ERROR_SUCCESS {
+        return Err(WindowsError::from_code(status).to_elevate_error(
+            WindowsErrorKind::SecurityOperation,
+            &format!("Failed to write security of {}", name),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Resolves an SDDL alias, `S-1-...` string or account name such as `DOMAIN\user`
+pub fn lookup_account(name: &str) -> ElevateResult<Sid> {
+    if let Ok(sid) = Sid::parse(name) {
+        return Ok(sid);
+    }
+
+    let name_wide = to_wide(name);
+    let mut sid_buffer = vec![0u8; 68];
+    let mut domain_buffer = vec![0u16; 256];
+
+    loop {
+        let mut sid_len = sid_buffer.len() as u32;
+        let mut domain_len = domain_buffer.len() as u32;
+        let mut sid_use = 0;
+        let found = unsafe {
+            LookupAccountNameW(
+                null_mut(),
+                name_wide.as_ptr(),
+                sid_buffer.as_mut_ptr() as *mut _,
+                &mut sid_len,
+                domain_buffer.as_mut_ptr(),
+                &mut domain_len,
+                &mut sid_use,
+            )
+        };
+        if found != 0 {
+            break;
+        }
+
+        // grow the buffers once if they were too small
+        if (sid_len as usize) > sid_buffer.len() || (domain_len as usize) > domain_buffer.len() {
+            sid_buffer.resize(sid_len as usize, 0);
+            domain_buffer.resize(domain_len as usize, 0);
+            continue;
+        }
+        return Err(ElevateError::ValidationError(format!("Unknown account: {}", name)));
+    }
+
+    let mut text = null_mut();
+    if unsafe { ConvertSidToStringSidW(sid_buffer.as_mut_ptr() as *mut _, &mut text) } == 0 {
+        return Err(WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::SecurityOperation,
+            "Failed to convert SID to string",
+        ));
+    }
+    let text = LocalGuard(text as *mut c_void);
+
+    Sid::parse(&from_wide_ptr(text.0 as *const u16))
+}
+
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+fn from_wide_ptr(ptr: *const u16) -> String {
+    unsafe {
+        let len = (0..).take_while(|&i| *ptr.add(i) != 0).count();
+        String::from_utf16_lossy(std::slice::from_raw_parts(ptr, len))
+    }
+}
@@ -0,0 +1,7 @@
+pub mod sddl;
+#[cfg(windows)]
+pub mod descriptor;
+#[cfg(windows)]
+pub mod ownership;
+#[cfg(windows)]
+pub mod acl_backup;
@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::core::elevation::impersonation::{TrustedInstallerImpersonation, SECURITY_PRIVILEGES};
+use crate::core::security::descriptor::{apply_security, read_security, ObjectType};
+use crate::core::security::sddl::{AccessMask, Acl, Ace, AceFlags, SecurityDescriptor, Sid, TRUSTED_INSTALLER_SID};
+use crate::utils::config::Config;
+use crate::utils::error::{ElevateError, ElevateResult};
+use crate::utils::files::{canonical_path, collect_paths, file_error, path_name};
+use crate::utils::time::unix_timestamp;
+
+const BACKUP_FILE: &str = "ownership.json";
+const FILE_ALL_ACCESS: u32 = 0x001F_01FF;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BackupStore {
+    // keyed by the lowercased canonical root path
+    backups: BTreeMap<String, OwnershipBackup>,
+}
+
+/// Security descriptors of a path (and its children) before `own` changed them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OwnershipBackup {
+    pub root: PathBuf,
+    /// seconds since the unix epoch
+    pub saved_at: u64,
+    pub entries: Vec<OwnershipEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OwnershipEntry {
+    pub path: PathBuf,
+    pub sddl: String,
+}
+
+impl OwnershipBackup {
+    /// Adds entries for paths not backed up yet, after the existing ones so
+    /// parents still come first. Returns how many were added.
+    fn merge(&mut self, entries: impl IntoIterator<Item = OwnershipEntry>) -> usize {
+        let before = self.entries.len();
+        for entry in entries {
+            if !self.entries.iter().any(|saved| saved.path == entry.path) {
+                self.entries.push(entry);
+            }
+        }
+        self.entries.len() - before
+    }
+}
+
+pub struct OwnOptions {
+    pub owner: Sid,
+    /// principal that receives the same full access TrustedInstaller has
+    pub grant: Option<Sid>,
+    pub recursive: bool,
+}
+
+/// Takes ownership of protected files and hands it back to TrustedInstaller
+pub struct OwnershipManager {
+    store_path: PathBuf,
+}
+
+impl OwnershipManager {
+    pub fn new() -> ElevateResult<Self> {
+        Ok(Self {
+            store_path: Config::state_dir()?.join(BACKUP_FILE),
+        })
+    }
+
+    /// Saves the original descriptors, then changes owner and optionally the DACL.
+    /// Returns the number of files and folders changed.
+    pub fn take(&self, path: &Path, options: &OwnOptions) -> ElevateResult<usize> {
+        let root = canonical_path(path)?;
+
+        let originals = {
+            let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+            collect_paths(&root, options.recursive)?
+                .into_iter()
+                .map(|p| Ok((read_security(&path_name(&p), ObjectType::File, false)?, p)))
+                .collect::<ElevateResult<Vec<_>>>()?
+        };
+
+        // descriptors already backed up are never overwritten, they would
+        // capture our own changes; paths new to this run are added
+        let mut store = self.load()?;
+        let backup = store.backups.entry(store_key(&root)).or_insert_with(|| OwnershipBackup {
+            root: root.clone(),
+            saved_at: unix_timestamp(),
+            entries: Vec::new(),
+        });
+        let added = backup.merge(originals.iter().map(|(sd, p)| OwnershipEntry { path: p.clone(), sddl: sd.to_string() }));
+        if added < originals.len() {
+            warn!("Keeping the existing backup of {} item(s) under {}", originals.len() - added, root.display());
+        }
+        if added > 0 {
+            self.save(&store)?;
+            info!("Saved {} security descriptor(s) to {}", added, self.store_path.display());
+        }
+
+        let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+        for (index, (original, entry)) in originals.iter().enumerate() {
+            let mut updated = SecurityDescriptor {
+                owner: Some(options.owner.clone()),
+                ..Default::default()
+            };
+
+            // children that inherit from the root pick the grant up from there
+            if let (Some(grantee), Some(dacl)) = (&options.grant, &original.dacl) {
+                if index == 0 || dacl.flags.protected {
+                    let mut dacl = dacl.without_inherited();
+                    dacl.add_allow(Ace::allow(
+                        grantee.clone(),
+                        AccessMask(FILE_ALL_ACCESS),
+                        inherit_flags(entry, options.recursive),
+                    ));
+                    updated.dacl = Some(dacl);
+                }
+            }
+
+            apply_security(&path_name(entry), ObjectType::File, &updated)?;
+        }
+
+        Ok(originals.len())
+    }
+
+    /// Gives ownership back to TrustedInstaller and restores the saved DACLs.
+    /// Without a backup only the owner is reset, on children too if `recursive`.
+    pub fn restore(&self, path: &Path, recursive: bool) -> ElevateResult<usize> {
+        let root = canonical_path(path)?;
+        let trusted_installer = Sid::parse(TRUSTED_INSTALLER_SID)?;
+        let mut store = self.load()?;
+
+        let Some(backup) = store.backups.remove(&store_key(&root)) else {
+            warn!("No saved security descriptor for {}, only the owner is restored", root.display());
+
+            let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+            let paths = collect_paths(&root, recursive)?;
+            let owner_only = SecurityDescriptor {
+                owner: Some(trusted_installer),
+                ..Default::default()
+            };
+            for entry in &paths {
+                apply_security(&path_name(entry), ObjectType::File, &owner_only)?;
+            }
+            return Ok(paths.len());
+        };
+
+        {
+            // parents come first, so their inheritance is settled before children are restored
+            let _ti = TrustedInstallerImpersonation::begin(SECURITY_PRIVILEGES)?;
+            for entry in &backup.entries {
+                let original = SecurityDescriptor::parse(&entry.sddl)?;
+                if !original.owner.as_ref().is_some_and(Sid::is_trusted_installer) {
+                    info!("{} was not owned by TrustedInstaller before", entry.path.display());
+                }
+                let restored = SecurityDescriptor {
+                    owner: Some(trusted_installer.clone()),
+                    group: original.group,
+                    dacl: original.dacl.as_ref().map(Acl::without_inherited),
+                    sacl: None,
+                };
+                apply_security(&path_name(&entry.path), ObjectType::File, &restored)?;
+            }
+        }
+
+        self.save(&store)?;
+        Ok(backup.entries.len())
+    }
+
+    fn load(&self) -> ElevateResult<BackupStore> {
+        if !self.store_path.exists() {
+            return Ok(BackupStore::default());
+        }
+
+        let contents = fs::read_to_string(&self.store_path)
+            .map_err(|e| file_error(e, "Failed to read ownership backups"))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| ElevateError::ConfigError(format!("Failed to parse ownership backups: {}", e)))
+    }
+
+    fn save(&self, store: &BackupStore) -> ElevateResult<()> {
+        let contents = serde_json::to_string_pretty(store)
+            .map_err(|e| ElevateError::ConfigError(format!("Failed to serialize ownership backups: {}", e)))?;
+        fs::write(&self.store_path, contents).map_err(|e| file_error(e, "Failed to write ownership backups"))
+    }
+}
+
+fn store_key(root: &Path) -> String {
+    path_name(root).to_lowercase()
+}
+
+fn inherit_flags(path: &Path, recursive: bool) -> AceFlags {
+    if recursive && path.is_dir() {
+        AceFlags(AceFlags::OBJECT_INHERIT | AceFlags::CONTAINER_INHERIT)
+    } else {
+        AceFlags::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, sddl: &str) -> OwnershipEntry {
+        OwnershipEntry { path: PathBuf::from(path), sddl: sddl.into() }
+    }
+
+    #[test]
+    fn test_merge_keeps_saved_descriptors_and_adds_new_paths() {
+        let mut backup = OwnershipBackup {
+            root: PathBuf::from(r"C:\Tools"),
+            saved_at: 0,
+            entries: vec![entry(r"C:\Tools", "O:TID:(A;;FA;;;TI)")],
+        };
+        let added = backup.merge([entry(r"C:\Tools", "O:BAD:(A;;FA;;;BA)"), entry(r"C:\Tools\a.dll", "O:TI")]);
+
+        assert_eq!(added, 1);
+        let saved: Vec<_> = backup.entries.iter().map(|entry| entry.sddl.as_str()).collect();
+        assert_eq!(saved, ["O:TID:(A;;FA;;;TI)", "O:TI"]);
+    }
+}
@@ -0,0 +1,1039 @@
+use std::fmt;
+use std::str::FromStr;
+use serde::Serialize;
+
+use crate::utils::error::{ElevateError, ElevateResult};
+
+/// SID of the `NT SERVICE\TrustedInstaller` service account
+pub const TRUSTED_INSTALLER_SID: &str =
+    "S-1-5-80-956008885-3418522649-1831038044-1853292631-2271478464";
+
+struct SidAlias {
+    alias: &'static str,
+    // None for domain relative aliases that can only be resolved on a domain member
+    sid: Option<&'static str>,
+    name: &'static str,
+    // false for aliases that windows itself does not understand
+    canonical: bool,
+}
+
+const fn sid_alias(alias: &'static str, sid: Option<&'static str>, name: &'static str) -> SidAlias {
+    SidAlias { alias, sid, name, canonical: true }
+}
+
+static SID_ALIASES: &[SidAlias] = &[
+    sid_alias("AA", Some("S-1-5-32-579"), "BUILTIN\\Access Control Assistance Operators"),
+    sid_alias("AC", Some("S-1-15-2-1"), "ALL APPLICATION PACKAGES"),
+    sid_alias("AN", Some("S-1-5-7"), "NT AUTHORITY\\ANONYMOUS LOGON"),
+    sid_alias("AO", Some("S-1-5-32-548"), "BUILTIN\\Account Operators"),
+    sid_alias("AS", Some("S-1-18-1"), "Authentication authority asserted identity"),
+    sid_alias("AU", Some("S-1-5-11"), "NT AUTHORITY\\Authenticated Users"),
+    sid_alias("BA", Some("S-1-5-32-544"), "BUILTIN\\Administrators"),
+    sid_alias("BG", Some("S-1-5-32-546"), "BUILTIN\\Guests"),
+    sid_alias("BO", Some("S-1-5-32-551"), "BUILTIN\\Backup Operators"),
+    sid_alias("BU", Some("S-1-5-32-545"), "BUILTIN\\Users"),
+    sid_alias("CD", Some("S-1-5-32-574"), "BUILTIN\\Certificate Service DCOM Access"),
+    sid_alias("CG", Some("S-1-3-1"), "CREATOR GROUP"),
+    sid_alias("CO", Some("S-1-3-0"), "CREATOR OWNER"),
+    sid_alias("CY", Some("S-1-5-32-569"), "BUILTIN\\Cryptographic Operators"),
+    sid_alias("ED", Some("S-1-5-9"), "NT AUTHORITY\\ENTERPRISE DOMAIN CONTROLLERS"),
+    sid_alias("ER", Some("S-1-5-32-573"), "BUILTIN\\Event Log Readers"),
+    sid_alias("ES", Some("S-1-5-32-576"), "BUILTIN\\RDS Endpoint Servers"),
+    sid_alias("HA", Some("S-1-5-32-578"), "BUILTIN\\Hyper-V Administrators"),
+    sid_alias("HI", Some("S-1-16-12288"), "Mandatory Label\\High Mandatory Level"),
+    sid_alias("IS", Some("S-1-5-32-568"), "BUILTIN\\IIS_IUSRS"),
+    sid_alias("IU", Some("S-1-5-4"), "NT AUTHORITY\\INTERACTIVE"),
+    sid_alias("LS", Some("S-1-5-19"), "NT AUTHORITY\\LOCAL SERVICE"),
+    sid_alias("LU", Some("S-1-5-32-559"), "BUILTIN\\Performance Log Users"),
+    sid_alias("LW", Some("S-1-16-4096"), "Mandatory Label\\Low Mandatory Level"),
+    sid_alias("ME", Some("S-1-16-8192"), "Mandatory Label\\Medium Mandatory Level"),
+    sid_alias("MP", Some("S-1-16-8448"), "Mandatory Label\\Medium Plus Mandatory Level"),
+    sid_alias("MU", Some("S-1-5-32-558"), "BUILTIN\\Performance Monitor Users"),
+    sid_alias("NO", Some("S-1-5-32-556"), "BUILTIN\\Network Configuration Operators"),
+    sid_alias("NS", Some("S-1-5-20"), "NT AUTHORITY\\NETWORK SERVICE"),
+    sid_alias("NU", Some("S-1-5-2"), "NT AUTHORITY\\NETWORK"),
+    sid_alias("OW", Some("S-1-3-4"), "OWNER RIGHTS"),
+    sid_alias("PO", Some("S-1-5-32-550"), "BUILTIN\\Print Operators"),
+    sid_alias("PS", Some("S-1-5-10"), "NT AUTHORITY\\SELF"),
+    sid_alias("PU", Some("S-1-5-32-547"), "BUILTIN\\Power Users"),
+    sid_alias("RA", Some("S-1-5-32-575"), "BUILTIN\\RDS Remote Access Servers"),
+    sid_alias("RC", Some("S-1-5-12"), "NT AUTHORITY\\RESTRICTED"),
+    sid_alias("RD", Some("S-1-5-32-555"), "BUILTIN\\Remote Desktop Users"),
+    sid_alias("RE", Some("S-1-5-32-552"), "BUILTIN\\Replicator"),
+    sid_alias("RM", Some("S-1-5-32-580"), "BUILTIN\\Remote Management Users"),
+    sid_alias("RU", Some("S-1-5-32-554"), "BUILTIN\\Pre-Windows 2000 Compatible Access"),
+    sid_alias("SI", Some("S-1-16-16384"), "Mandatory Label\\System Mandatory Level"),
+    sid_alias("SO", Some("S-1-5-32-549"), "BUILTIN\\Server Operators"),
+    sid_alias("SS", Some("S-1-18-2"), "Service asserted identity"),
+    sid_alias("SU", Some("S-1-5-6"), "NT AUTHORITY\\SERVICE"),
+    sid_alias("SY", Some("S-1-5-18"), "NT AUTHORITY\\SYSTEM"),
+    sid_alias("UD", Some("S-1-5-84-0-0-0-0-0"), "NT AUTHORITY\\USER MODE DRIVERS"),
+    sid_alias("WD", Some("S-1-1-0"), "Everyone"),
+    sid_alias("WR", Some("S-1-5-33"), "NT AUTHORITY\\WRITE RESTRICTED"),
+    // domain and machine relative
+    sid_alias("AP", None, "Protected Users"),
+    sid_alias("CA", None, "Cert Publishers"),
+    sid_alias("CN", None, "Cloneable Domain Controllers"),
+    sid_alias("DA", None, "Domain Admins"),
+    sid_alias("DC", None, "Domain Computers"),
+    sid_alias("DD", None, "Domain Controllers"),
+    sid_alias("DG", None, "Domain Guests"),
+    sid_alias("DU", None, "Domain Users"),
+    sid_alias("EA", None, "Enterprise Admins"),
+    sid_alias("EK", None, "Enterprise Key Admins"),
+    sid_alias("KA", None, "Key Admins"),
+    sid_alias("LA", None, "Local Administrator"),
+    sid_alias("LG", None, "Local Guest"),
+    sid_alias("PA", None, "Group Policy Creator Owners"),
+    sid_alias("RS", None, "RAS and IAS Servers"),
+    sid_alias("SA", None, "Schema Admins"),
+    // elevatekit extension, always serialized as the full SID
+    SidAlias {
+        alias: "TI",
+        sid: Some(TRUSTED_INSTALLER_SID),
+        name: "NT SERVICE\\TrustedInstaller",
+        canonical: false,
+    },
+];
+
+/// A security identifier, stored as `S-1-...` or as a domain relative alias
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Sid(String);
+
+impl Sid {
+    pub fn parse(value: &str) -> ElevateResult<Self> {
+        let value = value.trim();
+
+        if let Some(entry) = SID_ALIASES.iter().find(|a| a.alias == value) {
+            return Ok(Self(entry.sid.unwrap_or(entry.alias).to_string()));
+        }
+
+        Self::parse_literal(value)
+            .map(Self)
+            .ok_or_else(|| ElevateError::ValidationError(format!("Invalid SID: {:?}", value)))
+    }
+
+    fn parse_literal(value: &str) -> Option<String> {
+        let rest = value.strip_prefix("S-").or_else(|| value.strip_prefix("s-"))?;
+        let mut parts = rest.split('-');
+
+        if parts.next()? != "1" {
+            return None;
+        }
+
+        // identifier authority is 48 bits, decimal or hex
+        let authority = parts.next()?;
+        let authority = match authority.strip_prefix("0x").or_else(|| authority.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+            None => authority.parse::<u64>().ok()?,
+        };
+        if authority >= 1 << 48 {
+            return None;
+        }
+
+        let sub_authorities = parts.map(|p| p.parse::<u32>().ok()).collect::<Option<Vec<_>>>()?;
+        if sub_authorities.len() > 15 {
+            return None;
+        }
+
+        let mut sid = format!("S-1-{}", authority);
+        for sub in sub_authorities {
+            sid.push_str(&format!("-{}", sub));
+        }
+        Some(sid)
+    }
+
+    /// `S-1-...` string, or the alias for domain relative SIDs
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn alias(&self) -> Option<&'static str> {
+        self.entry().map(|e| e.alias)
+    }
+
+    pub fn display_name(&self) -> Option<&'static str> {
+        self.entry().map(|e| e.name)
+    }
+
+    pub fn is_trusted_installer(&self) -> bool {
+        self.0 == TRUSTED_INSTALLER_SID
+    }
+
+    fn entry(&self) -> Option<&'static SidAlias> {
+        SID_ALIASES
+            .iter()
+            .find(|a| a.sid.unwrap_or(a.alias) == self.0)
+    }
+}
+
+impl fmt::Display for Sid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.entry() {
+            Some(entry) if entry.canonical => f.write_str(entry.alias),
+            _ => f.write_str(&self.0),
+        }
+    }
+}
+
+impl FromStr for Sid {
+    type Err = ElevateError;
+
+    fn from_str(s: &str) -> ElevateResult<Self> {
+        Self::parse(s)
+    }
+}
+
+/// An account as seen in a token
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Principal {
+    /// `DOMAIN\name`, empty if the SID could not be resolved
+    pub name: String,
+    pub sid: String,
+}
+
+impl Principal {
+    /// `pattern` is a SID, a full `DOMAIN\name` or a bare name, compared without case
+    pub fn matches(&self, pattern: &str) -> bool {
+        if pattern.eq_ignore_ascii_case(&self.sid) {
+            return true;
+        }
+        if self.name.is_empty() {
+            return false;
+        }
+        if pattern.contains('\\') {
+            return pattern.eq_ignore_ascii_case(&self.name);
+        }
+        let bare = self.name.rsplit('\\').next().unwrap_or(&self.name);
+        pattern.eq_ignore_ascii_case(bare)
+    }
+}
+
+/// Kind of an access control entry, as written in the first ACE field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AceType {
+    AccessAllowed,
+    AccessDenied,
+    ObjectAccessAllowed,
+    ObjectAccessDenied,
+    Audit,
+    Alarm,
+    ObjectAudit,
+    ObjectAlarm,
+    MandatoryLabel,
+    CallbackAccessAllowed,
+    CallbackAccessDenied,
+    CallbackObjectAccessAllowed,
+    CallbackAudit,
+    ResourceAttribute,
+    ScopedPolicyId,
+    ProcessTrustLabel,
+    AccessFilter,
+}
+
+static ACE_TYPES: &[(&str, AceType, &str)] = &[
+    ("A", AceType::AccessAllowed, "Allow"),
+    ("D", AceType::AccessDenied, "Deny"),
+    ("OA", AceType::ObjectAccessAllowed, "Allow (object)"),
+    ("OD", AceType::ObjectAccessDenied, "Deny (object)"),
+    ("AU", AceType::Audit, "Audit"),
+    ("AL", AceType::Alarm, "Alarm"),
+    ("OU", AceType::ObjectAudit, "Audit (object)"),
+    ("OL", AceType::ObjectAlarm, "Alarm (object)"),
+    ("ML", AceType::MandatoryLabel, "Mandatory label"),
+    ("XA", AceType::CallbackAccessAllowed, "Allow (conditional)"),
+    ("XD", AceType::CallbackAccessDenied, "Deny (conditional)"),
+    ("ZA", AceType::CallbackObjectAccessAllowed, "Allow (conditional object)"),
+    ("XU", AceType::CallbackAudit, "Audit (conditional)"),
+    ("RA", AceType::ResourceAttribute, "Resource attribute"),
+    ("SP", AceType::ScopedPolicyId, "Scoped policy"),
+    ("TL", AceType::ProcessTrustLabel, "Trust label"),
+    ("FL", AceType::AccessFilter, "Access filter"),
+];
+
+impl AceType {
+    pub fn parse(value: &str) -> ElevateResult<Self> {
+        ACE_TYPES
+            .iter()
+            .find(|(code, _, _)| *code == value)
+            .map(|(_, ty, _)| *ty)
+            .ok_or_else(|| ElevateError::ValidationError(format!("Unknown ACE type: {:?}", value)))
+    }
+
+    pub fn code(self) -> &'static str {
+        self.entry().0
+    }
+
+    pub fn description(self) -> &'static str {
+        self.entry().2
+    }
+
+    /// callback ACEs carry a conditional expression
+    pub fn is_conditional(self) -> bool {
+        matches!(
+            self,
+            Self::CallbackAccessAllowed
+                | Self::CallbackAccessDenied
+                | Self::CallbackObjectAccessAllowed
+                | Self::CallbackAudit
+        )
+    }
+
+    fn entry(self) -> &'static (&'static str, AceType, &'static str) {
+        ACE_TYPES.iter().find(|(_, ty, _)| *ty == self).expect("every ACE type is listed")
+    }
+}
+
+static ACE_FLAGS: &[(&str, u8, &str)] = &[
+    ("OI", 0x01, "object inherit"),
+    ("CI", 0x02, "container inherit"),
+    ("NP", 0x04, "no propagate"),
+    ("IO", 0x08, "inherit only"),
+    ("ID", 0x10, "inherited"),
+    ("CR", 0x20, "critical"),
+    ("SA", 0x40, "audit success"),
+    ("FA", 0x80, "audit failure"),
+];
+
+/// ACE header flags (`OI`, `CI`, `ID`, ...)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AceFlags(pub u8);
+
+impl AceFlags {
+    pub const OBJECT_INHERIT: u8 = 0x01;
+    pub const CONTAINER_INHERIT: u8 = 0x02;
+    pub const INHERIT_ONLY: u8 = 0x08;
+    pub const INHERITED: u8 = 0x10;
+
+    pub fn parse(value: &str) -> ElevateResult<Self> {
+        let mut bits = 0;
+        for token in two_char_tokens(value, "ACE flags")? {
+            let (_, flag, _) = ACE_FLAGS
+                .iter()
+                .find(|(alias, _, _)| *alias == token)
+                .ok_or_else(|| ElevateError::ValidationError(format!("Unknown ACE flag: {:?}", token)))?;
+            bits |= flag;
+        }
+        Ok(Self(bits))
+    }
+
+    pub fn contains(self, flag: u8) -> bool {
+        self.0 & flag == flag
+    }
+
+    pub fn aliases(self) -> Vec<&'static str> {
+        ACE_FLAGS
+            .iter()
+            .filter(|(_, flag, _)| self.0 & flag != 0)
+            .map(|(alias, _, _)| *alias)
+            .collect()
+    }
+
+    pub fn describe(self) -> Vec<&'static str> {
+        ACE_FLAGS
+            .iter()
+            .filter(|(_, flag, _)| self.0 & flag != 0)
+            .map(|(_, _, desc)| *desc)
+            .collect()
+    }
+}
+
+impl fmt::Display for AceFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.aliases().concat())
+    }
+}
+
+// masks that cover a whole object type, checked before single bits
+static COMPOSITE_RIGHTS: &[(&str, u32, &str)] = &[
+    ("FA", 0x001F_01FF, "file all access"),
+    ("FR", 0x0012_0089, "file read"),
+    ("FW", 0x0012_0116, "file write"),
+    ("FX", 0x0012_00A0, "file execute"),
+    ("KA", 0x000F_003F, "key all access"),
+    ("KR", 0x0002_0019, "key read"),
+    ("KW", 0x0002_0006, "key write"),
+    ("KX", 0x0002_0019, "key execute"),
+];
+
+// ordered by bit value, which is the order windows prints them in
+static SINGLE_RIGHTS: &[(&str, u32, &str)] = &[
+    ("CC", 0x0000_0001, "create child"),
+    ("DC", 0x0000_0002, "delete child"),
+    ("LC", 0x0000_0004, "list children"),
+    ("SW", 0x0000_0008, "self write"),
+    ("RP", 0x0000_0010, "read property"),
+    ("WP", 0x0000_0020, "write property"),
+    ("DT", 0x0000_0040, "delete tree"),
+    ("LO", 0x0000_0080, "list object"),
+    ("CR", 0x0000_0100, "control access"),
+    ("SD", 0x0001_0000, "delete"),
+    ("RC", 0x0002_0000, "read control"),
+    ("WD", 0x0004_0000, "write DAC"),
+    ("WO", 0x0008_0000, "write owner"),
+    ("GA", 0x1000_0000, "generic all"),
+    ("GX", 0x2000_0000, "generic execute"),
+    ("GW", 0x4000_0000, "generic write"),
+    ("GR", 0x8000_0000, "generic read"),
+];
+
+// only meaningful on mandatory label ACEs, where they replace CC/DC/LC
+static LABEL_RIGHTS: &[(&str, u32, &str)] = &[
+    ("NR", 0x0000_0001, "no read up"),
+    ("NW", 0x0000_0002, "no write up"),
+    ("NX", 0x0000_0004, "no execute up"),
+];
+
+/// ACCESS_MASK of an ACE
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AccessMask(pub u32);
+
+impl AccessMask {
+    pub fn parse(value: &str) -> ElevateResult<Self> {
+        if let Some(hex) = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+            return u32::from_str_radix(hex, 16)
+                .map(Self)
+                .map_err(|_| ElevateError::ValidationError(format!("Invalid access mask: {:?}", value)));
+        }
+        if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+            return value
+                .parse()
+                .map(Self)
+                .map_err(|_| ElevateError::ValidationError(format!("Invalid access mask: {:?}", value)));
+        }
+
+        let mut mask = 0;
+        for token in two_char_tokens(value, "access mask")? {
+            let (_, bits, _) = COMPOSITE_RIGHTS
+                .iter()
+                .chain(SINGLE_RIGHTS)
+                .chain(LABEL_RIGHTS)
+                .find(|(alias, _, _)| *alias == token)
+                .ok_or_else(|| ElevateError::ValidationError(format!("Unknown access right: {:?}", token)))?;
+            mask |= bits;
+        }
+        Ok(Self(mask))
+    }
+
+    /// splits the mask into aliases, or None when some bits have no alias
+    fn decompose(self, label: bool) -> Option<Vec<&'static (&'static str, u32, &'static str)>> {
+        if let Some(composite) = COMPOSITE_RIGHTS.iter().find(|(_, bits, _)| *bits == self.0) {
+            if !label {
+                return Some(vec![composite]);
+            }
+        }
+
+        let table = if label { LABEL_RIGHTS } else { SINGLE_RIGHTS };
+        let mut remaining = self.0;
+        let mut parts = Vec::new();
+        for entry in table {
+            if remaining & entry.1 != 0 {
+                remaining &= !entry.1;
+                parts.push(entry);
+            }
+        }
+
+        if remaining == 0 { Some(parts) } else { None }
+    }
+
+    /// canonical SDDL text, as windows would print it
+    pub fn to_sddl(self, label: bool) -> String {
+        match self.decompose(label) {
+            Some(parts) if !parts.is_empty() => parts.iter().map(|(alias, _, _)| *alias).collect(),
+            _ => format!("{:#x}", self.0),
+        }
+    }
+
+    pub fn describe(self, label: bool) -> String {
+        match self.decompose(label) {
+            Some(parts) if !parts.is_empty() => parts
+                .iter()
+                .map(|(_, _, desc)| *desc)
+                .collect::<Vec<_>>()
+                .join(", "),
+            Some(_) => "none".to_string(),
+            None => self.describe_composites().unwrap_or_else(|| "custom rights".to_string()),
+        }
+    }
+
+    // masks like FRFX (0x1200a9) have no alias but are a union of composite rights
+    fn describe_composites(self) -> Option<String> {
+        let parts: Vec<_> = COMPOSITE_RIGHTS
+            .iter()
+            .filter(|(_, bits, _)| self.0 & bits == *bits)
+            .collect();
+        let union = parts.iter().fold(0, |acc, (_, bits, _)| acc | bits);
+
+        if parts.is_empty() || union != self.0 {
+            return None;
+        }
+        Some(parts.iter().map(|(_, _, desc)| *desc).collect::<Vec<_>>().join(", "))
+    }
+}
+
+/// A single access control entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ace {
+    pub ace_type: AceType,
+    pub flags: AceFlags,
+    pub rights: AccessMask,
+    pub object_type: Option<String>,
+    pub inherited_object_type: Option<String>,
+    pub trustee: Sid,
+    /// conditional expression of callback ACEs, or the attribute of a resource attribute ACE
+    pub application_data: Option<String>,
+}
+
+impl Ace {
+    /// plain allow ACE without object types or conditions
+    pub fn allow(trustee: Sid, rights: AccessMask, flags: AceFlags) -> Self {
+        Self {
+            ace_type: AceType::AccessAllowed,
+            flags,
+            rights,
+            object_type: None,
+            inherited_object_type: None,
+            trustee,
+            application_data: None,
+        }
+    }
+
+    fn parse(body: &str) -> ElevateResult<Self> {
+        let fields: Vec<&str> = body.splitn(7, ';').collect();
+        if fields.len() < 6 {
+            return Err(ElevateError::ValidationError(format!(
+                "ACE needs 6 fields, found {}: ({})",
+                fields.len(),
+                body
+            )));
+        }
+
+        let ace_type = AceType::parse(fields[0].trim())?;
+        let application_data = fields
+            .get(6)
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(str::to_string);
+
+        if let Some(data) = &application_data {
+            if !data.starts_with('(') && ace_type != AceType::ResourceAttribute {
+                return Err(ElevateError::ValidationError(format!(
+                    "Conditional expression must be parenthesized: {}",
+                    data
+                )));
+            }
+        } else if ace_type.is_conditional() {
+            return Err(ElevateError::ValidationError(format!(
+                "{} ACE requires a conditional expression",
+                ace_type.code()
+            )));
+        }
+
+        Ok(Self {
+            ace_type,
+            flags: AceFlags::parse(fields[1].trim())?,
+            rights: AccessMask::parse(fields[2].trim())?,
+            object_type: parse_guid(fields[3].trim())?,
+            inherited_object_type: parse_guid(fields[4].trim())?,
+            trustee: Sid::parse(fields[5])?,
+            application_data,
+        })
+    }
+}
+
+impl Ace {
+    pub fn is_inherited(&self) -> bool {
+        self.flags.contains(AceFlags::INHERITED)
+    }
+}
+
+impl fmt::Display for Ace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "({};{};{};{};{};{}",
+            self.ace_type.code(),
+            self.flags,
+            self.rights.to_sddl(self.ace_type == AceType::MandatoryLabel),
+            self.object_type.as_deref().unwrap_or(""),
+            self.inherited_object_type.as_deref().unwrap_or(""),
+            self.trustee,
+        )?;
+        if let Some(data) = &self.application_data {
+            write!(f, ";{}", data)?;
+        }
+        f.write_str(")")
+    }
+}
+
+/// Control flags of a DACL or SACL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AclFlags {
+    pub protected: bool,
+    pub auto_inherit_required: bool,
+    pub auto_inherited: bool,
+    /// null ACL, everyone is granted full access
+    pub no_access_control: bool,
+}
+
+impl AclFlags {
+    pub fn describe(self) -> Vec<&'static str> {
+        let mut out = Vec::new();
+        if self.protected {
+            out.push("protected");
+        }
+        if self.auto_inherit_required {
+            out.push("auto-inherit required");
+        }
+        if self.auto_inherited {
+            out.push("auto-inherited");
+        }
+        if self.no_access_control {
+            out.push("null ACL (no access control)");
+        }
+        out
+    }
+}
+
+impl fmt::Display for AclFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.protected {
+            f.write_str("P")?;
+        }
+        if self.auto_inherit_required {
+            f.write_str("AR")?;
+        }
+        if self.auto_inherited {
+            f.write_str("AI")?;
+        }
+        if self.no_access_control {
+            f.write_str("NO_ACCESS_CONTROL")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Acl {
+    pub flags: AclFlags,
+    pub aces: Vec<Ace>,
+}
+
+impl Acl {
+    /// copy without inherited ACEs, which windows recomputes when the ACL is written
+    pub fn without_inherited(&self) -> Self {
+        Self {
+            flags: self.flags,
+            aces: self.aces.iter().filter(|ace| !ace.is_inherited()).cloned().collect(),
+        }
+    }
+
+    /// adds an explicit ACE after the explicit deny ACEs, keeping canonical order
+    pub fn add_allow(&mut self, ace: Ace) {
+        let position = self
+            .aces
+            .iter()
+            .position(|a| a.is_inherited() || a.ace_type != AceType::AccessDenied)
+            .unwrap_or(self.aces.len());
+        self.aces.insert(position, ace);
+    }
+}
+
+impl fmt::Display for Acl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.flags)?;
+        for ace in &self.aces {
+            write!(f, "{}", ace)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parsed security descriptor. `Display` produces canonical SDDL.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SecurityDescriptor {
+    pub owner: Option<Sid>,
+    pub group: Option<Sid>,
+    pub dacl: Option<Acl>,
+    pub sacl: Option<Acl>,
+}
+
+impl SecurityDescriptor {
+    pub fn parse(sddl: &str) -> ElevateResult<Self> {
+        Parser { input: sddl.trim(), pos: 0 }.parse()
+    }
+
+    /// human readable table of owner, group and every ACE
+    pub fn explain(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str(&format!("Owner : {}\n", describe_sid(self.owner.as_ref(), true)));
+        out.push_str(&format!("Group : {}\n", describe_sid(self.group.as_ref(), true)));
+
+        for (name, acl) in [("DACL", &self.dacl), ("SACL", &self.sacl)] {
+            let Some(acl) = acl else {
+                out.push_str(&format!("{}  : not present\n", name));
+                continue;
+            };
+
+            let flags = acl.flags.describe();
+            out.push_str(&format!(
+                "{}  : {} ACE(s){}\n",
+                name,
+                acl.aces.len(),
+                if flags.is_empty() { String::new() } else { format!(", {}", flags.join(", ")) }
+            ));
+            if !acl.aces.is_empty() {
+                out.push_str(&ace_table(&acl.aces));
+            }
+        }
+
+        out
+    }
+}
+
+impl fmt::Display for SecurityDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(owner) = &self.owner {
+            write!(f, "O:{}", owner)?;
+        }
+        if let Some(group) = &self.group {
+            write!(f, "G:{}", group)?;
+        }
+        if let Some(dacl) = &self.dacl {
+            write!(f, "D:{}", dacl)?;
+        }
+        if let Some(sacl) = &self.sacl {
+            write!(f, "S:{}", sacl)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SecurityDescriptor {
+    type Err = ElevateError;
+
+    fn from_str(s: &str) -> ElevateResult<Self> {
+        Self::parse(s)
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn parse(mut self) -> ElevateResult<SecurityDescriptor> {
+        let mut sd = SecurityDescriptor::default();
+
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.is_empty() {
+                break;
+            }
+
+            let tag = rest.as_bytes()[0];
+            if rest.as_bytes().get(1) != Some(&b':') {
+                return Err(self.error("expected one of O:, G:, D: or S:"));
+            }
+            self.pos += 2;
+
+            let duplicate = match tag {
+                b'O' => sd.owner.replace(self.parse_sid_component()?).is_some(),
+                b'G' => sd.group.replace(self.parse_sid_component()?).is_some(),
+                b'D' => sd.dacl.replace(self.parse_acl()?).is_some(),
+                b'S' => sd.sacl.replace(self.parse_acl()?).is_some(),
+                _ => {
+                    self.pos -= 2;
+                    return Err(self.error("unknown component"));
+                }
+            };
+
+            if duplicate {
+                return Err(self.error(&format!("{}: appears more than once", tag as char)));
+            }
+        }
+
+        Ok(sd)
+    }
+
+    fn rest(&self) -> &str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let trimmed = self.rest().trim_start();
+        self.pos = self.input.len() - trimmed.len();
+    }
+
+    fn error(&self, message: &str) -> ElevateError {
+        ElevateError::ValidationError(format!("Invalid SDDL at offset {}: {}", self.pos, message))
+    }
+
+    // SIDs never contain ':' so the next tag starts one byte before it
+    fn parse_sid_component(&mut self) -> ElevateResult<Sid> {
+        let rest = self.rest();
+        let end = rest.find(':').map(|i| i.saturating_sub(1)).unwrap_or(rest.len());
+        let value = &rest[..end];
+        let sid = Sid::parse(value).map_err(|e| self.error(&e.to_string()))?;
+        self.pos += end;
+        Ok(sid)
+    }
+
+    fn parse_acl(&mut self) -> ElevateResult<Acl> {
+        let mut acl = Acl::default();
+
+        loop {
+            let rest = self.rest();
+            if let Some(r) = rest.strip_prefix("NO_ACCESS_CONTROL") {
+                acl.flags.no_access_control = true;
+                self.pos += rest.len() - r.len();
+            } else if rest.starts_with("AR") {
+                acl.flags.auto_inherit_required = true;
+                self.pos += 2;
+            } else if rest.starts_with("AI") {
+                acl.flags.auto_inherited = true;
+                self.pos += 2;
+            } else if rest.starts_with('P') {
+                acl.flags.protected = true;
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+
+        loop {
+            self.skip_whitespace();
+            if !self.rest().starts_with('(') {
+                break;
+            }
+            let len = self.ace_length()?;
+            let body = &self.rest()[1..len - 1];
+            let ace = Ace::parse(body).map_err(|e| self.error(&e.to_string()))?;
+            acl.aces.push(ace);
+            self.pos += len;
+        }
+
+        Ok(acl)
+    }
+
+    // length of the ACE at pos including both parentheses, honouring nested
+    // parentheses and string literals inside conditional expressions
+    fn ace_length(&self) -> ElevateResult<usize> {
+        let mut depth = 0usize;
+        let mut in_string = false;
+
+        for (i, c) in self.rest().char_indices() {
+            match c {
+                '"' => in_string = !in_string,
+                '(' if !in_string => depth += 1,
+                ')' if !in_string => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(i + 1);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Err(self.error("unterminated ACE"))
+    }
+}
+
+fn two_char_tokens<'a>(value: &'a str, what: &str) -> ElevateResult<Vec<&'a str>> {
+    if value.len() % 2 != 0 || !value.is_ascii() {
+        return Err(ElevateError::ValidationError(format!("Invalid {}: {:?}", what, value)));
+    }
+    Ok((0..value.len()).step_by(2).map(|i| &value[i..i + 2]).collect())
+}
+
+fn parse_guid(value: &str) -> ElevateResult<Option<String>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    let groups: Vec<&str> = value.split('-').collect();
+    let valid = groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(g, len)| g.len() == len && g.bytes().all(|b| b.is_ascii_hexdigit()));
+
+    if !valid {
+        return Err(ElevateError::ValidationError(format!("Invalid GUID: {:?}", value)));
+    }
+    Ok(Some(value.to_ascii_lowercase()))
+}
+
+fn describe_sid(sid: Option<&Sid>, with_sid: bool) -> String {
+    match sid {
+        None => "not present".to_string(),
+        Some(sid) => match (sid.alias(), sid.display_name()) {
+            (Some(alias), Some(name)) if with_sid && alias != sid.as_str() => {
+                format!("{} ({}, {})", alias, name, sid.as_str())
+            }
+            (Some(alias), Some(name)) => format!("{} ({})", alias, name),
+            _ => sid.as_str().to_string(),
+        },
+    }
+}
+
+fn ace_table(aces: &[Ace]) -> String {
+    let headers = ["#", "Type", "Flags", "Rights", "Trustee"];
+    let rows: Vec<[String; 5]> = aces
+        .iter()
+        .enumerate()
+        .map(|(i, ace)| {
+            let label = ace.ace_type == AceType::MandatoryLabel;
+            [
+                i.to_string(),
+                ace.ace_type.description().to_string(),
+                ace.flags.describe().join(", "),
+                format!("{} ({})", ace.rights.to_sddl(label), ace.rights.describe(label)),
+                describe_sid(Some(&ace.trustee), false),
+            ]
+        })
+        .collect();
+
+    let mut widths = headers.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: &[&str]| {
+        let line = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        format!("  {}\n", line.trim_end())
+    };
+
+    let mut out = format_row(&headers);
+    out.push_str(&format_row(&widths.map(|w| "-".repeat(w)).iter().map(String::as_str).collect::<Vec<_>>()));
+    for (row, ace) in rows.iter().zip(aces) {
+        out.push_str(&format_row(&row.iter().map(String::as_str).collect::<Vec<_>>()));
+        if let Some(object) = &ace.object_type {
+            out.push_str(&format!("      object type: {}\n", object));
+        }
+        if let Some(inherited) = &ace.inherited_object_type {
+            out.push_str(&format!("      inherited object type: {}\n", inherited));
+        }
+        if let Some(data) = &ace.application_data {
+            let label = if ace.ace_type.is_conditional() { "condition" } else { "attribute" };
+            out.push_str(&format!("      {}: {}\n", label, data));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_canonical() {
+        let sddl = "O:S-1-5-80-956008885-3418522649-1831038044-1853292631-2271478464\
+                    G:S-1-5-80-956008885-3418522649-1831038044-1853292631-2271478464\
+                    D:PAI(A;;FA;;;S-1-5-80-956008885-3418522649-1831038044-1853292631-2271478464)\
+                    (A;OICIIO;GA;;;CO)(A;;0x1200a9;;;BA)(A;OICIIO;GXGR;;;BU)";
+        let sd = SecurityDescriptor::parse(sddl).unwrap();
+        assert!(sd.owner.as_ref().unwrap().is_trusted_installer());
+        assert_eq!(sd.to_string(), sddl);
+    }
+
+    #[test]
+    fn test_aliases_are_normalized() {
+        let sd = SecurityDescriptor::parse("O:TIG:S-1-5-18D:(A;CIOI;0x1f01ff;;;S-1-5-32-544)").unwrap();
+        assert_eq!(
+            sd.to_string(),
+            format!("O:{}G:SYD:(A;OICI;FA;;;BA)", TRUSTED_INSTALLER_SID)
+        );
+    }
+
+    #[test]
+    fn test_access_mask_aliases() {
+        assert_eq!(AccessMask::parse("FA").unwrap(), AccessMask(0x1F01FF));
+        assert_eq!(AccessMask::parse("KA").unwrap(), AccessMask(0xF003F));
+        assert_eq!(AccessMask::parse("GRGX").unwrap().to_sddl(false), "GXGR");
+        assert_eq!(AccessMask::parse("RCSDWDWO").unwrap().to_sddl(false), "SDRCWDWO");
+        assert_eq!(AccessMask(0x0010_0000).to_sddl(false), "0x100000");
+        assert!(AccessMask::parse("ZZ").is_err());
+    }
+
+    #[test]
+    fn test_conditional_and_label_aces() {
+        let sddl = "D:(XA;;FX;;;WD;(@User.Title == \"PM\" && (@User.Division == \"Finance\")))\
+                    S:(ML;;NWNR;;;HI)";
+        let sd = SecurityDescriptor::parse(sddl).unwrap();
+
+        let dacl = sd.dacl.as_ref().unwrap();
+        assert!(dacl.aces[0].ace_type.is_conditional());
+        assert_eq!(
+            dacl.aces[0].application_data.as_deref(),
+            Some("(@User.Title == \"PM\" && (@User.Division == \"Finance\"))")
+        );
+
+        let sacl = sd.sacl.as_ref().unwrap();
+        assert_eq!(sacl.aces[0].ace_type, AceType::MandatoryLabel);
+        assert_eq!(sacl.aces[0].rights, AccessMask(0x3));
+        assert_eq!(sacl.to_string(), "(ML;;NRNW;;;HI)");
+    }
+
+    #[test]
+    fn test_object_aces() {
+        let sddl = "D:(OA;CI;RPWP;BF967A7F-0DE6-11D0-A285-00AA003049E2;;PS)";
+        let sd = SecurityDescriptor::parse(sddl).unwrap();
+        let ace = &sd.dacl.as_ref().unwrap().aces[0];
+        assert_eq!(ace.ace_type, AceType::ObjectAccessAllowed);
+        assert_eq!(ace.object_type.as_deref(), Some("bf967a7f-0de6-11d0-a285-00aa003049e2"));
+        assert_eq!(sd.to_string(), "D:(OA;CI;RPWP;bf967a7f-0de6-11d0-a285-00aa003049e2;;PS)");
+        assert!(SecurityDescriptor::parse("D:(OA;;RP;not-a-guid;;PS)").is_err());
+    }
+
+    #[test]
+    fn test_null_dacl_and_flags() {
+        let sd = SecurityDescriptor::parse("O:BAD:NO_ACCESS_CONTROLS:PARAI").unwrap();
+        assert!(sd.dacl.as_ref().unwrap().flags.no_access_control);
+        let sacl = sd.sacl.as_ref().unwrap();
+        assert!(sacl.flags.protected && sacl.flags.auto_inherit_required && sacl.flags.auto_inherited);
+        assert_eq!(sd.to_string(), "O:BAD:NO_ACCESS_CONTROLS:PARAI");
+    }
+
+    #[test]
+    fn test_add_allow_keeps_canonical_order() {
+        let sd = SecurityDescriptor::parse("D:(D;;FW;;;WD)(A;;FR;;;BU)(A;ID;FA;;;SY)").unwrap();
+        let mut dacl = sd.dacl.unwrap().without_inherited();
+        dacl.add_allow(Ace::allow(Sid::parse("BA").unwrap(), AccessMask(0x1F01FF), AceFlags::default()));
+        assert_eq!(dacl.to_string(), "(D;;FW;;;WD)(A;;FA;;;BA)(A;;FR;;;BU)");
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        assert!(SecurityDescriptor::parse("X:BA").is_err());
+        assert!(SecurityDescriptor::parse("O:ZZ").is_err());
+        assert!(SecurityDescriptor::parse("O:BAO:SY").is_err());
+        assert!(SecurityDescriptor::parse("D:(A;;FA;;;BA").is_err());
+        assert!(SecurityDescriptor::parse("D:(A;;FA;;BA)").is_err());
+        assert!(SecurityDescriptor::parse("D:(XA;;FX;;;WD)").is_err());
+        assert!(Sid::parse("S-2-5-18").is_err());
+    }
+
+    #[test]
+    fn test_explain_lists_every_ace() {
+        let sd = SecurityDescriptor::parse("O:TIG:TID:PAI(A;;FA;;;TI)(A;OICIIO;GA;;;SY)(A;;FRFX;;;BU)").unwrap();
+        let text = sd.explain();
+        assert!(text.contains("NT SERVICE\\TrustedInstaller"));
+        assert!(text.contains("protected, auto-inherited"));
+        assert!(text.contains("FA (file all access)"));
+        assert!(text.contains("0x1200a9 (file read, file execute)"));
+        assert!(text.contains("object inherit, container inherit, inherit only"));
+        assert!(text.contains("SACL  : not present"));
+    }
+}
@@ -0,0 +1,447 @@
+use std::ptr::null_mut;
+use tracing::info;
+use winapi::{
+    shared::winerror::{
+        ERROR_INSUFFICIENT_BUFFER, ERROR_MORE_DATA, ERROR_SERVICE_ALREADY_RUNNING, ERROR_SERVICE_MARKED_FOR_DELETE,
+    },
+    um::{
+        winnt::{DELETE, SERVICE_ERROR_NORMAL, SERVICE_WIN32, SERVICE_WIN32_OWN_PROCESS},
+        winsvc::{
+            ChangeServiceConfigW, CloseServiceHandle, ControlService, CreateServiceW, DeleteService,
+            EnumDependentServicesW, EnumServicesStatusExW, OpenSCManagerW, OpenServiceW, QueryServiceConfigW,
+            QueryServiceStatusEx, StartServiceW, ENUM_SERVICE_STATUSW, ENUM_SERVICE_STATUS_PROCESSW,
+            QUERY_SERVICE_CONFIGW, SC_ENUM_PROCESS_INFO, SC_HANDLE, SC_MANAGER_CONNECT, SC_MANAGER_CREATE_SERVICE,
+            SC_MANAGER_ENUMERATE_SERVICE, SC_STATUS_PROCESS_INFO, SERVICE_ACTIVE, SERVICE_ALL_ACCESS,
+            SERVICE_CHANGE_CONFIG, SERVICE_CONTROL_CONTINUE, SERVICE_CONTROL_PAUSE, SERVICE_CONTROL_STOP,
+            SERVICE_ENUMERATE_DEPENDENTS, SERVICE_NO_CHANGE, SERVICE_PAUSE_CONTINUE, SERVICE_QUERY_CONFIG,
+            SERVICE_QUERY_STATUS, SERVICE_START, SERVICE_STATE_ALL, SERVICE_STATUS, SERVICE_STATUS_PROCESS,
+            SERVICE_STOP,
+        },
+    },
+};
+
+use crate::core::handle::{self, HandleKind};
+use crate::core::service::machine::{ServiceApi, ServiceControl, ServiceStateMachine, WaitOptions};
+use crate::core::service::state::{ServiceState, ServiceStatus, StartType};
+use crate::utils::error::{ElevateError, ElevateResult, WindowsError, WindowsErrorKind};
+use crate::utils::time::SystemClock;
+
+const READ_ACCESS: u32 = SERVICE_QUERY_STATUS | SERVICE_QUERY_CONFIG;
+const CONTROL_ACCESS: u32 = READ_ACCESS
+    | SERVICE_START
+    | SERVICE_STOP
+    | SERVICE_PAUSE_CONTINUE
+    | SERVICE_CHANGE_CONFIG
+    | SERVICE_ENUMERATE_DEPENDENTS;
+// dependencies starting with this are load order groups, not services
+const GROUP_PREFIX: char = '+';
+
+// closes service and service manager handles
+struct ScHandle(SC_HANDLE);
+
+impl ScHandle {
+    fn new(raw: SC_HANDLE) -> Self {
+        if !raw.is_null() {
+            handle::opened(HandleKind::Service);
+        }
+        Self(raw)
+    }
+}
+
+impl Drop for ScHandle {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { CloseServiceHandle(self.0) };
+            handle::closed(HandleKind::Service);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServiceConfig {
+    pub display_name: String,
+    pub binary_path: String,
+    /// `None` for a start type this tool does not know
+    pub start_type: Option<StartType>,
+    pub account: String,
+    /// services and `+group` names that must run first
+    pub dependencies: Vec<String>,
+}
+
+/// Queries and controls one Windows service through the service control manager
+pub struct ServiceController {
+    name: String,
+    service: ScHandle,
+    // the service handle is only valid while the manager handle is open
+    _manager: ScHandle,
+}
+
+impl ServiceController {
+    /// Opens a service for status and config queries
+    pub fn open(name: &str) -> ElevateResult<Self> {
+        Self::open_with(name, READ_ACCESS)
+    }
+
+    /// Opens a service to query it and start it, nothing more
+    pub fn open_to_start(name: &str) -> ElevateResult<Self> {
+        Self::open_with(name, READ_ACCESS | SERVICE_START)
+    }
+
+    /// Opens a service to start, stop and reconfigure it
+    pub fn open_writable(name: &str) -> ElevateResult<Self> {
+        Self::open_with(name, CONTROL_ACCESS)
+    }
+
+    /// Opens a service to stop and delete it
+    pub fn open_to_delete(name: &str) -> ElevateResult<Self> {
+        Self::open_with(name, CONTROL_ACCESS | DELETE)
+    }
+
+    /// Registers an own-process service that runs `command_line` as LocalSystem
+    /// when started, and opens it with full access
+    pub fn create(name: &str, display_name: &str, command_line: &str) -> ElevateResult<Self> {
+        let manager = open_manager(SC_MANAGER_CONNECT | SC_MANAGER_CREATE_SERVICE)?;
+        let (name_wide, display_wide, command_wide) = (to_wide(name), to_wide(display_name), to_wide(command_line));
+
+        // no account name means LocalSystem
+        let service = ScHandle::new(unsafe {
+            CreateServiceW(
+                manager.0,
+                name_wide.as_ptr(),
+                display_wide.as_ptr(),
+                SERVICE_ALL_ACCESS,
+                SERVICE_WIN32_OWN_PROCESS,
+                StartType::Manual.to_raw(),
+                SERVICE_ERROR_NORMAL,
+                command_wide.as_ptr(),
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                null_mut(),
+            )
+        });
+        if service.0.is_null() {
+            return Err(service_error(&format!("Failed to create service {}", name)));
+        }
+        info!("Created service {}", name);
+
+        Ok(Self { name: name.to_string(), service, _manager: manager })
+    }
+
+    /// Names of all Win32 services starting with `prefix`, in any state
+    pub fn find(prefix: &str) -> ElevateResult<Vec<String>> {
+        let manager = open_manager(SC_MANAGER_CONNECT | SC_MANAGER_ENUMERATE_SERVICE)?;
+        let prefix = prefix.to_lowercase();
+        let entry_size = std::mem::size_of::<ENUM_SERVICE_STATUS_PROCESSW>();
+        let mut names = Vec::new();
+        let mut resume = 0;
+
+        // the manager hands out as many entries as fit and resumes from there
+        loop {
+            let (mut needed, mut count) = (0, 0);
+            let mut buffer: Vec<ENUM_SERVICE_STATUS_PROCESSW> = Vec::with_capacity(64 * 1024 / entry_size);
+            let ok = unsafe {
+                EnumServicesStatusExW(
+                    manager.0,
+                    SC_ENUM_PROCESS_INFO,
+                    SERVICE_WIN32,
+                    SERVICE_STATE_ALL,
+                    buffer.as_mut_ptr() as *mut u8,
+                    (buffer.capacity() * entry_size) as u32,
+                    &mut needed,
+                    &mut count,
+                    &mut resume,
+                    null_mut(),
+                )
+            };
+            let more = ok == 0 && WindowsError::last_error().code() == ERROR_MORE_DATA;
+            if ok == 0 && !more {
+                return Err(service_error("Failed to enumerate services"));
+            }
+
+            let entries = unsafe { std::slice::from_raw_parts(buffer.as_ptr(), count as usize) };
+            names.extend(
+                entries
+                    .iter()
+                    .map(|entry| from_wide_ptr(entry.lpServiceName))
+                    .filter(|name| name.to_lowercase().starts_with(&prefix)),
+            );
+            if !more {
+                return Ok(names);
+            }
+        }
+    }
+
+    fn open_with(name: &str, access: u32) -> ElevateResult<Self> {
+        let manager = open_manager(SC_MANAGER_CONNECT)?;
+        let name_wide = to_wide(name);
+        let service = ScHandle::new(unsafe { OpenServiceW(manager.0, name_wide.as_ptr(), access) });
+        if service.0.is_null() {
+            return Err(service_error(&format!("Failed to open service {}", name)));
+        }
+
+        Ok(Self { name: name.to_string(), service, _manager: manager })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn status(&self) -> ElevateResult<ServiceStatus> {
+        let mut status: SERVICE_STATUS_PROCESS = unsafe { std::mem::zeroed() };
+        let mut needed = 0;
+        let ok = unsafe {
+            QueryServiceStatusEx(
+                self.service.0,
+                SC_STATUS_PROCESS_INFO,
+                &mut status as *mut _ as *mut u8,
+                std::mem::size_of::<SERVICE_STATUS_PROCESS>() as u32,
+                &mut needed,
+            )
+        };
+        if ok == 0 {
+            return Err(service_error(&format!("Failed to query the status of {}", self.name)));
+        }
+
+        Ok(ServiceStatus {
+            state: ServiceState::from_raw(status.dwCurrentState),
+            pid: status.dwProcessId,
+            exit_code: status.dwWin32ExitCode,
+            checkpoint: status.dwCheckPoint,
+            wait_hint: status.dwWaitHint,
+        })
+    }
+
+    pub fn config(&self) -> ElevateResult<ServiceConfig> {
+        let mut needed = 0;
+        unsafe { QueryServiceConfigW(self.service.0, null_mut(), 0, &mut needed) };
+
+        // u64 elements keep the buffer aligned for QUERY_SERVICE_CONFIGW
+        let mut buffer = vec![0u64; (needed as usize).div_ceil(8)];
+        let ok = unsafe {
+            QueryServiceConfigW(
+                self.service.0,
+                buffer.as_mut_ptr() as *mut QUERY_SERVICE_CONFIGW,
+                (buffer.len() * 8) as u32,
+                &mut needed,
+            )
+        };
+        if ok == 0 {
+            return Err(service_error(&format!("Failed to query the config of {}", self.name)));
+        }
+
+        let config = unsafe { &*(buffer.as_ptr() as *const QUERY_SERVICE_CONFIGW) };
+        Ok(ServiceConfig {
+            display_name: from_wide_ptr(config.lpDisplayName),
+            binary_path: from_wide_ptr(config.lpBinaryPathName),
+            start_type: StartType::from_raw(config.dwStartType),
+            account: from_wide_ptr(config.lpServiceStartName),
+            dependencies: from_wide_list(config.lpDependencies),
+        })
+    }
+
+    /// Starts the service once its dependencies run, then waits until it runs.
+    /// A service that is still stopping is allowed to finish first.
+    pub fn start(&self, options: &WaitOptions) -> ElevateResult<ServiceStatus> {
+        // the manager would start them too, but fails without saying which one is disabled
+        for dependency in self.config()?.dependencies {
+            if dependency.starts_with(GROUP_PREFIX) {
+                continue;
+            }
+            let dependency = Self::open_writable(&dependency)?;
+            if dependency.status()?.state == ServiceState::Running {
+                continue;
+            }
+            if dependency.config()?.start_type == Some(StartType::Disabled) {
+                return Err(ElevateError::ValidationError(format!(
+                    "{} depends on {}, which is disabled",
+                    self.name,
+                    dependency.name()
+                )));
+            }
+            dependency.start(options)?;
+        }
+
+        self.reach(ServiceState::Running, options)
+    }
+
+    /// Stops the service, and first the running services that depend on it if `dependents` is set
+    pub fn stop(&self, dependents: bool, options: &WaitOptions) -> ElevateResult<ServiceStatus> {
+        if dependents {
+            // listed in the order they have to be stopped
+            for dependent in self.dependents()? {
+                Self::open_writable(&dependent)?.stop(false, options)?;
+            }
+        }
+        self.reach(ServiceState::Stopped, options)
+    }
+
+    pub fn pause(&self, options: &WaitOptions) -> ElevateResult<ServiceStatus> {
+        self.reach(ServiceState::Paused, options)
+    }
+
+    pub fn resume(&self, options: &WaitOptions) -> ElevateResult<ServiceStatus> {
+        self.reach(ServiceState::Running, options)
+    }
+
+    /// Waits out pending transitions and issues whatever leads to `target`
+    pub fn reach(&self, target: ServiceState, options: &WaitOptions) -> ElevateResult<ServiceStatus> {
+        ServiceStateMachine::new(self, &SystemClock, options).reach(target)
+    }
+
+    pub fn set_start_type(&self, start_type: StartType) -> ElevateResult<()> {
+        let ok = unsafe {
+            ChangeServiceConfigW(
+                self.service.0,
+                SERVICE_NO_CHANGE,
+                start_type.to_raw(),
+                SERVICE_NO_CHANGE,
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(service_error(&format!("Failed to change the start type of {}", self.name)));
+        }
+        info!("Start type of {} set to {}", self.name, start_type);
+        Ok(())
+    }
+
+    /// Names of the running services that depend on this one
+    pub fn dependents(&self) -> ElevateResult<Vec<String>> {
+        let (mut needed, mut count) = (0, 0);
+        let ok = unsafe { EnumDependentServicesW(self.service.0, SERVICE_ACTIVE, null_mut(), 0, &mut needed, &mut count) };
+        if ok != 0 {
+            return Ok(Vec::new());
+        }
+        let error = WindowsError::last_error();
+        if error.code() != ERROR_MORE_DATA && error.code() != ERROR_INSUFFICIENT_BUFFER {
+            return Err(error.to_elevate_error(
+                WindowsErrorKind::ServiceOperation,
+                &format!("Failed to list the dependents of {}", self.name),
+            ));
+        }
+
+        let entry_size = std::mem::size_of::<ENUM_SERVICE_STATUSW>();
+        let mut buffer: Vec<ENUM_SERVICE_STATUSW> =
+            Vec::with_capacity((needed as usize).div_ceil(entry_size));
+        let ok = unsafe {
+            EnumDependentServicesW(
+                self.service.0,
+                SERVICE_ACTIVE,
+                buffer.as_mut_ptr(),
+                (buffer.capacity() * entry_size) as u32,
+                &mut needed,
+                &mut count,
+            )
+        };
+        if ok == 0 {
+            return Err(service_error(&format!("Failed to list the dependents of {}", self.name)));
+        }
+
+        let entries = unsafe { std::slice::from_raw_parts(buffer.as_ptr(), count as usize) };
+        Ok(entries.iter().map(|entry| from_wide_ptr(entry.lpServiceName)).collect())
+    }
+
+    /// Marks the service for deletion; the manager removes it once it is
+    /// stopped and every handle to it is closed
+    pub fn delete(&self) -> ElevateResult<()> {
+        if unsafe { DeleteService(self.service.0) } == 0 {
+            let error = WindowsError::last_error();
+            if error.code() != ERROR_SERVICE_MARKED_FOR_DELETE {
+                return Err(error.to_elevate_error(
+                    WindowsErrorKind::ServiceOperation,
+                    &format!("Failed to delete {}", self.name),
+                ));
+            }
+        }
+        info!("Marked {} for deletion", self.name);
+        Ok(())
+    }
+}
+
+impl ServiceApi for ServiceController {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn query(&self) -> ElevateResult<ServiceStatus> {
+        self.status()
+    }
+
+    fn request_start(&self) -> ElevateResult<()> {
+        info!("Starting {}", self.name);
+        if unsafe { StartServiceW(self.service.0, 0, null_mut()) } == 0 {
+            let error = WindowsError::last_error();
+            if error.code() != ERROR_SERVICE_ALREADY_RUNNING {
+                return Err(error.to_elevate_error(
+                    WindowsErrorKind::ServiceOperation,
+                    &format!("Failed to start {}", self.name),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn request_control(&self, control: ServiceControl) -> ElevateResult<()> {
+        let (code, action) = match control {
+            ServiceControl::Stop => (SERVICE_CONTROL_STOP, "stop"),
+            ServiceControl::Pause => (SERVICE_CONTROL_PAUSE, "pause"),
+            ServiceControl::Continue => (SERVICE_CONTROL_CONTINUE, "continue"),
+        };
+        info!("Sending {} to {}", action, self.name);
+
+        let mut status: SERVICE_STATUS = unsafe { std::mem::zeroed() };
+        if unsafe { ControlService(self.service.0, code, &mut status) } == 0 {
+            return Err(service_error(&format!("Failed to {} {}", action, self.name)));
+        }
+        Ok(())
+    }
+}
+
+fn open_manager(access: u32) -> ElevateResult<ScHandle> {
+    let manager = ScHandle::new(unsafe { OpenSCManagerW(null_mut(), null_mut(), access) });
+    if manager.0.is_null() {
+        return Err(service_error("Failed to open the service control manager"));
+    }
+    Ok(manager)
+}
+
+fn service_error(context: &str) -> ElevateError {
+    WindowsError::last_error().to_elevate_error(WindowsErrorKind::ServiceOperation, context)
+}
+
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+fn from_wide_ptr(ptr: *const u16) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    unsafe { String::from_utf16_lossy(std::slice::from_raw_parts(ptr, wide_len(ptr))) }
+}
+
+// a list of strings ended by an empty one
+fn from_wide_list(mut ptr: *const u16) -> Vec<String> {
+    let mut strings = Vec::new();
+    while !ptr.is_null() {
+        let len = unsafe { wide_len(ptr) };
+        if len == 0 {
+            break;
+        }
+        strings.push(from_wide_ptr(ptr));
+        ptr = unsafe { ptr.add(len + 1) };
+    }
+    strings
+}
+
+unsafe fn wide_len(ptr: *const u16) -> usize {
+    (0..).take_while(|&i| *ptr.add(i) != 0).count()
+}
@@ -0,0 +1,98 @@
+//! Runs this process as a service: hands the main thread to the service
+//! control dispatcher, reports running while the body runs and stopped with
+//! its exit code afterwards.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::ptr::null_mut;
+use tracing::warn;
+use winapi::{
+    shared::{
+        minwindef::{DWORD, LPVOID},
+        winerror::{ERROR_CALL_NOT_IMPLEMENTED, ERROR_SERVICE_SPECIFIC_ERROR, NO_ERROR},
+    },
+    um::{
+        winnt::{LPWSTR, SERVICE_WIN32_OWN_PROCESS},
+        winsvc::{
+            RegisterServiceCtrlHandlerExW, SetServiceStatus, StartServiceCtrlDispatcherW, SERVICE_ACCEPT_SHUTDOWN,
+            SERVICE_ACCEPT_STOP, SERVICE_CONTROL_INTERROGATE, SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP,
+            SERVICE_RUNNING, SERVICE_STATUS, SERVICE_STATUS_HANDLE, SERVICE_STOPPED, SERVICE_STOP_PENDING,
+            SERVICE_TABLE_ENTRYW,
+        },
+    },
+};
+
+use crate::utils::cancel::CancellationToken;
+use crate::utils::error::{ElevateResult, WindowsError, WindowsErrorKind};
+
+type ServiceBody = Box<dyn FnOnce(CancellationToken) -> u32 + Send>;
+
+// the dispatcher calls back into plain functions, so everything they need lives here
+static NAME: OnceLock<Vec<u16>> = OnceLock::new();
+static BODY: Mutex<Option<ServiceBody>> = Mutex::new(None);
+static STATUS_HANDLE: AtomicUsize = AtomicUsize::new(0);
+static STOP: OnceLock<CancellationToken> = OnceLock::new();
+
+/// Connects to the service control manager and runs `body` as service `name`.
+/// The token passed to `body` is cancelled by a stop or shutdown request; its
+/// return value becomes the service specific exit code. Only returns once the
+/// service has stopped, and fails if the process was not started by the manager.
+pub fn run_as_service(name: &str, body: impl FnOnce(CancellationToken) -> u32 + Send + 'static) -> ElevateResult<()> {
+    let name = NAME.get_or_init(|| name.encode_utf16().chain(std::iter::once(0)).collect());
+    *BODY.lock().unwrap() = Some(Box::new(body));
+
+    let table = [
+        SERVICE_TABLE_ENTRYW { lpServiceName: name.as_ptr() as LPWSTR, lpServiceProc: Some(service_main) },
+        SERVICE_TABLE_ENTRYW { lpServiceName: null_mut(), lpServiceProc: None },
+    ];
+    if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+        return Err(WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::ServiceOperation,
+            "Failed to connect to the service control manager",
+        ));
+    }
+    Ok(())
+}
+
+unsafe extern "system" fn service_main(_argc: DWORD, _argv: *mut LPWSTR) {
+    let Some(name) = NAME.get() else { return };
+    let handle = RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(control_handler), null_mut());
+    if handle.is_null() {
+        warn!("Failed to register the service control handler: error {}", WindowsError::last_error().code());
+        return;
+    }
+    STATUS_HANDLE.store(handle as usize, Ordering::SeqCst);
+
+    report(SERVICE_RUNNING, 0);
+    let body = BODY.lock().unwrap().take();
+    let exit_code = body.map_or(1, |body| body(STOP.get_or_init(CancellationToken::new).clone()));
+    report(SERVICE_STOPPED, exit_code);
+}
+
+unsafe extern "system" fn control_handler(control: DWORD, _event: DWORD, _data: LPVOID, _context: LPVOID) -> DWORD {
+    match control {
+        SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+            STOP.get_or_init(CancellationToken::new).cancel();
+            report(SERVICE_STOP_PENDING, 0);
+            NO_ERROR
+        }
+        SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+        _ => ERROR_CALL_NOT_IMPLEMENTED,
+    }
+}
+
+fn report(state: DWORD, exit_code: u32) {
+    let handle = STATUS_HANDLE.load(Ordering::SeqCst) as SERVICE_STATUS_HANDLE;
+    let mut status = SERVICE_STATUS {
+        dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+        dwCurrentState: state,
+        dwControlsAccepted: if state == SERVICE_RUNNING { SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN } else { 0 },
+        dwWin32ExitCode: if exit_code == 0 { NO_ERROR } else { ERROR_SERVICE_SPECIFIC_ERROR },
+        dwServiceSpecificExitCode: exit_code,
+        dwCheckPoint: 0,
+        dwWaitHint: if state == SERVICE_STOP_PENDING { 5000 } else { 0 },
+    };
+    if unsafe { SetServiceStatus(handle, &mut status) } == 0 {
+        warn!("Failed to report service state {}: error {}", state, WindowsError::last_error().code());
+    }
+}
@@ -0,0 +1,335 @@
+//! Drives a service into a target state. Every `SERVICE_*` state maps to the
+//! next step: wait out a pending transition, issue a start or control, or give
+//! up. Waits follow `dwCheckPoint`/`dwWaitHint` as the SCM documentation
+//! describes and stop at the configured timeout or when cancelled.
+
+use std::time::Duration;
+use tracing::debug;
+
+use crate::core::service::state::{ServiceState, ServiceStatus};
+use crate::utils::cancel::CancellationToken;
+use crate::utils::config::ServicesConfig;
+use crate::utils::error::{ElevateError, ElevateResult};
+use crate::utils::time::Clock;
+
+// a hint of zero still gets this long to show progress
+const MIN_WAIT_HINT: Duration = Duration::from_secs(2);
+const MIN_POLL: Duration = Duration::from_millis(100);
+const MAX_POLL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceControl {
+    Stop,
+    Pause,
+    Continue,
+}
+
+/// What the state machine needs from a service
+pub trait ServiceApi {
+    fn name(&self) -> &str;
+    fn query(&self) -> ElevateResult<ServiceStatus>;
+    /// Asks the SCM to start the service; already running is not an error
+    fn request_start(&self) -> ElevateResult<()>;
+    fn request_control(&self, control: ServiceControl) -> ElevateResult<()>;
+}
+
+#[derive(Debug, Clone)]
+pub struct WaitOptions {
+    pub timeout: Duration,
+    pub cancel: CancellationToken,
+}
+
+impl WaitOptions {
+    pub fn from_config(config: &ServicesConfig, cancel: CancellationToken) -> Self {
+        Self { timeout: Duration::from_secs(config.timeout), cancel }
+    }
+}
+
+impl Default for WaitOptions {
+    fn default() -> Self {
+        Self::from_config(&ServicesConfig::default(), CancellationToken::new())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Done,
+    /// let the current transition finish first
+    WaitWhilePending,
+    Start,
+    Control(ServiceControl),
+    /// no action leads from the current state to the target
+    Fail,
+}
+
+/// The next step from `current` towards `target`, which is running, stopped or paused
+pub fn next_step(current: ServiceState, target: ServiceState) -> Step {
+    use ServiceState::*;
+
+    if current == target {
+        return Step::Done;
+    }
+    if current.is_pending() {
+        return Step::WaitWhilePending;
+    }
+    match (current, target) {
+        (Stopped, Running | Paused) => Step::Start,
+        (Paused, Running) => Step::Control(ServiceControl::Continue),
+        (Running | Paused, Stopped) => Step::Control(ServiceControl::Stop),
+        (Running, Paused) => Step::Control(ServiceControl::Pause),
+        _ => Step::Fail,
+    }
+}
+
+pub struct ServiceStateMachine<'a, S: ServiceApi + ?Sized, C: Clock> {
+    service: &'a S,
+    clock: &'a C,
+    options: &'a WaitOptions,
+}
+
+impl<'a, S: ServiceApi + ?Sized, C: Clock> ServiceStateMachine<'a, S, C> {
+    pub fn new(service: &'a S, clock: &'a C, options: &'a WaitOptions) -> Self {
+        Self { service, clock, options }
+    }
+
+    /// Issues starts and controls until the service settles in `target`
+    pub fn reach(&self, target: ServiceState) -> ElevateResult<ServiceStatus> {
+        let deadline = self.clock.now() + self.options.timeout;
+        let mut last_action = None;
+        let mut status = self.service.query()?;
+
+        loop {
+            let step = next_step(status.state, target);
+            debug!("{} is {}, next step towards {}: {:?}", self.service.name(), status.state, target, step);
+
+            match step {
+                Step::Done => return Ok(status),
+                Step::WaitWhilePending => {
+                    status = self.wait_while_pending(status, target, deadline)?;
+                    continue;
+                }
+                Step::Fail => return Err(self.settled_elsewhere(&status, target)),
+                Step::Start | Step::Control(_) => {}
+            }
+
+            // the same request twice in a row means the last one did not stick,
+            // such as a service that exits right after starting
+            if last_action == Some(step) {
+                return Err(self.settled_elsewhere(&status, target));
+            }
+            match step {
+                Step::Start => self.service.request_start()?,
+                Step::Control(control) => self.service.request_control(control)?,
+                _ => unreachable!(),
+            }
+            last_action = Some(step);
+            status = self.service.query()?;
+        }
+    }
+
+    fn wait_while_pending(
+        &self,
+        mut status: ServiceStatus,
+        target: ServiceState,
+        deadline: std::time::Instant,
+    ) -> ElevateResult<ServiceStatus> {
+        let mut progress_deadline = self.clock.now() + wait_hint(&status);
+
+        while status.state.is_pending() {
+            if self.options.cancel.is_cancelled() {
+                return Err(ElevateError::ProcessError(format!(
+                    "Cancelled while {} was {}",
+                    self.service.name(),
+                    status.state
+                )));
+            }
+            let now = self.clock.now();
+            if now >= deadline {
+                return Err(ElevateError::ProcessError(format!(
+                    "Timed out after {:?} waiting for {} to become {}, it is {}",
+                    self.options.timeout,
+                    self.service.name(),
+                    target,
+                    status.state
+                )));
+            }
+            if now >= progress_deadline {
+                return Err(ElevateError::ProcessError(format!(
+                    "{} stopped responding while {} (checkpoint {})",
+                    self.service.name(),
+                    status.state,
+                    status.checkpoint
+                )));
+            }
+
+            self.clock.sleep(poll_interval(&status));
+            let next = self.service.query()?;
+            // a new state or checkpoint is progress and restarts the hint
+            if next.state != status.state || next.checkpoint > status.checkpoint {
+                progress_deadline = self.clock.now() + wait_hint(&next);
+            }
+            status = next;
+        }
+        Ok(status)
+    }
+
+    fn settled_elsewhere(&self, status: &ServiceStatus, target: ServiceState) -> ElevateError {
+        ElevateError::ProcessError(format!(
+            "{} is {} instead of {} (exit code {})",
+            self.service.name(),
+            status.state,
+            target,
+            status.exit_code
+        ))
+    }
+}
+
+fn wait_hint(status: &ServiceStatus) -> Duration {
+    Duration::from_millis(status.wait_hint as u64).max(MIN_WAIT_HINT)
+}
+
+// a tenth of the hint, as the SCM documentation suggests
+fn poll_interval(status: &ServiceStatus) -> Duration {
+    (Duration::from_millis(status.wait_hint as u64) / 10).clamp(MIN_POLL, MAX_POLL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use ServiceState::*;
+    use crate::utils::time::FakeClock;
+
+    #[derive(Debug, PartialEq)]
+    enum Request {
+        Start,
+        Control(ServiceControl),
+    }
+
+    // answers queries from a script, repeating the last status once it runs out
+    struct ScriptedService {
+        script: RefCell<VecDeque<ServiceStatus>>,
+        requests: RefCell<Vec<Request>>,
+    }
+
+    impl ScriptedService {
+        fn new(script: &[(ServiceState, u32)]) -> Self {
+            let script = script
+                .iter()
+                .map(|&(state, checkpoint)| ServiceStatus {
+                    state,
+                    pid: if state == Running { 1234 } else { 0 },
+                    exit_code: if state == Stopped { 1067 } else { 0 },
+                    checkpoint,
+                    wait_hint: 3000,
+                })
+                .collect();
+            Self { script: RefCell::new(script), requests: RefCell::new(Vec::new()) }
+        }
+    }
+
+    impl ServiceApi for ScriptedService {
+        fn name(&self) -> &str {
+            "Fake"
+        }
+
+        fn query(&self) -> ElevateResult<ServiceStatus> {
+            let mut script = self.script.borrow_mut();
+            if script.len() > 1 {
+                Ok(script.pop_front().unwrap())
+            } else {
+                Ok(script[0])
+            }
+        }
+
+        fn request_start(&self) -> ElevateResult<()> {
+            self.requests.borrow_mut().push(Request::Start);
+            Ok(())
+        }
+
+        fn request_control(&self, control: ServiceControl) -> ElevateResult<()> {
+            self.requests.borrow_mut().push(Request::Control(control));
+            Ok(())
+        }
+    }
+
+    fn reach(service: &ScriptedService, target: ServiceState, options: &WaitOptions) -> ElevateResult<ServiceStatus> {
+        ServiceStateMachine::new(service, &FakeClock::new(), options).reach(target)
+    }
+
+    #[test]
+    fn test_next_step_covers_every_state() {
+        assert_eq!(next_step(Running, Running), Step::Done);
+        assert_eq!(next_step(StopPending, Running), Step::WaitWhilePending);
+        assert_eq!(next_step(Stopped, Running), Step::Start);
+        assert_eq!(next_step(Paused, Running), Step::Control(ServiceControl::Continue));
+        assert_eq!(next_step(Paused, Stopped), Step::Control(ServiceControl::Stop));
+        assert_eq!(next_step(Running, Paused), Step::Control(ServiceControl::Pause));
+        assert_eq!(next_step(Stopped, Paused), Step::Start);
+        assert_eq!(next_step(Unknown(9), Running), Step::Fail);
+    }
+
+    #[test]
+    fn test_start_from_stopped() {
+        let service = ScriptedService::new(&[(Stopped, 0), (StartPending, 1), (StartPending, 2), (Running, 0)]);
+        let status = reach(&service, Running, &WaitOptions::default()).unwrap();
+        assert_eq!(status.pid, 1234);
+        assert_eq!(*service.requests.borrow(), vec![Request::Start]);
+    }
+
+    #[test]
+    fn test_pending_stop_finishes_before_start() {
+        let service = ScriptedService::new(&[(StopPending, 1), (StopPending, 2), (Stopped, 0), (Running, 0)]);
+        reach(&service, Running, &WaitOptions::default()).unwrap();
+        assert_eq!(*service.requests.borrow(), vec![Request::Start]);
+    }
+
+    #[test]
+    fn test_paused_service_is_continued() {
+        let service = ScriptedService::new(&[(Paused, 0), (ContinuePending, 1), (Running, 0)]);
+        reach(&service, Running, &WaitOptions::default()).unwrap();
+        assert_eq!(*service.requests.borrow(), vec![Request::Control(ServiceControl::Continue)]);
+    }
+
+    #[test]
+    fn test_stop_running_service() {
+        let service = ScriptedService::new(&[(Running, 0), (StopPending, 1), (Stopped, 0)]);
+        reach(&service, Stopped, &WaitOptions::default()).unwrap();
+        assert_eq!(*service.requests.borrow(), vec![Request::Control(ServiceControl::Stop)]);
+    }
+
+    #[test]
+    fn test_service_that_exits_after_start_fails_once() {
+        let service = ScriptedService::new(&[(Stopped, 0), (StartPending, 1), (Stopped, 0)]);
+        let error = reach(&service, Running, &WaitOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("exit code 1067"), "{}", error);
+        assert_eq!(*service.requests.borrow(), vec![Request::Start]);
+    }
+
+    #[test]
+    fn test_stuck_checkpoint_is_not_responding() {
+        let service = ScriptedService::new(&[(StartPending, 4)]);
+        let error = reach(&service, Running, &WaitOptions::default()).unwrap_err();
+        assert!(error.to_string().contains("stopped responding"), "{}", error);
+    }
+
+    #[test]
+    fn test_timeout_despite_progress() {
+        let script: Vec<_> = (1..1000).map(|checkpoint| (StartPending, checkpoint)).collect();
+        let service = ScriptedService::new(&script);
+        let options = WaitOptions { timeout: Duration::from_secs(5), cancel: CancellationToken::new() };
+        let error = reach(&service, Running, &options).unwrap_err();
+        assert!(error.to_string().contains("Timed out"), "{}", error);
+    }
+
+    #[test]
+    fn test_cancelled_wait() {
+        let service = ScriptedService::new(&[(StopPending, 1), (StopPending, 2)]);
+        let options = WaitOptions::default();
+        options.cancel.cancel();
+        let error = reach(&service, Running, &options).unwrap_err();
+        assert!(error.to_string().contains("Cancelled"), "{}", error);
+        assert!(service.requests.borrow().is_empty());
+    }
+}
@@ -0,0 +1,9 @@
+#[cfg(windows)]
+pub mod controller;
+#[cfg(windows)]
+pub mod host;
+pub mod machine;
+pub mod relay;
+#[cfg(windows)]
+pub mod restore;
+pub mod state;
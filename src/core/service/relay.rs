@@ -0,0 +1,185 @@
+//! Output of a command relayed to another process. Each frame is one tag
+//! byte, a little-endian u32 length and the payload.
+
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use crate::utils::cancel::CancellationToken;
+use crate::utils::error::{ElevateError, ElevateResult};
+
+const TAG_STDOUT: u8 = 1;
+const TAG_STDERR: u8 = 2;
+const TAG_EXIT: u8 = 3;
+const TAG_ERROR: u8 = 4;
+// a frame larger than this means the stream is corrupt
+const MAX_FRAME: usize = 16 * 1024 * 1024;
+const READ_BUFFER: usize = 4096;
+const CANCEL_POLL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelayMessage {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    /// the command exited, always the last message
+    Exit(i32),
+    /// the command could not be run
+    Error(String),
+}
+
+pub fn write_message(writer: &mut impl Write, message: &RelayMessage) -> io::Result<()> {
+    let (tag, payload) = match message {
+        RelayMessage::Stdout(bytes) => (TAG_STDOUT, bytes.clone()),
+        RelayMessage::Stderr(bytes) => (TAG_STDERR, bytes.clone()),
+        RelayMessage::Exit(code) => (TAG_EXIT, code.to_le_bytes().to_vec()),
+        RelayMessage::Error(text) => (TAG_ERROR, text.as_bytes().to_vec()),
+    };
+
+    let mut frame = Vec::with_capacity(5 + payload.len());
+    frame.push(tag);
+    frame.extend((payload.len() as u32).to_le_bytes());
+    frame.extend(payload);
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+/// The next message, `None` if the stream ended between messages
+pub fn read_message(reader: &mut impl Read) -> io::Result<Option<RelayMessage>> {
+    let mut header = [0u8; 5];
+    let mut filled = 0;
+    while filled < header.len() {
+        match reader.read(&mut header[filled..])? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            read => filled += read,
+        }
+    }
+
+    let len = u32::from_le_bytes(header[1..5].try_into().unwrap()) as usize;
+    if len > MAX_FRAME {
+        return Err(invalid(format!("frame of {} bytes", len)));
+    }
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+
+    match header[0] {
+        TAG_STDOUT => Ok(Some(RelayMessage::Stdout(payload))),
+        TAG_STDERR => Ok(Some(RelayMessage::Stderr(payload))),
+        TAG_EXIT => {
+            let code: [u8; 4] = payload.try_into().map_err(|_| invalid("exit code of the wrong size".into()))?;
+            Ok(Some(RelayMessage::Exit(i32::from_le_bytes(code))))
+        }
+        TAG_ERROR => Ok(Some(RelayMessage::Error(String::from_utf8_lossy(&payload).into_owned()))),
+        tag => Err(invalid(format!("unknown tag {}", tag))),
+    }
+}
+
+/// Runs a command with its output going to `output` as it arrives and returns
+/// its exit code. Cancelling the token kills it.
+pub fn run_captured(
+    command: &str,
+    args: &[String],
+    cancel: &CancellationToken,
+    output: &mut dyn FnMut(RelayMessage),
+) -> ElevateResult<i32> {
+    let mut child = Command::new(command)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| ElevateError::ProcessError(format!("Failed to start {}: {}", command, e)))?;
+
+    let (sender, receiver) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        forward(stdout, RelayMessage::Stdout, sender.clone());
+    }
+    if let Some(stderr) = child.stderr.take() {
+        forward(stderr, RelayMessage::Stderr, sender);
+    }
+
+    // the channel closes once the child has closed both outputs
+    let mut killed = false;
+    loop {
+        match receiver.recv_timeout(CANCEL_POLL) {
+            Ok(message) => output(message),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        if cancel.is_cancelled() && !killed {
+            let _ = child.kill();
+            killed = true;
+        }
+    }
+
+    let status = child
+        .wait()
+        .map_err(|e| ElevateError::ProcessError(format!("Failed to wait for {}: {}", command, e)))?;
+    Ok(status.code().unwrap_or(1))
+}
+
+fn forward(mut output: impl Read + Send + 'static, wrap: fn(Vec<u8>) -> RelayMessage, sender: mpsc::Sender<RelayMessage>) {
+    thread::spawn(move || {
+        let mut buffer = vec![0u8; READ_BUFFER];
+        while let Ok(read @ 1..) = output.read(&mut buffer) {
+            if sender.send(wrap(buffer[..read].to_vec())).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Writes relayed output to our own stdout or stderr; a closed console is no
+/// reason to stop relaying, so write errors are ignored
+pub fn print_output(message: &RelayMessage) {
+    let _ = match message {
+        RelayMessage::Stdout(bytes) => {
+            let mut stdout = io::stdout();
+            stdout.write_all(bytes).and_then(|_| stdout.flush())
+        }
+        RelayMessage::Stderr(bytes) => io::stderr().write_all(bytes),
+        _ => Ok(()),
+    };
+}
+
+fn invalid(what: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid relay message: {}", what))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_round_trip() {
+        let messages = vec![
+            RelayMessage::Stdout(b"hello\r\n".to_vec()),
+            RelayMessage::Stderr(Vec::new()),
+            RelayMessage::Error("not found".into()),
+            RelayMessage::Exit(-1073741510),
+        ];
+        let mut stream = Vec::new();
+        for message in &messages {
+            write_message(&mut stream, message).unwrap();
+        }
+
+        let mut reader = Cursor::new(stream);
+        for message in &messages {
+            assert_eq!(read_message(&mut reader).unwrap().as_ref(), Some(message));
+        }
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_truncated_and_corrupt_frames() {
+        let mut stream = Vec::new();
+        write_message(&mut stream, &RelayMessage::Stdout(b"partial".to_vec())).unwrap();
+        stream.pop();
+        assert!(read_message(&mut Cursor::new(&stream)).is_err());
+        assert!(read_message(&mut Cursor::new(&stream[..3])).is_err());
+        assert!(read_message(&mut Cursor::new([9u8, 0, 0, 0, 0])).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use tracing::{info, warn};
+
+use crate::core::service::controller::ServiceController;
+use crate::core::service::machine::WaitOptions;
+use crate::core::service::state::{ServiceState, StartType};
+use crate::utils::cancel::CancellationToken;
+use crate::utils::error::ElevateResult;
+
+/// State of a service before we started it, put back by `restore` or on drop
+pub struct ServiceRestore {
+    name: String,
+    was_stopped: bool,
+    /// start type to put back, set only if we changed it
+    changed_start_type: Option<StartType>,
+    /// the caller's timeout with a token of its own, as the restore runs
+    /// after Ctrl+C has cancelled the caller's
+    wait: WaitOptions,
+    restored: bool,
+}
+
+impl ServiceRestore {
+    /// Records the current state and enables the service if it is disabled
+    pub fn capture(name: &str, wait: &WaitOptions) -> ElevateResult<Self> {
+        let service = ServiceController::open(name)?;
+        let was_stopped = service.status()?.state == ServiceState::Stopped;
+        let start_type = service.config()?.start_type;
+        info!("{} is {}, start type {:?}", name, if was_stopped { "stopped" } else { "running" }, start_type);
+
+        let mut restore = Self {
+            name: name.to_string(),
+            was_stopped,
+            changed_start_type: None,
+            wait: WaitOptions { timeout: wait.timeout, cancel: CancellationToken::new() },
+            restored: false,
+        };
+        if start_type == Some(StartType::Disabled) {
+            ServiceController::open_writable(name)?.set_start_type(StartType::Manual)?;
+            restore.changed_start_type = Some(StartType::Disabled);
+        }
+        Ok(restore)
+    }
+
+    pub fn restore(mut self) -> ElevateResult<()> {
+        self.restored = true;
+        self.put_back()
+    }
+
+    fn put_back(&self) -> ElevateResult<()> {
+        if !self.was_stopped && self.changed_start_type.is_none() {
+            return Ok(());
+        }
+
+        // the start type is put back even if the stop failed, the first error is returned
+        let service = ServiceController::open_writable(&self.name)?;
+        let stopped = if self.was_stopped { service.stop(false, &self.wait).map(|_| ()) } else { Ok(()) };
+        let start_type_set = match self.changed_start_type {
+            Some(start_type) => service.set_start_type(start_type),
+            None => Ok(()),
+        };
+        stopped.and(start_type_set)?;
+        info!("Restored the state of {}", self.name);
+        Ok(())
+    }
+}
+
+impl Drop for ServiceRestore {
+    fn drop(&mut self) {
+        if !self.restored {
+            if let Err(e) = self.put_back() {
+                warn!("Failed to restore the state of {}: {}", self.name, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::error::ElevateError;
+
+// dwCurrentState values
+const SERVICE_STOPPED: u32 = 1;
+const SERVICE_START_PENDING: u32 = 2;
+const SERVICE_STOP_PENDING: u32 = 3;
+const SERVICE_RUNNING: u32 = 4;
+const SERVICE_CONTINUE_PENDING: u32 = 5;
+const SERVICE_PAUSE_PENDING: u32 = 6;
+const SERVICE_PAUSED: u32 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceState {
+    Stopped,
+    StartPending,
+    StopPending,
+    Running,
+    ContinuePending,
+    PausePending,
+    Paused,
+    Unknown(u32),
+}
+
+impl ServiceState {
+    pub fn from_raw(state: u32) -> Self {
+        match state {
+            SERVICE_STOPPED => Self::Stopped,
+            SERVICE_START_PENDING => Self::StartPending,
+            SERVICE_STOP_PENDING => Self::StopPending,
+            SERVICE_RUNNING => Self::Running,
+            SERVICE_CONTINUE_PENDING => Self::ContinuePending,
+            SERVICE_PAUSE_PENDING => Self::PausePending,
+            SERVICE_PAUSED => Self::Paused,
+            other => Self::Unknown(other),
+        }
+    }
+
+    /// The service is moving between states and reports progress through its checkpoint
+    pub fn is_pending(self) -> bool {
+        matches!(self, Self::StartPending | Self::StopPending | Self::ContinuePending | Self::PausePending)
+    }
+}
+
+impl fmt::Display for ServiceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stopped => write!(f, "STOPPED"),
+            Self::StartPending => write!(f, "START_PENDING"),
+            Self::StopPending => write!(f, "STOP_PENDING"),
+            Self::Running => write!(f, "RUNNING"),
+            Self::ContinuePending => write!(f, "CONTINUE_PENDING"),
+            Self::PausePending => write!(f, "PAUSE_PENDING"),
+            Self::Paused => write!(f, "PAUSED"),
+            Self::Unknown(state) => write!(f, "UNKNOWN ({})", state),
+        }
+    }
+}
+
+/// A snapshot of `SERVICE_STATUS_PROCESS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServiceStatus {
+    pub state: ServiceState,
+    /// 0 unless the service is running or pending
+    pub pid: u32,
+    pub exit_code: u32,
+    pub checkpoint: u32,
+    /// milliseconds until the next checkpoint is expected
+    pub wait_hint: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StartType {
+    Boot,
+    System,
+    Automatic,
+    Manual,
+    Disabled,
+}
+
+// (start type, dwStartType, name accepted and shown)
+const START_TYPES: &[(StartType, u32, &str)] = &[
+    (StartType::Boot, 0, "boot"),
+    (StartType::System, 1, "system"),
+    (StartType::Automatic, 2, "auto"),
+    (StartType::Manual, 3, "demand"),
+    (StartType::Disabled, 4, "disabled"),
+];
+
+impl StartType {
+    pub fn from_raw(start_type: u32) -> Option<Self> {
+        START_TYPES.iter().find(|(_, raw, _)| *raw == start_type).map(|(kind, ..)| *kind)
+    }
+
+    pub fn to_raw(self) -> u32 {
+        START_TYPES.iter().find(|(kind, ..)| *kind == self).unwrap().1
+    }
+}
+
+impl fmt::Display for StartType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", START_TYPES.iter().find(|(kind, ..)| kind == self).unwrap().2)
+    }
+}
+
+/// Takes the names `sc config start=` takes, plus `manual` and `automatic`
+impl FromStr for StartType {
+    type Err = ElevateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        let name = match s.as_str() {
+            "manual" => "demand",
+            "automatic" => "auto",
+            other => other,
+        };
+        START_TYPES
+            .iter()
+            .find(|(.., known)| *known == name)
+            .map(|(kind, ..)| *kind)
+            .ok_or_else(|| {
+                ElevateError::ValidationError(format!(
+                    "Unknown start type {}, expected boot, system, auto, demand or disabled",
+                    s
+                ))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_start_type_names() {
+        assert_eq!("Manual".parse::<StartType>().unwrap(), StartType::Manual);
+        assert_eq!("auto".parse::<StartType>().unwrap().to_raw(), 2);
+        assert_eq!(StartType::from_raw(4), Some(StartType::Disabled));
+        assert_eq!(StartType::Manual.to_string(), "demand");
+        assert!("sometimes".parse::<StartType>().is_err());
+    }
+
+    #[test]
+    fn test_pending_states() {
+        assert!(ServiceState::from_raw(SERVICE_STOP_PENDING).is_pending());
+        assert!(!ServiceState::from_raw(SERVICE_PAUSED).is_pending());
+        assert_eq!(ServiceState::from_raw(42), ServiceState::Unknown(42));
+    }
+}
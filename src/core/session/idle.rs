@@ -0,0 +1,68 @@
+//! Decides when a session worker has been idle long enough to exit. Time is
+//! passed in, so the worker uses the real clock and tests their own instants.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub struct IdleTracker {
+    /// zero never expires
+    timeout: Duration,
+    state: Mutex<IdleState>,
+}
+
+struct IdleState {
+    active: usize,
+    last_activity: Instant,
+}
+
+impl IdleTracker {
+    pub fn new(timeout: Duration, now: Instant) -> Self {
+        Self { timeout, state: Mutex::new(IdleState { active: 0, last_activity: now }) }
+    }
+
+    /// A request started; the worker is busy until the matching `finish`
+    pub fn start(&self, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        state.active += 1;
+        state.last_activity = now;
+    }
+
+    pub fn finish(&self, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        state.active = state.active.saturating_sub(1);
+        state.last_activity = now;
+    }
+
+    /// Nothing ran for the whole timeout
+    pub fn is_expired(&self, now: Instant) -> bool {
+        let state = self.state.lock().unwrap();
+        !self.timeout.is_zero() && state.active == 0 && now.duration_since(state.last_activity) >= self.timeout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expires_only_when_idle_for_the_timeout() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let idle = IdleTracker::new(Duration::from_secs(10), start);
+
+        assert!(!idle.is_expired(at(9)));
+        idle.start(at(9));
+        // a long request keeps the worker alive
+        assert!(!idle.is_expired(at(100)));
+        idle.finish(at(100));
+        assert!(!idle.is_expired(at(109)));
+        assert!(idle.is_expired(at(110)));
+    }
+
+    #[test]
+    fn test_zero_timeout_never_expires() {
+        let start = Instant::now();
+        let idle = IdleTracker::new(Duration::ZERO, start);
+        assert!(!idle.is_expired(start + Duration::from_secs(86400)));
+    }
+}
@@ -0,0 +1,5 @@
+pub mod idle;
+#[cfg(windows)]
+pub mod registry;
+#[cfg(windows)]
+pub mod worker;
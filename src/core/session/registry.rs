@@ -0,0 +1,87 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::core::process::process_instance_is_running;
+use crate::utils::config::Config;
+use crate::utils::error::{ElevateError, ElevateResult};
+use crate::utils::files::file_error;
+
+const SESSIONS_FILE: &str = "sessions.json";
+
+/// A worker started by `session start`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub id: String,
+    pub pid: u32,
+    /// creation time of the worker, as `process::creation_time` reads it
+    pub created: u64,
+    /// name under `\\.\pipe\`
+    pub pipe: String,
+    /// seconds since the unix epoch
+    pub started_at: u64,
+    /// seconds without requests before the worker exits, zero for never
+    pub idle_timeout: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SessionStore {
+    sessions: Vec<SessionRecord>,
+}
+
+/// Remembers running sessions across runs in `sessions.json`
+pub struct SessionRegistry {
+    store_path: PathBuf,
+}
+
+impl SessionRegistry {
+    pub fn new() -> ElevateResult<Self> {
+        Ok(Self { store_path: Config::state_dir()?.join(SESSIONS_FILE) })
+    }
+
+    /// Running sessions; workers that exited, idle or otherwise, are forgotten,
+    /// also when another process has since taken the id
+    pub fn list(&self) -> ElevateResult<Vec<SessionRecord>> {
+        let mut store = read_store(&self.store_path)?;
+        let before = store.sessions.len();
+        store.sessions.retain(|session| process_instance_is_running(session.pid, session.created));
+        if store.sessions.len() != before {
+            write_store(&self.store_path, &store)?;
+        }
+        Ok(store.sessions)
+    }
+
+    pub fn find(&self, id: &str) -> ElevateResult<SessionRecord> {
+        self.list()?
+            .into_iter()
+            .find(|session| session.id.eq_ignore_ascii_case(id))
+            .ok_or_else(|| ElevateError::ValidationError(format!("No running session {}", id)))
+    }
+
+    pub fn add(&self, session: SessionRecord) -> ElevateResult<()> {
+        let mut store = read_store(&self.store_path)?;
+        store.sessions.push(session);
+        write_store(&self.store_path, &store)
+    }
+
+    pub fn remove(&self, id: &str) -> ElevateResult<()> {
+        let mut store = read_store(&self.store_path)?;
+        store.sessions.retain(|session| !session.id.eq_ignore_ascii_case(id));
+        write_store(&self.store_path, &store)
+    }
+}
+
+fn read_store(path: &Path) -> ElevateResult<SessionStore> {
+    if !path.exists() {
+        return Ok(SessionStore::default());
+    }
+
+    let contents = fs::read_to_string(path).map_err(|e| file_error(e, "Failed to read sessions"))?;
+    serde_json::from_str(&contents).map_err(|e| ElevateError::ConfigError(format!("Failed to parse sessions: {}", e)))
+}
+
+fn write_store(path: &Path, store: &SessionStore) -> ElevateResult<()> {
+    let contents = serde_json::to_string_pretty(store)
+        .map_err(|e| ElevateError::ConfigError(format!("Failed to serialize sessions: {}", e)))?;
+    fs::write(path, contents).map_err(|e| file_error(e, "Failed to write sessions"))
+}
@@ -0,0 +1,218 @@
+//! A long-lived process running as TrustedInstaller that runs commands sent
+//! over its pipe, so repeated commands skip starting the service, spoofing the
+//! parent and setting privileges. Only the user who started it may connect,
+//! and only from an elevated process.
+
+use std::fs::File;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::core::broker::identity::{current_user, pipe_client_token};
+use crate::core::broker::protocol::call;
+use crate::core::elevation::trusted_installer::TrustedInstallerElevation;
+use crate::core::pipe::{
+    connect_pipe, create_server_pipe, pipe_server_pid, read_request_within, wait_for_client, CLIENT_ACCESS,
+};
+use crate::core::process::process_instance_is_running;
+use crate::core::service::machine::WaitOptions;
+use crate::core::service::relay::{print_output, run_captured, write_message, RelayMessage};
+use crate::core::session::idle::IdleTracker;
+use crate::core::session::registry::{SessionRecord, SessionRegistry};
+use crate::core::token::{token_elevated, token_integrity, token_user};
+use crate::utils::cancel::CancellationToken;
+use crate::utils::config::IntegrityLevel;
+use crate::utils::error::{ElevateError, ElevateResult};
+use crate::utils::security::SecurityContext;
+use crate::utils::time::{short_id, unix_timestamp};
+
+const PIPE_PREFIX: &str = "ElevateKitSession-";
+const IDLE_POLL: Duration = Duration::from_secs(1);
+const START_POLL: Duration = Duration::from_millis(100);
+// a client that connects but never asks would keep the worker from idling out
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SessionRequest {
+    /// answered with exit code 0 once the worker listens
+    Ping,
+    Run { command: String, args: Vec<String> },
+    /// ends the worker and the commands it is running
+    Stop,
+}
+
+/// Starts a worker as TrustedInstaller running `worker_args` of this binary,
+/// followed by its id, owner and timeout, and waits until it answers
+pub fn start_session(
+    context: &SecurityContext,
+    worker_args: &[&str],
+    idle_timeout: u64,
+    wait: &WaitOptions,
+) -> ElevateResult<SessionRecord> {
+    // the worker refuses a pipe name that is taken, so a clash cannot go unnoticed
+    let id = short_id();
+    let owner = current_user()?;
+    let exe = std::env::current_exe()
+        .map_err(|e| ElevateError::ProcessError(format!("Failed to locate ElevateKit: {}", e)))?;
+
+    let mut args: Vec<String> = worker_args.iter().map(|arg| arg.to_string()).collect();
+    args.extend([
+        "--id".to_string(),
+        id.clone(),
+        "--owner".to_string(),
+        owner.sid,
+        "--idle-timeout".to_string(),
+        idle_timeout.to_string(),
+    ]);
+    let (pid, created) = TrustedInstallerElevation::new(context)
+        .wait_options(wait.clone())
+        .spawn_hidden(&exe.to_string_lossy(), &args)?;
+
+    let session = SessionRecord {
+        id: id.clone(),
+        pid,
+        created,
+        pipe: pipe_name(&id),
+        started_at: unix_timestamp(),
+        idle_timeout,
+    };
+    let deadline = Instant::now() + wait.timeout;
+    while let Err(e) = send_request(&session, &SessionRequest::Ping) {
+        if !process_instance_is_running(pid, created) || Instant::now() >= deadline || wait.cancel.is_cancelled() {
+            return Err(ElevateError::ProcessError(format!("Session worker {} did not start: {}", id, e)));
+        }
+        thread::sleep(START_POLL);
+    }
+
+    SessionRegistry::new()?.add(session.clone())?;
+    info!("Session {} started, worker pid {}", id, pid);
+    Ok(session)
+}
+
+/// Runs a command in the session, printing its output, and returns its exit code
+pub fn run_in_session(session: &SessionRecord, command: &str, args: &[String]) -> ElevateResult<i32> {
+    let mut pipe = connect_session(session)?;
+    let request = SessionRequest::Run { command: command.to_string(), args: args.to_vec() };
+    call(&mut pipe, &request, &mut print_output)
+}
+
+pub fn stop_session(session: &SessionRecord) -> ElevateResult<()> {
+    send_request(session, &SessionRequest::Stop)?;
+    SessionRegistry::new()?.remove(&session.id)
+}
+
+fn send_request(session: &SessionRecord, request: &SessionRequest) -> ElevateResult<i32> {
+    let mut pipe = connect_session(session)?;
+    call(&mut pipe, request, &mut |_| {})
+}
+
+// anyone may create a pipe of the name once the worker is gone, so it has to
+// be served by the worker we started before a command is sent
+fn connect_session(session: &SessionRecord) -> ElevateResult<File> {
+    let pipe = connect_pipe(&session.pipe)?;
+    let server = pipe_server_pid(&pipe)?;
+    if server != session.pid {
+        return Err(ElevateError::ProcessError(format!(
+            "Session {} pipe is served by process {}, not its worker {}",
+            session.id, server, session.pid
+        )));
+    }
+    Ok(pipe)
+}
+
+/// The worker itself. Serves requests from `owner` (a SID) until stopped or
+/// idle for `idle_timeout` seconds.
+pub fn run_worker(id: &str, owner: &str, idle_timeout: u64) -> ElevateResult<()> {
+    let pipe = pipe_name(id);
+    // the label keeps processes below high integrity from writing requests
+    let sddl = format!("D:P(A;;GA;;;SY)(A;;{};;;{})S:(ML;;NW;;;HI)", CLIENT_ACCESS, owner);
+    let stop = CancellationToken::new();
+    let idle = Arc::new(IdleTracker::new(Duration::from_secs(idle_timeout), Instant::now()));
+
+    // connecting ourselves releases the wait for the next client once it is time to go
+    {
+        let (stop, idle, pipe) = (stop.clone(), idle.clone(), pipe.clone());
+        thread::spawn(move || {
+            while !stop.is_cancelled() {
+                if idle.is_expired(Instant::now()) {
+                    info!("Session idle for {} seconds, exiting", idle_timeout);
+                    stop.cancel();
+                }
+                thread::sleep(IDLE_POLL);
+            }
+            let _ = connect_pipe(&pipe);
+        });
+    }
+
+    let mut first = true;
+    loop {
+        let client = create_server_pipe(&pipe, &sddl, first)?;
+        first = false;
+        wait_for_client(&client)?;
+        if stop.is_cancelled() {
+            return Ok(());
+        }
+
+        let (owner, idle, stop) = (owner.to_string(), idle.clone(), stop.clone());
+        thread::spawn(move || {
+            idle.start(Instant::now());
+            if let Err(e) = serve_client(client, &owner, &stop) {
+                warn!("Session client failed: {}", e);
+            }
+            idle.finish(Instant::now());
+        });
+    }
+}
+
+fn serve_client(mut pipe: File, owner: &str, stop: &CancellationToken) -> ElevateResult<()> {
+    // the pipe's DACL and label already keep others out; this also rejects SYSTEM
+    let token = pipe_client_token(&pipe)?;
+    let user = token_user(&token)?;
+    if !user.sid.eq_ignore_ascii_case(owner) {
+        warn!("Rejected session client {} ({})", user.name, user.sid);
+        return send(&mut pipe, &RelayMessage::Error("Denied: the session belongs to another user".into()));
+    }
+    // the owner's unelevated processes share the SID but must not run commands as TrustedInstaller
+    if !token_elevated(&token)? || !is_high_integrity(token_integrity(&token)?) {
+        warn!("Rejected unelevated session client {} ({})", user.name, user.sid);
+        return send(&mut pipe, &RelayMessage::Error("Denied: the session only serves elevated processes".into()));
+    }
+
+    match read_request_within(&mut pipe, REQUEST_TIMEOUT)? {
+        SessionRequest::Ping => send(&mut pipe, &RelayMessage::Exit(0)),
+        SessionRequest::Stop => {
+            stop.cancel();
+            send(&mut pipe, &RelayMessage::Exit(0))
+        }
+        SessionRequest::Run { command, args } => {
+            info!("Session running {} {:?}", command, args);
+            let mut write_error = None;
+            let result = run_captured(&command, &args, stop, &mut |message| {
+                if write_error.is_none() {
+                    write_error = send(&mut pipe, &message).err();
+                }
+            });
+            match (result, write_error) {
+                (_, Some(e)) => Err(e),
+                (Ok(code), None) => send(&mut pipe, &RelayMessage::Exit(code)),
+                (Err(e), None) => send(&mut pipe, &RelayMessage::Error(e.to_string())),
+            }
+        }
+    }
+}
+
+fn send(pipe: &mut File, message: &RelayMessage) -> ElevateResult<()> {
+    write_message(pipe, message)
+        .map_err(|e| ElevateError::ProcessError(format!("Failed to answer the session client: {}", e)))
+}
+
+fn is_high_integrity(level: IntegrityLevel) -> bool {
+    matches!(level, IntegrityLevel::High | IntegrityLevel::System)
+}
+
+fn pipe_name(id: &str) -> String {
+    format!("{}{}", PIPE_PREFIX, id)
+}
@@ -0,0 +1,141 @@
+//! Keeps an elevated child running, as `run --supervise` does for monitoring
+//! agents, restarting it as `restart::RestartPolicy` decides.
+
+use std::fmt;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::core::elevation::command::{ElevatedCommand, ElevatedProcess};
+use crate::core::restart::{describe_exit, Decision, RestartPolicy};
+use crate::core::terminate::{end, Ending};
+use crate::utils::cancel::CancellationToken;
+use crate::utils::error::ElevateResult;
+use crate::utils::time::Clock;
+
+const POLL: Duration = Duration::from_millis(200);
+
+/// What the supervisor does, for the caller to report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SupervisorEvent {
+    Started { pid: u32 },
+    /// the child exited and is started again after `delay`
+    Restarting { pid: u32, code: u32, delay: Duration, restart: u32, max_restarts: u32 },
+    Exited { pid: u32, code: u32 },
+    GaveUp { pid: u32, code: u32, restarts: u32 },
+    /// the child was stopped on a stop request
+    Stopped { pid: u32, ending: Ending },
+}
+
+impl fmt::Display for SupervisorEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SupervisorEvent::Started { pid } => write!(f, "Started pid {}", pid),
+            SupervisorEvent::Restarting { pid, code, delay, restart, max_restarts } => write!(
+                f,
+                "Pid {} {}, restart {} of {} in {}s",
+                pid,
+                describe_exit(code),
+                restart,
+                max_restarts,
+                delay.as_secs_f32()
+            ),
+            SupervisorEvent::Exited { pid, code } => write!(f, "Pid {} {}, not restarting", pid, describe_exit(code)),
+            SupervisorEvent::GaveUp { pid, code, restarts } => {
+                write!(f, "Pid {} {}, giving up after {} restarts in a row", pid, describe_exit(code), restarts)
+            }
+            SupervisorEvent::Stopped { pid, ending } => write!(f, "Stop requested, pid {} {}", pid, ending),
+        }
+    }
+}
+
+/// Runs `command` and restarts it as `policy` decides, until it exits cleanly,
+/// the policy gives up or `cancel` is cancelled, such as by Ctrl+C. The child
+/// is then asked to close and terminated after `stop_timeout`. With a job,
+/// everything it started goes with it, also before each restart. Returns the
+/// last exit code.
+pub fn supervise<C: Clock>(
+    command: &mut ElevatedCommand,
+    policy: &mut RestartPolicy,
+    clock: &C,
+    cancel: &CancellationToken,
+    stop_timeout: Duration,
+    report: &mut dyn FnMut(SupervisorEvent),
+) -> ElevateResult<u32> {
+    loop {
+        let started = clock.now();
+        let mut child = command.spawn()?;
+        let pid = child.id();
+        report(SupervisorEvent::Started { pid });
+
+        let code = loop {
+            if let Some(status) = child.try_wait()? {
+                break status.code().unwrap_or(0) as u32;
+            }
+            if cancel.is_cancelled() {
+                let (ending, code) = stop(&mut child, stop_timeout)?;
+                report(SupervisorEvent::Stopped { pid, ending });
+                return Ok(code);
+            }
+            clock.sleep(POLL);
+        };
+
+        // Ctrl+C reaches a child on our console before we see it, which is a stop, not a crash
+        if cancel.is_cancelled() {
+            child.kill()?;
+            report(SupervisorEvent::Stopped { pid, ending: Ending::Closed(code) });
+            return Ok(code);
+        }
+
+        let decision = policy.on_exit(code, started, clock.now());
+        if decision != Decision::Done {
+            // what a crashed child started would otherwise pile up with every restart
+            child.kill()?;
+        }
+        match decision {
+            Decision::Done => {
+                report(SupervisorEvent::Exited { pid, code });
+                return Ok(code);
+            }
+            Decision::GiveUp => {
+                report(SupervisorEvent::GaveUp { pid, code, restarts: policy.restarts() });
+                return Ok(code);
+            }
+            Decision::Restart(delay) => {
+                report(SupervisorEvent::Restarting {
+                    pid,
+                    code,
+                    delay,
+                    restart: policy.restarts(),
+                    max_restarts: policy.max_restarts(),
+                });
+                let until = clock.now() + delay;
+                while clock.now() < until {
+                    if cancel.is_cancelled() {
+                        report(SupervisorEvent::Stopped { pid, ending: Ending::AlreadyExited });
+                        return Ok(code);
+                    }
+                    clock.sleep(POLL.min(until - clock.now()));
+                }
+            }
+        }
+    }
+}
+
+// asks the child to close, then ends it with our own handle, which works even
+// where `end` cannot open it. Returns how it ended and its exit code.
+fn stop(child: &mut ElevatedProcess, stop_timeout: Duration) -> ElevateResult<(Ending, u32)> {
+    let pid = child.id();
+    let asked = end(pid, stop_timeout);
+    child.kill()?;
+    let code = child.wait()?.code().unwrap_or(0) as u32;
+
+    let ending = match asked {
+        Ok(Ending::Refused(_)) => Ending::Terminated(code),
+        Ok(ending) => ending,
+        Err(e) => {
+            warn!("Failed to ask pid {} to close: {}", pid, e);
+            Ending::Terminated(code)
+        }
+    };
+    Ok((ending, code))
+}
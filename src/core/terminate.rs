@@ -0,0 +1,292 @@
+//! Ends processes, asking them to close before terminating them. A process
+//! gets WM_CLOSE on its top-level windows, or Ctrl+Break when it has no windows
+//! and leads a process group on our console, and is terminated only if it is
+//! still running after the grace period. Protected processes are refused:
+//! even TrustedInstaller cannot open them for termination.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::mem::{size_of, zeroed};
+use std::ptr::null_mut;
+use std::time::Duration;
+use winapi::{
+    shared::{
+        minwindef::{BOOL, DWORD, LPARAM, TRUE},
+        ntdef::NTSTATUS,
+        windef::HWND,
+    },
+    um::{
+        memoryapi::ReadProcessMemory,
+        processthreadsapi::{GetCurrentProcessId, GetExitCodeProcess, OpenProcess, TerminateProcess},
+        wincon::{GenerateConsoleCtrlEvent, GetConsoleProcessList, CTRL_BREAK_EVENT},
+        winnt::{HANDLE, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_TERMINATE, PROCESS_VM_READ, SYNCHRONIZE},
+        winuser::{EnumWindows, GetWindowThreadProcessId, IsWindowVisible, PostMessageW, WM_CLOSE},
+    },
+};
+
+use crate::core::handle::{HandleKind, OwnedHandle};
+use crate::core::inspect::{protection, Protection};
+use crate::core::process::{creation_time, processes, ProcessEntry};
+use crate::utils::error::{ElevateError, ElevateResult, WindowsError, WindowsErrorKind};
+
+/// Access `end_opened` needs
+pub const END_ACCESS: DWORD = PROCESS_TERMINATE | SYNCHRONIZE | PROCESS_QUERY_LIMITED_INFORMATION;
+
+// how long a terminated process gets to actually go away
+const TERMINATE_WAIT: Duration = Duration::from_secs(5);
+
+// where a 64-bit process keeps its console process group: the parameters
+// pointer in its PEB, and the group id in those parameters
+const PEB_PROCESS_PARAMETERS: usize = 0x20;
+const PARAMETERS_PROCESS_GROUP_ID: usize = 0x408;
+const PROCESS_BASIC_INFORMATION_CLASS: u32 = 0;
+
+// winapi leaves out the ntdll process query
+#[link(name = "ntdll")]
+extern "system" {
+    #[link_name = "NtQueryInformationProcess"]
+    fn nt_query_information_process(
+        process: HANDLE,
+        class: u32,
+        information: *mut ProcessBasicInformation,
+        length: u32,
+        returned: *mut u32,
+    ) -> NTSTATUS;
+}
+
+#[repr(C)]
+struct ProcessBasicInformation {
+    exit_status: NTSTATUS,
+    peb_base_address: usize,
+    affinity_mask: usize,
+    base_priority: i32,
+    unique_process_id: usize,
+    inherited_from_unique_process_id: usize,
+}
+
+/// How a process ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ending {
+    /// exited on its own after being asked to close
+    Closed(u32),
+    Terminated(u32),
+    /// was gone before anything was done
+    AlreadyExited,
+    /// is a protected process and was left alone
+    Refused(Protection),
+}
+
+impl fmt::Display for Ending {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ending::Closed(code) => write!(f, "closed, exit code {}", code),
+            Ending::Terminated(code) => write!(f, "terminated, exit code {}", code),
+            Ending::AlreadyExited => f.write_str("had already exited"),
+            Ending::Refused(level) => write!(f, "refused, it is a protected process ({})", level),
+        }
+    }
+}
+
+/// Processes a target names: a process id, or every process with that image
+/// name, `.exe` optional. With `tree`, everything they started follows them,
+/// parents before their children.
+pub fn targets(target: &str, tree: bool) -> ElevateResult<Vec<ProcessEntry>> {
+    let all = processes()?;
+    let mut found: Vec<ProcessEntry> = match target.parse::<u32>() {
+        Ok(pid) => all.iter().filter(|entry| entry.pid == pid).cloned().collect(),
+        Err(_) => {
+            let with_exe = format!("{}.exe", target);
+            all.iter()
+                .filter(|entry| entry.name.eq_ignore_ascii_case(target) || entry.name.eq_ignore_ascii_case(&with_exe))
+                .cloned()
+                .collect()
+        }
+    };
+    if found.is_empty() {
+        return Err(ElevateError::ProcessError(format!("No process matches {}", target)));
+    }
+
+    let own = unsafe { GetCurrentProcessId() };
+    if found.iter().any(|entry| entry.pid == own) {
+        return Err(ElevateError::ValidationError("Refusing to end ElevateKit itself".into()));
+    }
+
+    if tree {
+        let mut seen: HashSet<u32> = found.iter().map(|entry| entry.pid).collect();
+        let mut next = 0;
+        while next < found.len() {
+            let parent = found[next].clone();
+            next += 1;
+            for child in all.iter().filter(|entry| entry.parent_pid == parent.pid && entry.pid != own) {
+                if seen.insert(child.pid) && started_after(child.pid, parent.pid) {
+                    found.push(child.clone());
+                }
+            }
+        }
+    }
+    Ok(found)
+}
+
+// parent ids are never updated, so a "child" older than its parent belongs to
+// an earlier process that had the same id
+fn started_after(child: u32, parent: u32) -> bool {
+    let created = |pid| open(pid, PROCESS_QUERY_LIMITED_INFORMATION).and_then(|process| creation_time(&process));
+    match (created(child), created(parent)) {
+        (Ok(child), Ok(parent)) => child >= parent,
+        // an exited parent cannot be compared, and the child is still its
+        _ => true,
+    }
+}
+
+/// Ends one process, giving it `grace` to close after being asked
+pub fn end(pid: u32, grace: Duration) -> ElevateResult<Ending> {
+    let process = match open(pid, END_ACCESS) {
+        Ok(process) => process,
+        Err(error) => {
+            // what refuses termination may still allow a limited query
+            if let Ok(Some(level)) = open(pid, PROCESS_QUERY_LIMITED_INFORMATION).and_then(|process| protection(&process)) {
+                if level.is_protected() {
+                    return Ok(Ending::Refused(level));
+                }
+            }
+            return Err(error);
+        }
+    };
+    end_opened(&process, pid, grace)
+}
+
+/// Ends a process opened with `END_ACCESS`, such as one already checked to be
+/// the process it should be
+pub fn end_opened(process: &OwnedHandle, pid: u32, grace: Duration) -> ElevateResult<Ending> {
+    if let Some(level) = protection(process)?.filter(|level| level.is_protected()) {
+        return Ok(Ending::Refused(level));
+    }
+    if process.wait(Some(Duration::ZERO))? {
+        return Ok(Ending::AlreadyExited);
+    }
+
+    let asked = close_windows(pid) > 0 || send_ctrl_break(pid);
+    if asked && process.wait(Some(grace))? {
+        return Ok(Ending::Closed(exit_code(process)?));
+    }
+
+    if unsafe { TerminateProcess(process.as_raw(), 1) } == 0 && !process.wait(Some(Duration::ZERO))? {
+        return Err(process_error(&format!("Failed to terminate process {}", pid)));
+    }
+    if !process.wait(Some(TERMINATE_WAIT))? {
+        return Err(ElevateError::ProcessError(format!("Process {} did not exit after being terminated", pid)));
+    }
+    Ok(Ending::Terminated(exit_code(process)?))
+}
+
+/// Opens a process by id with the given access
+pub fn open(pid: u32, access: DWORD) -> ElevateResult<OwnedHandle> {
+    unsafe { OwnedHandle::try_from_raw(OpenProcess(access, 0, pid), HandleKind::Process) }
+        .ok_or_else(|| process_error(&format!("Failed to open process {}", pid)))
+}
+
+/// The exit code of a process, `STILL_ACTIVE` while it runs
+pub fn exit_code(process: &OwnedHandle) -> ElevateResult<u32> {
+    let mut code = 0;
+    if unsafe { GetExitCodeProcess(process.as_raw(), &mut code) } == 0 {
+        return Err(process_error("Failed to get process exit code"));
+    }
+    Ok(code)
+}
+
+struct WindowSearch {
+    pid: DWORD,
+    posted: usize,
+}
+
+unsafe extern "system" fn close_window(window: HWND, search: LPARAM) -> BOOL {
+    let search = &mut *(search as *mut WindowSearch);
+    let mut pid = 0;
+    GetWindowThreadProcessId(window, &mut pid);
+    if pid == search.pid && IsWindowVisible(window) != 0 && PostMessageW(window, WM_CLOSE, 0, 0) != 0 {
+        search.posted += 1;
+    }
+    TRUE
+}
+
+// how many windows were asked to close; windows of higher integrity or in
+// another session cannot be reached and count as none
+fn close_windows(pid: u32) -> usize {
+    let mut search = WindowSearch { pid, posted: 0 };
+    unsafe { EnumWindows(Some(close_window), &mut search as *mut WindowSearch as LPARAM) };
+    search.posted
+}
+
+// Ctrl+Break only reaches processes on our console, and its group id has to
+// be one a process leads, or the break goes to every process on the console.
+// A process that shares our group is not sent it either, since the break
+// would end us too. Whether the break was sent.
+fn send_ctrl_break(pid: u32) -> bool {
+    let mut shared = [0u32; 64];
+    let count = unsafe { GetConsoleProcessList(shared.as_mut_ptr(), shared.len() as DWORD) } as usize;
+    if !shared[..count.min(shared.len())].contains(&pid) {
+        return false;
+    }
+    if process_group(pid) != Some(pid) || process_group(unsafe { GetCurrentProcessId() }) == Some(pid) {
+        return false;
+    }
+    unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pid) != 0 }
+}
+
+// the console process group a process belongs to, as its parameters record
+// it; None where they cannot be read or predate the field
+fn process_group(pid: u32) -> Option<u32> {
+    if cfg!(not(target_pointer_width = "64")) {
+        return None;
+    }
+    let process = open(pid, PROCESS_QUERY_LIMITED_INFORMATION | PROCESS_VM_READ).ok()?;
+    let mut info: ProcessBasicInformation = unsafe { zeroed() };
+    let status = unsafe {
+        nt_query_information_process(
+            process.as_raw(),
+            PROCESS_BASIC_INFORMATION_CLASS,
+            &mut info,
+            size_of::<ProcessBasicInformation>() as u32,
+            null_mut(),
+        )
+    };
+    if status < 0 {
+        return None;
+    }
+
+    let parameters: usize = read_remote(&process, info.peb_base_address + PEB_PROCESS_PARAMETERS)?;
+    // the parameters start with their maximum length and length
+    let length: u32 = read_remote(&process, parameters + 4)?;
+    if (length as usize) < PARAMETERS_PROCESS_GROUP_ID + size_of::<u32>() {
+        return None;
+    }
+    read_remote(&process, parameters + PARAMETERS_PROCESS_GROUP_ID)
+}
+
+fn read_remote<T: Copy + Default>(process: &OwnedHandle, address: usize) -> Option<T> {
+    let mut value = T::default();
+    let mut read = 0;
+    let ok = unsafe {
+        ReadProcessMemory(process.as_raw(), address as _, &mut value as *mut T as _, size_of::<T>(), &mut read)
+    };
+    (ok != 0 && read == size_of::<T>()).then_some(value)
+}
+
+fn process_error(context: &str) -> ElevateError {
+    WindowsError::last_error().to_elevate_error(WindowsErrorKind::ProcessOperation, context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endings_read_as_a_report() {
+        assert_eq!(Ending::Terminated(1).to_string(), "terminated, exit code 1");
+        assert_eq!(Ending::Closed(0).to_string(), "closed, exit code 0");
+        assert_eq!(
+            Ending::Refused(Protection::AntimalwareLight).to_string(),
+            "refused, it is a protected process (antimalware-light)"
+        );
+    }
+}
@@ -1,41 +1,211 @@
 use std::ptr;
+use winapi::ctypes::c_void;
+use winapi::shared::minwindef::DWORD;
+use winapi::shared::sddl::{ConvertSidToStringSidW, ConvertStringSidToSidW};
 use winapi::um::winnt::{
-    TOKEN_ADJUST_PRIVILEGES, TOKEN_QUERY, HANDLE
+    HANDLE, PSID, SE_GROUP_ENABLED, SE_GROUP_INTEGRITY,
+    SE_GROUP_USE_FOR_DENY_ONLY, SID_AND_ATTRIBUTES, TOKEN_ELEVATION, TOKEN_GROUPS, TOKEN_INFORMATION_CLASS,
+    TOKEN_MANDATORY_LABEL, TOKEN_OWNER, TOKEN_USER, TokenElevation, TokenGroups, TokenIntegrityLevel, TokenOwner,
+    TokenUser,
 };
-use winapi::um::processthreadsapi::{OpenProcessToken, GetCurrentProcess};
-use crate::utils::error::{ElevateError, ElevateResult, TokenErrorKind};
+use winapi::um::processthreadsapi::OpenProcessToken;
+use winapi::um::securitybaseapi::{
+    GetLengthSid, GetSidSubAuthority, GetSidSubAuthorityCount, GetTokenInformation, SetTokenInformation,
+};
+use winapi::um::winbase::{LocalFree, LookupAccountSidW};
+use crate::core::handle::{HandleKind, OwnedHandle};
+pub use crate::core::security::sddl::Principal;
+use crate::core::security::descriptor::LocalGuard;
+use crate::utils::config::IntegrityLevel;
+use crate::utils::error::{ElevateResult, WindowsError, WindowsErrorKind};
+
+/// Mandatory label SID of an integrity level
+pub fn integrity_sid(level: IntegrityLevel) -> &'static str {
+    match level {
+        IntegrityLevel::Untrusted => "S-1-16-0",
+        IntegrityLevel::Low => "S-1-16-4096",
+        IntegrityLevel::Medium => "S-1-16-8192",
+        IntegrityLevel::High => "S-1-16-12288",
+        IntegrityLevel::System => "S-1-16-16384",
+    }
+}
+
+/// Opens the primary token of a process, such as a child that is still suspended
+pub(crate) fn open_process_token(process: HANDLE, access: DWORD) -> ElevateResult<OwnedHandle> {
+    let mut token = ptr::null_mut();
+    if unsafe { OpenProcessToken(process, access, &mut token) } == 0 {
+        return Err(WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::TokenOperation,
+            "Failed to open process token",
+        ));
+    }
+    Ok(unsafe { OwnedHandle::from_raw(token, HandleKind::Token) })
+}
 
-pub struct TokenManager {
-    handle: HANDLE,
+/// The account a token runs as
+pub fn token_user(token: &OwnedHandle) -> ElevateResult<Principal> {
+    let user = token_information(token.as_raw(), TokenUser)?;
+    principal(unsafe { &*(user.as_ptr() as *const TOKEN_USER) }.User.Sid)
 }
 
-impl TokenManager {
-    pub fn new() -> ElevateResult<Self> {
-        let mut token = ptr::null_mut();
-        
-        let success = unsafe {
-            OpenProcessToken(
-                GetCurrentProcess(),
-                TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY,
-                &mut token,
-            )
-        };
+/// The owner given to objects a token creates, such as a service SID for services
+pub fn token_owner(token: &OwnedHandle) -> ElevateResult<Principal> {
+    let owner = token_information(token.as_raw(), TokenOwner)?;
+    principal(unsafe { &*(owner.as_ptr() as *const TOKEN_OWNER) }.Owner)
+}
+
+/// The enabled groups of a token
+pub fn token_groups(token: &OwnedHandle) -> ElevateResult<Vec<Principal>> {
+    enabled_groups(&token_information(token.as_raw(), TokenGroups)?)
+}
+
+/// The integrity level of a token. Levels in between, such as medium plus,
+/// count as the one below them.
+pub fn token_integrity(token: &OwnedHandle) -> ElevateResult<IntegrityLevel> {
+    let label = token_information(token.as_raw(), TokenIntegrityLevel)?;
+    let sid = unsafe { &*(label.as_ptr() as *const TOKEN_MANDATORY_LABEL) }.Label.Sid;
+    let rid = unsafe { *GetSidSubAuthority(sid, (*GetSidSubAuthorityCount(sid) - 1) as DWORD) };
+    Ok(integrity_of_rid(rid))
+}
+
+/// Whether a token is elevated, as an administrator's after consent
+pub fn token_elevated(token: &OwnedHandle) -> ElevateResult<bool> {
+    let elevation = token_information(token.as_raw(), TokenElevation)?;
+    Ok(unsafe { &*(elevation.as_ptr() as *const TOKEN_ELEVATION) }.TokenIsElevated != 0)
+}
+
+fn integrity_of_rid(rid: DWORD) -> IntegrityLevel {
+    match rid {
+        0..=0x0fff => IntegrityLevel::Untrusted,
+        0x1000..=0x1fff => IntegrityLevel::Low,
+        0x2000..=0x2fff => IntegrityLevel::Medium,
+        0x3000..=0x3fff => IntegrityLevel::High,
+        _ => IntegrityLevel::System,
+    }
+}
+
+/// The enabled groups of a `TokenGroups` buffer; deny-only groups grant nothing
+pub(crate) fn enabled_groups(buffer: &[u64]) -> ElevateResult<Vec<Principal>> {
+    let groups = unsafe { &*(buffer.as_ptr() as *const TOKEN_GROUPS) };
+    let groups = unsafe { std::slice::from_raw_parts(groups.Groups.as_ptr(), groups.GroupCount as usize) };
+    groups
+        .iter()
+        .filter(|g| g.Attributes & SE_GROUP_ENABLED != 0 && g.Attributes & SE_GROUP_USE_FOR_DENY_ONLY == 0)
+        .map(|g| principal(g.Sid))
+        .collect()
+}
 
-        if success == 0 {
-            return Err(ElevateError::TokenError {
-                kind: TokenErrorKind::OpenProcessToken,
-                source: None,
-            });
-        }
+/// Reads one class of token information. The buffer is of u64 so it is
+/// aligned for the token structures.
+pub(crate) fn token_information(token: HANDLE, class: TOKEN_INFORMATION_CLASS) -> ElevateResult<Vec<u64>> {
+    let mut needed = 0;
+    unsafe { GetTokenInformation(token, class, ptr::null_mut(), 0, &mut needed) };
 
-        Ok(Self { handle: token })
+    let mut buffer = vec![0u64; (needed as usize).div_ceil(8)];
+    let ok = unsafe {
+        GetTokenInformation(token, class, buffer.as_mut_ptr() as *mut _, (buffer.len() * 8) as u32, &mut needed)
+    };
+    if ok == 0 {
+        return Err(WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::TokenOperation,
+            "Failed to read token information",
+        ));
     }
+    Ok(buffer)
 }
 
-impl Drop for TokenManager {
-    fn drop(&mut self) {
-        unsafe {
-            winapi::um::handleapi::CloseHandle(self.handle);
-        }
+/// The SID and account name of a SID in a token
+pub(crate) fn principal(sid: PSID) -> ElevateResult<Principal> {
+    let mut text = ptr::null_mut();
+    if unsafe { ConvertSidToStringSidW(sid, &mut text) } == 0 {
+        return Err(WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::TokenOperation,
+            "Failed to convert SID to string",
+        ));
+    }
+    let sid_string = unsafe { from_wide_ptr(text) };
+    unsafe { LocalFree(text as *mut c_void) };
+
+    Ok(Principal { name: account_name(sid).unwrap_or_default(), sid: sid_string })
+}
+
+// `DOMAIN\name`, or `None` for a SID without an account such as a logon session
+fn account_name(sid: PSID) -> Option<String> {
+    let mut name = vec![0u16; 256];
+    let mut domain = vec![0u16; 256];
+    let (mut name_len, mut domain_len, mut sid_use) = (name.len() as u32, domain.len() as u32, 0);
+    let found = unsafe {
+        LookupAccountSidW(
+            ptr::null_mut(),
+            sid,
+            name.as_mut_ptr(),
+            &mut name_len,
+            domain.as_mut_ptr(),
+            &mut domain_len,
+            &mut sid_use,
+        )
+    };
+    if found == 0 {
+        return None;
+    }
+
+    let name = String::from_utf16_lossy(&name[..name_len as usize]);
+    let domain = String::from_utf16_lossy(&domain[..domain_len as usize]);
+    Some(if domain.is_empty() { name } else { format!(r"{}\{}", domain, name) })
+}
+
+unsafe fn from_wide_ptr(ptr: *const u16) -> String {
+    let len = (0..).take_while(|&i| *ptr.add(i) != 0).count();
+    String::from_utf16_lossy(std::slice::from_raw_parts(ptr, len))
+}
+
+/// Sets the integrity level of a token opened with `TOKEN_ADJUST_DEFAULT`.
+/// Lowering it is always allowed, raising it needs SeRelabelPrivilege.
+pub(crate) fn set_integrity_level(token: HANDLE, level: IntegrityLevel) -> ElevateResult<()> {
+    let text: Vec<u16> = integrity_sid(level).encode_utf16().chain(Some(0)).collect();
+    let mut sid = ptr::null_mut();
+    if unsafe { ConvertStringSidToSidW(text.as_ptr(), &mut sid) } == 0 {
+        return Err(WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::IntegrityOperation,
+            "Failed to build integrity level SID",
+        ));
+    }
+    let sid = LocalGuard(sid);
+
+    let mut label = TOKEN_MANDATORY_LABEL {
+        Label: SID_AND_ATTRIBUTES { Sid: sid.0, Attributes: SE_GROUP_INTEGRITY },
+    };
+    let size = std::mem::size_of::<TOKEN_MANDATORY_LABEL>() + unsafe { GetLengthSid(sid.0) } as usize;
+    let ok = unsafe {
+        SetTokenInformation(token, TokenIntegrityLevel, &mut label as *mut _ as *mut c_void, size as DWORD)
+    };
+    if ok == 0 {
+        return Err(WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::IntegrityOperation,
+            &format!("Failed to set integrity level {:?}", level),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integrity_sids_follow_the_mandatory_label_rids() {
+        assert_eq!(integrity_sid(IntegrityLevel::Untrusted), "S-1-16-0");
+        assert_eq!(integrity_sid(IntegrityLevel::Medium), "S-1-16-8192");
+        assert_eq!(integrity_sid(IntegrityLevel::System), "S-1-16-16384");
+    }
+
+    #[test]
+    fn test_integrity_between_levels_rounds_down() {
+        assert_eq!(integrity_of_rid(0x2000), IntegrityLevel::Medium);
+        // medium plus, as UIAccess processes get
+        assert_eq!(integrity_of_rid(0x2100), IntegrityLevel::Medium);
+        assert_eq!(integrity_of_rid(0x0fff), IntegrityLevel::Untrusted);
+        // protected processes are above system
+        assert_eq!(integrity_of_rid(0x5000), IntegrityLevel::System);
     }
 }
@@ -1,3 +1,4 @@
 pub mod core;
 pub mod utils;
+#[cfg(windows)]
 pub mod domain;
@@ -2,49 +2,62 @@
 //@Description: Windows TrustedInstaller elevation toolkit
 
 
+#[cfg(windows)]
 use clap::Parser;
+#[cfg(windows)]
 use tracing::info;
 
+// everything but the pure parsers needs Windows; elsewhere only the library
+// builds, so its tests run on any host
+#[cfg(windows)]
+mod commands;
+#[cfg(windows)]
 mod core;
+#[cfg(windows)]
 mod domain;
+#[cfg(windows)]
 mod utils;
 
-use core::elevation::trusted_installer::TrustedInstallerElevation;
-use utils::{config::Config, security::SecurityContext};
+#[cfg(windows)]
+use commands::{run::RunArgs, Command};
+#[cfg(windows)]
+use utils::config::Config;
 
+#[cfg(windows)]
 #[derive(Parser)]
 #[clap(
     name = "ElevateKit",
     about = "Windows TrustedInstaller elevation toolkit",
-    version
+    version,
+    args_conflicts_with_subcommands = true
 )]
 struct Cli {
-    /// command and arguments to execute (defaults to "powershell.exe" if not provided)
-    #[clap(trailing_var_arg = true)]
-    args: Vec<String>,
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(flatten)]
+    run: RunArgs,
 }
 
+#[cfg(windows)]
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("Starting ElevateKit");
 
     let cli = Cli::parse();
-    let _config = Config::load()?;
-    let security_context = SecurityContext::new()?;
-
-    //  default to powershell.exe if no command is provided
-    let (command, args) = if cli.args.is_empty() {
-        ("powershell.exe".to_string(), Vec::new())
-    } else {
-        (cli.args[0].clone(), cli.args[1..].to_vec())
-    };
-
-    info!("Running command: {} {:?}", command, args);
+    let config = Config::load()?;
 
-    // execute with TrustedInstaller privileges
-    let ti = TrustedInstallerElevation::new(&security_context);
-    ti.execute(&command, &args)?;
+    let result = match cli.command {
+        Some(command) => command.run(&config),
+        None => commands::run::run(cli.run, &config),
+    };
+    // only debug builds count handles
+    core::handle::report_leaks();
+    result
+}
 
-    info!("Operation completed successfully");
-    Ok(())
-}
\ No newline at end of file
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("ElevateKit only runs on Windows");
+    std::process::exit(1);
+}
@@ -1,7 +1,10 @@
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
+use std::str::FromStr;
 use std::path::PathBuf;
 use crate::utils::error::{ElevateError, ElevateResult};
-use serde::{Serialize, Deserialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -20,7 +23,7 @@ pub enum IsolationLevel {
     High,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum IntegrityLevel {
     Untrusted,
@@ -35,6 +38,12 @@ pub struct Config {
     pub logging: LoggingConfig,
     pub security: SecurityConfig,
     pub process: ProcessConfig,
+    #[serde(default)]
+    pub services: ServicesConfig,
+    #[serde(default)]
+    pub broker: BrokerConfig,
+    #[serde(default)]
+    pub sessions: SessionsConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -54,8 +63,240 @@ pub struct SecurityConfig {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ProcessConfig {
     pub default_shell: String,
-    pub creation_flags: u32,
+    /// launch flags for every TrustedInstaller child, by name
+    #[serde(default, deserialize_with = "launch_flags", serialize_with = "launch_flag_names")]
+    pub creation_flags: Vec<LaunchFlag>,
     pub timeout: Option<u64>,
+    #[serde(default)]
+    pub job: JobConfig,
+    /// named hardening presets for `run --hardening`, added to or replacing the built-in ones
+    #[serde(default)]
+    pub hardening_presets: BTreeMap<String, HardeningConfig>,
+    /// restart policy for `run --supervise`
+    #[serde(default)]
+    pub supervise: SupervisorConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SupervisorConfig {
+    /// seconds before the first restart, doubled for each one after it
+    pub initial_delay: u64,
+    /// seconds the delay between restarts grows to at most
+    pub max_delay: u64,
+    /// restarts in a row before giving up
+    pub max_restarts: u32,
+    /// seconds a child has to run for its restarts to count from zero again
+    pub cooldown: u64,
+    /// seconds the child gets to close on Ctrl+C or a service stop before it is terminated
+    pub stop_timeout: u64,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self { initial_delay: 1, max_delay: 60, max_restarts: 5, cooldown: 300, stop_timeout: 10 }
+    }
+}
+
+/// A named launch option, as written in `creation_flags`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LaunchFlag {
+    IdlePriority,
+    BelowNormalPriority,
+    NormalPriority,
+    AboveNormalPriority,
+    HighPriority,
+    RealtimePriority,
+    Hidden,
+    Minimized,
+    Maximized,
+    NoWindow,
+    Detached,
+    NewConsole,
+    BreakawayFromJob,
+}
+
+impl LaunchFlag {
+    pub const ALL: [LaunchFlag; 13] = [
+        LaunchFlag::IdlePriority,
+        LaunchFlag::BelowNormalPriority,
+        LaunchFlag::NormalPriority,
+        LaunchFlag::AboveNormalPriority,
+        LaunchFlag::HighPriority,
+        LaunchFlag::RealtimePriority,
+        LaunchFlag::Hidden,
+        LaunchFlag::Minimized,
+        LaunchFlag::Maximized,
+        LaunchFlag::NoWindow,
+        LaunchFlag::Detached,
+        LaunchFlag::NewConsole,
+        LaunchFlag::BreakawayFromJob,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LaunchFlag::IdlePriority => "idle_priority",
+            LaunchFlag::BelowNormalPriority => "below_normal_priority",
+            LaunchFlag::NormalPriority => "normal_priority",
+            LaunchFlag::AboveNormalPriority => "above_normal_priority",
+            LaunchFlag::HighPriority => "high_priority",
+            LaunchFlag::RealtimePriority => "realtime_priority",
+            LaunchFlag::Hidden => "hidden",
+            LaunchFlag::Minimized => "minimized",
+            LaunchFlag::Maximized => "maximized",
+            LaunchFlag::NoWindow => "no_window",
+            LaunchFlag::Detached => "detached",
+            LaunchFlag::NewConsole => "new_console",
+            LaunchFlag::BreakawayFromJob => "breakaway_from_job",
+        }
+    }
+}
+
+impl fmt::Display for LaunchFlag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for LaunchFlag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LaunchFlag::ALL.into_iter().find(|flag| flag.name() == s).ok_or_else(|| {
+            let names: Vec<_> = LaunchFlag::ALL.iter().map(|flag| flag.name()).collect();
+            format!("unknown launch flag \"{}\", expected one of: {}", s, names.join(", "))
+        })
+    }
+}
+
+// names, or the raw number older configs had, which only ever worked as 0
+fn launch_flags<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<LaunchFlag>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Names(Vec<String>),
+        Bits(u32),
+    }
+
+    match Raw::deserialize(deserializer)? {
+        Raw::Names(names) => names.iter().map(|name| name.parse().map_err(serde::de::Error::custom)).collect(),
+        Raw::Bits(0) => Ok(Vec::new()),
+        Raw::Bits(bits) => Err(serde::de::Error::custom(format!(
+            "creation_flags {:#x} is a number; write flags by name, such as [\"no_window\", \"below_normal_priority\"]",
+            bits
+        ))),
+    }
+}
+
+fn launch_flag_names<S: Serializer>(flags: &[LaunchFlag], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(flags.iter().map(|flag| flag.name()))
+}
+
+/// Job object limits for TrustedInstaller children; all off by default
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JobConfig {
+    /// kill the child and everything it started once ElevateKit exits, even if it is killed
+    pub kill_on_close: bool,
+    /// megabytes of committed memory for all processes in the job together
+    pub memory_limit: Option<u64>,
+    /// hard cap on CPU use in percent of all processors, 1 to 100
+    pub cpu_rate: Option<u32>,
+    pub max_processes: Option<u32>,
+    /// deny clipboard, desktop, display and system setting changes, and logging off
+    pub restrict_ui: bool,
+}
+
+/// Exploit mitigations and child process policy for TrustedInstaller children; all off by default
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HardeningConfig {
+    /// only load DLLs signed by Microsoft
+    pub microsoft_signed_only: bool,
+    /// refuse to load images from network shares
+    pub no_remote_images: bool,
+    /// the child may not create processes of its own
+    pub no_child_processes: bool,
+    /// using an invalid handle ends the child
+    pub strict_handle_checks: bool,
+    /// control flow guard, for images built with it
+    pub control_flow_guard: bool,
+    /// arbitrary code guard; breaks JIT compilers such as .NET and PowerShell
+    pub no_dynamic_code: bool,
+}
+
+/// When a restored TrustedInstaller service is put back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RestoreAfter {
+    /// once the elevated process exits
+    Exit,
+    /// as soon as the elevated process is started
+    Detach,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServicesConfig {
+    /// seconds to wait for services such as TrustedInstaller to change state
+    pub timeout: u64,
+    /// stop and re-disable TrustedInstaller if it was before a run
+    pub restore_trusted_installer: bool,
+    pub restore_after: RestoreAfter,
+}
+
+impl Default for ServicesConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 30,
+            restore_trusted_installer: false,
+            restore_after: RestoreAfter::Exit,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionsConfig {
+    /// seconds without requests before a session worker exits, zero for never
+    pub idle_timeout: u64,
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        Self { idle_timeout: 600 }
+    }
+}
+
+/// Commands the broker service runs as TrustedInstaller for callers who are not admins
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BrokerConfig {
+    /// name under `\\.\pipe\`
+    pub pipe: String,
+    /// JSON lines, defaults to `broker-audit.log` in the state directory
+    pub audit_log: Option<PathBuf>,
+    pub rules: Vec<BrokerRule>,
+}
+
+impl Default for BrokerConfig {
+    fn default() -> Self {
+        Self { pipe: "ElevateKitBroker".to_string(), audit_log: None, rules: Vec::new() }
+    }
+}
+
+/// Lets the listed users and members of the listed groups run one command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BrokerRule {
+    /// account names such as `CONTOSO\alice`, or SIDs
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// full path of the program, as callers name it
+    pub command: String,
+    /// one pattern per argument, `*` for any value; absent allows any arguments
+    pub args: Option<Vec<String>>,
 }
 
 impl Config {
@@ -74,7 +315,18 @@ impl Config {
             })
     }
 
-    fn get_config_path() -> ElevateResult<PathBuf> {
+    /// Directory for backups and other state that must outlive a single run
+    pub fn state_dir() -> ElevateResult<PathBuf> {
+        let dir = dirs::data_local_dir()
+            .ok_or_else(|| ElevateError::ConfigError("Failed to locate local app data directory".into()))?
+            .join("ElevateKit");
+
+        fs::create_dir_all(&dir)
+            .map_err(|e| ElevateError::ConfigError(format!("Failed to create state directory: {}", e)))?;
+        Ok(dir)
+    }
+
+    pub fn get_config_path() -> ElevateResult<PathBuf> {
         let exe_path = std::env::current_exe()
             .map_err(|e| ElevateError::ConfigError(format!("Failed to get executable path: {}", e)))?;
         
@@ -102,9 +354,15 @@ impl Default for Config {
             },
             process: ProcessConfig {
                 default_shell: "powershell.exe".to_string(),
-                creation_flags: 0,
+                creation_flags: Vec::new(),
                 timeout: Some(30),
+                job: JobConfig::default(),
+                hardening_presets: BTreeMap::new(),
+                supervise: SupervisorConfig::default(),
             },
+            services: ServicesConfig::default(),
+            broker: BrokerConfig::default(),
+            sessions: SessionsConfig::default(),
         }
     }
 }
\ No newline at end of file
@@ -76,6 +76,7 @@ pub struct WindowsError {
 }
 
 impl WindowsError {
+    #[cfg(windows)]
     pub fn last_error() -> Self {
         use winapi::um::errhandlingapi::GetLastError;
         Self {
@@ -83,6 +84,15 @@ impl WindowsError {
         }
     }
 
+    pub fn code(&self) -> u32 {
+        self.code as u32
+    }
+
+    /// wraps an error code returned directly, as the security APIs do
+    pub fn from_code(code: u32) -> Self {
+        Self { code: code as i32 }
+    }
+
     pub fn to_elevate_error(&self, kind: WindowsErrorKind, context: &str) -> ElevateError {
         // Implement error conversion logic here
         // For now, just create a basic error wrapper
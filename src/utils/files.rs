@@ -0,0 +1,48 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::utils::error::{ElevateError, ElevateResult, WindowsError, WindowsErrorKind};
+
+/// The path followed by all of its descendants, parents before children.
+/// Symlinks and junctions are listed but not followed.
+pub fn collect_paths(root: &Path, recursive: bool) -> ElevateResult<Vec<PathBuf>> {
+    let mut paths = vec![root.to_path_buf()];
+    if !recursive {
+        return Ok(paths);
+    }
+
+    let mut next = 0;
+    while next < paths.len() {
+        let current = paths[next].clone();
+        next += 1;
+
+        let metadata = fs::symlink_metadata(&current)
+            .map_err(|e| file_error(e, &format!("Failed to read {}", current.display())))?;
+        if !metadata.is_dir() {
+            continue;
+        }
+
+        let entries = fs::read_dir(&current)
+            .map_err(|e| file_error(e, &format!("Failed to list {}", current.display())))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| file_error(e, &format!("Failed to list {}", current.display())))?;
+            paths.push(entry.path());
+        }
+    }
+
+    Ok(paths)
+}
+
+pub fn file_error(error: io::Error, context: &str) -> ElevateError {
+    WindowsError::from_code(error.raw_os_error().unwrap_or(0) as u32)
+        .to_elevate_error(WindowsErrorKind::FileOperation, context)
+}
+
+pub fn path_name(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+pub fn canonical_path(path: &Path) -> ElevateResult<PathBuf> {
+    fs::canonicalize(path).map_err(|e| file_error(e, &format!("Failed to resolve {}", path.display())))
+}
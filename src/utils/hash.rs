@@ -0,0 +1,10 @@
+use sha2::{Digest, Sha256};
+
+/// Lowercase hex SHA-256 of a byte slice
+pub fn sha256_hex(data: &[u8]) -> String {
+    to_hex(&Sha256::digest(data))
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -1,3 +1,11 @@
 pub mod error;
 pub mod config;
-pub mod security;
\ No newline at end of file
+#[cfg(windows)]
+pub mod security;
+pub mod files;
+pub mod time;
+pub mod hash;
+#[cfg(windows)]
+pub mod signal;
+pub mod cancel;
+pub mod command_line;
@@ -1,30 +1,22 @@
 use std::ptr;
-use winapi::um::winnt::{HANDLE, TOKEN_ADJUST_PRIVILEGES, TOKEN_QUERY, SE_PRIVILEGE_ENABLED, TOKEN_ALL_ACCESS};
-use winapi::um::processthreadsapi::{CreateProcessW, OpenProcessToken, GetCurrentProcess};
+use winapi::um::winnt::{HANDLE, TOKEN_ADJUST_PRIVILEGES, TOKEN_QUERY, SE_PRIVILEGE_ENABLED};
+use winapi::um::processthreadsapi::{CreateProcessW, OpenProcessToken};
 use winapi::um::securitybaseapi::ImpersonateSelf;
 use winapi::um::winnt::SecurityImpersonation;
 use winapi::um::processthreadsapi::{OpenThreadToken, GetCurrentThread};
 use winapi::um::winbase::{CREATE_SUSPENDED, CREATE_NEW_CONSOLE};
 use winapi::shared::minwindef::DWORD;
 
-pub struct SecurityContext {
-    handle: HANDLE,
-}
+use crate::core::handle::{HandleKind, OwnedHandle};
+use crate::core::process::ProcessInfo;
+
+// only `new` makes one, once SeDebugPrivilege is enabled
+pub struct SecurityContext(());
 
 impl SecurityContext {
     pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        // Enable debug privilege first
         Self::set_debug_privilege()?;
-        
-        // Get current process token
-        let mut token = ptr::null_mut();
-        let current_process = unsafe { GetCurrentProcess() };
-        
-        if unsafe { OpenProcessToken(current_process, TOKEN_ALL_ACCESS, &mut token) } == 0 {
-            return Err("Failed to open process token".into());
-        }
-
-        Ok(SecurityContext { handle: token })
+        Ok(SecurityContext(()))
     }
 
     fn set_debug_privilege() -> Result<(), Box<dyn std::error::Error>> {
@@ -44,14 +36,15 @@ impl SecurityContext {
             ) == 0 {
                 return Err("Failed to open thread token".into());
             }
+            let token = OwnedHandle::from_raw(token, HandleKind::Token);
 
             // Set debug privilege
-            Self::set_token_privilege(token, "SeDebugPrivilege")?;
+            Self::set_token_privilege(token.as_raw(), "SeDebugPrivilege")?;
         }
         Ok(())
     }
 
-    pub fn create_process(&self, command: &str, suspended: bool) -> Result<Process, Box<dyn std::error::Error>> {
+    pub fn create_process(&self, command: &str, suspended: bool) -> Result<ProcessInfo, Box<dyn std::error::Error>> {
         let mut wide_command: Vec<u16> = command.encode_utf16().chain(std::iter::once(0)).collect();
         let mut startup_info = unsafe { std::mem::zeroed::<winapi::um::processthreadsapi::STARTUPINFOW>() };
         startup_info.cb = std::mem::size_of::<winapi::um::processthreadsapi::STARTUPINFOW>() as u32;
@@ -83,10 +76,10 @@ impl SecurityContext {
             return Err("Failed to create process".into());
         }
 
-        Ok(Process::new(process_info))
+        Ok(unsafe { ProcessInfo::from_raw(process_info) })
     }
 
-    fn set_token_privilege(token: HANDLE, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    pub(crate) fn set_token_privilege(token: HANDLE, name: &str) -> Result<(), Box<dyn std::error::Error>> {
         use winapi::um::winbase::LookupPrivilegeValueW;
         use winapi::um::securitybaseapi::AdjustTokenPrivileges;
         
@@ -125,20 +118,18 @@ impl SecurityContext {
         Ok(())
     }
 
-    pub fn set_all_privileges(&self, process_handle: HANDLE) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn set_all_privileges(&self, process: &OwnedHandle) -> Result<(), Box<dyn std::error::Error>> {
         // Enable all privileges for the process
-        unsafe {
-            let token = self.get_process_token(process_handle, TOKEN_ADJUST_PRIVILEGES)?;
-            self.enable_all_privileges(token.0)
-        }
+        let token = self.get_process_token(process.as_raw(), TOKEN_ADJUST_PRIVILEGES)?;
+        self.enable_all_privileges(token.as_raw())
     }
 
-    fn get_process_token(&self, process_handle: HANDLE, access: DWORD) -> Result<HandleWrapper, Box<dyn std::error::Error>> {
+    fn get_process_token(&self, process_handle: HANDLE, access: DWORD) -> Result<OwnedHandle, Box<dyn std::error::Error>> {
         let mut token = ptr::null_mut();
         if unsafe { OpenProcessToken(process_handle, access, &mut token) } == 0 {
             return Err("Failed to open process token".into());
         }
-        Ok(HandleWrapper(token))
+        Ok(unsafe { OwnedHandle::from_raw(token, HandleKind::Token) })
     }
 
     fn enable_all_privileges(&self, token: HANDLE) -> Result<(), Box<dyn std::error::Error>> {
@@ -160,54 +151,3 @@ impl SecurityContext {
         Ok(())
     }
 }
-
-pub struct Process {
-    handle: HANDLE,
-    thread_handle: HANDLE,
-    process_id: DWORD,
-    thread_id: DWORD,
-}
-
-impl Process {
-    pub fn new(info: winapi::um::processthreadsapi::PROCESS_INFORMATION) -> Self {
-        Self {
-            handle: info.hProcess,
-            thread_handle: info.hThread,
-            process_id: info.dwProcessId,
-            thread_id: info.dwThreadId,
-        }
-    }
-
-    pub fn resume(&self) -> Result<(), Box<dyn std::error::Error>> {
-        if unsafe { winapi::um::processthreadsapi::ResumeThread(self.thread_handle) } == u32::MAX {
-            return Err("Failed to resume thread".into());
-        }
-        Ok(())
-    }
-}
-
-impl Drop for Process {
-    fn drop(&mut self) {
-        unsafe {
-            if !self.handle.is_null() {
-                winapi::um::handleapi::CloseHandle(self.handle);
-            }
-            if !self.thread_handle.is_null() {
-                winapi::um::handleapi::CloseHandle(self.thread_handle);
-            }
-        }
-    }
-}
-
-// Add this struct to handle automatic cleanup of Windows handles
-struct HandleWrapper(HANDLE);
-
-impl Drop for HandleWrapper {
-    fn drop(&mut self) {
-        unsafe {
-            if !self.0.is_null() {
-                winapi::um::handleapi::CloseHandle(self.0);
-            }
-        }
-    }
-}
\ No newline at end of file
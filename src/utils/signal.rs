@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+use winapi::{
+    shared::minwindef::{BOOL, DWORD, FALSE, TRUE},
+    um::{consoleapi::SetConsoleCtrlHandler, wincon::CTRL_BREAK_EVENT, wincon::CTRL_C_EVENT},
+};
+
+use crate::utils::cancel::CancellationToken;
+use crate::utils::error::{ElevateResult, WindowsError, WindowsErrorKind};
+
+static STOP_REQUESTED: OnceLock<CancellationToken> = OnceLock::new();
+static CLEANUP_FINISHED: AtomicBool = AtomicBool::new(false);
+
+// windows ends the process once the handler returns from a close, logoff or
+// shutdown event, so those wait here for the main thread to clean up
+const CLEANUP_GRACE: Duration = Duration::from_secs(4);
+
+fn stop_requested_token() -> &'static CancellationToken {
+    STOP_REQUESTED.get_or_init(CancellationToken::new)
+}
+
+unsafe extern "system" fn ctrl_handler(ctrl_type: DWORD) -> BOOL {
+    stop_requested_token().cancel();
+
+    if ctrl_type != CTRL_C_EVENT && ctrl_type != CTRL_BREAK_EVENT {
+        let started = Instant::now();
+        while !CLEANUP_FINISHED.load(Ordering::SeqCst) && started.elapsed() < CLEANUP_GRACE {
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+    TRUE
+}
+
+/// Turns Ctrl+C, Ctrl+Break and closing the console into a stop request
+/// instead of killing the process, so cleanup code gets to run
+pub fn install_ctrl_handler() -> ElevateResult<()> {
+    if unsafe { SetConsoleCtrlHandler(Some(ctrl_handler), TRUE) } == FALSE {
+        return Err(WindowsError::last_error().to_elevate_error(
+            WindowsErrorKind::ProcessOperation,
+            "Failed to install console control handler",
+        ));
+    }
+    Ok(())
+}
+
+pub fn stop_requested() -> bool {
+    stop_requested_token().is_cancelled()
+}
+
+/// Token cancelled by the same events, for waits that take a token
+pub fn stop_token() -> CancellationToken {
+    stop_requested_token().clone()
+}
+
+/// Blocks until Ctrl+C or another console control event arrives
+pub fn wait_for_stop() {
+    while !stop_requested() {
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Lets a pending close or shutdown event end the process
+pub fn cleanup_finished() {
+    CLEANUP_FINISHED.store(true, Ordering::SeqCst);
+}
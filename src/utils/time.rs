@@ -0,0 +1,93 @@
+#[cfg(test)]
+use std::cell::Cell;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Seconds since the unix epoch, used for timestamps in saved state
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Seconds since the unix epoch of a `FILETIME`, which counts 100ns intervals since 1601
+pub fn filetime_to_unix(filetime: u64) -> u64 {
+    (filetime / 10_000_000).saturating_sub(11_644_473_600)
+}
+
+/// A number of seconds as `42s`, `5m` or `3h07m`
+pub fn format_age(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Eight hex digits from the clock and process id, unique enough to tell
+/// apart things in saved state such as sessions and runs
+pub fn short_id() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    format!("{:08x}", nanos ^ std::process::id().rotate_left(16))
+}
+
+/// Time source for polling loops, so tests can run them without sleeping
+pub trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A clock that only moves when slept on, for tests of polling loops
+#[cfg(test)]
+pub struct FakeClock {
+    start: Instant,
+    elapsed: Cell<Duration>,
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn new() -> Self {
+        Self { start: Instant::now(), elapsed: Cell::new(Duration::ZERO) }
+    }
+}
+
+#[cfg(test)]
+impl Default for FakeClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.elapsed.set(self.elapsed.get() + duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filetime_to_unix() {
+        assert_eq!(filetime_to_unix(116_444_736_000_000_000), 0);
+        // 2021-01-01T00:00:00Z
+        assert_eq!(filetime_to_unix(132_539_328_000_000_000), 1_609_459_200);
+        assert_eq!(filetime_to_unix(0), 0);
+    }
+}